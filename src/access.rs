@@ -0,0 +1,495 @@
+//! Which schemas and tables clients may see and query.
+//!
+//! `ALLOWED_TABLES` and `DENIED_TABLES` take comma-separated `[schema.]table`
+//! patterns in which `*` matches any run of characters, such as
+//! `public.*,reporting.*` or `audit.*,*_secrets`; a pattern without a schema
+//! matches the table in every schema. When `ALLOWED_TABLES` is set only the
+//! tables it matches are accessible, and a table matched by `DENIED_TABLES`
+//! never is. Hidden tables are left out of `resources/list`, read as if they
+//! did not exist, and refused by the tools that take a table name. SQL given
+//! to `query`, `explain`, and `export_query` is parsed to find the tables it
+//! references, and refused if any is hidden or if it cannot be parsed, or if
+//! it calls a function that reads tables named in text, such as
+//! `query_to_xml` or `dblink`, or data outside the tables: the server's files
+//! and directories, large objects, and changes decoded from the WAL.
+//!
+//! The lists check the SQL clients send, not what it reads when it runs: a
+//! view or function that reads a hidden table goes unnoticed, so enforcing
+//! them takes database privileges.
+
+use std::ops::ControlFlow;
+
+use anyhow::{Result, bail};
+use sqlparser::ast::{Expr, Ident, visit_expressions, visit_relations};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use tokio_postgres::Client;
+use tokio_postgres::types::Type;
+
+use crate::tools::{ToolError, failed};
+use crate::{McpServer, PolicyDenial, config, pooler, quote_ident};
+
+/// Functions that read tables the SQL does not name where it can be checked,
+/// or data outside tables altogether.
+const OPAQUE_FUNCTIONS: &[&str] = &[
+    // A query, table, or schema given as text.
+    "query_to_xml",
+    "query_to_xmlschema",
+    "query_to_xml_and_xmlschema",
+    "table_to_xml",
+    "table_to_xmlschema",
+    "table_to_xml_and_xmlschema",
+    "cursor_to_xml",
+    "cursor_to_xmlschema",
+    "schema_to_xml",
+    "schema_to_xmlschema",
+    "schema_to_xml_and_xmlschema",
+    "database_to_xml",
+    "database_to_xmlschema",
+    "database_to_xml_and_xmlschema",
+    "ts_stat",
+    // Queries run over another connection.
+    "dblink",
+    "dblink_exec",
+    "dblink_open",
+    "dblink_fetch",
+    "dblink_send_query",
+    "dblink_get_result",
+    // Raw pages of a relation named as text, from pageinspect.
+    "get_raw_page",
+    "bt_page_items",
+    // Row changes decoded from the WAL, of every table.
+    "pg_logical_slot_get_changes",
+    "pg_logical_slot_peek_changes",
+    "pg_logical_slot_get_binary_changes",
+    "pg_logical_slot_peek_binary_changes",
+    // The server's files and directories.
+    "pg_read_file",
+    "pg_read_binary_file",
+    "pg_stat_file",
+    "pg_ls_dir",
+    "pg_ls_logdir",
+    "pg_ls_waldir",
+    "pg_ls_tmpdir",
+    "pg_ls_archive_statusdir",
+    "pg_ls_logicalsnapdir",
+    "pg_ls_logicalmapdir",
+    "pg_ls_replslotdir",
+    // Large objects, and the server's files they are imported from and
+    // exported to.
+    "lo_import",
+    "lo_export",
+    "lo_get",
+    "lo_open",
+    "loread",
+];
+
+/// A `[schema.]table` pattern.
+#[derive(Debug, Clone)]
+struct TablePattern {
+    schema: Option<String>,
+    table: String,
+}
+
+impl TablePattern {
+    fn parse(pattern: &str) -> Self {
+        match pattern.split_once('.') {
+            Some((schema, table)) => Self {
+                schema: Some(schema.to_string()),
+                table: table.to_string(),
+            },
+            None => Self {
+                schema: None,
+                table: pattern.to_string(),
+            },
+        }
+    }
+
+    fn matches(&self, schema: &str, table: &str) -> bool {
+        self.schema
+            .as_deref()
+            .is_none_or(|pattern| wildcard_match(pattern, schema))
+            && wildcard_match(&self.table, table)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TableAccess {
+    /// Every table is allowed when `None`.
+    allowed: Option<Vec<TablePattern>>,
+    denied: Vec<TablePattern>,
+}
+
+impl TableAccess {
+    /// Reads `ALLOWED_TABLES` and `DENIED_TABLES`.
+    pub(crate) fn from_env() -> Result<Self> {
+        let patterns = |name: &str| -> Result<Option<Vec<TablePattern>>> {
+            let Ok(value) = config::var(name) else {
+                return Ok(None);
+            };
+            let mut patterns = Vec::new();
+            for pattern in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                if pattern.matches('.').count() > 1 {
+                    bail!(
+                        "{} pattern '{}' must look like schema.table or table, as in audit.* or *_secrets",
+                        name,
+                        pattern
+                    );
+                }
+                patterns.push(TablePattern::parse(pattern));
+            }
+            Ok(Some(patterns))
+        };
+        Ok(Self {
+            allowed: patterns("ALLOWED_TABLES")?,
+            denied: patterns("DENIED_TABLES")?.unwrap_or_default(),
+        })
+    }
+
+    /// Whether any table is hidden.
+    pub(crate) fn is_restricted(&self) -> bool {
+        self.allowed.is_some() || !self.denied.is_empty()
+    }
+
+    pub(crate) fn allows(&self, schema: &str, table: &str) -> bool {
+        self.allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.iter().any(|p| p.matches(schema, table)))
+            && !self.denied.iter().any(|p| p.matches(schema, table))
+    }
+
+    fn denial(&self, schema: &str, table: &str) -> PolicyDenial {
+        let denied = self.denied.iter().any(|p| p.matches(schema, table));
+        PolicyDenial {
+            rule: "table_access",
+            matched: format!("{}.{}", schema, table),
+            message: format!(
+                "Table '{}.{}' is not accessible through this server",
+                schema, table
+            ),
+            remedy: if denied {
+                "Remove the pattern matching it from DENIED_TABLES to allow it.".to_string()
+            } else {
+                "Add a pattern matching it to ALLOWED_TABLES to allow it.".to_string()
+            },
+        }
+    }
+}
+
+/// Matches `text` against `pattern`, in which `*` stands for any run of
+/// characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*`: the pattern must match the whole text.
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// An identifier as PostgreSQL reads it: folded to lower case unless quoted.
+fn ident_name(ident: &Ident) -> String {
+    match ident.quote_style {
+        Some(_) => ident.value.clone(),
+        None => ident.value.to_lowercase(),
+    }
+}
+
+/// The relations `sql` refers to, each with its schema if one was written.
+pub(crate) fn referenced_tables(sql: &str) -> Result<Vec<(Option<String>, String)>, String> {
+    let statements = Parser::parse_sql(&PostgreSqlDialect {}, sql).map_err(|e| e.to_string())?;
+    let mut tables = Vec::new();
+    let _ = visit_relations(&statements, |name| {
+        let mut parts = name.0.iter().rev();
+        if let Some(table) = parts.next() {
+            tables.push((parts.next().map(ident_name), ident_name(table)));
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    Ok(tables)
+}
+
+/// The first function `sql` calls that the access lists cannot see through,
+/// whether in an expression or as a table, as in `FROM dblink(...)`.
+fn opaque_function(sql: &str) -> Option<String> {
+    let statements = Parser::parse_sql(&PostgreSqlDialect {}, sql).ok()?;
+    let opaque = |name: &sqlparser::ast::ObjectName| {
+        name.0
+            .last()
+            .map(ident_name)
+            .filter(|name| OPAQUE_FUNCTIONS.contains(&name.as_str()))
+    };
+    let found = visit_expressions(&statements, |expr| match expr {
+        Expr::Function(function) => match opaque(&function.name) {
+            Some(name) => ControlFlow::Break(name),
+            None => ControlFlow::Continue(()),
+        },
+        _ => ControlFlow::Continue(()),
+    });
+    if let ControlFlow::Break(name) = found {
+        return Some(name);
+    }
+    match visit_relations(&statements, |name| match opaque(name) {
+        Some(name) => ControlFlow::Break(name),
+        None => ControlFlow::Continue(()),
+    }) {
+        ControlFlow::Break(name) => Some(name),
+        ControlFlow::Continue(()) => None,
+    }
+}
+
+impl McpServer {
+    /// Refuses a table the access lists hide.
+    pub(crate) fn check_table_access(&self, schema: &str, table: &str) -> Result<(), ToolError> {
+        if self.access.allows(schema, table) {
+            Ok(())
+        } else {
+            Err(self.access.denial(schema, table).into())
+        }
+    }
+
+    /// Refuses SQL that refers to a hidden table, for running on the
+    /// session's connection.
+    pub(crate) async fn check_sql_access(&self, sql: &str) -> Result<(), ToolError> {
+        if !self.access.is_restricted() {
+            return Ok(());
+        }
+        self.check_sql_access_on(self.client.as_ref().unwrap(), sql)
+            .await
+    }
+
+    /// Refuses SQL that refers to a hidden table, for running on `client`.
+    /// Names without a schema are resolved through the `search_path` of
+    /// `client`, and so see the tables a transaction on it created; those
+    /// that resolve to no table, such as common table expressions, are left
+    /// for PostgreSQL to judge.
+    pub(crate) async fn check_sql_access_on(
+        &self,
+        client: &Client,
+        sql: &str,
+    ) -> Result<(), ToolError> {
+        if !self.access.is_restricted() {
+            return Ok(());
+        }
+        let tables = referenced_tables(sql).map_err(|e| PolicyDenial {
+            rule: "table_access",
+            matched: e.clone(),
+            message: format!(
+                "The tables this SQL refers to could not be determined, so it cannot be checked against the table access lists ({})",
+                e
+            ),
+            remedy: "Rewrite the statement in plainer SQL, or unset ALLOWED_TABLES and DENIED_TABLES."
+                .to_string(),
+        })?;
+        if let Some(function) = opaque_function(sql) {
+            return Err(PolicyDenial {
+                rule: "table_access",
+                matched: function.clone(),
+                message: format!(
+                    "Function '{}' reads tables or files that the table access lists cannot check, so it is not allowed while they are set",
+                    function
+                ),
+                remedy: "Query the tables by name instead, or unset ALLOWED_TABLES and DENIED_TABLES."
+                    .to_string(),
+            }
+            .into());
+        }
+        for (schema, table) in &tables {
+            if let Some(schema) = schema {
+                self.check_table_access(schema, table)?;
+            }
+        }
+        let resolved = resolve_tables(client, &tables)
+            .await
+            .map_err(|e| failed("Failed to resolve the tables the SQL refers to", e))?;
+        for (schema, table) in &resolved {
+            self.check_table_access(schema, table)?;
+        }
+        Ok(())
+    }
+}
+
+/// The schema and name of each of `tables` that exists, with names lacking a
+/// schema resolved through the `search_path` of `client`.
+pub(crate) async fn resolve_tables(
+    client: &Client,
+    tables: &[(Option<String>, String)],
+) -> Result<Vec<(String, String)>, tokio_postgres::Error> {
+    let names: Vec<String> = tables
+        .iter()
+        .map(|(schema, table)| match schema {
+            Some(schema) => format!("{}.{}", quote_ident(schema), quote_ident(table)),
+            None => quote_ident(table),
+        })
+        .collect();
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rows = pooler::query(
+        client,
+        "SELECT n.nspname::text, c.relname::text \
+         FROM unnest($1::text[]) AS r(name) \
+         JOIN pg_class c ON c.oid = to_regclass(r.name) \
+         JOIN pg_namespace n ON n.oid = c.relnamespace",
+        &[(&names, Type::TEXT_ARRAY)],
+    )
+    .await?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("orders", "orders"));
+        assert!(!wildcard_match("orders", "orders_2024"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*_secrets", "api_secrets"));
+        assert!(!wildcard_match("*_secrets", "secrets"));
+        assert!(wildcard_match("audit*", "audit_log"));
+        assert!(wildcard_match("a*b*c", "a-b-b-c"));
+        assert!(!wildcard_match("a*b*c", "a-c"));
+        // The prefix and suffix may not overlap.
+        assert!(!wildcard_match("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn patterns() {
+        let pattern = TablePattern::parse("public.*");
+        assert!(pattern.matches("public", "orders"));
+        assert!(!pattern.matches("audit", "orders"));
+        let pattern = TablePattern::parse("*_secrets");
+        assert!(pattern.matches("public", "api_secrets"));
+        assert!(pattern.matches("audit", "db_secrets"));
+    }
+
+    #[test]
+    fn allowed_and_denied() {
+        let access = TableAccess {
+            allowed: Some(vec![TablePattern::parse("public.*")]),
+            denied: vec![TablePattern::parse("*_secrets")],
+        };
+        assert!(access.is_restricted());
+        assert!(access.allows("public", "orders"));
+        assert!(!access.allows("audit", "log"));
+        assert!(!access.allows("public", "api_secrets"));
+        assert!(!TableAccess::default().is_restricted());
+    }
+
+    #[test]
+    fn tables_are_found() {
+        let tables = referenced_tables(
+            "SELECT * FROM Orders o JOIN audit.\"Log\" l ON true \
+             WHERE o.id IN (SELECT id FROM public.items)",
+        )
+        .unwrap();
+        assert_eq!(
+            tables,
+            [
+                (None, "orders".to_string()),
+                (Some("audit".to_string()), "Log".to_string()),
+                (Some("public".to_string()), "items".to_string()),
+            ]
+        );
+        assert!(referenced_tables("SELECT FROM WHERE").is_err());
+    }
+
+    #[test]
+    fn opaque_functions_are_found() {
+        assert_eq!(opaque_function("SELECT count(*) FROM t"), None);
+        assert_eq!(
+            opaque_function("SELECT query_to_xml('SELECT * FROM secrets', true, false, '')"),
+            Some("query_to_xml".to_string())
+        );
+        assert_eq!(
+            opaque_function("SELECT * FROM dblink('dbname=x', 'SELECT 1') AS t(a int)"),
+            Some("dblink".to_string())
+        );
+        assert_eq!(
+            opaque_function("SELECT PG_CATALOG.pg_read_file('/etc/passwd')"),
+            Some("pg_read_file".to_string())
+        );
+        // A mention in a string is no call.
+        assert_eq!(opaque_function("SELECT 'dblink'"), None);
+    }
+
+    fn assert_opaque(sql: &str, function: &str) {
+        assert_eq!(opaque_function(sql).as_deref(), Some(function), "{}", sql);
+    }
+
+    #[test]
+    fn file_functions_are_opaque() {
+        assert_opaque("SELECT pg_read_file('postgresql.conf')", "pg_read_file");
+        assert_opaque(
+            "SELECT pg_read_binary_file('global/pg_control')",
+            "pg_read_binary_file",
+        );
+        assert_opaque("SELECT * FROM pg_stat_file('PG_VERSION')", "pg_stat_file");
+        assert_opaque("SELECT f FROM pg_ls_dir('.') AS f", "pg_ls_dir");
+        for function in [
+            "pg_ls_logdir",
+            "pg_ls_waldir",
+            "pg_ls_tmpdir",
+            "pg_ls_archive_statusdir",
+            "pg_ls_logicalsnapdir",
+            "pg_ls_logicalmapdir",
+        ] {
+            assert_opaque(&format!("SELECT name FROM {}()", function), function);
+        }
+        assert_opaque(
+            "SELECT * FROM pg_ls_replslotdir('slot')",
+            "pg_ls_replslotdir",
+        );
+    }
+
+    #[test]
+    fn large_object_functions_are_opaque() {
+        assert_opaque("SELECT lo_import('/etc/passwd')", "lo_import");
+        assert_opaque("SELECT lo_export(16400, '/tmp/out')", "lo_export");
+        assert_opaque("SELECT convert_from(lo_get(16400), 'UTF8')", "lo_get");
+        assert_opaque("SELECT loread(lo_open(16400, 262144), 100)", "loread");
+    }
+
+    #[test]
+    fn text_query_functions_are_opaque() {
+        assert_opaque(
+            "SELECT table_to_xml('secrets', true, false, '')",
+            "table_to_xml",
+        );
+        assert_opaque(
+            "SELECT schema_to_xml('audit', true, false, '')",
+            "schema_to_xml",
+        );
+        assert_opaque("SELECT * FROM ts_stat('SELECT v FROM secrets')", "ts_stat");
+        assert_opaque(
+            "SELECT dblink_exec('dbname=x', 'DELETE FROM secrets')",
+            "dblink_exec",
+        );
+    }
+
+    #[test]
+    fn page_and_wal_functions_are_opaque() {
+        assert_opaque("SELECT get_raw_page('secrets', 0)", "get_raw_page");
+        assert_opaque(
+            "SELECT * FROM bt_page_items('secrets_pkey', 1)",
+            "bt_page_items",
+        );
+        assert_opaque(
+            "SELECT data FROM pg_logical_slot_peek_changes('slot', NULL, NULL)",
+            "pg_logical_slot_peek_changes",
+        );
+    }
+}
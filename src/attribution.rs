@@ -0,0 +1,107 @@
+//! Attributing requests to the people and agents behind them.
+//!
+//! Everyone who reaches the database through the server does so as the same
+//! database user, so the database alone cannot tell whose statement it is
+//! running. Each request is attributed to what the session knows of who made
+//! it:
+//!
+//! - the client named in `initialize`'s `clientInfo`, such as
+//!   `claude-desktop 0.9.2`
+//! - with the HTTP transport, the subject that an authenticating proxy passes
+//!   in the header named by `HTTP_SUBJECT_HEADER`, such as `X-Forwarded-User`
+//! - the person or agent the request says it acts for, in its
+//!   `_meta.on_behalf_of`
+//!
+//! Only the subject is vouched for, by the proxy; the client and
+//! `on_behalf_of` are what the client says. Hook events carry each of them,
+//! and every connection's `application_name` is set to a summary of them as
+//! it is checked out, or with transaction pooling as each transaction
+//! begins, so `pg_stat_activity` and the server log's `%a` show who ran a
+//! statement.
+
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+
+/// The longest `application_name` PostgreSQL keeps.
+const MAX_APPLICATION_NAME_BYTES: usize = 63;
+
+/// Who a request is made by and for, as far as the session knows.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Attribution {
+    /// The client's name and version from `clientInfo`, shared by the
+    /// session's requests.
+    client: Arc<Mutex<Option<String>>>,
+    /// The authenticated subject of an HTTP session.
+    pub(crate) subject: Option<String>,
+    /// Whom the request being handled says it acts for.
+    pub(crate) on_behalf_of: Option<String>,
+}
+
+impl Attribution {
+    /// Records the client `initialize` names in its `params`.
+    pub(crate) fn set_client(&self, params: Option<&Value>) {
+        let Some(info) = params.and_then(|p| p.get("clientInfo")) else {
+            return;
+        };
+        let field = |name: &str| {
+            info.get(name)
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+        let client = match (field("name"), field("version")) {
+            (Some(name), Some(version)) => Some(format!("{} {}", name, version)),
+            (name, _) => name.map(str::to_string),
+        };
+        *self.client.lock().unwrap_or_else(|e| e.into_inner()) = client;
+    }
+
+    /// Records whom the request with `params` says it acts for.
+    pub(crate) fn set_request(&mut self, params: Option<&Value>) {
+        self.on_behalf_of = params
+            .and_then(|p| p.get("_meta"))
+            .and_then(|m| m.get("on_behalf_of"))
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string);
+    }
+
+    pub(crate) fn client(&self) -> Option<String> {
+        self.client
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Who the request is for, then through which client, as in "alice via
+    /// claude-desktop 0.9.2"; `None` when nothing is known.
+    pub(crate) fn summary(&self) -> Option<String> {
+        let person = self.on_behalf_of.as_ref().or(self.subject.as_ref());
+        match (person, self.client()) {
+            (Some(person), Some(client)) => Some(format!("{} via {}", person, client)),
+            (Some(person), None) => Some(person.clone()),
+            (None, client) => client,
+        }
+    }
+
+    /// The `application_name` for the request's connection: the summary in
+    /// printable ASCII, which is all PostgreSQL keeps, cut to the length it
+    /// keeps.
+    pub(crate) fn application_name(&self) -> Option<String> {
+        let mut name: String = self
+            .summary()?
+            .chars()
+            .map(|c| {
+                if c.is_ascii_graphic() || c == ' ' {
+                    c
+                } else {
+                    '?'
+                }
+            })
+            .collect();
+        name.truncate(MAX_APPLICATION_NAME_BYTES);
+        Some(name)
+    }
+}
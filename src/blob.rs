@@ -0,0 +1,163 @@
+//! Binary column values (`bytea` and large objects) as their own resources.
+//!
+//! Table reads replace each binary value with a reference to
+//! `postgres:///<table>/<key>/<column>`, which is read back as blob content
+//! with a MIME type sniffed from the data. Large objects can also be listed,
+//! read in chunks, and exported by OID through the large object tools.
+
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use tokio::io::AsyncWriteExt;
+use tokio_postgres::Client;
+use tokio_postgres::types::Type;
+
+use crate::{pooler, quote_ident};
+
+/// Largest binary value served as a blob resource.
+pub(crate) const MAX_BLOB_BYTES: i64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BlobKind {
+    Bytea,
+    /// An `oid` (or `lo` domain) column referencing a large object.
+    LargeObject,
+}
+
+impl BlobKind {
+    pub(crate) fn from_type(type_name: &str) -> Option<Self> {
+        match type_name {
+            "bytea" => Some(Self::Bytea),
+            "oid" | "lo" => Some(Self::LargeObject),
+            _ => None,
+        }
+    }
+
+    /// Expression selecting what a table read shows instead of the value:
+    /// the size of a `bytea`, or the large object's OID.
+    pub(crate) fn summary_expr(self, column: &str) -> String {
+        match self {
+            Self::Bytea => format!("octet_length({})::int8", quote_ident(column)),
+            Self::LargeObject => format!("{}::int8", quote_ident(column)),
+        }
+    }
+
+    /// Key under which `summary_expr` is reported.
+    pub(crate) fn summary_key(self) -> &'static str {
+        match self {
+            Self::Bytea => "bytes",
+            Self::LargeObject => "oid",
+        }
+    }
+
+    /// Expression reading at most `MAX_BLOB_BYTES + 1` bytes of the value, so
+    /// oversized values are detected without transferring them.
+    fn data_expr(self, column: &str) -> String {
+        match self {
+            Self::Bytea => format!(
+                "substring({} from 1 for {})",
+                quote_ident(column),
+                MAX_BLOB_BYTES + 1
+            ),
+            Self::LargeObject => {
+                format!("lo_get({}, 0, {})", quote_ident(column), MAX_BLOB_BYTES + 1)
+            }
+        }
+    }
+}
+
+/// Reads the binary value of `column` in the row whose `key_column` has the
+/// text representation `key`.
+pub(crate) async fn read_blob(
+    client: &Client,
+    qualified: &str,
+    key_column: &str,
+    key: &str,
+    column: &str,
+    kind: BlobKind,
+) -> Result<Vec<u8>> {
+    let sql = format!(
+        "SELECT {} FROM {} WHERE {}::text = $1",
+        kind.data_expr(column),
+        qualified,
+        quote_ident(key_column)
+    );
+    let rows = pooler::query(client, &sql, &[(&key, Type::TEXT)])
+        .await
+        .with_context(|| format!("Failed to read {}", column))?;
+    let row = rows
+        .first()
+        .with_context(|| format!("No row with {} = {}", key_column, key))?;
+    let data: Option<Vec<u8>> = row.get(0);
+    let data = data.with_context(|| format!("{} is NULL in this row", column))?;
+    if data.len() as i64 > MAX_BLOB_BYTES {
+        bail!(
+            "{} is larger than the {} byte limit for blob resources",
+            column,
+            MAX_BLOB_BYTES
+        );
+    }
+    Ok(data)
+}
+
+/// Bytes fetched per round trip when exporting a large object.
+const EXPORT_CHUNK_BYTES: i32 = 1024 * 1024;
+
+/// Streams large object `oid` to `path` in chunks, returning its size.
+pub(crate) async fn export_large_object(client: &Client, oid: i64, path: &Path) -> Result<u64> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut offset: i64 = 0;
+    loop {
+        let chunk: Vec<u8> = pooler::query_one(
+            client,
+            "SELECT lo_get($1::int8::oid, $2, $3)",
+            &[
+                (&oid, Type::INT8),
+                (&offset, Type::INT8),
+                (&EXPORT_CHUNK_BYTES, Type::INT4),
+            ],
+        )
+        .await
+        .with_context(|| format!("Failed to read large object {}", oid))?
+        .get(0);
+        file.write_all(&chunk).await?;
+        offset += chunk.len() as i64;
+        if chunk.len() < EXPORT_CHUNK_BYTES as usize {
+            break;
+        }
+    }
+    file.flush().await?;
+    Ok(offset as u64)
+}
+
+/// Guesses a MIME type from well-known file signatures, falling back to
+/// `text/plain` for UTF-8 and `application/octet-stream` otherwise.
+pub(crate) fn detect_mime_type(data: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"BM", "image/bmp"),
+    ];
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return mime;
+    }
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    if std::str::from_utf8(data).is_ok() {
+        return "text/plain";
+    }
+    "application/octet-stream"
+}
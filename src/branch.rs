@@ -0,0 +1,195 @@
+//! Disposable copies of the database, for experiments that must not touch it.
+//!
+//! `create_branch` makes a copy in one of two ways, chosen by
+//! `BRANCH_PROVIDER`:
+//!
+//! - `neon` creates a branch of a Neon project through the Neon API, with a
+//!   compute endpoint of its own. Branches are copy-on-write, so one is made
+//!   in seconds whatever the size of the database. `NEON_API_KEY` and
+//!   `NEON_PROJECT_ID` are required; `NEON_PARENT_BRANCH_ID` names the branch
+//!   to copy, the project's default branch when unset.
+//! - `template` runs `CREATE DATABASE <copy> TEMPLATE <database>` on the same
+//!   server, from its `postgres` database. The server's role needs
+//!   `CREATEDB`, and PostgreSQL refuses while any other session is connected
+//!   to the database, so this suits development databases. The server closes
+//!   its own idle connections to the database first.
+//!
+//! Unset, `create_branch` is not offered. A copy has the roles and passwords
+//! of the database it copies, so the tool reports how to connect to it with
+//! the server's own credentials, the password masked. Copies are left for the
+//! user to drop when done with them.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use tokio_postgres::Config;
+use tokio_postgres::config::Host;
+
+use crate::outbound::{self, PostError};
+use crate::{config, percent_encode};
+
+const DEFAULT_NEON_API_URL: &str = "https://console.neon.tech/api/v2";
+const NEON_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The database template copies are made from, since PostgreSQL cannot copy
+/// the database a statement runs in.
+pub(crate) const MAINTENANCE_DATABASE: &str = "postgres";
+
+/// The longest database name PostgreSQL keeps whole.
+pub(crate) const MAX_DATABASE_NAME_BYTES: usize = 63;
+
+/// How `create_branch` copies the database.
+#[derive(Clone)]
+pub(crate) enum BranchProvider {
+    Neon(NeonSettings),
+    Template,
+}
+
+#[derive(Clone)]
+pub(crate) struct NeonSettings {
+    api_url: String,
+    api_key: String,
+    project_id: String,
+    /// The branch to copy; the project's default branch when unset.
+    parent_branch_id: Option<String>,
+}
+
+impl BranchProvider {
+    /// Reads `BRANCH_PROVIDER` and, for Neon, `NEON_API_KEY`,
+    /// `NEON_PROJECT_ID`, `NEON_PARENT_BRANCH_ID`, and `NEON_API_URL`. `None`
+    /// when branching is off.
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        let setting = |name: &str| config::var(name).ok().filter(|v| !v.is_empty());
+        let provider = setting("BRANCH_PROVIDER").unwrap_or_default();
+        Ok(Some(match provider.to_ascii_lowercase().as_str() {
+            "" => return Ok(None),
+            "template" => Self::Template,
+            "neon" => {
+                outbound::require("BRANCH_PROVIDER=neon")?;
+                Self::Neon(NeonSettings {
+                    api_url: setting("NEON_API_URL")
+                        .as_deref()
+                        .unwrap_or(DEFAULT_NEON_API_URL)
+                        .trim_end_matches('/')
+                        .to_string(),
+                    api_key: setting("NEON_API_KEY")
+                        .context("BRANCH_PROVIDER=neon needs NEON_API_KEY")?,
+                    project_id: setting("NEON_PROJECT_ID")
+                        .context("BRANCH_PROVIDER=neon needs NEON_PROJECT_ID")?,
+                    parent_branch_id: setting("NEON_PARENT_BRANCH_ID"),
+                })
+            }
+            _ => bail!(
+                "Unknown BRANCH_PROVIDER '{}' (expected 'neon' or 'template')",
+                provider
+            ),
+        }))
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Neon(_) => "neon",
+            Self::Template => "template",
+        }
+    }
+}
+
+/// A Neon branch, as created.
+pub(crate) struct NeonBranch {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    /// The host of the branch's compute endpoint.
+    pub(crate) host: String,
+}
+
+impl NeonSettings {
+    /// Creates a branch named `name` with a read-write compute endpoint.
+    pub(crate) async fn create_branch(&self, name: &str) -> Result<NeonBranch> {
+        let url = format!("{}/projects/{}/branches", self.api_url, self.project_id);
+        let mut branch = json!({ "name": name });
+        if let Some(parent) = &self.parent_branch_id {
+            branch["parent_id"] = json!(parent);
+        }
+        let body = json!({ "branch": branch, "endpoints": [{ "type": "read_write" }] });
+        let authorization = format!("Bearer {}", self.api_key);
+        let sent = outbound::post_json(url, Some(authorization), body.to_string(), NEON_TIMEOUT);
+        let text = match sent.await {
+            Ok(text) => text,
+            Err(PostError::Status(status, text)) => {
+                // The API explains refusals in a `message` field.
+                let message = serde_json::from_str::<Value>(&text)
+                    .ok()
+                    .and_then(|v| v["message"].as_str().map(str::to_string))
+                    .unwrap_or(text);
+                bail!(
+                    "The Neon API refused to create the branch ({}): {}",
+                    status,
+                    message
+                );
+            }
+            Err(PostError::Failed(e)) => bail!("Cannot reach the Neon API: {}", e),
+        };
+        let answer: Value =
+            serde_json::from_str(&text).context("The Neon API answered with invalid JSON")?;
+        let field = |pointer: &str| {
+            answer
+                .pointer(pointer)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        Ok(NeonBranch {
+            id: field("/branch/id").context("The Neon API's answer has no branch id")?,
+            name: field("/branch/name").unwrap_or_else(|| name.to_string()),
+            host: field("/endpoints/0/host")
+                .context("The Neon API's answer has no compute endpoint")?,
+        })
+    }
+}
+
+/// A connection string for the database `dbname` with the settings of
+/// `config` and `sslmode`, on `host` instead of its own if given, and with
+/// the password masked.
+pub(crate) fn masked_url(
+    config: &Config,
+    host: Option<&str>,
+    dbname: &str,
+    sslmode: &str,
+) -> String {
+    let host = host.map(str::to_string).or_else(|| {
+        config.get_hosts().first().map(|host| match host {
+            Host::Tcp(name) => name.clone(),
+            #[cfg(unix)]
+            Host::Unix(path) => path.display().to_string(),
+        })
+    });
+    let mut url = String::from("postgresql://");
+    if let Some(user) = config.get_user() {
+        url.push_str(&percent_encode(user));
+        if config.get_password().is_some() {
+            url.push_str(":********");
+        }
+        url.push('@');
+    }
+    let mut params = Vec::new();
+    match host {
+        // A socket directory goes in the `host` parameter instead.
+        Some(host) if host.starts_with('/') => params.push(format!("host={}", host)),
+        Some(host) if host.contains(':') => url.push_str(&format!("[{}]", host)),
+        Some(host) => url.push_str(&host),
+        None => {}
+    }
+    if let Some(port) = config.get_ports().first() {
+        url.push_str(&format!(":{}", port));
+    }
+    url.push('/');
+    url.push_str(&percent_encode(dbname));
+    if sslmode != "prefer" {
+        params.push(format!("sslmode={}", sslmode));
+    }
+    if !params.is_empty() {
+        url.push('?');
+        url.push_str(&params.join("&"));
+    }
+    url
+}
@@ -0,0 +1,172 @@
+//! Cancellation of requests the client no longer wants answered.
+//!
+//! A client sends `notifications/cancelled` with the `requestId` of a request
+//! it gave up on. If that request is being handled, the statement it is
+//! waiting on is cancelled on the server, the same way a statement that runs
+//! past its time limit is, and the request is answered with a "request
+//! cancelled" error as soon as it stops. A cancellation naming any other
+//! request, such as one that already finished or is still queued, is
+//! ignored.
+//!
+//! The statements that can be cancelled are those that run under the query
+//! time limit: those of `query` and its later pages, `explain`,
+//! `export_query`, `materialize_result`, and the other tools that read user
+//! data. Catalog lookups are short and run to completion.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde_json::{Value, json};
+use tokio_postgres::CancelToken;
+use tracing::{error, info};
+
+use crate::JsonRpcResponse;
+use crate::tls::Connector;
+
+/// JSON-RPC error code for a request the client cancelled.
+const REQUEST_CANCELLED: i32 = -32800;
+
+/// The requests a session is handling, shared with whatever reads the
+/// client's cancellations while they run. Each clone stands for one request
+/// once started.
+#[derive(Clone)]
+pub(crate) struct Cancellation {
+    /// The requests being handled, by their id as JSON.
+    in_flight: Arc<Mutex<HashMap<String, InFlight>>>,
+    /// This clone's request.
+    request: Option<String>,
+    tls: Connector,
+}
+
+#[derive(Default)]
+struct InFlight {
+    cancelled: bool,
+    /// The statement the request is waiting on.
+    statement: Option<CancelToken>,
+}
+
+impl Cancellation {
+    pub(crate) fn new(tls: Connector) -> Self {
+        Self {
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            request: None,
+            tls,
+        }
+    }
+
+    /// Records the request with `id` as being handled. Notifications, which
+    /// have none, cannot be cancelled.
+    pub(crate) fn start(&mut self, id: &Option<Value>) {
+        self.request = id.as_ref().map(Value::to_string);
+        if let Some(request) = &self.request {
+            self.lock().insert(request.clone(), InFlight::default());
+        }
+    }
+
+    /// Records that the request has been handled, returning whether it was
+    /// cancelled meanwhile.
+    pub(crate) fn finish(&mut self) -> bool {
+        let Some(request) = self.request.take() else {
+            return false;
+        };
+        self.lock()
+            .remove(&request)
+            .is_some_and(|in_flight| in_flight.cancelled)
+    }
+
+    /// Records the statement the request is now waiting on, returning
+    /// `false`, and recording nothing, if the request was already cancelled.
+    pub(crate) fn statement_started(&self, statement: CancelToken) -> bool {
+        let Some(request) = &self.request else {
+            return true;
+        };
+        match self.lock().get_mut(request) {
+            Some(in_flight) if in_flight.cancelled => false,
+            Some(in_flight) => {
+                in_flight.statement = Some(statement);
+                true
+            }
+            None => true,
+        }
+    }
+
+    pub(crate) fn statement_finished(&self) {
+        if let Some(request) = &self.request
+            && let Some(in_flight) = self.lock().get_mut(request)
+        {
+            in_flight.statement = None;
+        }
+    }
+
+    /// Handles `notifications/cancelled` with `params`.
+    pub(crate) fn cancel(&self, params: Option<&Value>) {
+        let Some(request_id) = params.and_then(|p| p.get("requestId")) else {
+            return;
+        };
+        let statement = {
+            let mut requests = self.lock();
+            let Some(in_flight) = requests
+                .get_mut(&request_id.to_string())
+                .filter(|in_flight| !in_flight.cancelled)
+            else {
+                return;
+            };
+            in_flight.cancelled = true;
+            in_flight.statement.take()
+        };
+        match params.and_then(|p| p.get("reason")).and_then(Value::as_str) {
+            Some(reason) => info!("Request {} cancelled by the client: {}", request_id, reason),
+            None => info!("Request {} cancelled by the client", request_id),
+        }
+        if let Some(statement) = statement {
+            self.cancel_statement(statement);
+        }
+    }
+
+    /// Cancels the statement this request is waiting on, if any, when the
+    /// request is abandoned at its deadline.
+    pub(crate) fn interrupt(&self) {
+        let statement = self
+            .request
+            .as_ref()
+            .and_then(|request| self.lock().get_mut(request)?.statement.take());
+        if let Some(statement) = statement {
+            self.cancel_statement(statement);
+        }
+    }
+
+    /// Cancels every request being handled, returning how many there were.
+    pub(crate) fn cancel_all(&self) -> usize {
+        let mut requests = self.lock();
+        let count = requests.len();
+        for in_flight in requests.values_mut() {
+            in_flight.cancelled = true;
+            if let Some(statement) = in_flight.statement.take() {
+                self.cancel_statement(statement);
+            }
+        }
+        count
+    }
+
+    fn cancel_statement(&self, statement: CancelToken) {
+        let tls = self.tls.clone();
+        tokio::spawn(async move {
+            if let Err(e) = statement.cancel_query(tls).await {
+                error!("Failed to cancel query of an abandoned request: {}", e);
+            }
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, InFlight>> {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The response to a request the client cancelled.
+pub(crate) fn cancelled_response(id: Option<Value>) -> JsonRpcResponse {
+    let mut response = JsonRpcResponse::error(id, REQUEST_CANCELLED, "Request cancelled");
+    if let Some(error) = response.error.as_mut() {
+        error.data = Some(json!({ "type": "cancelled" }));
+    }
+    response
+}
@@ -0,0 +1,476 @@
+//! Table descriptions, and a cache of them built ahead of time.
+//!
+//! `describe_table` reads a relation's columns, constraints, and indexes from
+//! the system catalogs, which on a database with tens of thousands of
+//! objects can take long enough to notice. With `CATALOG_WARMUP=true` the
+//! server describes every relation when it starts, on a connection of its
+//! own, and `describe_table` and `resources/list` answer from the result.
+//! The cache is rebuilt every `CATALOG_REFRESH_SECS` (300 by default; 0 never
+//! rebuilds it on a timer), and also as soon as the `query` tool runs
+//! `CREATE`, `ALTER`, `DROP`, `COMMENT`, or `SELECT INTO`. Relations created
+//! elsewhere are described directly until the next rebuild includes them,
+//! while changes made elsewhere to relations already cached show after it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use deadpool_postgres::Pool;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use tokio::sync::Notify;
+use tokio_postgres::Client;
+use tokio_postgres::types::Type;
+use tracing::{info, warn};
+
+use crate::{config, pooler, quote_ident};
+
+const DEFAULT_REFRESH_SECS: u64 = 300;
+
+/// Relations described per round of catalog queries.
+const DESCRIBE_BATCH: usize = 500;
+
+/// The schema, name, and kind, as in `TableDescription::kind`, of every
+/// table, partitioned table, view, materialized view, and foreign table
+/// outside the system catalogs that the user may read.
+pub(crate) const RELATIONS_QUERY: &str = "SELECT n.nspname::text, c.relname::text, \
+            CASE c.relkind WHEN 'r' THEN 'table' WHEN 'p' THEN 'partitioned table' \
+            WHEN 'v' THEN 'view' WHEN 'm' THEN 'materialized view' \
+            ELSE 'foreign table' END \
+     FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+     WHERE c.relkind IN ('r', 'p', 'v', 'm', 'f') \
+       AND n.nspname NOT IN ('pg_catalog', 'information_schema') \
+       AND has_table_privilege(c.oid, 'SELECT')";
+
+#[derive(Clone, Serialize, JsonSchema)]
+pub(crate) struct TableDescription {
+    schema: String,
+    table: String,
+    /// 'table', 'partitioned table', 'view', 'materialized view', or 'foreign
+    /// table'
+    kind: String,
+    comment: Option<String>,
+    columns: Vec<ColumnDescription>,
+    /// Primary key columns in key order; empty when there is none
+    primary_key: Vec<String>,
+    unique_constraints: Vec<UniqueConstraint>,
+    foreign_keys: Vec<ForeignKeyDescription>,
+    check_constraints: Vec<CheckConstraint>,
+    indexes: Vec<IndexDescription>,
+}
+
+#[derive(Clone, Serialize, JsonSchema)]
+struct ColumnDescription {
+    name: String,
+    /// Type with its modifiers, such as 'character varying(255)' or
+    /// 'numeric(10,2)'
+    data_type: String,
+    nullable: bool,
+    /// Default expression, such as 'now()'
+    default: Option<String>,
+    /// 'ALWAYS' or 'BY DEFAULT' for identity columns
+    identity: Option<String>,
+    comment: Option<String>,
+}
+
+#[derive(Clone, Serialize, JsonSchema)]
+struct UniqueConstraint {
+    name: String,
+    columns: Vec<String>,
+}
+
+#[derive(Clone, Serialize, JsonSchema)]
+struct ForeignKeyDescription {
+    name: String,
+    columns: Vec<String>,
+    references_schema: String,
+    references_table: String,
+    /// Referenced columns, paired with `columns` in order
+    references_columns: Vec<String>,
+    /// 'NO ACTION', 'RESTRICT', 'CASCADE', 'SET NULL', or 'SET DEFAULT'
+    on_update: String,
+    on_delete: String,
+}
+
+#[derive(Clone, Serialize, JsonSchema)]
+struct CheckConstraint {
+    name: String,
+    /// The constraint as written in DDL, such as 'CHECK ((age >= 0))'
+    definition: String,
+}
+
+#[derive(Clone, Serialize, JsonSchema)]
+struct IndexDescription {
+    name: String,
+    /// The index's CREATE INDEX statement
+    definition: String,
+    unique: bool,
+    primary: bool,
+}
+
+impl TableDescription {
+    /// The relation's quoted `schema.table` name.
+    pub(crate) fn qualified_name(&self) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.table))
+    }
+
+    /// A compact form of the description: the kind and comment, the columns
+    /// as `name type`, and the keys, without constraint or index details.
+    pub(crate) fn summary(&self) -> Value {
+        let mut summary = Map::new();
+        summary.insert("table".to_string(), json!(self.table));
+        summary.insert("kind".to_string(), json!(self.kind));
+        if let Some(comment) = &self.comment {
+            summary.insert("comment".to_string(), json!(comment));
+        }
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|c| {
+                let mut column = format!("{} {}", c.name, c.data_type);
+                if !c.nullable {
+                    column.push_str(" not null");
+                }
+                column
+            })
+            .collect();
+        summary.insert("columns".to_string(), json!(columns));
+        if !self.primary_key.is_empty() {
+            summary.insert("primary_key".to_string(), json!(self.primary_key));
+        }
+        let foreign_keys: Vec<String> = self
+            .foreign_keys
+            .iter()
+            .map(|fk| {
+                format!(
+                    "({}) -> {}.{}({})",
+                    fk.columns.join(", "),
+                    fk.references_schema,
+                    fk.references_table,
+                    fk.references_columns.join(", ")
+                )
+            })
+            .collect();
+        if !foreign_keys.is_empty() {
+            summary.insert("foreign_keys".to_string(), json!(foreign_keys));
+        }
+        Value::Object(summary)
+    }
+}
+
+/// Describes the relations named by `qualified`, each a quoted
+/// `schema.table`, leaving out names that resolve to no relation.
+pub(crate) async fn describe_tables(
+    client: &Client,
+    qualified: &[String],
+) -> Result<Vec<TableDescription>, tokio_postgres::Error> {
+    let relations = pooler::query(
+        client,
+        "SELECT c.oid, n.nspname::text, c.relname::text, \
+                    CASE c.relkind WHEN 'r' THEN 'table' WHEN 'p' THEN 'partitioned table' \
+                    WHEN 'v' THEN 'view' WHEN 'm' THEN 'materialized view' \
+                    WHEN 'f' THEN 'foreign table' ELSE c.relkind::text END, \
+                    obj_description(c.oid, 'pg_class') \
+             FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+             WHERE c.oid IN (SELECT to_regclass(name) FROM unnest($1::text[]) AS name) \
+             ORDER BY n.nspname, c.relname",
+        &[(&qualified, Type::TEXT_ARRAY)],
+    )
+    .await?;
+    let oids: Vec<u32> = relations.iter().map(|row| row.get(0)).collect();
+    let mut descriptions: HashMap<u32, TableDescription> = relations
+        .iter()
+        .map(|row| {
+            let description = TableDescription {
+                schema: row.get(1),
+                table: row.get(2),
+                kind: row.get(3),
+                comment: row.get(4),
+                columns: Vec::new(),
+                primary_key: Vec::new(),
+                unique_constraints: Vec::new(),
+                foreign_keys: Vec::new(),
+                check_constraints: Vec::new(),
+                indexes: Vec::new(),
+            };
+            (row.get(0), description)
+        })
+        .collect();
+
+    let columns = pooler::query(
+        client,
+        "SELECT a.attrelid, c.column_name::text, format_type(a.atttypid, a.atttypmod), \
+                    c.is_nullable = 'YES', c.column_default::text, \
+                    c.identity_generation::text, col_description(a.attrelid, a.attnum) \
+             FROM pg_attribute a \
+             JOIN pg_class r ON r.oid = a.attrelid \
+             JOIN pg_namespace n ON n.oid = r.relnamespace \
+             JOIN information_schema.columns c \
+               ON c.table_schema = n.nspname AND c.table_name = r.relname \
+              AND c.column_name = a.attname \
+             WHERE a.attrelid = ANY($1) AND a.attnum > 0 AND NOT a.attisdropped \
+             ORDER BY a.attrelid, c.ordinal_position",
+        &[(&oids, Type::OID_ARRAY)],
+    )
+    .await?;
+    for row in &columns {
+        let Some(description) = descriptions.get_mut(&row.get(0)) else {
+            continue;
+        };
+        description.columns.push(ColumnDescription {
+            name: row.get(1),
+            data_type: row.get(2),
+            nullable: row.get(3),
+            default: row.get(4),
+            identity: row.get(5),
+            comment: row.get(6),
+        });
+    }
+
+    let primary_keys = pooler::query(client,
+            "SELECT i.indrelid, \
+                    ARRAY(SELECT a.attname::text FROM unnest(i.indkey) WITH ORDINALITY k(attnum, n) \
+                          JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = k.attnum \
+                          ORDER BY k.n) \
+             FROM pg_index i WHERE i.indrelid = ANY($1) AND i.indisprimary",
+            &[(&oids, Type::OID_ARRAY)],
+        )
+        .await?;
+    for row in &primary_keys {
+        if let Some(description) = descriptions.get_mut(&row.get(0)) {
+            description.primary_key = row.get(1);
+        }
+    }
+
+    let constraints = pooler::query(client,
+            "SELECT con.conrelid, con.conname::text, con.contype::text, \
+                    ARRAY(SELECT a.attname::text FROM unnest(con.conkey) WITH ORDINALITY k(attnum, n) \
+                          JOIN pg_attribute a ON a.attrelid = con.conrelid AND a.attnum = k.attnum \
+                          ORDER BY k.n), \
+                    rn.nspname::text, rc.relname::text, \
+                    ARRAY(SELECT a.attname::text FROM unnest(con.confkey) WITH ORDINALITY k(attnum, n) \
+                          JOIN pg_attribute a ON a.attrelid = con.confrelid AND a.attnum = k.attnum \
+                          ORDER BY k.n), \
+                    con.confupdtype::text, con.confdeltype::text, \
+                    pg_get_constraintdef(con.oid) \
+             FROM pg_constraint con \
+             LEFT JOIN pg_class rc ON rc.oid = con.confrelid \
+             LEFT JOIN pg_namespace rn ON rn.oid = rc.relnamespace \
+             WHERE con.conrelid = ANY($1) AND con.contype IN ('u', 'f', 'c') \
+             ORDER BY con.conrelid, con.conname",
+            &[(&oids, Type::OID_ARRAY)],
+        )
+        .await?;
+    for row in &constraints {
+        let Some(description) = descriptions.get_mut(&row.get(0)) else {
+            continue;
+        };
+        let name: String = row.get(1);
+        match row.get::<_, String>(2).as_str() {
+            "u" => description.unique_constraints.push(UniqueConstraint {
+                name,
+                columns: row.get(3),
+            }),
+            "f" => description.foreign_keys.push(ForeignKeyDescription {
+                name,
+                columns: row.get(3),
+                references_schema: row.get(4),
+                references_table: row.get(5),
+                references_columns: row.get(6),
+                on_update: foreign_key_action(&row.get::<_, String>(7)),
+                on_delete: foreign_key_action(&row.get::<_, String>(8)),
+            }),
+            _ => description.check_constraints.push(CheckConstraint {
+                name,
+                definition: row.get(9),
+            }),
+        }
+    }
+
+    let indexes = pooler::query(
+        client,
+        "SELECT i.indrelid, c.relname::text, pg_get_indexdef(i.indexrelid), \
+                    i.indisunique, i.indisprimary \
+             FROM pg_index i JOIN pg_class c ON c.oid = i.indexrelid \
+             WHERE i.indrelid = ANY($1) \
+             ORDER BY i.indrelid, i.indisprimary DESC, c.relname",
+        &[(&oids, Type::OID_ARRAY)],
+    )
+    .await?;
+    for row in &indexes {
+        let Some(description) = descriptions.get_mut(&row.get(0)) else {
+            continue;
+        };
+        description.indexes.push(IndexDescription {
+            name: row.get(1),
+            definition: row.get(2),
+            unique: row.get(3),
+            primary: row.get(4),
+        });
+    }
+
+    Ok(oids
+        .iter()
+        .filter_map(|oid| descriptions.remove(oid))
+        .collect())
+}
+
+/// Names a foreign key's `confupdtype` or `confdeltype` action.
+fn foreign_key_action(code: &str) -> String {
+    match code {
+        "r" => "RESTRICT",
+        "c" => "CASCADE",
+        "n" => "SET NULL",
+        "d" => "SET DEFAULT",
+        _ => "NO ACTION",
+    }
+    .to_string()
+}
+
+/// The cache, shared by every session of the process.
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+/// Relation descriptions by schema and name.
+type Relations = HashMap<(String, String), TableDescription>;
+
+/// Descriptions of every relation visible to the server's role.
+#[derive(Default)]
+pub(crate) struct Catalog {
+    /// `None` until first built.
+    relations: Mutex<Option<Arc<Relations>>>,
+    changed: Notify,
+}
+
+impl Catalog {
+    /// The cache, if `CATALOG_WARMUP` is on.
+    pub(crate) fn shared() -> Option<&'static Catalog> {
+        CATALOG.get()
+    }
+
+    fn snapshot(&self) -> Option<Arc<Relations>> {
+        self.relations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub(crate) fn get(&self, schema: &str, table: &str) -> Option<TableDescription> {
+        self.snapshot()?
+            .get(&(schema.to_string(), table.to_string()))
+            .cloned()
+    }
+
+    /// The names of the relations in `schema`, once the cache is built.
+    pub(crate) fn table_names(&self, schema: &str) -> Option<Vec<String>> {
+        let mut names: Vec<String> = self
+            .snapshot()?
+            .keys()
+            .filter(|(s, _)| s == schema)
+            .map(|(_, table)| table.clone())
+            .collect();
+        names.sort();
+        Some(names)
+    }
+
+    /// The schema, name, and kind of every relation, once the cache is
+    /// built.
+    pub(crate) fn relations(&self) -> Option<Vec<(String, String, String)>> {
+        let mut relations: Vec<(String, String, String)> = self
+            .snapshot()?
+            .values()
+            .map(|d| (d.schema.clone(), d.table.clone(), d.kind.clone()))
+            .collect();
+        relations.sort();
+        Some(relations)
+    }
+
+    /// Asks for a rebuild after DDL.
+    pub(crate) fn schema_changed(&self) {
+        self.changed.notify_one();
+    }
+
+    async fn rebuild(&self, pool: &Pool) -> Result<usize> {
+        let client = pool
+            .get()
+            .await
+            .context("No connection for the catalog cache")?;
+        let names: Vec<String> = pooler::query(&client, RELATIONS_QUERY, &[])
+            .await
+            .context("Failed to list relations")?
+            .iter()
+            .map(|row| format!("{}.{}", quote_ident(row.get(0)), quote_ident(row.get(1))))
+            .collect();
+        let mut relations = HashMap::new();
+        for batch in names.chunks(DESCRIBE_BATCH) {
+            for description in describe_tables(&client, batch)
+                .await
+                .context("Failed to describe relations")?
+            {
+                relations.insert(
+                    (description.schema.clone(), description.table.clone()),
+                    description,
+                );
+            }
+        }
+        let count = relations.len();
+        *self.relations.lock().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(relations));
+        Ok(count)
+    }
+}
+
+/// How the cache is kept, when `CATALOG_WARMUP` turns it on.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CatalogSettings {
+    /// `None` when rebuilt only after DDL.
+    pub(crate) refresh: Option<Duration>,
+}
+
+impl CatalogSettings {
+    /// Reads `CATALOG_WARMUP` and `CATALOG_REFRESH_SECS`; `None` when warm-up
+    /// is off.
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        let warmup = config::var("CATALOG_WARMUP")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+        if !warmup {
+            return Ok(None);
+        }
+        let refresh_secs = match config::var("CATALOG_REFRESH_SECS") {
+            Ok(v) => v
+                .parse::<u64>()
+                .context("CATALOG_REFRESH_SECS must be a number of seconds")?,
+            Err(_) => DEFAULT_REFRESH_SECS,
+        };
+        Ok(Some(Self {
+            refresh: Some(Duration::from_secs(refresh_secs)).filter(|d| !d.is_zero()),
+        }))
+    }
+}
+
+/// Builds the cache in the background with connections from `pool`, and
+/// rebuilds it as `settings` say and whenever DDL is run.
+pub(crate) fn start(pool: Pool, settings: CatalogSettings) {
+    let catalog = CATALOG.get_or_init(Catalog::default);
+    tokio::spawn(async move {
+        loop {
+            let started = Instant::now();
+            match catalog.rebuild(&pool).await {
+                Ok(count) => info!(
+                    "Catalog cache holds {} relations, built in {} ms",
+                    count,
+                    started.elapsed().as_millis()
+                ),
+                Err(e) => warn!("Could not build the catalog cache: {:#}", e),
+            }
+            match settings.refresh {
+                Some(interval) => tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = catalog.changed.notified() => {}
+                },
+                None => catalog.changed.notified().await,
+            }
+        }
+    });
+}
@@ -0,0 +1,200 @@
+//! Simulated failures, for testing clients.
+//!
+//! A client has to cope with the server losing its database connection,
+//! taking too long, or answering with more, or other, than it expected, but
+//! a healthy database rarely does any of that on cue. With `CHAOS_FAULTS`
+//! set, the server fails some tool calls and resource reads on purpose, in
+//! the ways listed:
+//!
+//! - `connection_drop`: the request's connection is closed on the database
+//!   side once checked out, so its statements fail as with a real dropped
+//!   connection, and the pool replaces it
+//! - `slow_query`: the request waits `CHAOS_DELAY_MS` before it runs, so
+//!   that client timeouts and deadlines fire
+//! - `oversized_result`: the result gets an extra text item of
+//!   `CHAOS_RESULT_BYTES` bytes of filler
+//! - `malformed_row`: the first text item holding JSON is cut off halfway,
+//!   so that it no longer parses
+//!
+//! `all` stands for every one of them. Each request has a `CHAOS_RATE`
+//! chance of one of them, chosen at random, and each injected fault is
+//! logged. The mode is for test databases only: the server refuses to start
+//! with it when `DB_ENVIRONMENT` is production.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{Value, json};
+
+use crate::config;
+use crate::environment::Environment;
+
+const DEFAULT_RATE: f64 = 0.2;
+const DEFAULT_DELAY_MS: u64 = 5000;
+const DEFAULT_RESULT_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    ConnectionDrop,
+    SlowQuery,
+    OversizedResult,
+    MalformedRow,
+}
+
+impl Fault {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "connection_drop" => Self::ConnectionDrop,
+            "slow_query" => Self::SlowQuery,
+            "oversized_result" => Self::OversizedResult,
+            "malformed_row" => Self::MalformedRow,
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::ConnectionDrop => "connection_drop",
+            Self::SlowQuery => "slow_query",
+            Self::OversizedResult => "oversized_result",
+            Self::MalformedRow => "malformed_row",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Chaos {
+    faults: Vec<Fault>,
+    rate: f64,
+    pub(crate) delay: Duration,
+    result_bytes: usize,
+    random: SystemRandom,
+}
+
+impl Chaos {
+    /// Reads `CHAOS_FAULTS` and the settings that go with it; `None` when
+    /// it is unset or empty.
+    pub(crate) fn from_env(environment: Option<Environment>) -> Result<Option<Self>> {
+        let value = config::var("CHAOS_FAULTS").unwrap_or_default();
+        let mut faults = Vec::new();
+        for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let fault = match name.to_ascii_lowercase().as_str() {
+                "all" => {
+                    faults = vec![
+                        Fault::ConnectionDrop,
+                        Fault::SlowQuery,
+                        Fault::OversizedResult,
+                        Fault::MalformedRow,
+                    ];
+                    continue;
+                }
+                name => Fault::parse(name),
+            };
+            let fault = fault.with_context(|| {
+                format!(
+                    "Unknown CHAOS_FAULTS fault '{}' (expected 'connection_drop', 'slow_query', 'oversized_result', 'malformed_row', or 'all')",
+                    name
+                )
+            })?;
+            if !faults.contains(&fault) {
+                faults.push(fault);
+            }
+        }
+        if faults.is_empty() {
+            return Ok(None);
+        }
+        if environment == Some(Environment::Production) {
+            bail!("CHAOS_FAULTS cannot be set when DB_ENVIRONMENT is production");
+        }
+        let rate = match config::var("CHAOS_RATE") {
+            Ok(v) => v
+                .parse::<f64>()
+                .ok()
+                .filter(|rate| (0.0..=1.0).contains(rate))
+                .context("CHAOS_RATE must be a number from 0 to 1")?,
+            Err(_) => DEFAULT_RATE,
+        };
+        let delay_ms = match config::var("CHAOS_DELAY_MS") {
+            Ok(v) => v
+                .parse::<u64>()
+                .context("CHAOS_DELAY_MS must be a number of milliseconds")?,
+            Err(_) => DEFAULT_DELAY_MS,
+        };
+        let result_bytes = match config::var("CHAOS_RESULT_BYTES") {
+            Ok(v) => v
+                .parse::<usize>()
+                .context("CHAOS_RESULT_BYTES must be a number of bytes")?,
+            Err(_) => DEFAULT_RESULT_BYTES,
+        };
+        Ok(Some(Self {
+            faults,
+            rate,
+            delay: Duration::from_millis(delay_ms),
+            result_bytes,
+            random: SystemRandom::new(),
+        }))
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        let faults: Vec<&str> = self.faults.iter().map(|f| f.name()).collect();
+        format!(
+            "{} on {:.0}% of tool calls and resource reads",
+            faults.join(", "),
+            self.rate * 100.0
+        )
+    }
+
+    /// The fault to inject into a request for `method`, if any.
+    pub(crate) fn pick(&self, method: &str) -> Option<Fault> {
+        if method != "tools/call" && method != "resources/read" {
+            return None;
+        }
+        let mut bytes = [0u8; 8];
+        self.random.fill(&mut bytes).ok()?;
+        let roll = u64::from_le_bytes(bytes);
+        if (roll >> 11) as f64 / (1u64 << 53) as f64 >= self.rate {
+            return None;
+        }
+        Some(self.faults[(roll as usize) % self.faults.len()])
+    }
+
+    /// Applies `fault` to the `result` of a tool call or resource read,
+    /// if it is one that changes results.
+    pub(crate) fn corrupt(&self, fault: Fault, result: &mut Value) {
+        let key = if result.get("contents").is_some() {
+            "contents"
+        } else {
+            "content"
+        };
+        let Some(items) = result.get_mut(key).and_then(Value::as_array_mut) else {
+            return;
+        };
+        match fault {
+            Fault::OversizedResult => {
+                let filler = "chaos ".repeat(self.result_bytes.div_ceil(6));
+                items.push(json!({ "type": "text", "text": &filler[..self.result_bytes] }));
+            }
+            Fault::MalformedRow => {
+                let text = items.iter_mut().find_map(|item| {
+                    item.get_mut("text")
+                        .filter(|t| t.as_str().is_some_and(is_json))
+                });
+                if let Some(Value::String(text)) = text {
+                    let mut half = text.len() / 2;
+                    while !text.is_char_boundary(half) {
+                        half -= 1;
+                    }
+                    text.truncate(half);
+                }
+            }
+            Fault::ConnectionDrop | Fault::SlowQuery => {}
+        }
+    }
+}
+
+fn is_json(text: &str) -> bool {
+    let text = text.trim_start();
+    (text.starts_with('{') || text.starts_with('['))
+        && serde_json::from_str::<serde::de::IgnoredAny>(text).is_ok()
+}
@@ -0,0 +1,142 @@
+//! Byte ranges of resource contents.
+//!
+//! A client whose messages are limited in size can read a large resource a
+//! piece at a time by adding `offset` and `length`, in bytes, to
+//! `resources/read`. Each item of the contents is cut to that range: `text`
+//! by its UTF-8 bytes, ending early rather than splitting a character, and
+//! `blob` by its decoded bytes. The item's `_meta.chunk` gives the range
+//! returned, the `totalBytes`, and the `nextOffset` to ask for next, which is
+//! `null` after the last chunk.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use serde_json::{Value, json};
+
+/// The range a `resources/read` asked for.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ByteRange {
+    pub(crate) offset: u64,
+    /// To the end when `None`.
+    pub(crate) length: Option<u64>,
+}
+
+impl ByteRange {
+    /// Reads `offset` and `length` from the request parameters; `None` when
+    /// neither is given.
+    pub(crate) fn from_params(params: Option<&Value>) -> Result<Option<Self>, String> {
+        let field = |name: &str| match params.and_then(|p| p.get(name)) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => value
+                .as_u64()
+                .map(Some)
+                .ok_or_else(|| format!("'{}' must be a non-negative number of bytes", name)),
+        };
+        let offset = field("offset")?;
+        let length = field("length")?;
+        if length == Some(0) {
+            return Err("'length' must be at least 1".to_string());
+        }
+        Ok((offset.is_some() || length.is_some()).then(|| Self {
+            offset: offset.unwrap_or(0),
+            length,
+        }))
+    }
+
+    /// The end of the range within content of `total` bytes.
+    pub(crate) fn end(&self, total: u64) -> u64 {
+        match self.length {
+            Some(length) => self.offset.saturating_add(length).min(total),
+            None => total,
+        }
+    }
+}
+
+/// The `_meta.chunk` of an item cut from `start` to `end` of `total` bytes.
+pub(crate) fn chunk_meta(start: u64, end: u64, total: u64) -> Value {
+    json!({
+        "offset": start,
+        "length": end - start,
+        "totalBytes": total,
+        "nextOffset": (end < total).then_some(end),
+    })
+}
+
+/// Where a text chunk of `bytes` starting at `start` should end, given the
+/// end asked for: moved back to the start of a character cut in two, or, if
+/// that leaves nothing, forward past it. `start` must begin a character.
+pub(crate) fn text_end(bytes: &[u8], start: usize, wanted: usize) -> usize {
+    let is_continuation = |i: usize| i < bytes.len() && bytes[i] & 0xC0 == 0x80;
+    let mut end = wanted;
+    while end > start && is_continuation(end) {
+        end -= 1;
+    }
+    if end == start && wanted > start {
+        end = wanted;
+        while is_continuation(end) {
+            end += 1;
+        }
+    }
+    end
+}
+
+/// Whether `offset` falls inside a UTF-8 character of `bytes`.
+pub(crate) fn splits_character(bytes: &[u8], offset: usize) -> bool {
+    bytes.get(offset).is_some_and(|b| b & 0xC0 == 0x80)
+}
+
+/// Cuts each item of a `resources/read` result's contents to `range`.
+pub(crate) fn apply(result: &mut Value, range: ByteRange) -> Result<(), String> {
+    let Some(Value::Array(items)) = result.get_mut("contents") else {
+        return Ok(());
+    };
+    for item in items {
+        let Value::Object(item) = item else {
+            continue;
+        };
+        let meta = if let Some(Value::String(text)) = item.get_mut("text") {
+            let bytes = text.as_bytes();
+            let total = bytes.len() as u64;
+            check_offset(range.offset, total)?;
+            let start = range.offset as usize;
+            if splits_character(bytes, start) {
+                return Err(format!(
+                    "Offset {} is inside a UTF-8 character; continue from the nextOffset of the previous chunk",
+                    range.offset
+                ));
+            }
+            let end = text_end(bytes, start, range.end(total) as usize);
+            *text = text[start..end].to_string();
+            chunk_meta(start as u64, end as u64, total)
+        } else if let Some(Value::String(blob)) = item.get_mut("blob") {
+            let data = BASE64_STANDARD
+                .decode(blob.as_bytes())
+                .map_err(|e| format!("Resource blob is not valid base64: {}", e))?;
+            let total = data.len() as u64;
+            check_offset(range.offset, total)?;
+            let end = range.end(total);
+            *blob = BASE64_STANDARD.encode(&data[range.offset as usize..end as usize]);
+            chunk_meta(range.offset, end, total)
+        } else {
+            continue;
+        };
+        match item.get_mut("_meta") {
+            Some(Value::Object(existing)) => {
+                existing.insert("chunk".to_string(), meta);
+            }
+            _ => {
+                item.insert("_meta".to_string(), json!({ "chunk": meta }));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn check_offset(offset: u64, total: u64) -> Result<(), String> {
+    if offset > total {
+        return Err(format!(
+            "Offset {} is past the end of the resource, which is {} bytes",
+            offset, total
+        ));
+    }
+    Ok(())
+}
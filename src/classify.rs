@@ -0,0 +1,285 @@
+//! Telling which SQL statements write.
+//!
+//! The `query` tool refuses a write statement unless write operations are
+//! enabled, reports each one it runs, and rebuilds the catalog cache after
+//! one that changes the schema. A statement is classified from its parse
+//! tree, which sees past leading comments and into `EXPLAIN ANALYZE`, which
+//! runs its statement, `PREPARE`, data-modifying `WITH` queries, and
+//! `SELECT INTO`, which creates a table. Statements the parser does not
+//! know, such as `DO`, `LOCK`, `SECURITY LABEL`, and `REFRESH MATERIALIZED
+//! VIEW`, are classified by their leading keywords instead.
+//!
+//! Without write operations, statements also run in a read-only transaction,
+//! which PostgreSQL enforces whatever the classification. A query with a
+//! row-locking clause such as `FOR UPDATE` is not a write, but cannot run in
+//! one either, so the clause is found the same way to refuse it up front.
+
+use std::ops::ControlFlow;
+
+use sqlparser::ast::{LockType, Query, SetExpr, Statement, Visit, Visitor};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+
+/// The leading keywords of write statements.
+const WRITE_KEYWORDS: &[&str] = &[
+    "INSERT",
+    "UPDATE",
+    "DELETE",
+    "MERGE",
+    "TRUNCATE",
+    "CREATE",
+    "ALTER",
+    "DROP",
+    "CALL",
+    "DO",
+    "GRANT",
+    "REVOKE",
+    "COMMENT",
+    "SECURITY LABEL",
+    "REFRESH MATERIALIZED VIEW",
+    "LOCK",
+];
+
+/// Write keywords of statements that change what the catalog describes.
+const SCHEMA_KEYWORDS: &[&str] = &["CREATE", "ALTER", "DROP", "COMMENT", "SELECT INTO"];
+
+/// Statements a data-modifying `WITH` query can hold.
+const DATA_KEYWORDS: &[&str] = &["INSERT", "UPDATE", "DELETE", "MERGE"];
+
+/// The keyword that makes `sql` a write statement, such as "MERGE", if any.
+pub(crate) fn write_keyword(sql: &str) -> Option<&'static str> {
+    match Parser::parse_sql(&PostgreSqlDialect {}, sql) {
+        Ok(statements) => statements.iter().find_map(statement_keyword),
+        Err(_) => unparsed_keyword(sql),
+    }
+}
+
+/// The first row-locking clause in `sql`, such as "FOR UPDATE", if any.
+pub(crate) fn locking_clause(sql: &str) -> Option<&'static str> {
+    match Parser::parse_sql(&PostgreSqlDialect {}, sql) {
+        Ok(statements) => statements.visit(&mut LockFinder).break_value(),
+        // The parser knows neither FOR NO KEY UPDATE nor FOR KEY SHARE.
+        Err(_) => {
+            let words = words(skip_comments(sql));
+            words
+                .iter()
+                .enumerate()
+                .filter(|(_, word)| *word == "FOR")
+                .find_map(|(index, _)| {
+                    let next: Vec<&str> = words[index + 1..]
+                        .iter()
+                        .take(3)
+                        .map(String::as_str)
+                        .collect();
+                    match next[..] {
+                        ["UPDATE", ..] => Some("FOR UPDATE"),
+                        ["SHARE", ..] => Some("FOR SHARE"),
+                        ["NO", "KEY", "UPDATE"] => Some("FOR NO KEY UPDATE"),
+                        ["KEY", "SHARE", ..] => Some("FOR KEY SHARE"),
+                        _ => None,
+                    }
+                })
+        }
+    }
+}
+
+/// Finds the locking clause of any query, subqueries included.
+struct LockFinder;
+
+impl Visitor for LockFinder {
+    type Break = &'static str;
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        match query.locks.first().map(|lock| &lock.lock_type) {
+            Some(LockType::Update) => ControlFlow::Break("FOR UPDATE"),
+            Some(LockType::Share) => ControlFlow::Break("FOR SHARE"),
+            None => ControlFlow::Continue(()),
+        }
+    }
+}
+
+/// Whether the write statement `keyword` changes the schema.
+pub(crate) fn changes_schema(keyword: &str) -> bool {
+    SCHEMA_KEYWORDS.contains(&keyword)
+}
+
+fn statement_keyword(statement: &Statement) -> Option<&'static str> {
+    match statement {
+        Statement::Query(query) => query_keyword(query),
+        Statement::Explain {
+            analyze,
+            options,
+            statement,
+            ..
+        } => {
+            // `EXPLAIN (ANALYZE)` sets an option rather than `analyze`.
+            let analyze = *analyze
+                || options.iter().flatten().any(|option| {
+                    option.name.value.eq_ignore_ascii_case("analyze")
+                        && option.arg.as_ref().is_none_or(|arg| {
+                            !matches!(
+                                arg.to_string().to_ascii_lowercase().as_str(),
+                                "false" | "off" | "0"
+                            )
+                        })
+                });
+            if analyze {
+                statement_keyword(statement)
+            } else {
+                None
+            }
+        }
+        // A prepared write runs with a later EXECUTE.
+        Statement::Prepare { statement, .. } => statement_keyword(statement),
+        statement => leading_keyword(&statement.to_string()),
+    }
+}
+
+fn query_keyword(query: &Query) -> Option<&'static str> {
+    query
+        .with
+        .iter()
+        .flat_map(|with| &with.cte_tables)
+        .find_map(|cte| query_keyword(&cte.query))
+        .or_else(|| set_expr_keyword(&query.body))
+}
+
+fn set_expr_keyword(body: &SetExpr) -> Option<&'static str> {
+    match body {
+        SetExpr::Insert(statement) | SetExpr::Update(statement) => statement_keyword(statement),
+        SetExpr::Select(select) if select.into.is_some() => Some("SELECT INTO"),
+        SetExpr::Query(query) => query_keyword(query),
+        SetExpr::SetOperation { left, right, .. } => {
+            set_expr_keyword(left).or_else(|| set_expr_keyword(right))
+        }
+        _ => None,
+    }
+}
+
+/// Classifies SQL the parser rejects by its words alone, counting a `WITH`
+/// query or `EXPLAIN ANALYZE` that mentions a data-modifying statement
+/// anywhere as one.
+fn unparsed_keyword(sql: &str) -> Option<&'static str> {
+    let words = words(skip_comments(sql));
+    match words.first().map(String::as_str) {
+        Some("WITH") => find_data_keyword(&words),
+        Some("EXPLAIN") if words.iter().any(|w| w == "ANALYZE") => find_data_keyword(&words),
+        _ => leading_keyword(skip_comments(sql)),
+    }
+}
+
+fn find_data_keyword(words: &[String]) -> Option<&'static str> {
+    words
+        .iter()
+        .find_map(|word| DATA_KEYWORDS.iter().copied().find(|k| k == word))
+}
+
+/// The write keyword `sql` starts with, if any.
+fn leading_keyword(sql: &str) -> Option<&'static str> {
+    let words = words(sql);
+    let first = words.first()?.as_str();
+    match first {
+        "SECURITY" if words.get(1).is_some_and(|w| w == "LABEL") => Some("SECURITY LABEL"),
+        "REFRESH" => Some("REFRESH MATERIALIZED VIEW"),
+        _ => WRITE_KEYWORDS.iter().copied().find(|k| *k == first),
+    }
+}
+
+/// The words of `sql`, upper-cased.
+fn words(sql: &str) -> Vec<String> {
+    sql.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .filter(|w| !w.is_empty())
+        .map(str::to_ascii_uppercase)
+        .collect()
+}
+
+/// `sql` without the whitespace, comments, and opening parentheses it
+/// starts with.
+pub(crate) fn skip_comments(mut sql: &str) -> &str {
+    loop {
+        sql = sql.trim_start();
+        if let Some(rest) = sql.strip_prefix("--") {
+            sql = rest.split_once('\n').map_or("", |(_, rest)| rest);
+        } else if let Some(rest) = sql.strip_prefix("/*") {
+            sql = rest.split_once("*/").map_or("", |(_, rest)| rest);
+        } else if let Some(rest) = sql.strip_prefix('(') {
+            sql = rest;
+        } else {
+            return sql;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_are_not_writes() {
+        for sql in [
+            "SELECT 1",
+            "EXPLAIN DELETE FROM t",
+            "EXPLAIN (ANALYZE false) DELETE FROM t",
+            "WITH x AS (SELECT 1) SELECT * FROM x",
+            "SHOW work_mem",
+        ] {
+            assert_eq!(write_keyword(sql), None, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn writes_are_found() {
+        for (sql, keyword) in [
+            ("insert into t values (1)", "INSERT"),
+            ("/* note */ -- line\nUPDATE t SET a = 1", "UPDATE"),
+            ("EXPLAIN ANALYZE DELETE FROM t", "DELETE"),
+            ("EXPLAIN (ANALYZE) DELETE FROM t", "DELETE"),
+            (
+                "WITH gone AS (DELETE FROM t RETURNING *) SELECT * FROM gone",
+                "DELETE",
+            ),
+            ("PREPARE p AS INSERT INTO t VALUES (1)", "INSERT"),
+            ("SELECT * INTO copy FROM t", "SELECT INTO"),
+            ("SELECT 1; DROP TABLE t", "DROP"),
+            ("DO $$ BEGIN END $$", "DO"),
+            ("REFRESH MATERIALIZED VIEW v", "REFRESH MATERIALIZED VIEW"),
+            ("SECURITY LABEL ON TABLE t IS 'x'", "SECURITY LABEL"),
+        ] {
+            assert_eq!(write_keyword(sql), Some(keyword), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn schema_changes() {
+        assert!(changes_schema("CREATE"));
+        assert!(changes_schema("SELECT INTO"));
+        assert!(!changes_schema("INSERT"));
+    }
+
+    #[test]
+    fn locking_clauses_are_found() {
+        assert_eq!(locking_clause("SELECT * FROM t"), None);
+        assert_eq!(
+            locking_clause("SELECT * FROM t FOR UPDATE"),
+            Some("FOR UPDATE")
+        );
+        assert_eq!(
+            locking_clause("SELECT * FROM (SELECT * FROM t FOR SHARE) s"),
+            Some("FOR SHARE")
+        );
+        assert_eq!(
+            locking_clause("SELECT * FROM t FOR NO KEY UPDATE"),
+            Some("FOR NO KEY UPDATE")
+        );
+        assert_eq!(
+            locking_clause("SELECT * FROM t FOR KEY SHARE"),
+            Some("FOR KEY SHARE")
+        );
+    }
+
+    #[test]
+    fn leading_comments_are_skipped() {
+        assert_eq!(skip_comments("  -- a\n /* b */ (SELECT 1)"), "SELECT 1)");
+        assert_eq!(skip_comments("/* unterminated"), "");
+    }
+}
@@ -0,0 +1,153 @@
+//! Command-line arguments.
+//!
+//! The only positional argument is the connection string, accepted for
+//! compatibility with the reference server. `--transport http` serves MCP over
+//! HTTP instead of stdio, on `--host` (default `127.0.0.1`) and `--port`
+//! (default 8080). `--config` names a TOML configuration file.
+//!
+//! `load-fixtures <dir>` as the first argument loads the fixture files in
+//! `<dir>` into the database instead of serving MCP, emptying the tables they
+//! load first unless `--keep-existing` is given.
+//!
+//! `export-config` prints the settings in effect as a configuration file,
+//! TOML unless `--format json` is given, instead of serving MCP.
+
+#[cfg(feature = "http")]
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use anyhow::{Context, Result, bail};
+
+use crate::config::FileFormat;
+
+#[cfg(feature = "http")]
+const DEFAULT_PORT: u16 = 8080;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Transport {
+    /// JSON-RPC over stdin and stdout, for clients that spawn the server.
+    Stdio,
+    /// Streamable HTTP on the given address.
+    #[cfg(feature = "http")]
+    Http(SocketAddr),
+}
+
+#[derive(Debug)]
+pub(crate) struct CliArgs {
+    /// Used when `DATABASE_URL` is not set.
+    pub(crate) database_url: Option<String>,
+    pub(crate) transport: Transport,
+    /// Overrides `POSTGRES_MCP_CONFIG`.
+    pub(crate) config: Option<String>,
+    /// The fixture directory to load, for `load-fixtures`.
+    pub(crate) load_fixtures: Option<String>,
+    /// Whether `load-fixtures` keeps the rows already in the tables.
+    pub(crate) keep_existing: bool,
+    /// The kind of file to print, for `export-config`.
+    pub(crate) export_config: Option<FileFormat>,
+}
+
+impl CliArgs {
+    /// Parses the arguments after the program name.
+    pub(crate) fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut database_url = None;
+        let mut transport = None;
+        let mut host = None;
+        let mut port = None;
+        let mut config = None;
+        let mut keep_existing = false;
+        let mut format = None;
+
+        let mut args = args.into_iter().peekable();
+        let load_fixtures = if args.peek().is_some_and(|arg| arg == "load-fixtures") {
+            args.next();
+            Some(
+                args.next()
+                    .filter(|dir| !dir.starts_with("--"))
+                    .context("load-fixtures needs the fixture directory")?,
+            )
+        } else {
+            None
+        };
+        let export_config =
+            load_fixtures.is_none() && args.peek().is_some_and(|arg| arg == "export-config");
+        if export_config {
+            args.next();
+        }
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| {
+                inline
+                    .map(str::to_string)
+                    .or_else(|| args.next())
+                    .with_context(|| format!("{} needs a value", name))
+            };
+            match flag.as_str() {
+                "--transport" => transport = Some(value("--transport")?),
+                "--host" => host = Some(value("--host")?),
+                "--port" => port = Some(value("--port")?),
+                "--config" => config = Some(value("--config")?),
+                "--keep-existing" if load_fixtures.is_some() => keep_existing = true,
+                "--format" if export_config => format = Some(value("--format")?),
+                _ if arg.starts_with("--") => bail!("Unknown option '{}'", flag),
+                _ if database_url.is_none() => database_url = Some(arg),
+                _ => bail!("Unexpected argument '{}'", arg),
+            }
+        }
+
+        if load_fixtures.is_some() && (transport.is_some() || host.is_some() || port.is_some()) {
+            bail!("load-fixtures does not serve MCP, so takes no --transport, --host, or --port");
+        }
+        if export_config && (transport.is_some() || host.is_some() || port.is_some()) {
+            bail!("export-config does not serve MCP, so takes no --transport, --host, or --port");
+        }
+        let export_config = if export_config {
+            let format = format.as_deref().unwrap_or("toml");
+            Some(FileFormat::parse(format).with_context(|| {
+                format!("Unknown --format '{}' (expected 'toml' or 'json')", format)
+            })?)
+        } else {
+            None
+        };
+        let transport = match transport.as_deref() {
+            None | Some("stdio") => {
+                if host.is_some() || port.is_some() {
+                    bail!("--host and --port need --transport http");
+                }
+                Transport::Stdio
+            }
+            #[cfg(feature = "http")]
+            Some("http") => {
+                let host = match host {
+                    Some(host) => host
+                        .parse::<IpAddr>()
+                        .with_context(|| format!("--host must be an IP address, not '{}'", host))?,
+                    None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                };
+                let port = match port {
+                    Some(port) => port
+                        .parse::<u16>()
+                        .with_context(|| format!("--port must be a port number, not '{}'", port))?,
+                    None => DEFAULT_PORT,
+                };
+                Transport::Http(SocketAddr::new(host, port))
+            }
+            #[cfg(not(feature = "http"))]
+            Some("http") => {
+                bail!("This build does not include the HTTP transport (the 'http' feature)")
+            }
+            Some(other) => bail!("Unknown transport '{}' (expected 'stdio' or 'http')", other),
+        };
+
+        Ok(Self {
+            database_url,
+            transport,
+            config,
+            load_fixtures,
+            keep_existing,
+            export_config,
+        })
+    }
+}
@@ -0,0 +1,277 @@
+//! Wire compatibility with the reference TypeScript server,
+//! `@modelcontextprotocol/server-postgres`.
+//!
+//! In this mode the server exposes exactly what the reference server does: a
+//! single read-only `query` tool and one `<base>/<table>/schema` resource per
+//! public table, so existing client configurations and prompts keep working.
+
+use std::time::Instant;
+
+use serde_json::{Value, json};
+use tokio_postgres::Config;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::Type;
+
+use crate::{
+    JsonRpcRequest, JsonRpcResponse, McpServer, metrics, pooler, row_to_map, timeout_message,
+};
+
+const SCHEMA_PATH: &str = "schema";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompatMode {
+    Native,
+    TypeScript,
+}
+
+impl CompatMode {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "" | "native" => Some(Self::Native),
+            "typescript" | "ts" => Some(Self::TypeScript),
+            _ => None,
+        }
+    }
+}
+
+impl McpServer {
+    pub(crate) async fn handle_ts_compat_request(
+        &mut self,
+        request: JsonRpcRequest,
+    ) -> JsonRpcResponse {
+        let id = request.id;
+        match request.method.as_str() {
+            "initialize" => JsonRpcResponse::success(
+                id,
+                json!({
+                    "protocolVersion": "2024-11-05",
+                    "capabilities": {
+                        "tools": {},
+                        "resources": {}
+                    },
+                    "serverInfo": {
+                        "name": "example-servers/postgres",
+                        "version": "0.1.0"
+                    }
+                }),
+            ),
+            "tools/list" => JsonRpcResponse::success(
+                id,
+                json!({
+                    "tools": [
+                        {
+                            "name": "query",
+                            "description": "Run a read-only SQL query",
+                            "inputSchema": {
+                                "type": "object",
+                                "properties": {
+                                    "sql": { "type": "string" }
+                                }
+                            }
+                        }
+                    ]
+                }),
+            ),
+            "tools/call" => self.ts_compat_query(id, request.params).await,
+            "resources/list" => self.ts_compat_resources_list(id).await,
+            "resources/read" => self.ts_compat_resources_read(id, request.params).await,
+            _ => {
+                JsonRpcResponse::error(id, -32601, format!("Method not found: {}", request.method))
+            }
+        }
+    }
+
+    async fn ts_compat_query(
+        &mut self,
+        id: Option<Value>,
+        params: Option<Value>,
+    ) -> JsonRpcResponse {
+        let params = params.unwrap_or(Value::Null);
+        let name = params.get("name").and_then(|v| v.as_str()).unwrap_or("");
+        if name != "query" {
+            return JsonRpcResponse::error(id, -32603, format!("Unknown tool: {}", name));
+        }
+        let sql = params
+            .get("arguments")
+            .and_then(|a| a.get("sql"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+
+        if let Err(e) = self.ensure_connected().await {
+            return JsonRpcResponse::error(id, -32603, e.to_string());
+        }
+        if let Err(e) = self.check_sql_access(sql).await {
+            return e.into_response(id);
+        }
+        let client = self.client.as_ref().unwrap();
+
+        // The reference server always runs queries in a read-only transaction
+        // and rolls it back afterwards, regardless of write settings.
+        if let Err(e) = client.batch_execute("BEGIN TRANSACTION READ ONLY").await {
+            return JsonRpcResponse::error(id, -32603, e.to_string());
+        }
+        let started = Instant::now();
+        let result = self
+            .with_timeout(
+                client.cancel_token(),
+                self.query_timeout,
+                pooler::query(client, sql, &[]),
+            )
+            .await;
+        let elapsed = started.elapsed();
+        self.report_slow_query(sql, elapsed);
+        match &result {
+            Some(Ok(rows)) => metrics::record_query(elapsed, rows.len()),
+            Some(Err(e)) => metrics::record_query_error(elapsed, e.code().map(SqlState::code)),
+            None => metrics::record_query_error(elapsed, Some(metrics::QUERY_CANCELED)),
+        }
+        if let Err(e) = client.batch_execute("ROLLBACK").await {
+            tracing::warn!("Could not roll back transaction: {}", e);
+        }
+
+        let Some(result) = result else {
+            return JsonRpcResponse::error(
+                id,
+                -32603,
+                timeout_message(self.query_timeout.unwrap_or_default()),
+            );
+        };
+        match result {
+            Ok(rows) => {
+                let rows: Vec<_> = rows.iter().map(row_to_map).collect();
+                JsonRpcResponse::success(
+                    id,
+                    json!({
+                        "content": [
+                            {
+                                "type": "text",
+                                "text": serde_json::to_string_pretty(&rows).unwrap_or_else(|_| "[]".to_string())
+                            }
+                        ],
+                        "isError": false
+                    }),
+                )
+            }
+            Err(e) => JsonRpcResponse::error(
+                id,
+                -32603,
+                e.as_db_error()
+                    .map(|db| db.message().to_string())
+                    .unwrap_or_else(|| e.to_string()),
+            ),
+        }
+    }
+
+    async fn ts_compat_resources_list(&mut self, id: Option<Value>) -> JsonRpcResponse {
+        if let Err(e) = self.ensure_connected().await {
+            return JsonRpcResponse::error(id, -32603, e.to_string());
+        }
+        let client = self.client.as_ref().unwrap();
+        let base = resource_base_url(&self.database_url);
+
+        match pooler::query(
+            client,
+            "SELECT table_name::text FROM information_schema.tables WHERE table_schema = 'public'",
+            &[],
+        )
+        .await
+        {
+            Ok(rows) => {
+                let resources: Vec<Value> = rows
+                    .iter()
+                    .map(|row| row.get::<_, String>(0))
+                    .filter(|table_name| self.access.allows("public", table_name))
+                    .map(|table_name| {
+                        json!({
+                            "uri": format!("{}/{}/{}", base, table_name, SCHEMA_PATH),
+                            "mimeType": "application/json",
+                            "name": format!("\"{}\" database schema", table_name)
+                        })
+                    })
+                    .collect();
+                JsonRpcResponse::success(id, json!({ "resources": resources }))
+            }
+            Err(e) => JsonRpcResponse::error(id, -32603, e.to_string()),
+        }
+    }
+
+    async fn ts_compat_resources_read(
+        &mut self,
+        id: Option<Value>,
+        params: Option<Value>,
+    ) -> JsonRpcResponse {
+        let uri = params
+            .as_ref()
+            .and_then(|p| p.get("uri"))
+            .and_then(|u| u.as_str())
+            .unwrap_or("")
+            .to_string();
+
+        let mut components = uri.trim_end_matches('/').rsplit('/');
+        let schema = components.next();
+        let table_name = components.next();
+        let table_name = match (schema, table_name) {
+            (Some(SCHEMA_PATH), Some(table_name)) if !table_name.is_empty() => table_name,
+            _ => return JsonRpcResponse::error(id, -32603, "Invalid resource URI"),
+        };
+        if !self.access.allows("public", table_name) {
+            return JsonRpcResponse::error(id, -32603, "Invalid resource URI");
+        }
+
+        if let Err(e) = self.ensure_connected().await {
+            return JsonRpcResponse::error(id, -32603, e.to_string());
+        }
+        let client = self.client.as_ref().unwrap();
+
+        match pooler::query(
+            client,
+            "SELECT column_name::text AS column_name, data_type::text AS data_type \
+                 FROM information_schema.columns WHERE table_name = $1",
+            &[(&table_name, Type::TEXT)],
+        )
+        .await
+        {
+            Ok(rows) => {
+                let rows: Vec<_> = rows.iter().map(row_to_map).collect();
+                JsonRpcResponse::success(
+                    id,
+                    json!({
+                        "contents": [
+                            {
+                                "uri": uri,
+                                "mimeType": "application/json",
+                                "text": serde_json::to_string_pretty(&rows).unwrap_or_else(|_| "[]".to_string())
+                            }
+                        ]
+                    }),
+                )
+            }
+            Err(e) => JsonRpcResponse::error(id, -32603, e.to_string()),
+        }
+    }
+}
+
+/// Builds the `postgres://user@host:port/dbname` prefix used for resource URIs,
+/// mirroring the reference server which strips the password from the URL.
+fn resource_base_url(database_url: &str) -> String {
+    let Ok(config) = database_url.parse::<Config>() else {
+        return "postgres://".to_string();
+    };
+
+    let mut base = "postgres://".to_string();
+    if let Some(user) = config.get_user() {
+        base.push_str(user);
+        base.push('@');
+    }
+    if let Some(tokio_postgres::config::Host::Tcp(host)) = config.get_hosts().first() {
+        base.push_str(host);
+    }
+    if let Some(port) = config.get_ports().first() {
+        base.push_str(&format!(":{}", port));
+    }
+    if let Some(dbname) = config.get_dbname() {
+        base.push('/');
+        base.push_str(dbname);
+    }
+    base
+}
@@ -0,0 +1,144 @@
+//! Compression of large tool results and resource contents.
+//!
+//! A multi-megabyte result can overwhelm a stdio client that reads whole
+//! messages into memory. A client that can decompress says so by listing
+//! encodings in the request's `_meta.acceptEncoding`, such as
+//! `["zstd", "gzip"]`; text payloads of at least `RESULT_COMPRESSION_MIN_BYTES`
+//! are then compressed with the first encoding listed that the server
+//! supports and base64-encoded. Each compressed item's `_meta.contentEncoding`
+//! names the encoding, and `_meta.uncompressedBytes` the original size. Over
+//! HTTP, whole responses are also compressed according to `Accept-Encoding`.
+//!
+//! The encodings need the `compression` feature; without it no encoding is
+//! supported and results are sent as they are.
+
+#[cfg(feature = "compression")]
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+#[cfg(feature = "compression")]
+use flate2::Compression;
+#[cfg(feature = "compression")]
+use flate2::write::GzEncoder;
+use serde_json::{Value, json};
+
+use crate::config;
+
+const DEFAULT_MIN_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    #[cfg(feature = "compression")]
+    Gzip,
+    #[cfg(feature = "compression")]
+    Zstd,
+}
+
+impl Encoding {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            #[cfg(feature = "compression")]
+            "gzip" => Some(Self::Gzip),
+            #[cfg(feature = "compression")]
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "compression")]
+            Self::Gzip => "gzip",
+            #[cfg(feature = "compression")]
+            Self::Zstd => "zstd",
+        }
+    }
+
+    #[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+    fn compress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "compression")]
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "compression")]
+            Self::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+}
+
+/// Reads `RESULT_COMPRESSION_MIN_BYTES`, the smallest payload compressed;
+/// `None` when it is 0, which turns compression off.
+pub(crate) fn min_bytes_from_env() -> Result<Option<usize>> {
+    match config::var("RESULT_COMPRESSION_MIN_BYTES") {
+        Ok(v) => match v
+            .parse::<usize>()
+            .context("RESULT_COMPRESSION_MIN_BYTES must be a number of bytes")?
+        {
+            0 => Ok(None),
+            bytes => Ok(Some(bytes)),
+        },
+        Err(_) => Ok(Some(DEFAULT_MIN_BYTES)),
+    }
+}
+
+/// The encoding to use for a request, from its `_meta.acceptEncoding`: an
+/// array of names or a comma-separated string, in order of preference.
+pub(crate) fn accepted(params: Option<&Value>) -> Option<Encoding> {
+    let accept = params?.get("_meta")?.get("acceptEncoding")?;
+    match accept {
+        Value::Array(names) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .find_map(Encoding::parse),
+        Value::String(names) => names.split(',').find_map(Encoding::parse),
+        _ => None,
+    }
+}
+
+/// Compresses the large text items of a tool result's `content` or a
+/// resource read's `contents`. A compressed tool result drops its
+/// `structuredContent`, which would carry the same payload uncompressed.
+pub(crate) fn compress_result(result: &mut Value, encoding: Encoding, min_bytes: usize) {
+    let mut compressed_any = false;
+    for key in ["content", "contents"] {
+        let Some(Value::Array(items)) = result.get_mut(key) else {
+            continue;
+        };
+        for item in items {
+            let Some(text) = item.get("text").and_then(Value::as_str) else {
+                continue;
+            };
+            if text.len() < min_bytes {
+                continue;
+            }
+            let Ok(data) = encoding.compress(text.as_bytes()) else {
+                continue;
+            };
+            let meta = json!({
+                "contentEncoding": encoding.name(),
+                "uncompressedBytes": text.len(),
+            });
+            let encoded = BASE64_STANDARD.encode(&data);
+            let Value::Object(item) = item else {
+                continue;
+            };
+            if key == "contents" {
+                // Resource contents carry base64 data as a blob.
+                item.remove("text");
+                item.insert("blob".to_string(), json!(encoded));
+            } else {
+                item.insert("text".to_string(), json!(encoded));
+            }
+            item.insert("_meta".to_string(), meta);
+            compressed_any = true;
+        }
+    }
+    if compressed_any && let Value::Object(result) = result {
+        result.remove("structuredContent");
+    }
+}
@@ -0,0 +1,289 @@
+//! Settings from a TOML configuration file.
+//!
+//! Every setting is an environment variable, which suits a client that
+//! spawns the server but not a deployment with dozens of them. A file named
+//! by `--config` or `POSTGRES_MCP_CONFIG` can give the same settings in
+//! sections instead, for example:
+//!
+//! ```toml
+//! database_url = "postgresql://app@db.internal/app"
+//!
+//! [tls]
+//! mode = "verify-full"
+//! root_cert = "/etc/ssl/db-ca.pem"
+//!
+//! [timeouts]
+//! query_ms = 30000
+//!
+//! [tools]
+//! disabled = ["import_large_object", "export_large_object"]
+//! ```
+//!
+//! Each key stands for the environment variable listed in `KEYS`, and the
+//! environment variable wins when both are set. Settings are read through
+//! `var`, which consults the environment first and then the file.
+//!
+//! A file whose name ends in `.json` holds the same keys as a JSON object
+//! instead. `export-config` writes the settings in effect as either kind of
+//! file, so that a deployment's access lists, disabled tools, and other
+//! policy can be kept under version control and shared with other
+//! deployments. The connection string and API keys are left out.
+
+use std::collections::HashMap;
+use std::env::{self, VarError};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result, bail};
+use toml::{Table, Value};
+
+static FILE_VALUES: OnceLock<HashMap<&'static str, String>> = OnceLock::new();
+
+/// Configuration file keys, as `section.key`, and the environment variables
+/// they set.
+const KEYS: &[(&str, &str)] = &[
+    ("database_url", "DATABASE_URL"),
+    ("allow_write_ops", "DANGEROUSLY_ALLOW_WRITE_OPS"),
+    ("compat_mode", "COMPAT_MODE"),
+    ("export_dir", "EXPORT_DIR"),
+    ("environment", "DB_ENVIRONMENT"),
+    ("db_timezone", "DB_TIMEZONE"),
+    ("soft_delete_filters", "SOFT_DELETE_FILTERS"),
+    ("tls.mode", "PGSSLMODE"),
+    ("tls.root_cert", "PGSSLROOTCERT"),
+    ("tls.client_cert", "PGSSLCERT"),
+    ("tls.client_key", "PGSSLKEY"),
+    ("timeouts.query_ms", "QUERY_TIMEOUT_MS"),
+    ("timeouts.slow_query_ms", "SLOW_QUERY_THRESHOLD_MS"),
+    ("query.cost_estimates", "QUERY_COST_ESTIMATES"),
+    ("query.stale_stats_days", "STALE_STATS_DAYS"),
+    ("query.max_result_columns", "MAX_RESULT_COLUMNS"),
+    ("query.max_result_rows", "MAX_RESULT_ROWS"),
+    ("query.max_result_bytes", "MAX_RESULT_BYTES"),
+    ("timeouts.idle_secs", "IDLE_TIMEOUT_SECS"),
+    ("sandbox.schema_prefix", "SANDBOX_SCHEMA_PREFIX"),
+    ("pool.max_size", "DB_POOL_MAX_SIZE"),
+    ("pool.min_size", "DB_POOL_MIN_SIZE"),
+    ("pool.timeout_secs", "DB_POOL_TIMEOUT_SECS"),
+    ("pool.health_check", "DB_POOL_HEALTH_CHECK"),
+    ("pool.reconnect_timeout_secs", "DB_RECONNECT_TIMEOUT_SECS"),
+    ("serverless.mode", "DB_SERVERLESS"),
+    ("serverless.resume_timeout_secs", "DB_RESUME_TIMEOUT_SECS"),
+    ("pooler.mode", "DB_POOLER"),
+    ("queue.max_concurrent", "QUEUE_MAX_CONCURRENT"),
+    ("queue.max_depth", "QUEUE_MAX_DEPTH"),
+    ("queue.max_wait_ms", "QUEUE_MAX_WAIT_MS"),
+    ("values.big_numbers_as_strings", "BIG_NUMBERS_AS_STRINGS"),
+    ("values.non_finite_floats", "NON_FINITE_FLOATS"),
+    ("identity.principal", "MCP_PRINCIPAL"),
+    ("identity.role_map", "ROLE_MAP"),
+    ("identity.role_switch", "ROLE_SWITCH"),
+    ("http.allowed_origins", "HTTP_ALLOWED_ORIGINS"),
+    ("http.max_sessions", "HTTP_MAX_SESSIONS"),
+    ("http.session_timeout_secs", "HTTP_SESSION_TIMEOUT_SECS"),
+    ("http.subject_header", "HTTP_SUBJECT_HEADER"),
+    ("fixtures.dir", "FIXTURES_DIR"),
+    ("branch.provider", "BRANCH_PROVIDER"),
+    ("neon.api_key", "NEON_API_KEY"),
+    ("neon.project_id", "NEON_PROJECT_ID"),
+    ("neon.parent_branch_id", "NEON_PARENT_BRANCH_ID"),
+    ("neon.api_url", "NEON_API_URL"),
+    ("transactions.timeout_secs", "TRANSACTION_TIMEOUT_SECS"),
+    ("spool.dir", "SPOOL_DIR"),
+    ("spool.max_bytes", "SPOOL_MAX_BYTES"),
+    ("spool.encryption_key", "SPOOL_ENCRYPTION_KEY"),
+    ("results.max_bytes", "MATERIALIZE_MAX_BYTES"),
+    ("compression.min_bytes", "RESULT_COMPRESSION_MIN_BYTES"),
+    ("tools.disabled", "DISABLED_TOOLS"),
+    ("plugins.commands", "TOOL_PLUGINS"),
+    ("plugins.timeout_secs", "PLUGIN_TIMEOUT_SECS"),
+    ("access.allowed_tables", "ALLOWED_TABLES"),
+    ("access.denied_tables", "DENIED_TABLES"),
+    ("catalog.warmup", "CATALOG_WARMUP"),
+    ("catalog.refresh_secs", "CATALOG_REFRESH_SECS"),
+    ("subscriptions.channel", "SUBSCRIPTION_CHANNEL"),
+    ("subscriptions.triggers", "SUBSCRIPTION_TRIGGERS"),
+    ("telemetry.endpoint", "TELEMETRY_ENDPOINT"),
+    ("telemetry.interval_secs", "TELEMETRY_INTERVAL_SECS"),
+    ("hooks.url", "HOOK_URL"),
+    ("hooks.command", "HOOK_COMMAND"),
+    ("hooks.events", "HOOK_EVENTS"),
+    ("hooks.timeout_secs", "HOOK_TIMEOUT_SECS"),
+    ("metrics.addr", "METRICS_ADDR"),
+    ("log.level", "LOG_LEVEL"),
+    ("log.format", "LOG_FORMAT"),
+    ("log.file", "LOG_FILE"),
+    ("log.max_files", "LOG_MAX_FILES"),
+    ("log.max_file_mb", "LOG_MAX_FILE_MB"),
+    ("log.wire_log", "WIRE_LOG"),
+    ("log.wire_log_data", "WIRE_LOG_DATA"),
+    ("log.wire_log_redact_keys", "WIRE_LOG_REDACT_KEYS"),
+    ("log.wire_log_redact_sql", "WIRE_LOG_REDACT_SQL"),
+    ("chaos.faults", "CHAOS_FAULTS"),
+    ("chaos.rate", "CHAOS_RATE"),
+    ("chaos.delay_ms", "CHAOS_DELAY_MS"),
+    ("chaos.result_bytes", "CHAOS_RESULT_BYTES"),
+];
+
+/// Settings `export` leaves out, since they hold credentials.
+const SECRET_VARS: &[&str] = &["DATABASE_URL", "NEON_API_KEY"];
+
+/// The kinds of configuration file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileFormat {
+    Toml,
+    Json,
+}
+
+impl FileFormat {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "toml" => Some(Self::Toml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// The kind of the file at `path`, JSON if it is named `*.json`.
+    fn of(path: &Path) -> Self {
+        if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+        {
+            Self::Json
+        } else {
+            Self::Toml
+        }
+    }
+}
+
+/// Loads the configuration file named by `--config` (`path`) or else by
+/// `POSTGRES_MCP_CONFIG`, if either is given. Must run before any setting is
+/// read.
+pub(crate) fn load(path: Option<&str>) -> Result<()> {
+    let from_env = env::var("POSTGRES_MCP_CONFIG").ok();
+    let Some(path) = path.or(from_env.as_deref()).filter(|p| !p.is_empty()) else {
+        return Ok(());
+    };
+    let values =
+        read(Path::new(path)).with_context(|| format!("Invalid configuration file {}", path))?;
+    let _ = FILE_VALUES.set(values);
+    Ok(())
+}
+
+/// The setting `name`: the environment variable if set, or else the
+/// configuration file's value for it.
+pub(crate) fn var(name: &str) -> Result<String, VarError> {
+    match env::var(name) {
+        Err(VarError::NotPresent) => FILE_VALUES
+            .get()
+            .and_then(|values| values.get(name))
+            .cloned()
+            .ok_or(VarError::NotPresent),
+        result => result,
+    }
+}
+
+fn read(path: &Path) -> Result<HashMap<&'static str, String>> {
+    let text = std::fs::read_to_string(path).context("Cannot read the file")?;
+    let table: Table = match FileFormat::of(path) {
+        FileFormat::Toml => text.parse()?,
+        FileFormat::Json => serde_json::from_str(&text)?,
+    };
+    let mut values = HashMap::new();
+    collect(&table, "", &mut values)?;
+    Ok(values)
+}
+
+/// Adds the settings in `table`, whose keys are prefixed with `prefix`.
+fn collect(table: &Table, prefix: &str, values: &mut HashMap<&'static str, String>) -> Result<()> {
+    for (key, value) in table {
+        let path = format!("{}{}", prefix, key);
+        if let Some(&(_, var)) = KEYS.iter().find(|(k, _)| *k == path) {
+            values.insert(var, setting(&path, value)?);
+        } else if let Value::Table(section) = value
+            && KEYS
+                .iter()
+                .any(|(k, _)| k.starts_with(&format!("{}.", path)))
+        {
+            collect(section, &format!("{}.", path), values)?;
+        } else {
+            bail!("Unknown setting '{}'", path);
+        }
+    }
+    Ok(())
+}
+
+/// A value in the form its environment variable takes: arrays become
+/// comma-separated lists, and tables `key=value` pairs, separated by
+/// semicolons for soft-delete filters and by commas otherwise.
+fn setting(path: &str, value: &Value) -> Result<String> {
+    Ok(match value {
+        Value::String(s) => s.clone(),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| setting(path, item))
+            .collect::<Result<Vec<_>>>()?
+            .join(","),
+        Value::Table(pairs) => {
+            let separator = if path == "soft_delete_filters" {
+                ";"
+            } else {
+                ","
+            };
+            pairs
+                .iter()
+                .map(|(key, value)| Ok(format!("{}={}", key, setting(path, value)?)))
+                .collect::<Result<Vec<_>>>()?
+                .join(separator)
+        }
+        Value::Datetime(_) => bail!("'{}' cannot be a date", path),
+    })
+}
+
+/// The settings in effect, from the environment and the file, as a
+/// configuration file of the kind `format` that `load` reads back. Settings
+/// holding credentials are left out.
+pub(crate) fn export(format: FileFormat) -> Result<String> {
+    let mut table = Table::new();
+    for (key, name) in KEYS {
+        if SECRET_VARS.contains(name) {
+            continue;
+        }
+        let Ok(value) = var(name) else {
+            continue;
+        };
+        let (section, key) = match key.split_once('.') {
+            Some((section, key)) => {
+                let section = table
+                    .entry(section)
+                    .or_insert_with(|| Value::Table(Table::new()))
+                    .as_table_mut()
+                    .with_context(|| format!("'{}' is both a setting and a section", section))?;
+                (section, key)
+            }
+            None => (&mut table, *key),
+        };
+        section.insert(key.to_string(), exported(value));
+    }
+    Ok(match format {
+        FileFormat::Toml => toml::to_string(&table)?,
+        FileFormat::Json => serde_json::to_string_pretty(&table)? + "\n",
+    })
+}
+
+/// A setting's value as a file gives it: a boolean or an integer if it is
+/// one, and otherwise the string an environment variable would hold.
+fn exported(value: String) -> Value {
+    match value.as_str() {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => match value.parse::<i64>() {
+            Ok(integer) => Value::Integer(integer),
+            Err(_) => Value::String(value),
+        },
+    }
+}
@@ -0,0 +1,168 @@
+//! Validation of the connection string at startup.
+//!
+//! A mistake in `DATABASE_URL` would otherwise surface as a generic connection
+//! failure at the first tool call. It is reported when the server starts
+//! instead, with a message naming the problem, and the parsed settings are
+//! logged with the password masked so a wrong host or database is easy to
+//! spot.
+
+use std::error::Error;
+
+use anyhow::{Context, Result, anyhow, bail};
+use tokio_postgres::Config;
+use tokio_postgres::config::Host;
+use tracing::{info, warn};
+
+use crate::config;
+use crate::tls::TlsSettings;
+
+const DEFAULT_PORT: u16 = 5432;
+
+/// The connection string from `DATABASE_URL`, set in the environment or the
+/// configuration file, or else the first command-line argument.
+pub(crate) fn database_url(arg: Option<String>) -> Result<String> {
+    config::var("DATABASE_URL")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .or(arg)
+        .context("DATABASE_URL is not set in the environment or the configuration file")
+}
+
+/// Parses a connection string whose TLS parameters have been removed,
+/// explaining the common mistakes that `tokio_postgres` reports only as a
+/// syntax error.
+pub(crate) fn parse(database_url: &str) -> Result<Config> {
+    if let Some((scheme, rest)) = database_url.split_once("://") {
+        check_url(scheme, rest)?;
+    }
+    database_url
+        .parse::<Config>()
+        .map_err(|e| match e.source() {
+            // The source says which part is wrong; the error itself only that
+            // something is.
+            Some(cause) => anyhow!("Invalid DATABASE_URL: {}", cause),
+            None => anyhow!("Invalid DATABASE_URL: {}", e),
+        })
+}
+
+fn check_url(scheme: &str, rest: &str) -> Result<()> {
+    if scheme != "postgresql" && scheme != "postgres" {
+        bail!(
+            "DATABASE_URL must start with postgresql:// or postgres://, not {}://",
+            scheme
+        );
+    }
+    let authority = rest.split(['/', '?']).next().unwrap_or_default();
+    if authority.matches('@').count() > 1 {
+        bail!(
+            "DATABASE_URL contains more than one '@': percent-encode '@' in the user name or password as %40"
+        );
+    }
+    let hosts = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, hosts)| hosts);
+    for host in hosts.split(',').filter(|h| !h.is_empty()) {
+        let port = if let Some(bracketed) = host.strip_prefix('[') {
+            let Some((_, after)) = bracketed.split_once(']') else {
+                bail!("DATABASE_URL host '{}' is missing its closing ']'", host);
+            };
+            match after.strip_prefix(':') {
+                Some(port) => Some(port),
+                None if after.is_empty() => None,
+                None => bail!("DATABASE_URL host '{}' has text after ']'", host),
+            }
+        } else if host.matches(':').count() > 1 {
+            bail!(
+                "DATABASE_URL host '{}' looks like an IPv6 address: enclose it in brackets, as in postgresql://user@[::1]:5432/db",
+                host
+            );
+        } else {
+            host.split_once(':').map(|(_, port)| port)
+        };
+        if let Some(port) = port
+            && !port.parse::<u16>().is_ok_and(|p| p > 0)
+        {
+            bail!(
+                "DATABASE_URL port '{}' is not a number between 1 and 65535",
+                port
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Validates the connection string, logs its settings, and checks that each
+/// host name resolves.
+pub(crate) async fn check(database_url: &str) -> Result<()> {
+    let (database_url, tls_settings) = TlsSettings::from_url_and_env(database_url)?;
+    let config = parse(&database_url)?;
+    info!("Database: {}", describe(&config, &tls_settings));
+    if config.get_hosts().is_empty() && config.get_hostaddrs().is_empty() {
+        bail!("DATABASE_URL names no host, as in postgresql://user@localhost:5432/db");
+    }
+
+    if config.get_password().is_none() {
+        warn!(
+            "DATABASE_URL has no password; connecting works only if the server trusts this client \
+             (PGPASSWORD and ~/.pgpass are not read)"
+        );
+    }
+    // With hostaddr set, host names are only used to verify certificates.
+    if !config.get_hostaddrs().is_empty() {
+        return Ok(());
+    }
+    let ports = config.get_ports();
+    for (i, host) in config.get_hosts().iter().enumerate() {
+        let Host::Tcp(name) = host else {
+            continue;
+        };
+        let port = ports
+            .get(i)
+            .or(ports.first())
+            .copied()
+            .unwrap_or(DEFAULT_PORT);
+        let addresses = tokio::net::lookup_host((name.as_str(), port))
+            .await
+            .with_context(|| format!("Cannot resolve database host '{}'", name))?;
+        if addresses.count() == 0 {
+            bail!("Database host '{}' resolves to no addresses", name);
+        }
+    }
+    Ok(())
+}
+
+/// The connection settings, with the password masked.
+fn describe(config: &Config, tls_settings: &TlsSettings) -> String {
+    let hosts: Vec<String> = config
+        .get_hosts()
+        .iter()
+        .map(|host| match host {
+            Host::Tcp(name) => name.clone(),
+            #[cfg(unix)]
+            Host::Unix(path) => path.display().to_string(),
+        })
+        .collect();
+    let ports: Vec<String> = config.get_ports().iter().map(u16::to_string).collect();
+    let user = config.get_user().unwrap_or("(the operating system user)");
+    format!(
+        "host={} port={} dbname={} user={} password={} sslmode={}",
+        if hosts.is_empty() {
+            "(none)".to_string()
+        } else {
+            hosts.join(",")
+        },
+        if ports.is_empty() {
+            DEFAULT_PORT.to_string()
+        } else {
+            ports.join(",")
+        },
+        config.get_dbname().unwrap_or(user),
+        user,
+        if config.get_password().is_some() {
+            "********"
+        } else {
+            "(none)"
+        },
+        tls_settings.mode.name()
+    )
+}
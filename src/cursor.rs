@@ -0,0 +1,193 @@
+//! Server-side cursors for paging through `query` results.
+//!
+//! A query run with `max_rows` is declared as a cursor in a transaction on
+//! its own connection, and each page is fetched from it, so only one page of
+//! rows is held in memory at a time. The connection stays checked out of the
+//! pool until the last page is read; the transaction is then committed and
+//! the connection returned. A cursor abandoned before its last page is closed
+//! when the session goes idle (`IDLE_TIMEOUT_SECS`), when opening another
+//! cursor or beginning a transaction would leave the pool without a free
+//! connection, or when the session ends.
+//! Its connection is closed rather than returned, which rolls the transaction
+//! back.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use anyhow::{Result, anyhow};
+use deadpool_postgres::Object;
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio_postgres::{CancelToken, Row};
+
+use crate::pooler;
+
+/// The cursor's name on its connection, which holds no other cursor.
+pub(crate) const CURSOR_NAME: &str = "mcp_cursor";
+
+/// A declared cursor and the connection whose transaction holds it.
+pub(crate) struct QueryCursor {
+    client: Option<Object>,
+    /// Rows per page, unless a fetch asks for a different number.
+    max_rows: usize,
+    /// Rows fetched but not yet returned: the row after the last page,
+    /// fetched to learn whether another page follows, and any the last page
+    /// gave back.
+    pending: Vec<Row>,
+    last_used: Instant,
+}
+
+/// A page of rows fetched from a cursor.
+pub(crate) struct Fetched {
+    pub(crate) rows: Vec<Row>,
+    /// Whether another page follows.
+    pub(crate) more: bool,
+}
+
+impl QueryCursor {
+    /// Takes over `client`, on which `CURSOR_NAME` has been declared in an
+    /// open transaction.
+    pub(crate) fn new(client: Object, max_rows: usize) -> Self {
+        Self {
+            client: Some(client),
+            max_rows,
+            pending: Vec::new(),
+            last_used: Instant::now(),
+        }
+    }
+
+    /// The token for cancelling a fetch; `None` once every row has been
+    /// read from the database, so fetches need not wait for it.
+    pub(crate) fn cancel_token(&self) -> Option<CancelToken> {
+        self.client.as_ref().map(|client| client.cancel_token())
+    }
+
+    /// Fetches the next page. After the last row is read the transaction is
+    /// committed and the connection returned to the pool.
+    pub(crate) async fn fetch(
+        &mut self,
+        max_rows: Option<usize>,
+    ) -> Result<Fetched, tokio_postgres::Error> {
+        self.last_used = Instant::now();
+        if let Some(max_rows) = max_rows {
+            self.max_rows = max_rows;
+        }
+        let max_rows = self.max_rows;
+        let mut rows = std::mem::take(&mut self.pending);
+        if rows.len() <= max_rows
+            && let Some(client) = &self.client
+        {
+            let fetch = format!(
+                "FETCH FORWARD {} FROM {}",
+                max_rows + 1 - rows.len(),
+                CURSOR_NAME
+            );
+            rows.extend(pooler::query(client, &fetch, &[]).await?);
+            if rows.len() <= max_rows {
+                client.batch_execute("COMMIT").await?;
+                // Committed, so the connection can go back to the pool.
+                drop(self.client.take());
+            }
+        }
+        if rows.len() > max_rows {
+            self.pending = rows.split_off(max_rows);
+            return Ok(Fetched { rows, more: true });
+        }
+        Ok(Fetched { rows, more: false })
+    }
+
+    /// Puts `rows`, the end of the page just fetched, back in front of the
+    /// next page.
+    pub(crate) fn give_back(&mut self, mut rows: Vec<Row>) {
+        rows.append(&mut self.pending);
+        self.pending = rows;
+    }
+}
+
+impl Drop for QueryCursor {
+    fn drop(&mut self) {
+        // Closing the connection rolls back its transaction; returned to the
+        // pool, it would hand the open transaction to the next request.
+        if let Some(client) = self.client.take() {
+            drop(Object::take(client));
+        }
+    }
+}
+
+/// The session's open cursors, by token. Clones share them.
+#[derive(Clone)]
+pub(crate) struct Cursors {
+    open: Arc<Mutex<HashMap<String, QueryCursor>>>,
+    random: SystemRandom,
+}
+
+impl Cursors {
+    pub(crate) fn new() -> Self {
+        Self {
+            open: Arc::new(Mutex::new(HashMap::new())),
+            random: SystemRandom::new(),
+        }
+    }
+
+    /// Registers `cursor` and returns its token, first closing the least
+    /// recently used cursors so that at most `limit` are open.
+    pub(crate) fn insert(&self, cursor: QueryCursor, limit: usize) -> Result<String> {
+        let mut open = self.lock();
+        close_least_recent(&mut open, limit.max(1) - 1);
+        let mut bytes = [0u8; 12];
+        self.random
+            .fill(&mut bytes)
+            .map_err(|_| anyhow!("no random numbers for a cursor token"))?;
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        open.insert(token.clone(), cursor);
+        Ok(token)
+    }
+
+    /// Removes the cursor for `token`, to be put back if more pages follow.
+    /// While it is out, requests for the same token find no cursor.
+    pub(crate) fn take(&self, token: &str) -> Option<QueryCursor> {
+        self.lock().remove(token)
+    }
+
+    pub(crate) fn put_back(&self, token: String, cursor: QueryCursor) {
+        self.lock().insert(token, cursor);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Closes the least recently used cursors so that at most `limit` are
+    /// open, returning how many were closed.
+    pub(crate) fn shrink_to(&self, limit: usize) -> usize {
+        close_least_recent(&mut self.lock(), limit)
+    }
+
+    /// Closes every open cursor, returning how many there were.
+    pub(crate) fn close_all(&self) -> usize {
+        let mut open = self.lock();
+        let closed = open.len();
+        open.clear();
+        closed
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, QueryCursor>> {
+        self.open.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn close_least_recent(open: &mut HashMap<String, QueryCursor>, keep: usize) -> usize {
+    let mut closed = 0;
+    while open.len() > keep {
+        let Some(oldest) = open
+            .iter()
+            .min_by_key(|(_, cursor)| cursor.last_used)
+            .map(|(token, _)| token.clone())
+        else {
+            break;
+        };
+        open.remove(&oldest);
+        closed += 1;
+    }
+    closed
+}
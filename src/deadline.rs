@@ -0,0 +1,79 @@
+//! Deadlines clients set on their requests.
+//!
+//! A client that stops waiting for an answer after some time can say so, and
+//! the server then stops working on the request at that time instead of
+//! finishing work whose result nobody will read. A request's deadline is the
+//! earliest of
+//!
+//! - `_meta.timeoutMs`: milliseconds from when the server received the
+//!   request, so that time spent in the queue counts
+//! - `_meta.deadline`: a Unix time in milliseconds, for clients that pass on
+//!   a deadline of their own
+//! - with the HTTP transport, the `X-Request-Timeout-Ms` header, also counted
+//!   from when the request arrived
+//!
+//! A request whose deadline passes while it waits for a connection, runs a
+//! statement, or builds its result is abandoned: the statement is cancelled
+//! on the server, the connection goes back to the pool, and the request is
+//! answered with a "deadline exceeded" error instead of its result. One whose
+//! deadline has passed by the time it is taken from the queue is not started.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+
+use crate::JsonRpcResponse;
+
+/// JSON-RPC error code for a request whose deadline passed.
+const DEADLINE_EXCEEDED: i32 = -32001;
+
+/// The deadline the `_meta` of `params` sets for a request received at
+/// `received`, if any.
+pub(crate) fn from_meta(
+    params: Option<&Value>,
+    received: Instant,
+) -> Result<Option<Instant>, String> {
+    let Some(meta) = params.and_then(|p| p.get("_meta")) else {
+        return Ok(None);
+    };
+    let millis = |name: &str| -> Result<Option<u64>, String> {
+        match meta.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => value.as_u64().map(Some).ok_or_else(|| {
+                format!(
+                    "Invalid _meta.{}: expected a non-negative number of milliseconds",
+                    name
+                )
+            }),
+        }
+    };
+    let timeout = millis("timeoutMs")?.map(|ms| received + Duration::from_millis(ms));
+    let deadline = millis("deadline")?.map(|ms| {
+        let remaining = (UNIX_EPOCH + Duration::from_millis(ms))
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        Instant::now() + remaining
+    });
+    Ok(earliest(timeout, deadline))
+}
+
+/// The earlier of two deadlines.
+pub(crate) fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The response to a request whose deadline passed.
+pub(crate) fn exceeded_response(id: Option<Value>) -> JsonRpcResponse {
+    let mut response = JsonRpcResponse::error(
+        id,
+        DEADLINE_EXCEEDED,
+        "The request's deadline passed before it was answered",
+    );
+    if let Some(error) = response.error.as_mut() {
+        error.data = Some(json!({ "type": "deadline_exceeded" }));
+    }
+    response
+}
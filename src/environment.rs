@@ -0,0 +1,60 @@
+//! What kind of database the server is connected to.
+//!
+//! `DB_ENVIRONMENT` tags the connection as `production`, `staging`, or
+//! `development` (`prod` and `dev` for short), and every tool result starts
+//! with a text item naming it. Tools that replace data wholesale, such as
+//! `load_fixtures`, refuse to run against production. Unset, the environment
+//! is unknown and nothing is refused for it.
+//!
+//! Production connections get stricter defaults, whatever else is
+//! configured:
+//!
+//! - writes are off, even with `DANGEROUSLY_ALLOW_WRITE_OPS`
+//! - `query` returns at most `PRODUCTION_MAX_ROWS` rows a page, paging
+//!   through the rest
+//! - tools that read whole tables or copy data out of the database run only
+//!   when called with `"confirm": true`, so the client asks its user first
+
+use anyhow::{Result, bail};
+
+use crate::config;
+
+/// The most rows a page of `query` results holds on production.
+pub(crate) const PRODUCTION_MAX_ROWS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Environment {
+    Production,
+    Staging,
+    Development,
+}
+
+impl Environment {
+    /// Reads `DB_ENVIRONMENT`; `None` when it is unset or empty.
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        let value = config::var("DB_ENVIRONMENT").unwrap_or_default();
+        Ok(Some(match value.trim().to_ascii_lowercase().as_str() {
+            "" => return Ok(None),
+            "production" | "prod" => Self::Production,
+            "staging" => Self::Staging,
+            "development" | "dev" => Self::Development,
+            _ => bail!(
+                "Unknown DB_ENVIRONMENT '{}' (expected 'production', 'staging', or 'development')",
+                value
+            ),
+        }))
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Production => "production",
+            Self::Staging => "staging",
+            Self::Development => "development",
+        }
+    }
+
+    /// The text item that starts every tool result.
+    pub(crate) fn header(self) -> String {
+        format!("Environment: {}", self.name())
+    }
+}
@@ -0,0 +1,301 @@
+//! Read-only export of a schema's tables to a local directory.
+//!
+//! Each table is written as CSV (via `COPY ... TO STDOUT`) or Parquet, bounded
+//! by a per-table row cap and an overall byte budget, alongside a
+//! `schema.json` manifest describing the tables, columns, and what was
+//! exported.
+
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use futures_util::{TryStreamExt, pin_mut};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_postgres::Client;
+
+use crate::access::TableAccess;
+use crate::quote_ident;
+
+#[cfg(feature = "parquet")]
+mod parquet_file;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ExportFormat {
+    #[default]
+    Csv,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            #[cfg(feature = "parquet")]
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+pub(crate) struct ExportOptions {
+    pub(crate) schema: String,
+    pub(crate) dir: PathBuf,
+    pub(crate) format: ExportFormat,
+    pub(crate) max_rows_per_table: i64,
+    pub(crate) max_bytes: u64,
+    /// Tables hidden from the export.
+    pub(crate) access: TableAccess,
+}
+
+struct Column {
+    name: String,
+    data_type: String,
+    is_nullable: bool,
+}
+
+/// What was written for a table or query.
+pub(crate) struct TableExport {
+    pub(crate) rows: u64,
+    pub(crate) bytes: u64,
+    /// Whether the byte budget stopped the output before its last row.
+    pub(crate) truncated: bool,
+}
+
+/// Device names Windows resolves in every directory, with or without an
+/// extension.
+const WINDOWS_DEVICE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Resolves a client-supplied output directory against the configured export
+/// root, refusing absolute paths and `..` so exports cannot escape it. On
+/// Windows, drive-relative paths, alternate data streams (`file:stream`), and
+/// device names such as `NUL` are refused as well.
+pub(crate) fn resolve_output_dir(root: &Path, relative: &str) -> Result<PathBuf> {
+    let relative = Path::new(relative);
+    if relative.as_os_str().is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        bail!("output_dir must be a relative path without '..' components");
+    }
+    if cfg!(windows) {
+        for component in relative.components() {
+            let name = component.as_os_str().to_string_lossy();
+            let stem = name.split('.').next().unwrap_or_default().trim_end();
+            if name.contains(':') {
+                bail!("'{}' is not a valid file name on Windows", name);
+            }
+            if WINDOWS_DEVICE_NAMES
+                .iter()
+                .any(|device| device.eq_ignore_ascii_case(stem))
+            {
+                bail!("'{}' is a reserved device name on Windows", name);
+            }
+        }
+    }
+    Ok(root.join(relative))
+}
+
+/// The file a table is exported to. Characters that are not allowed in file
+/// names on Windows (or are path separators anywhere) become `_`, so every
+/// table can be exported on every platform.
+fn export_file_name(table: &str, format: ExportFormat) -> String {
+    let stem: String = table
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    format!("{}.{}", stem, format.extension())
+}
+
+/// Exports every base table in `options.schema` that `options.access`
+/// allows, calling `progress` with the
+/// number of tables completed, the total, and a status message.
+pub(crate) async fn export_schema_data(
+    client: &Client,
+    options: &ExportOptions,
+    mut progress: impl FnMut(usize, usize, &str),
+) -> Result<Value> {
+    let tables: Vec<String> = client
+        .query(
+            "SELECT table_name::text FROM information_schema.tables \
+             WHERE table_schema = $1 AND table_type = 'BASE TABLE' \
+             ORDER BY table_name",
+            &[&options.schema],
+        )
+        .await
+        .context("Failed to list tables")?
+        .iter()
+        .map(|row| row.get(0))
+        .filter(|table: &String| options.access.allows(&options.schema, table))
+        .collect();
+
+    tokio::fs::create_dir_all(&options.dir)
+        .await
+        .with_context(|| format!("Failed to create {}", options.dir.display()))?;
+
+    let mut remaining_bytes = options.max_bytes;
+    let mut manifest_tables = Vec::new();
+
+    for (done, table) in tables.iter().enumerate() {
+        progress(done, tables.len(), &format!("Exporting {}", table));
+
+        let columns = table_columns(client, &options.schema, table).await?;
+        let file_name = export_file_name(table, options.format);
+        let path = options.dir.join(&file_name);
+        let qualified = format!("{}.{}", quote_ident(&options.schema), quote_ident(table));
+
+        let export = match options.format {
+            _ if remaining_bytes == 0 => Ok(TableExport {
+                rows: 0,
+                bytes: 0,
+                truncated: true,
+            }),
+            ExportFormat::Csv => {
+                export_csv(
+                    client,
+                    &qualified,
+                    &path,
+                    options.max_rows_per_table,
+                    remaining_bytes,
+                )
+                .await
+            }
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => {
+                parquet_file::export_parquet(
+                    client,
+                    &qualified,
+                    &columns,
+                    &path,
+                    options.max_rows_per_table,
+                    remaining_bytes,
+                )
+                .await
+            }
+        }
+        .with_context(|| format!("Failed to export {}", table))?;
+
+        remaining_bytes = remaining_bytes.saturating_sub(export.bytes);
+        manifest_tables.push(json!({
+            "name": table,
+            "file": file_name,
+            "rows_exported": export.rows,
+            "bytes": export.bytes,
+            "truncated": export.truncated,
+            "columns": columns
+                .iter()
+                .map(|c| json!({
+                    "name": c.name,
+                    "data_type": c.data_type,
+                    "nullable": c.is_nullable,
+                }))
+                .collect::<Vec<_>>(),
+        }));
+    }
+
+    let exported_at: String = client
+        .query_one("SELECT now()::text", &[])
+        .await
+        .context("Failed to read server time")?
+        .get(0);
+    let manifest = json!({
+        "schema": options.schema,
+        "format": options.format.extension(),
+        "exported_at": exported_at,
+        "max_rows_per_table": options.max_rows_per_table,
+        "max_bytes": options.max_bytes,
+        "tables": manifest_tables,
+    });
+    let manifest_path = options.dir.join("schema.json");
+    tokio::fs::write(&manifest_path, serde_json::to_vec_pretty(&manifest)?)
+        .await
+        .with_context(|| format!("Failed to write {}", manifest_path.display()))?;
+
+    progress(tables.len(), tables.len(), "Export complete");
+    Ok(manifest)
+}
+
+async fn table_columns(client: &Client, schema: &str, table: &str) -> Result<Vec<Column>> {
+    let rows = client
+        .query(
+            "SELECT column_name::text, data_type::text, is_nullable = 'YES' \
+             FROM information_schema.columns \
+             WHERE table_schema = $1 AND table_name = $2 \
+             ORDER BY ordinal_position",
+            &[&schema, &table],
+        )
+        .await
+        .with_context(|| format!("Failed to read columns of {}", table))?;
+    Ok(rows
+        .iter()
+        .map(|row| Column {
+            name: row.get(0),
+            data_type: row.get(1),
+            is_nullable: row.get(2),
+        })
+        .collect())
+}
+
+async fn export_csv(
+    client: &Client,
+    qualified: &str,
+    path: &Path,
+    max_rows: i64,
+    byte_budget: u64,
+) -> Result<TableExport> {
+    let query = format!("SELECT * FROM {} LIMIT {}", qualified, max_rows);
+    let mut file = tokio::fs::File::create(path).await?;
+    copy_csv(client, &query, &mut file, byte_budget).await
+}
+
+/// What `copy_csv` puts before the query it is given.
+pub(crate) const COPY_PREFIX: &str = "COPY (";
+
+/// Streams a query's rows through `COPY ... TO STDOUT` into `file` as CSV
+/// with a header. PostgreSQL sends one CopyData message per row, so stopping at the
+/// byte budget never splits a row.
+pub(crate) async fn copy_csv(
+    client: &Client,
+    query: &str,
+    file: &mut (impl AsyncWrite + Unpin),
+    byte_budget: u64,
+) -> Result<TableExport> {
+    let sql = format!(
+        "{}{}) TO STDOUT WITH (FORMAT csv, HEADER true)",
+        COPY_PREFIX, query
+    );
+    let stream = client.copy_out(sql.as_str()).await?;
+    pin_mut!(stream);
+
+    let mut export = TableExport {
+        rows: 0,
+        bytes: 0,
+        truncated: false,
+    };
+    let mut header = true;
+    while let Some(chunk) = stream.try_next().await? {
+        if export.bytes + chunk.len() as u64 > byte_budget {
+            export.truncated = true;
+            break;
+        }
+        file.write_all(&chunk).await?;
+        export.bytes += chunk.len() as u64;
+        if header {
+            header = false;
+        } else {
+            export.rows += 1;
+        }
+    }
+    file.shutdown().await?;
+    Ok(export)
+}
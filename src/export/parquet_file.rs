@@ -0,0 +1,154 @@
+//! Parquet output for schema exports, built with the `parquet` feature.
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use arrow_array::builder::{
+    BooleanBuilder, Float64Builder, Int32Builder, Int64Builder, StringBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use futures_util::{TryStreamExt, pin_mut};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use tokio_postgres::Client;
+use tokio_postgres::types::ToSql;
+
+use super::{Column, TableExport};
+use crate::quote_ident;
+
+/// Rows buffered per Parquet record batch.
+const PARQUET_BATCH_ROWS: usize = 1000;
+
+/// Writes a table as Parquet. Integer, floating point, and boolean columns keep
+/// their type; everything else is exported as its text representation.
+pub(super) async fn export_parquet(
+    client: &Client,
+    qualified: &str,
+    columns: &[Column],
+    path: &Path,
+    max_rows: i64,
+    byte_budget: u64,
+) -> Result<TableExport> {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|c| Field::new(&c.name, parquet_type(&c.data_type), true))
+        .collect();
+    let schema = Arc::new(Schema::new(fields));
+    let select_list = columns
+        .iter()
+        .map(|c| {
+            let cast = match parquet_type(&c.data_type) {
+                DataType::Int32 => "int4",
+                DataType::Int64 => "int8",
+                DataType::Float64 => "float8",
+                DataType::Boolean => "bool",
+                _ => "text",
+            };
+            format!("{}::{}", quote_ident(&c.name), cast)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let sql = format!(
+        "SELECT {} FROM {} LIMIT {}",
+        select_list, qualified, max_rows
+    );
+
+    let file = File::create(path)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(properties))?;
+
+    let stream = client
+        .query_raw(sql.as_str(), std::iter::empty::<&(dyn ToSql + Sync)>())
+        .await?;
+    pin_mut!(stream);
+
+    let mut export = TableExport {
+        rows: 0,
+        bytes: 0,
+        truncated: false,
+    };
+    let mut batch = Vec::with_capacity(PARQUET_BATCH_ROWS);
+    loop {
+        let row = stream.try_next().await?;
+        let end = row.is_none();
+        if let Some(row) = row {
+            batch.push(row);
+        }
+        if batch.len() == PARQUET_BATCH_ROWS || (end && !batch.is_empty()) {
+            writer.write(&record_batch(&schema, &batch)?)?;
+            export.rows += batch.len() as u64;
+            batch.clear();
+            // The budget is checked per batch, so the file may exceed it by
+            // at most one batch.
+            if (writer.bytes_written() + writer.in_progress_size()) as u64 >= byte_budget {
+                export.truncated = !end;
+                break;
+            }
+        }
+        if end {
+            break;
+        }
+    }
+    writer.close()?;
+    export.bytes = std::fs::metadata(path)?.len();
+    Ok(export)
+}
+
+fn parquet_type(data_type: &str) -> DataType {
+    match data_type {
+        "smallint" | "integer" => DataType::Int32,
+        "bigint" => DataType::Int64,
+        "real" | "double precision" => DataType::Float64,
+        "boolean" => DataType::Boolean,
+        _ => DataType::Utf8,
+    }
+}
+
+fn record_batch(schema: &Arc<Schema>, rows: &[tokio_postgres::Row]) -> Result<RecordBatch> {
+    let arrays: Vec<ArrayRef> = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| -> ArrayRef {
+            match field.data_type() {
+                DataType::Int32 => {
+                    let mut b = Int32Builder::with_capacity(rows.len());
+                    rows.iter()
+                        .for_each(|r| b.append_option(r.get::<_, Option<i32>>(idx)));
+                    Arc::new(b.finish())
+                }
+                DataType::Int64 => {
+                    let mut b = Int64Builder::with_capacity(rows.len());
+                    rows.iter()
+                        .for_each(|r| b.append_option(r.get::<_, Option<i64>>(idx)));
+                    Arc::new(b.finish())
+                }
+                DataType::Float64 => {
+                    let mut b = Float64Builder::with_capacity(rows.len());
+                    rows.iter()
+                        .for_each(|r| b.append_option(r.get::<_, Option<f64>>(idx)));
+                    Arc::new(b.finish())
+                }
+                DataType::Boolean => {
+                    let mut b = BooleanBuilder::with_capacity(rows.len());
+                    rows.iter()
+                        .for_each(|r| b.append_option(r.get::<_, Option<bool>>(idx)));
+                    Arc::new(b.finish())
+                }
+                _ => {
+                    let mut b = StringBuilder::new();
+                    rows.iter()
+                        .for_each(|r| b.append_option(r.get::<_, Option<&str>>(idx)));
+                    Arc::new(b.finish())
+                }
+            }
+        })
+        .collect();
+    Ok(RecordBatch::try_new(schema.clone(), arrays)?)
+}
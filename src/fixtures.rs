@@ -0,0 +1,210 @@
+//! Fixture files for `load_fixtures`, which resets a development database to
+//! known data.
+//!
+//! A fixture set is a directory of files, read in file name order:
+//!
+//! - `.sql` files are run as they are, before any data is loaded, so they can
+//!   create or alter what the data files load
+//! - `.csv` files hold rows under a header line naming their columns, and are
+//!   loaded with `COPY`
+//! - `.json` files hold an array of objects mapping column names to values
+//! - `.yaml` and `.yml` files hold the same in YAML's flow style, which is
+//!   JSON; block style YAML is refused, as the server has no YAML parser
+//!
+//! Each data file loads the table it is named after: `orders.csv` loads
+//! `orders` in the default schema and `sales.orders.json` loads
+//! `sales.orders`. The tables are loaded parents first, following the
+//! foreign keys between them. Other files are skipped.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Map, Value};
+
+use crate::joins::TableName;
+
+/// How a fixture file is loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FixtureKind {
+    Sql,
+    Csv,
+    /// JSON rows, or YAML in flow style.
+    Rows,
+}
+
+pub(crate) struct FixtureFile {
+    pub(crate) path: PathBuf,
+    /// The file name, for reports.
+    pub(crate) name: String,
+    pub(crate) kind: FixtureKind,
+    /// The table a data file loads.
+    pub(crate) table: Option<TableName>,
+}
+
+/// The fixture files in `dir`, in file name order, and the names of the
+/// files skipped.
+pub(crate) async fn discover(
+    dir: &Path,
+    default_schema: &str,
+) -> Result<(Vec<FixtureFile>, Vec<String>)> {
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .with_context(|| format!("Cannot read the fixture directory {}", dir.display()))?;
+    let mut names = BTreeSet::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .with_context(|| format!("Cannot read the fixture directory {}", dir.display()))?
+    {
+        if entry.file_type().await.is_ok_and(|t| t.is_file())
+            && let Some(name) = entry.file_name().to_str()
+            && !name.starts_with('.')
+        {
+            names.insert(name.to_string());
+        }
+    }
+
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+    for name in names {
+        let Some((stem, extension)) = name.rsplit_once('.') else {
+            skipped.push(name);
+            continue;
+        };
+        let kind = match extension.to_ascii_lowercase().as_str() {
+            "sql" => FixtureKind::Sql,
+            "csv" => FixtureKind::Csv,
+            "json" | "yaml" | "yml" => FixtureKind::Rows,
+            _ => {
+                skipped.push(name);
+                continue;
+            }
+        };
+        let table = (kind != FixtureKind::Sql).then(|| match stem.split_once('.') {
+            Some((schema, table)) => (schema.to_string(), table.to_string()),
+            None => (default_schema.to_string(), stem.to_string()),
+        });
+        if let Some((schema, table)) = &table
+            && (schema.is_empty() || table.is_empty())
+        {
+            bail!("Fixture file '{}' does not name a table", name);
+        }
+        files.push(FixtureFile {
+            path: dir.join(&name),
+            name,
+            kind,
+            table,
+        });
+    }
+    Ok((files, skipped))
+}
+
+/// What a fixture file holds, read and checked before anything is loaded.
+pub(crate) enum FixtureContent {
+    Sql(String),
+    Csv { columns: Vec<String>, text: String },
+    Rows(Vec<Map<String, Value>>),
+}
+
+impl FixtureFile {
+    pub(crate) async fn read(&self) -> Result<FixtureContent> {
+        let text = tokio::fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("Cannot read fixture file '{}'", self.name))?;
+        Ok(match self.kind {
+            FixtureKind::Sql => FixtureContent::Sql(text),
+            FixtureKind::Csv => FixtureContent::Csv {
+                columns: csv_header(&self.name, &text)?,
+                text,
+            },
+            FixtureKind::Rows => FixtureContent::Rows(parse_rows(&self.name, &text)?),
+        })
+    }
+}
+
+/// Parses the rows of a JSON (or flow style YAML) fixture file.
+fn parse_rows(name: &str, text: &str) -> Result<Vec<Map<String, Value>>> {
+    let value: Value = serde_json::from_str(text).with_context(|| {
+        if name.ends_with(".json") {
+            format!("'{}' is not valid JSON", name)
+        } else {
+            format!(
+                "'{}' is not YAML in flow style; write it as a JSON array of objects",
+                name
+            )
+        }
+    })?;
+    let Value::Array(items) = value else {
+        bail!("'{}' must hold an array of rows", name);
+    };
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| match item {
+            Value::Object(row) => Ok(row),
+            _ => bail!("Row {} of '{}' is not an object", index, name),
+        })
+        .collect()
+}
+
+/// The column names in the header line of a CSV fixture file.
+fn csv_header(name: &str, text: &str) -> Result<Vec<String>> {
+    let mut columns = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => columns.push(std::mem::take(&mut field)),
+            '\n' | '\r' if !quoted => break,
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        bail!("The header line of '{}' has an unterminated quote", name);
+    }
+    columns.push(field);
+    if columns.iter().any(String::is_empty) {
+        bail!(
+            "The header line of '{}' must name every column, separated by commas",
+            name
+        );
+    }
+    Ok(columns)
+}
+
+/// The order to load `tables` in: each after the tables it references
+/// through `references` (pairs of referencing and referenced table), and
+/// otherwise in the order given. Tables in a reference cycle keep the order
+/// given, so deferrable constraints are the only way to load them.
+pub(crate) fn load_order(
+    tables: &[TableName],
+    references: &[(TableName, TableName)],
+) -> Vec<usize> {
+    let mut order = Vec::new();
+    let mut loaded = vec![false; tables.len()];
+    while order.len() < tables.len() {
+        let ready = (0..tables.len()).find(|&index| {
+            !loaded[index]
+                && !references.iter().any(|(from, to)| {
+                    from == &tables[index]
+                        && from != to
+                        && tables
+                            .iter()
+                            .enumerate()
+                            .any(|(other, name)| name == to && !loaded[other])
+                })
+        });
+        // A cycle: take the first table left.
+        let next = ready.unwrap_or_else(|| (0..tables.len()).find(|&i| !loaded[i]).unwrap_or(0));
+        loaded[next] = true;
+        order.push(next);
+    }
+    order
+}
@@ -0,0 +1,74 @@
+//! Detection of PostgreSQL wire-compatible databases.
+//!
+//! CockroachDB, YugabyteDB, and Greenplum speak the PostgreSQL protocol but
+//! differ in their catalogs: some lack MVCC internals such as transaction ID
+//! freezing and autovacuum entirely, others predate newer statistics views.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DatabaseFlavor {
+    PostgreSQL,
+    CockroachDB,
+    YugabyteDB,
+    Greenplum,
+}
+
+impl DatabaseFlavor {
+    /// Identifies the database from the output of `SELECT version()`.
+    pub(crate) fn detect(version: &str) -> Self {
+        if version.contains("CockroachDB") {
+            Self::CockroachDB
+        } else if version.contains("-YB-") || version.contains("YugabyteDB") {
+            Self::YugabyteDB
+        } else if version.contains("Greenplum") {
+            Self::Greenplum
+        } else {
+            Self::PostgreSQL
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::PostgreSQL => "PostgreSQL",
+            Self::CockroachDB => "CockroachDB",
+            Self::YugabyteDB => "YugabyteDB",
+            Self::Greenplum => "Greenplum",
+        }
+    }
+
+    /// Tools that depend on catalog objects this flavor does not provide.
+    pub(crate) fn unsupported_tools(self) -> &'static [&'static str] {
+        match self {
+            Self::PostgreSQL => &[],
+            // Neither has PostgreSQL's heap storage, so there is no vacuum,
+            // XID freezing, or amcheck, nor large object support. CockroachDB
+            // also has no pg_blocking_pids, and no lock waits in pg_locks.
+            Self::CockroachDB => &[
+                "autovacuum_status",
+                "wraparound_check",
+                "verify_integrity",
+                "list_large_objects",
+                "read_large_object",
+                "import_large_object",
+                "export_large_object",
+                "list_activity",
+                "list_locks",
+            ],
+            Self::YugabyteDB => &[
+                "autovacuum_status",
+                "wraparound_check",
+                "verify_integrity",
+                "list_large_objects",
+                "read_large_object",
+                "import_large_object",
+                "export_large_object",
+            ],
+            Self::Greenplum => &["verify_integrity"],
+        }
+    }
+
+    /// Whether `pg_stat_activity.backend_type` and `pg_stat_progress_vacuum`
+    /// (PostgreSQL 10+) are available.
+    pub(crate) fn has_vacuum_progress(self) -> bool {
+        self == Self::PostgreSQL
+    }
+}
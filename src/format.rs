@@ -0,0 +1,109 @@
+//! Alternative text representations of result rows.
+
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ResultFormat {
+    #[default]
+    Json,
+    Csv,
+    #[serde(alias = "md")]
+    Markdown,
+}
+
+impl ResultFormat {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            "markdown" | "md" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_mime_type(value: &str) -> Option<Self> {
+        match value {
+            "application/json" => Some(Self::Json),
+            "text/csv" => Some(Self::Csv),
+            "text/markdown" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::Markdown => "markdown",
+        }
+    }
+
+    pub(crate) fn mime_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv => "text/csv",
+            Self::Markdown => "text/markdown",
+        }
+    }
+
+    /// Renders rows as returned by `row_to_map`, with `columns` giving the
+    /// column order for the tabular formats.
+    pub(crate) fn render(self, columns: &[String], rows: &[HashMap<String, Value>]) -> String {
+        match self {
+            Self::Json => serde_json::to_string_pretty(rows).unwrap_or_else(|_| "[]".to_string()),
+            Self::Csv => {
+                let mut out = csv_line(columns.iter().map(|c| c.as_str()));
+                for row in rows {
+                    let cells: Vec<String> = columns.iter().map(|c| cell(row.get(c))).collect();
+                    out.push_str(&csv_line(cells.iter().map(|c| c.as_str())));
+                }
+                out
+            }
+            Self::Markdown => {
+                let mut out = markdown_line(columns.iter().map(|c| c.as_str()));
+                out.push_str(&markdown_line(columns.iter().map(|_| "---")));
+                for row in rows {
+                    let cells: Vec<String> = columns.iter().map(|c| cell(row.get(c))).collect();
+                    out.push_str(&markdown_line(cells.iter().map(|c| c.as_str())));
+                }
+                out
+            }
+        }
+    }
+}
+
+/// Text of a single value; NULL is rendered as an empty cell.
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(v) => v.to_string(),
+    }
+}
+
+fn csv_line<'a>(cells: impl Iterator<Item = &'a str>) -> String {
+    let mut line = cells
+        .map(|c| {
+            if c.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", c.replace('"', "\"\""))
+            } else {
+                c.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push_str("\r\n");
+    line
+}
+
+fn markdown_line<'a>(cells: impl Iterator<Item = &'a str>) -> String {
+    let cells: Vec<String> = cells
+        .map(|c| c.replace('|', "\\|").replace(['\r', '\n'], " "))
+        .collect();
+    format!("| {} |\n", cells.join(" | "))
+}
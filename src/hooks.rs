@@ -0,0 +1,353 @@
+//! Hooks fired on notable events, for alerting without scraping logs.
+//!
+//! Each event is described by a JSON object, which is POSTed to `HOOK_URL`
+//! and written to the stdin of a fresh run of `HOOK_COMMAND`, whichever are
+//! set:
+//!
+//! ```json
+//! {"event": "policy_denied", "timestamp": "2025-01-01T12:00:00.000000Z",
+//!  "server": "postgres-mcp-server", "principal": "alice",
+//!  "client": "claude-desktop 0.9.2", "subject": null, "on_behalf_of": "bob",
+//!  "text": "postgres-mcp-server: policy read_only denied tools/call (query) for alice (bob via claude-desktop 0.9.2): DELETE",
+//!  "method": "tools/call", "tool": "query", "rule": "read_only", "matched": "DELETE"}
+//! ```
+//!
+//! `principal` is the session's `MCP_PRINCIPAL`; `client`, `subject`, and
+//! `on_behalf_of` attribute the request as described in `attribution`.
+//!
+//! The events are `write_executed` (a write tool, or the `query` tool running
+//! a write statement, succeeded), `policy_denied` (a request was refused by
+//! server policy), and `slow_query` (a statement ran past
+//! `SLOW_QUERY_THRESHOLD_MS`). `HOOK_EVENTS` limits hooks to some of them.
+//! SQL is only ever included as its fingerprint, with every literal,
+//! dollar-quoted and escape strings included, replaced by `?` and comments
+//! dropped. `text` is a one-line summary, which is what Slack incoming
+//! webhooks display.
+//!
+//! Hooks run in the background and never delay or fail a request: a webhook
+//! that answers with an error, or a command that exits with one or outlives
+//! `HOOK_TIMEOUT_SECS`, is logged as a warning and the event is dropped.
+
+use std::process::Stdio;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Map, Value, json};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::warn;
+
+use crate::attribution::Attribution;
+use crate::{JsonRpcRequest, JsonRpcResponse, McpServer, config, logging, outbound};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+const EVENTS: &[&str] = &["write_executed", "policy_denied", "slow_query"];
+
+static HOOKS: OnceLock<Hooks> = OnceLock::new();
+
+struct Hooks {
+    url: Option<String>,
+    command: Option<String>,
+    events: Vec<String>,
+    timeout: Duration,
+}
+
+/// Reads `HOOK_URL`, `HOOK_COMMAND`, `HOOK_EVENTS`, and `HOOK_TIMEOUT_SECS`.
+pub(crate) fn init() -> Result<()> {
+    let setting = |name: &str| config::var(name).ok().filter(|v| !v.is_empty());
+    let url = setting("HOOK_URL");
+    let command = setting("HOOK_COMMAND");
+    if url.is_none() && command.is_none() {
+        return Ok(());
+    }
+    if let Some(url) = &url
+        && !url.starts_with("https://")
+        && !url.starts_with("http://")
+    {
+        bail!("HOOK_URL must be an http:// or https:// URL");
+    }
+    if url.is_some() {
+        outbound::require("HOOK_URL")?;
+    }
+    let events = match setting("HOOK_EVENTS") {
+        Some(v) => {
+            let events: Vec<String> = v
+                .split(',')
+                .map(str::trim)
+                .filter(|e| !e.is_empty())
+                .map(str::to_string)
+                .collect();
+            if let Some(unknown) = events.iter().find(|e| !EVENTS.contains(&e.as_str())) {
+                bail!(
+                    "HOOK_EVENTS lists unknown event '{}'; events are {}",
+                    unknown,
+                    EVENTS.join(", ")
+                );
+            }
+            events
+        }
+        None => EVENTS.iter().map(|e| e.to_string()).collect(),
+    };
+    let timeout = match config::var("HOOK_TIMEOUT_SECS") {
+        Ok(v) => Duration::from_secs(
+            v.parse::<u64>()
+                .context("HOOK_TIMEOUT_SECS must be a number of seconds")?,
+        ),
+        Err(_) => Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+    };
+    let _ = HOOKS.set(Hooks {
+        url,
+        command,
+        events,
+        timeout,
+    });
+    Ok(())
+}
+
+/// What a request was asking for, kept to report a policy denial in its
+/// response.
+pub(crate) struct RequestSummary {
+    method: String,
+    tool: Option<String>,
+}
+
+/// Summarizes `request`, if any hook fires on policy denials.
+pub(crate) fn summarize_request(request: &JsonRpcRequest) -> Option<RequestSummary> {
+    HOOKS.get().filter(|h| h.fires("policy_denied"))?;
+    let tool = (request.method == "tools/call")
+        .then(|| request.params.as_ref()?.get("name")?.as_str())
+        .flatten()
+        .map(str::to_string);
+    Some(RequestSummary {
+        method: request.method.clone(),
+        tool,
+    })
+}
+
+impl McpServer {
+    /// Fires `policy_denied` if `response` is a policy denial of the request
+    /// `summary` describes.
+    pub(crate) fn report_policy_denial(
+        &self,
+        summary: Option<RequestSummary>,
+        response: &JsonRpcResponse,
+    ) {
+        let (Some(summary), Some(data)) = (
+            summary,
+            response.error.as_ref().and_then(|e| e.data.as_ref()),
+        ) else {
+            return;
+        };
+        if data.get("type").and_then(Value::as_str) != Some("policy_denied") {
+            return;
+        }
+        let field = |name: &str| data.get(name).cloned().unwrap_or(Value::Null);
+        let (rule, matched) = (field("rule"), field("matched"));
+        let request = match &summary.tool {
+            Some(tool) => format!("{} ({})", summary.method, tool),
+            None => summary.method.clone(),
+        };
+        self.fire_hook(
+            "policy_denied",
+            format!(
+                "policy {} denied {}{}: {}",
+                rule.as_str().unwrap_or("?"),
+                request,
+                self.principal_suffix(),
+                matched.as_str().unwrap_or("?")
+            ),
+            json!({
+                "method": summary.method,
+                "tool": summary.tool,
+                "rule": rule,
+                "matched": matched,
+            }),
+        );
+    }
+
+    /// Fires `write_executed` for a write by `tool`, summarized as `summary`
+    /// and described by `details`.
+    pub(crate) fn report_write(&self, tool: &str, summary: &str, mut details: Value) {
+        details["tool"] = json!(tool);
+        self.fire_hook(
+            "write_executed",
+            format!("{}{}", summary, self.principal_suffix()),
+            details,
+        );
+    }
+
+    /// Fires `slow_query` for the statement with `fingerprint`.
+    pub(crate) fn report_slow_query_hook(
+        &self,
+        fingerprint: &str,
+        duration_ms: u64,
+        threshold_ms: u64,
+    ) {
+        let (summary, details) = slow_query(fingerprint, duration_ms, threshold_ms);
+        self.fire_hook(
+            "slow_query",
+            format!("{}{}: {}", summary, self.principal_suffix(), fingerprint),
+            details,
+        );
+    }
+
+    fn principal_suffix(&self) -> String {
+        let principal = self.identity.as_ref().map(|identity| &identity.principal);
+        match (principal, self.attribution.summary()) {
+            (Some(principal), Some(summary)) => format!(" for {} ({})", principal, summary),
+            (Some(principal), None) => format!(" for {}", principal),
+            (None, Some(summary)) => format!(" for {}", summary),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn fire_hook(&self, event: &'static str, summary: String, details: Value) {
+        let Some(hooks) = HOOKS.get().filter(|h| h.fires(event)) else {
+            return;
+        };
+        let principal = self.identity.as_ref().map(|i| i.principal.as_str());
+        let payload = payload(event, principal, &self.attribution, summary, details).to_string();
+        if let Some(url) = &hooks.url {
+            tokio::spawn(post(url.clone(), payload.clone(), hooks.timeout));
+        }
+        if let Some(command) = &hooks.command {
+            tokio::spawn(run(command.clone(), payload, hooks.timeout));
+        }
+    }
+}
+
+/// The summary, without its principal and fingerprint, and the details of
+/// a `slow_query` event.
+fn slow_query(fingerprint: &str, duration_ms: u64, threshold_ms: u64) -> (String, Value) {
+    (
+        format!("slow query ({} ms)", duration_ms),
+        json!({
+            "fingerprint": fingerprint,
+            "duration_ms": duration_ms,
+            "threshold_ms": threshold_ms,
+        }),
+    )
+}
+
+/// The JSON object describing `event`.
+fn payload(
+    event: &str,
+    principal: Option<&str>,
+    attribution: &Attribution,
+    summary: String,
+    details: Value,
+) -> Value {
+    let mut payload = Map::new();
+    payload.insert("event".to_string(), json!(event));
+    payload.insert("timestamp".to_string(), json!(logging::timestamp()));
+    payload.insert("server".to_string(), json!("postgres-mcp-server"));
+    payload.insert("principal".to_string(), json!(principal));
+    payload.insert("client".to_string(), json!(attribution.client()));
+    payload.insert("subject".to_string(), json!(attribution.subject));
+    payload.insert("on_behalf_of".to_string(), json!(attribution.on_behalf_of));
+    payload.insert(
+        "text".to_string(),
+        json!(format!("postgres-mcp-server: {}", summary)),
+    );
+    if let Value::Object(details) = details {
+        payload.extend(details);
+    }
+    Value::Object(payload)
+}
+
+impl Hooks {
+    fn fires(&self, event: &str) -> bool {
+        self.events.iter().any(|e| e == event)
+    }
+}
+
+async fn post(url: String, payload: String, timeout: Duration) {
+    if let Err(e) = outbound::post_json(url, None, payload, timeout).await {
+        warn!("Hook webhook failed: {}", e);
+    }
+}
+
+async fn run(command: String, payload: String, timeout: Duration) {
+    let child = Command::new(&command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to start hook command {}: {}", command, e);
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(format!("{}\n", payload).as_bytes()).await;
+    }
+    match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(Ok(output)) if output.status.success() => {}
+        Ok(Ok(output)) => warn!(
+            "Hook command {} exited with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Ok(Err(e)) => warn!("Failed to run hook command {}: {}", command, e),
+        Err(_) => warn!(
+            "Hook command {} did not finish within {}s",
+            command,
+            timeout.as_secs()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint_sql;
+
+    const SQL: &str = r"UPDATE users SET password = $$s3cret$$, note = $tag$s3cret$tag$
+        /* s3cret */ WHERE hint = E'it\'s s3cret' AND pin = 1234";
+
+    fn assert_no_literals(payload: &Value) {
+        let text = payload.to_string();
+        assert!(!text.contains("s3cret"), "{}", text);
+        assert!(!text.contains("1234"), "{}", text);
+    }
+
+    #[test]
+    fn write_payloads_carry_no_literals() {
+        let fingerprint = fingerprint_sql(SQL);
+        assert_eq!(
+            fingerprint,
+            "UPDATE users SET password = ?, note = ? WHERE hint = ? AND pin = ?"
+        );
+        let payload = payload(
+            "write_executed",
+            Some("alice"),
+            &Attribution::default(),
+            "query ran UPDATE".to_string(),
+            json!({ "statement": "UPDATE", "fingerprint": fingerprint, "tool": "query" }),
+        );
+        assert_eq!(payload["event"], "write_executed");
+        assert_eq!(payload["principal"], "alice");
+        assert_eq!(payload["fingerprint"], json!(fingerprint));
+        assert_no_literals(&payload);
+    }
+
+    #[test]
+    fn slow_query_payloads_carry_no_literals() {
+        let fingerprint = fingerprint_sql(SQL);
+        let (summary, details) = slow_query(&fingerprint, 2500, 1000);
+        let payload = payload(
+            "slow_query",
+            None,
+            &Attribution::default(),
+            format!("{}: {}", summary, fingerprint),
+            details,
+        );
+        assert_eq!(payload["duration_ms"], 2500);
+        assert_no_literals(&payload);
+    }
+}
@@ -0,0 +1,686 @@
+//! The Streamable HTTP transport (`--transport http`).
+//!
+//! Clients POST JSON-RPC requests to `/mcp`. `initialize` starts a session,
+//! whose id is returned in the `Mcp-Session-Id` header and must accompany
+//! every later request. Each session has its own server state, as each stdio
+//! client has its own process, but the sessions running as the same role
+//! share a connection pool. Their tool calls and resource reads share the
+//! `QUEUE_MAX_CONCURRENT` slots, taken by the sessions in turn (see the
+//! `queue` module), and at most `HTTP_MAX_SESSIONS` sessions are open at
+//! once.
+//!
+//! A request is answered with a JSON body, or, when the client accepts
+//! `text/event-stream`, with an SSE stream carrying the request's progress and
+//! log notifications followed by its response. A GET opens a stream for
+//! notifications sent between requests, such as idle connections being
+//! closed or subscribed resources changing, and a DELETE ends the session.
+//! A POSTed `notifications/cancelled` cancels the session's request being
+//! handled without waiting for it.
+//!
+//! The transport does not authenticate clients itself. Behind a proxy that
+//! does, `HTTP_SUBJECT_HEADER` names the header the proxy passes the
+//! authenticated user in: `initialize` without it is refused, the session
+//! belongs to that subject, and its requests are refused unless they carry
+//! the same one. With `ROLE_MAP` set as well, the subject is the session's
+//! principal: its connections run as the role the subject maps to, and a
+//! subject without a mapping cannot start a session.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow, bail};
+use axum::Router;
+use axum::extract::State;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use deadpool_postgres::Pool;
+use futures_util::{Stream, stream};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::json;
+use tokio::sync::mpsc;
+#[cfg(feature = "compression")]
+use tower_http::compression::CompressionLayer;
+use tracing::{error, info};
+
+use crate::cancel::Cancellation;
+use crate::identity::{RoleMap, SessionIdentity};
+use crate::queue::{self, FairQueue, QueueLimits};
+use crate::{
+    JsonRpcNotification, JsonRpcRequest, JsonRpcResponse, McpServer, NotificationSink, config,
+    pool, shutdown_signal, wirelog,
+};
+
+const SESSION_HEADER: &str = "mcp-session-id";
+/// The header giving how long the client waits for a request, in
+/// milliseconds (see the `deadline` module).
+const TIMEOUT_HEADER: &str = "x-request-timeout-ms";
+const DEFAULT_SESSION_TIMEOUT_SECS: u64 = 3600;
+const DEFAULT_MAX_SESSIONS: usize = 100;
+/// How often sessions are checked against `HTTP_SESSION_TIMEOUT_SECS` and
+/// `IDLE_TIMEOUT_SECS`.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+struct HttpState {
+    database_url: String,
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+    /// How long a session may go without requests before it is ended.
+    session_timeout: Option<Duration>,
+    /// Browser origins allowed besides the local ones; `*` allows any.
+    allowed_origins: Vec<String>,
+    /// The header an authenticating proxy passes the session's subject in.
+    subject_header: Option<HeaderName>,
+    /// How subjects map to roles, with `subject_header` and `ROLE_MAP`.
+    role_map: Option<RoleMap>,
+    /// The identity of every session without a role map, from
+    /// `MCP_PRINCIPAL`.
+    identity: Option<SessionIdentity>,
+    /// The connection pools, by the role their sessions run as.
+    pools: Mutex<HashMap<Option<String>, Pool>>,
+    /// The connections each pool keeps while all sessions are idle.
+    min_pool_size: usize,
+    /// How long all sessions must be idle before the pools are trimmed.
+    idle_timeout: Option<Duration>,
+    queue: FairQueue,
+    max_sessions: Option<usize>,
+    random: SystemRandom,
+}
+
+struct Session {
+    server: tokio::sync::Mutex<McpServer>,
+    /// The server's request being handled, to cancel without waiting for it.
+    cancellation: Cancellation,
+    last_active: Mutex<Instant>,
+    /// The stream opened by GET, if any.
+    stream: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    /// The authenticated subject the session belongs to, with
+    /// `HTTP_SUBJECT_HEADER`.
+    subject: Option<String>,
+    /// How many of the session's requests are queued or being handled.
+    in_flight: AtomicUsize,
+}
+
+impl Session {
+    fn touch(&self) {
+        *lock(&self.last_active) = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        lock(&self.last_active).elapsed()
+    }
+
+    /// Where notifications go outside a streamed request.
+    fn stream_sink(&self) -> NotificationSink {
+        match &*lock(&self.stream) {
+            Some(sender) => NotificationSink::Channel(sender.clone()),
+            None => NotificationSink::Discard,
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Serves MCP over HTTP on `addr` until the process is asked to stop.
+pub(crate) async fn serve(addr: SocketAddr, database_url: String) -> Result<()> {
+    let session_timeout = match config::var("HTTP_SESSION_TIMEOUT_SECS") {
+        Ok(v) => match v
+            .parse::<u64>()
+            .context("HTTP_SESSION_TIMEOUT_SECS must be a number of seconds")?
+        {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        },
+        Err(_) => Some(Duration::from_secs(DEFAULT_SESSION_TIMEOUT_SECS)),
+    };
+    let max_sessions = match config::var("HTTP_MAX_SESSIONS") {
+        Ok(v) => match v
+            .parse::<usize>()
+            .context("HTTP_MAX_SESSIONS must be a number of sessions")?
+        {
+            0 => None,
+            sessions => Some(sessions),
+        },
+        Err(_) => Some(DEFAULT_MAX_SESSIONS),
+    };
+    let allowed_origins = config::var("HTTP_ALLOWED_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    let subject_header = match config::var("HTTP_SUBJECT_HEADER") {
+        Ok(v) if !v.trim().is_empty() => Some(
+            HeaderName::from_bytes(v.trim().as_bytes())
+                .with_context(|| format!("HTTP_SUBJECT_HEADER '{}' is not a header name", v))?,
+        ),
+        _ => None,
+    };
+    let role_map = match subject_header {
+        Some(_) => RoleMap::from_env()?,
+        None => None,
+    };
+    let identity = match role_map {
+        Some(_) if config::var("MCP_PRINCIPAL").is_ok_and(|v| !v.is_empty()) => bail!(
+            "MCP_PRINCIPAL cannot be set with HTTP_SUBJECT_HEADER and ROLE_MAP, which make each session's subject its principal"
+        ),
+        Some(_) => None,
+        None => SessionIdentity::from_env()?,
+    };
+    let queue = FairQueue::new(QueueLimits::from_env()?);
+    // Report configuration errors now rather than to the first client.
+    let server = McpServer::with_identity(&database_url, identity.clone(), None)?;
+    let mut pools = HashMap::new();
+    if role_map.is_none() {
+        pools.insert(pool_key(identity.as_ref()), server.pool.clone());
+    }
+
+    let state = Arc::new(HttpState {
+        database_url,
+        sessions: Mutex::new(HashMap::new()),
+        session_timeout,
+        allowed_origins,
+        subject_header,
+        role_map,
+        identity,
+        pools: Mutex::new(pools),
+        min_pool_size: server.pool_settings.min_size,
+        idle_timeout: server.idle_timeout,
+        queue,
+        max_sessions,
+        random: SystemRandom::new(),
+    });
+    tokio::spawn(sweep_sessions(state.clone()));
+
+    let app = Router::new()
+        .route(
+            "/mcp",
+            post(post_message).get(open_stream).delete(end_session),
+        )
+        .with_state(state.clone());
+    // Compresses JSON responses by Accept-Encoding; event streams are left
+    // alone so each event is delivered as it is sent.
+    #[cfg(feature = "compression")]
+    let app = app.layer(CompressionLayer::new());
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    info!(
+        "Listening for MCP over HTTP at http://{}/mcp",
+        listener.local_addr()?
+    );
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            info!("Shutdown requested, exiting");
+            // Ending the sessions closes their open streams, which would
+            // otherwise hold the shutdown until their clients disconnect.
+            lock(&state.sessions).clear();
+        })
+        .await
+        .context("HTTP server failed")
+}
+
+async fn post_message(
+    State(state): State<Arc<HttpState>>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    if !state.origin_allowed(&headers) {
+        return origin_refused();
+    }
+    let received = Instant::now();
+    wirelog::inbound(&body);
+    let timeout = match headers.get(TIMEOUT_HEADER).map(|value| {
+        value
+            .to_str()
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(Duration::from_millis)
+    }) {
+        None => None,
+        Some(Some(timeout)) => Some(timeout),
+        Some(None) => {
+            return rpc_error(
+                StatusCode::BAD_REQUEST,
+                -32600,
+                "Invalid X-Request-Timeout-Ms header: expected a number of milliseconds",
+            );
+        }
+    };
+    let request: JsonRpcRequest = match serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return rpc_error(
+                StatusCode::BAD_REQUEST,
+                -32600,
+                format!("Invalid request: {}", e),
+            );
+        }
+    };
+
+    let (session_id, session, created) = if request.method == "initialize" {
+        let subject = match state.subject(&headers) {
+            Ok(subject) => subject,
+            Err(e) => return e.into_response(),
+        };
+        let identity = match state.identity(subject.as_deref()) {
+            Ok(identity) => identity,
+            Err(e) => return e.into_response(),
+        };
+        match state.start_session(subject, identity) {
+            Ok((id, session)) => (id, session, true),
+            Err(e) => return e.into_response(),
+        }
+    } else {
+        match state.session(&headers) {
+            Ok((id, session)) => (id, session, false),
+            Err(e) => return e.into_response(),
+        }
+    };
+    session.touch();
+
+    if request.method == "notifications/cancelled" {
+        session.cancellation.cancel(request.params.as_ref());
+    }
+    // Notifications from the client need no response.
+    if request.id.is_none() {
+        return StatusCode::ACCEPTED.into_response();
+    }
+
+    let streamed = accepts(&headers, "text/event-stream");
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let sink = if streamed {
+        NotificationSink::Channel(sender.clone())
+    } else {
+        session.stream_sink()
+    };
+    let lane = session_id.clone();
+    session.in_flight.fetch_add(1, Ordering::SeqCst);
+    // Handle the request in its own task so that it completes, and its
+    // connection returns to the pool, even if the client disconnects.
+    tokio::spawn(async move {
+        let slot = if queue::is_priority_method(&request.method) {
+            Ok(None)
+        } else {
+            state
+                .queue
+                .acquire(&lane, received, &request.id)
+                .await
+                .map(Some)
+        };
+        let response = match slot {
+            Ok(slot) => {
+                let mut handler = session.server.lock().await.for_request();
+                handler.notifications = sink;
+                handler.received = received;
+                handler.deadline = timeout.map(|timeout| received + timeout);
+                let response = handler.handle_request(request).await;
+                drop(slot);
+                response
+            }
+            Err(busy) => busy,
+        };
+        session.in_flight.fetch_sub(1, Ordering::SeqCst);
+        session.touch();
+        wirelog::outbound(&response);
+        match serde_json::to_string(&response) {
+            Ok(line) => {
+                let _ = sender.send(line);
+            }
+            Err(e) => error!("Failed to serialize response: {}", e),
+        }
+    });
+
+    let mut response = if streamed {
+        Sse::new(event_stream(receiver))
+            .keep_alive(KeepAlive::default())
+            .into_response()
+    } else {
+        match receiver.recv().await {
+            Some(line) => ([(header::CONTENT_TYPE, "application/json")], line).into_response(),
+            None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    };
+    if created && let Ok(value) = HeaderValue::from_str(&session_id) {
+        response.headers_mut().insert(SESSION_HEADER, value);
+    }
+    response
+}
+
+async fn open_stream(State(state): State<Arc<HttpState>>, headers: HeaderMap) -> Response {
+    if !state.origin_allowed(&headers) {
+        return origin_refused();
+    }
+    if !accepts(&headers, "text/event-stream") {
+        return StatusCode::NOT_ACCEPTABLE.into_response();
+    }
+    let (_, session) = match state.session(&headers) {
+        Ok(session) => session,
+        Err(e) => return e.into_response(),
+    };
+    let (sender, receiver) = mpsc::unbounded_channel();
+    // A session has one such stream; opening another closes the last.
+    *lock(&session.stream) = Some(sender);
+    Sse::new(event_stream(receiver))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn end_session(State(state): State<Arc<HttpState>>, headers: HeaderMap) -> Response {
+    if !state.origin_allowed(&headers) {
+        return origin_refused();
+    }
+    match state.session(&headers) {
+        Ok((id, _)) => {
+            lock(&state.sessions).remove(&id);
+            info!("Session {} ended by the client", id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+impl HttpState {
+    fn start_session(
+        &self,
+        subject: Option<String>,
+        identity: Option<SessionIdentity>,
+    ) -> Result<(String, Arc<Session>), SessionError> {
+        let mut sessions = lock(&self.sessions);
+        if let Some(max_sessions) = self.max_sessions
+            && sessions.len() >= max_sessions
+        {
+            return Err(SessionError::TooMany(max_sessions));
+        }
+        let mut bytes = [0u8; 16];
+        self.random
+            .fill(&mut bytes)
+            .map_err(|_| SessionError::Failed(anyhow!("no random numbers for a session id")))?;
+        let id: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let key = pool_key(identity.as_ref());
+        let shared = lock(&self.pools).get(&key).cloned();
+        let mut server = McpServer::with_identity(&self.database_url, identity, shared.clone())
+            .map_err(SessionError::Failed)?;
+        if shared.is_none() {
+            // The first session of its role; later ones use its pool.
+            server.shares_pool = true;
+            lock(&self.pools).insert(key, server.pool.clone());
+        }
+        server.notifications = NotificationSink::Discard;
+        server.attribution.subject = subject.clone();
+        let resource_updates = server.take_resource_updates();
+        let cancellation = server.cancellation.clone();
+        let session = Arc::new(Session {
+            server: tokio::sync::Mutex::new(server),
+            cancellation,
+            last_active: Mutex::new(Instant::now()),
+            stream: Mutex::new(None),
+            subject,
+            in_flight: AtomicUsize::new(0),
+        });
+        tokio::spawn(forward_resource_updates(
+            Arc::downgrade(&session),
+            resource_updates,
+        ));
+        sessions.insert(id.clone(), session.clone());
+        info!("Session {} started", id);
+        Ok((id, session))
+    }
+
+    /// The session named by the request's `Mcp-Session-Id` header.
+    fn session(&self, headers: &HeaderMap) -> Result<(String, Arc<Session>), SessionError> {
+        let id = headers
+            .get(SESSION_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .ok_or(SessionError::Missing)?;
+        let session = match lock(&self.sessions).get(id) {
+            Some(session) => session.clone(),
+            None => return Err(SessionError::Unknown),
+        };
+        if self.subject(headers)? != session.subject {
+            return Err(SessionError::OtherSubject);
+        }
+        Ok((id.to_string(), session))
+    }
+
+    /// The authenticated subject the request's `HTTP_SUBJECT_HEADER` names,
+    /// if the header is configured.
+    fn subject(&self, headers: &HeaderMap) -> Result<Option<String>, SessionError> {
+        let Some(name) = &self.subject_header else {
+            return Ok(None);
+        };
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| Some(v.to_string()))
+            .ok_or_else(|| SessionError::NoSubject(name.to_string()))
+    }
+
+    /// The identity a session of `subject` runs as: with a role map, the
+    /// role the subject maps to, and otherwise `MCP_PRINCIPAL`'s.
+    fn identity(&self, subject: Option<&str>) -> Result<Option<SessionIdentity>, SessionError> {
+        match (&self.role_map, subject) {
+            (Some(role_map), Some(subject)) => role_map
+                .identity(subject)
+                .map(Some)
+                .map_err(|_| SessionError::Unmapped(subject.to_string())),
+            _ => Ok(self.identity.clone()),
+        }
+    }
+
+    /// Whether the request may come from its `Origin`. Browser pages on other
+    /// sites are refused, as they could otherwise reach a server bound to
+    /// localhost through DNS rebinding.
+    fn origin_allowed(&self, headers: &HeaderMap) -> bool {
+        let Some(origin) = headers.get(header::ORIGIN) else {
+            return true;
+        };
+        origin.to_str().is_ok_and(|origin| {
+            is_local_origin(origin)
+                || self
+                    .allowed_origins
+                    .iter()
+                    .any(|allowed| allowed == "*" || allowed == origin)
+        })
+    }
+}
+
+enum SessionError {
+    Missing,
+    Unknown,
+    /// The request lacks the header `HTTP_SUBJECT_HEADER` names.
+    NoSubject(String),
+    /// The request's subject is not the one the session belongs to.
+    OtherSubject,
+    /// The subject has no entry in `ROLE_MAP`.
+    Unmapped(String),
+    /// `HTTP_MAX_SESSIONS` sessions are already open.
+    TooMany(usize),
+    Failed(anyhow::Error),
+}
+
+impl IntoResponse for SessionError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Missing => rpc_error(
+                StatusCode::BAD_REQUEST,
+                -32000,
+                "Missing Mcp-Session-Id header; send initialize first",
+            ),
+            Self::Unknown => rpc_error(
+                StatusCode::NOT_FOUND,
+                -32000,
+                "Unknown or expired session; send initialize to start a new one",
+            ),
+            Self::NoSubject(header) => rpc_error(
+                StatusCode::UNAUTHORIZED,
+                -32000,
+                format!("Missing {} header (see HTTP_SUBJECT_HEADER)", header),
+            ),
+            Self::OtherSubject => rpc_error(
+                StatusCode::FORBIDDEN,
+                -32000,
+                "The session belongs to another subject",
+            ),
+            Self::Unmapped(subject) => rpc_error(
+                StatusCode::FORBIDDEN,
+                -32000,
+                format!("Subject '{}' has no entry in ROLE_MAP", subject),
+            ),
+            Self::TooMany(max_sessions) => {
+                info!("Refused a session: {} are already open", max_sessions);
+                let busy = queue::server_busy(
+                    None,
+                    format!("Server busy: {} sessions are already open", max_sessions),
+                    json!({
+                        "reason": "too_many_sessions",
+                        "max_sessions": max_sessions,
+                    }),
+                );
+                json_response(StatusCode::SERVICE_UNAVAILABLE, &busy)
+            }
+            Self::Failed(e) => {
+                error!("Failed to start session: {:#}", e);
+                rpc_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    -32603,
+                    format!("Failed to start session: {:#}", e),
+                )
+            }
+        }
+    }
+}
+
+fn origin_refused() -> Response {
+    rpc_error(
+        StatusCode::FORBIDDEN,
+        -32000,
+        "Origin not allowed (see HTTP_ALLOWED_ORIGINS)",
+    )
+}
+
+/// Ends sessions that have been idle past `HTTP_SESSION_TIMEOUT_SECS`, and
+/// closes the idle connections of those idle past `IDLE_TIMEOUT_SECS`.
+async fn sweep_sessions(state: Arc<HttpState>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let sessions: Vec<(String, Arc<Session>)> = lock(&state.sessions)
+            .iter()
+            .map(|(id, session)| (id.clone(), session.clone()))
+            .collect();
+        let mut all_idle = true;
+        for (id, session) in sessions {
+            // A session handling a request is not idle.
+            if session.in_flight.load(Ordering::SeqCst) > 0 {
+                all_idle = false;
+                continue;
+            }
+            let idle = session.idle_for();
+            if state.session_timeout.is_some_and(|timeout| idle >= timeout) {
+                lock(&state.sessions).remove(&id);
+                info!("Session {} expired after {}s idle", id, idle.as_secs());
+                continue;
+            }
+            if state.idle_timeout.is_none_or(|timeout| idle < timeout) {
+                all_idle = false;
+            }
+            let Ok(mut server) = session.server.try_lock() else {
+                continue;
+            };
+            if server.idle_timeout.is_some_and(|timeout| idle >= timeout)
+                && server.has_idle_resources()
+                && let Some(message) = server.release_idle_resources().await
+            {
+                server.notifications = session.stream_sink();
+                server.notify(&JsonRpcNotification::log("info", message));
+                server.notifications = NotificationSink::Discard;
+            }
+        }
+        // The shared pools close their spare connections once no session
+        // needs them.
+        if all_idle && let Some(idle_timeout) = state.idle_timeout {
+            for pool in lock(&state.pools).values() {
+                let closed = pool::trim(pool, state.min_pool_size);
+                if closed > 0 {
+                    info!(
+                        "All sessions idle for {}s: closed {} database connection(s)",
+                        idle_timeout.as_secs(),
+                        closed
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Sends the session's resource updates down its GET stream, if one is open,
+/// until the session ends.
+async fn forward_resource_updates(
+    session: Weak<Session>,
+    mut updates: mpsc::UnboundedReceiver<String>,
+) {
+    while let Some(uri) = updates.recv().await {
+        let Some(session) = session.upgrade() else {
+            return;
+        };
+        session
+            .stream_sink()
+            .send(&JsonRpcNotification::resource_updated(&uri));
+    }
+}
+
+fn event_stream(
+    receiver: mpsc::UnboundedReceiver<String>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(receiver, |mut receiver| async move {
+        let line = receiver.recv().await?;
+        Some((Ok(Event::default().event("message").data(line)), receiver))
+    })
+}
+
+fn accepts(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| {
+            let v = v.split(';').next().unwrap_or_default().trim();
+            v == media_type
+        })
+}
+
+fn is_local_origin(origin: &str) -> bool {
+    let host = origin.split_once("://").map_or(origin, |(_, rest)| rest);
+    let host = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+fn rpc_error(status: StatusCode, code: i32, message: impl Into<String>) -> Response {
+    json_response(status, &JsonRpcResponse::error(None, code, message))
+}
+
+fn json_response(status: StatusCode, response: &JsonRpcResponse) -> Response {
+    let body = serde_json::to_string(response).unwrap_or_default();
+    (status, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+/// The key of the pool for sessions running as `identity`.
+fn pool_key(identity: Option<&SessionIdentity>) -> Option<String> {
+    identity.map(|identity| identity.role.clone())
+}
@@ -0,0 +1,220 @@
+//! Runs a session under the PostgreSQL role mapped to its authenticated
+//! principal, so database-native privileges and row-level security apply per
+//! end user.
+//!
+//! The principal is supplied by whatever authenticated the user, typically a
+//! gateway that starts one server process per session and passes it in
+//! `MCP_PRINCIPAL`. `ROLE_MAP` maps principals to roles; a principal without a
+//! mapping is refused rather than falling back to the connection's own role.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result, bail};
+
+use crate::{config, quote_ident, settings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RoleSwitch {
+    /// `SET ROLE`: the connecting user must be a member of the mapped role.
+    SetRole,
+    /// `SET SESSION AUTHORIZATION`: the connecting user must be a superuser.
+    SessionAuthorization,
+}
+
+impl RoleSwitch {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "" | "role" => Some(Self::SetRole),
+            "session_authorization" => Some(Self::SessionAuthorization),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SessionIdentity {
+    pub(crate) principal: String,
+    pub(crate) role: String,
+    switch: RoleSwitch,
+}
+
+impl SessionIdentity {
+    /// Reads `MCP_PRINCIPAL`, `ROLE_MAP`, and `ROLE_SWITCH`. Returns `None`
+    /// when neither a principal nor a role map is configured.
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        let principal = config::var("MCP_PRINCIPAL").ok().filter(|v| !v.is_empty());
+        match (principal, RoleMap::from_env()?) {
+            (None, None) => Ok(None),
+            (Some(principal), Some(role_map)) => role_map.identity(&principal).map(Some),
+            (Some(_), None) => bail!("MCP_PRINCIPAL is set but ROLE_MAP is not"),
+            (None, Some(_)) => bail!("ROLE_MAP is set but MCP_PRINCIPAL is not"),
+        }
+    }
+
+    /// The statement that switches a freshly opened connection to the role.
+    pub(crate) fn statement(&self) -> String {
+        match self.switch {
+            RoleSwitch::SetRole => format!("SET ROLE {}", quote_ident(&self.role)),
+            RoleSwitch::SessionAuthorization => {
+                format!("SET SESSION AUTHORIZATION {}", quote_ident(&self.role))
+            }
+        }
+    }
+}
+
+/// `ROLE_MAP` and `ROLE_SWITCH`, for mapping the principals of HTTP sessions
+/// that each have their own.
+#[derive(Debug, Clone)]
+pub(crate) struct RoleMap {
+    roles: HashMap<String, String>,
+    switch: RoleSwitch,
+}
+
+impl RoleMap {
+    /// Reads `ROLE_MAP` and `ROLE_SWITCH`. Returns `None` without a map.
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        let Some(role_map) = config::var("ROLE_MAP").ok().filter(|v| !v.is_empty()) else {
+            return Ok(None);
+        };
+        let roles = parse_role_map(&role_map)?;
+        let switch = config::var("ROLE_SWITCH").unwrap_or_default();
+        let switch = RoleSwitch::parse(&switch).with_context(|| {
+            format!(
+                "Unknown ROLE_SWITCH '{}' (expected 'role' or 'session_authorization')",
+                switch
+            )
+        })?;
+        Ok(Some(Self { roles, switch }))
+    }
+
+    /// The identity of `principal`, refused when the map has no entry for it.
+    pub(crate) fn identity(&self, principal: &str) -> Result<SessionIdentity> {
+        let role = self
+            .roles
+            .get(principal)
+            .cloned()
+            .with_context(|| format!("Principal '{}' has no entry in ROLE_MAP", principal))?;
+        Ok(SessionIdentity {
+            principal: principal.to_string(),
+            role,
+            switch: self.switch,
+        })
+    }
+}
+
+/// Parses `principal=role` pairs separated by commas.
+fn parse_role_map(value: &str) -> Result<HashMap<String, String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((principal, role)) if !principal.trim().is_empty() && !role.trim().is_empty() => {
+                Ok((principal.trim().to_string(), role.trim().to_string()))
+            }
+            _ => bail!(
+                "Invalid ROLE_MAP entry '{}' (expected principal=role)",
+                entry
+            ),
+        })
+        .collect()
+}
+
+/// Returns the construct that would let `sql` leave the mapped role, if any.
+/// Each statement is read without its comments, and each `set_config` call
+/// is found in its parse tree: anything that switches the role or session
+/// authorization is refused, as are anonymous code blocks, `DISCARD`, and
+/// `set_config` calls whose setting cannot be told.
+pub(crate) fn role_change(sql: &str) -> Option<&'static str> {
+    for statement in settings::statements(sql) {
+        let first = statement
+            .trim_start_matches(|c: char| c.is_whitespace() || c == '(')
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+        match first.as_str() {
+            "DO" => return Some("DO"),
+            "DISCARD" => return Some("DISCARD"),
+            _ => {}
+        }
+    }
+    settings::changes(sql).iter().find_map(|change| {
+        match (change.name.as_str(), change.statement) {
+            ("role", "SET") => Some("SET ROLE"),
+            ("role", "RESET") => Some("RESET ROLE"),
+            ("session_authorization", "SET") => Some("SET SESSION AUTHORIZATION"),
+            ("session_authorization", "RESET") => Some("RESET SESSION AUTHORIZATION"),
+            ("role" | "session_authorization" | "set_config", "set_config") => Some("set_config"),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn role_switches_are_found() {
+        for (sql, construct) in [
+            ("SET ROLE postgres", "SET ROLE"),
+            ("set session role postgres", "SET ROLE"),
+            ("SET LOCAL ROLE postgres", "SET ROLE"),
+            ("SET/*x*/ROLE postgres", "SET ROLE"),
+            ("SET role = postgres", "SET ROLE"),
+            ("SELECT 1; -- hi\nRESET ROLE", "RESET ROLE"),
+            (
+                "SET SESSION AUTHORIZATION postgres",
+                "SET SESSION AUTHORIZATION",
+            ),
+            ("RESET SESSION AUTHORIZATION", "RESET SESSION AUTHORIZATION"),
+            ("SELECT set_config('role', 'postgres', false)", "set_config"),
+            (
+                "SELECT pg_catalog.set_config('ROLE', 'postgres', true)",
+                "set_config",
+            ),
+            (
+                "SELECT set_config('session_authorization', 'postgres', false)",
+                "set_config",
+            ),
+            (
+                "SELECT set_config(name, 'postgres', false) FROM t",
+                "set_config",
+            ),
+            ("/* c */ DO $$ BEGIN END $$", "DO"),
+            ("DO$$BEGIN END$$", "DO"),
+            ("DISCARD ALL", "DISCARD"),
+        ] {
+            assert_eq!(role_change(sql), Some(construct), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn other_statements_pass() {
+        for sql in [
+            "SELECT 1",
+            "SELECT current_setting('role')",
+            "SELECT 'SET ROLE postgres'",
+            "SELECT 1 -- SET ROLE postgres",
+            "SET LOCAL work_mem = '64MB'",
+            "SELECT set_config('work_mem', '64MB', true)",
+            "SELECT * FROM docs WHERE body LIKE '%DO %'",
+        ] {
+            assert_eq!(role_change(sql), None, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn role_maps() {
+        let map = RoleMap {
+            roles: parse_role_map("alice@example.com=app_alice, bob=app_bob").unwrap(),
+            switch: RoleSwitch::SetRole,
+        };
+        let identity = map.identity("alice@example.com").unwrap();
+        assert_eq!(identity.role, "app_alice");
+        assert_eq!(identity.statement(), "SET ROLE \"app_alice\"");
+        assert!(map.identity("mallory").is_err());
+        assert!(parse_role_map("alice").is_err());
+    }
+}
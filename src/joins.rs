@@ -0,0 +1,293 @@
+//! Join paths between tables, for `suggest_joins`.
+//!
+//! Tables are joined along their foreign keys. A pair of tables without one
+//! between them may still be related through a column named after the other
+//! table's key, such as `orders.customer_id` for `customers.id`; when the two
+//! columns' types agree, that is offered as an undeclared link. The requested
+//! tables are connected one at a time, each by the cheapest path from those
+//! already connected. An undeclared link costs as much as three declared
+//! ones, so a path of up to two foreign keys is preferred to a guess.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+/// A table's schema and name.
+pub(crate) type TableName = (String, String);
+
+const DECLARED_COST: u32 = 1;
+const UNDECLARED_COST: u32 = 3;
+
+/// PostgreSQL's reserved key words, which must be quoted to be used as names.
+const RESERVED_WORDS: &str = "\
+    all analyse analyze and any array as asc asymmetric authorization binary \
+    both case cast check collate collation column concurrently constraint \
+    create cross current_catalog current_date current_role current_schema \
+    current_time current_timestamp current_user default deferrable desc \
+    distinct do else end except false fetch for foreign freeze from full \
+    grant group having ilike in initially inner intersect into is isnull \
+    join lateral leading left like limit localtime localtimestamp natural \
+    not notnull null offset on only or order outer overlaps placing primary \
+    references returning right select session_user similar some symmetric \
+    system_user table tablesample then to trailing true union unique user \
+    using variadic verbose when where window with";
+
+/// What the undeclared links of a table are inferred from.
+pub(crate) struct TableColumns {
+    pub(crate) name: TableName,
+    /// Column names with their types, without modifiers.
+    pub(crate) columns: Vec<(String, String)>,
+    pub(crate) primary_key: Vec<String>,
+}
+
+/// A relationship from the referencing table to the referenced one.
+#[derive(Clone)]
+pub(crate) struct Link {
+    /// The foreign key constraint, or `None` for an undeclared link.
+    pub(crate) constraint: Option<String>,
+    pub(crate) from: TableName,
+    pub(crate) from_columns: Vec<String>,
+    pub(crate) to: TableName,
+    pub(crate) to_columns: Vec<String>,
+}
+
+/// How to join a set of tables.
+pub(crate) struct JoinPlan {
+    /// The tables in join order, including any joined only to reach others.
+    pub(crate) tables: Vec<TableName>,
+    /// The links joining each table after the first to one before it.
+    pub(crate) links: Vec<Link>,
+    /// Requested tables no path reaches.
+    pub(crate) unconnected: Vec<TableName>,
+}
+
+/// Links between `tables` that no link in `declared` already relates,
+/// inferred from column names and types.
+pub(crate) fn undeclared_links(tables: &[TableColumns], declared: &[Link]) -> Vec<Link> {
+    // Column names that would refer to each table's single-column key.
+    let mut referring_names: HashMap<String, Vec<(&TableColumns, &str, &str)>> = HashMap::new();
+    for table in tables {
+        let key = match table.primary_key.as_slice() {
+            [key] => key.as_str(),
+            [] if table.columns.iter().any(|(name, _)| name == "id") => "id",
+            _ => continue,
+        };
+        let Some((_, key_type)) = table.columns.iter().find(|(name, _)| name == key) else {
+            continue;
+        };
+        let mut names = vec![
+            format!("{}_{}", table.name.1, key),
+            format!("{}_{}", singular(&table.name.1), key),
+        ];
+        // A key such as customer_id can be shared under its own name.
+        if key.contains('_') {
+            names.push(key.to_string());
+        }
+        names.dedup();
+        for name in names {
+            referring_names
+                .entry(name.to_lowercase())
+                .or_default()
+                .push((table, key, key_type.as_str()));
+        }
+    }
+
+    let related = |a: &TableName, b: &TableName, links: &[Link]| {
+        links
+            .iter()
+            .any(|l| (&l.from == a && &l.to == b) || (&l.from == b && &l.to == a))
+    };
+    let mut links: Vec<Link> = Vec::new();
+    for table in tables {
+        for (column, column_type) in &table.columns {
+            let Some(referenced) = referring_names.get(&column.to_lowercase()) else {
+                continue;
+            };
+            for (target, key, key_type) in referenced {
+                if target.name == table.name
+                    || !compatible_types(column_type, key_type)
+                    || related(&table.name, &target.name, declared)
+                    || related(&table.name, &target.name, &links)
+                {
+                    continue;
+                }
+                links.push(Link {
+                    constraint: None,
+                    from: table.name.clone(),
+                    from_columns: vec![column.clone()],
+                    to: target.name.clone(),
+                    to_columns: vec![key.to_string()],
+                });
+            }
+        }
+    }
+    links
+}
+
+/// Connects `requested` through `links`, using paths of at most `max_hops`
+/// links.
+pub(crate) fn plan(requested: &[TableName], links: &[Link], max_hops: usize) -> JoinPlan {
+    // Tables are numbered in name order so ties are broken the same way on
+    // every call.
+    let mut names: BTreeMap<&TableName, usize> = BTreeMap::new();
+    for name in requested
+        .iter()
+        .chain(links.iter().flat_map(|l| [&l.from, &l.to]))
+    {
+        names.insert(name, 0);
+    }
+    let nodes: Vec<&TableName> = names.keys().copied().collect();
+    for (index, name) in nodes.iter().enumerate() {
+        names.insert(name, index);
+    }
+    let mut adjacent: Vec<Vec<(usize, usize)>> = vec![Vec::new(); nodes.len()];
+    for (index, link) in links.iter().enumerate() {
+        let (from, to) = (names[&link.from], names[&link.to]);
+        adjacent[from].push((to, index));
+        adjacent[to].push((from, index));
+    }
+
+    let mut plan = JoinPlan {
+        tables: Vec::new(),
+        links: Vec::new(),
+        unconnected: Vec::new(),
+    };
+    let Some(first) = requested.first() else {
+        return plan;
+    };
+    let mut connected = vec![false; nodes.len()];
+    connected[names[first]] = true;
+    plan.tables.push(first.clone());
+    let mut remaining: Vec<usize> = requested[1..]
+        .iter()
+        .map(|name| names[name])
+        .filter(|node| !connected[*node])
+        .collect();
+
+    while !remaining.is_empty() {
+        // The cheapest path from any connected table to a remaining one.
+        let mut best: Vec<Option<(u32, usize)>> = vec![None; nodes.len()];
+        let mut via: Vec<Option<(usize, usize)>> = vec![None; nodes.len()];
+        let mut queue = BinaryHeap::new();
+        for node in (0..nodes.len()).filter(|node| connected[*node]) {
+            best[node] = Some((0, 0));
+            queue.push(Reverse((0, 0, node)));
+        }
+        let mut reached = None;
+        while let Some(Reverse((cost, hops, node))) = queue.pop() {
+            if best[node] != Some((cost, hops)) {
+                continue;
+            }
+            if remaining.contains(&node) {
+                reached = Some(node);
+                break;
+            }
+            if hops == max_hops {
+                continue;
+            }
+            for &(next, link) in &adjacent[node] {
+                let step = match links[link].constraint {
+                    Some(_) => DECLARED_COST,
+                    None => UNDECLARED_COST,
+                };
+                let candidate = (cost + step, hops + 1);
+                if !connected[next] && best[next].is_none_or(|b| candidate < b) {
+                    best[next] = Some(candidate);
+                    via[next] = Some((node, link));
+                    queue.push(Reverse((candidate.0, candidate.1, next)));
+                }
+            }
+        }
+        let Some(target) = reached else {
+            break;
+        };
+
+        let mut path = Vec::new();
+        let mut node = target;
+        while let Some((previous, link)) = via[node] {
+            path.push((node, link));
+            node = previous;
+        }
+        for (node, link) in path.into_iter().rev() {
+            connected[node] = true;
+            plan.tables.push(nodes[node].clone());
+            plan.links.push(links[link].clone());
+        }
+        remaining.retain(|node| !connected[*node]);
+    }
+    plan.unconnected = remaining.into_iter().map(|n| nodes[n].clone()).collect();
+    plan
+}
+
+/// Short aliases for `tables`, from the initials of their names' words.
+pub(crate) fn aliases(tables: &[TableName]) -> Vec<String> {
+    let mut aliases: Vec<String> = Vec::new();
+    for (_, table) in tables {
+        let initials: String = table
+            .split('_')
+            .filter_map(|word| word.chars().next())
+            .filter(char::is_ascii_alphabetic)
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        let base = if initials.is_empty() {
+            "t".to_string()
+        } else {
+            initials
+        };
+        let mut alias = base.clone();
+        let mut suffix = 2;
+        while aliases.contains(&alias) || is_reserved(&alias) {
+            alias = format!("{}{}", base, suffix);
+            suffix += 1;
+        }
+        aliases.push(alias);
+    }
+    aliases
+}
+
+/// `ident` as written in SQL: bare when it can be, quoted otherwise.
+pub(crate) fn sql_ident(ident: &str) -> String {
+    let bare = ident
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && ident
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && !is_reserved(ident);
+    if bare {
+        ident.to_string()
+    } else {
+        format!("\"{}\"", ident.replace('"', "\"\""))
+    }
+}
+
+fn is_reserved(word: &str) -> bool {
+    RESERVED_WORDS
+        .split_whitespace()
+        .any(|reserved| reserved == word)
+}
+
+/// The singular of an English plural table name, near enough for matching
+/// column names: `categories` to `category`, `addresses` to `address`,
+/// `users` to `user`.
+fn singular(name: &str) -> String {
+    if let Some(stem) = name.strip_suffix("ies") {
+        format!("{}y", stem)
+    } else if ["sses", "shes", "ches", "xes"]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+    {
+        name[..name.len() - 2].to_string()
+    } else if name.ends_with('s') && !name.ends_with("ss") {
+        name[..name.len() - 1].to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Whether columns of these types can be joined on without a cast that
+/// would suggest they hold different things.
+fn compatible_types(a: &str, b: &str) -> bool {
+    const INTEGERS: &[&str] = &["smallint", "integer", "bigint"];
+    a == b || (INTEGERS.contains(&a) && INTEGERS.contains(&b))
+}
@@ -0,0 +1,290 @@
+//! Log output configuration.
+//!
+//! Logs go to stderr, or to `LOG_FILE` with size-based rotation, and never to
+//! stdout, which carries the JSON-RPC stream. `LOG_FORMAT` selects plain
+//! text, multi-line pretty output, or one JSON object per line for log
+//! shippers, and `LOG_LEVEL` sets the level globally or per module.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, anyhow};
+use serde_json::{Map, Value, json};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{self, FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::config;
+
+const DEFAULT_MAX_FILE_MB: u64 = 10;
+const DEFAULT_MAX_FILES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LogFormat {
+    /// One line per event, as `tracing_subscriber` formats it by default.
+    Text,
+    /// Multi-line, human-oriented output.
+    Pretty,
+    /// One JSON object per line.
+    Json,
+}
+
+impl LogFormat {
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Some(Self::Text),
+            "pretty" => Some(Self::Pretty),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct LogConfig {
+    format: LogFormat,
+    filter: Targets,
+    file: Option<PathBuf>,
+    /// Size at which the log file is rotated; `None` never rotates.
+    max_file_bytes: Option<u64>,
+    max_files: usize,
+}
+
+impl LogConfig {
+    /// Reads `LOG_FORMAT`, `LOG_LEVEL` (falling back to `RUST_LOG`),
+    /// `LOG_FILE`, `LOG_MAX_FILE_MB`, and `LOG_MAX_FILES`.
+    pub(crate) fn from_env() -> Result<Self> {
+        let format = match config::var("LOG_FORMAT") {
+            Ok(v) => LogFormat::parse(&v).with_context(|| {
+                format!("LOG_FORMAT must be text, pretty, or json, not '{}'", v)
+            })?,
+            Err(_) => LogFormat::Text,
+        };
+        let directives = config::var("LOG_LEVEL")
+            .or_else(|_| env::var("RUST_LOG"))
+            .unwrap_or_else(|_| "info".to_string());
+        let filter = directives.parse::<Targets>().map_err(|e| {
+            anyhow!(
+                "LOG_LEVEL must be a level or directives such as 'info,tokio_postgres=warn': {}",
+                e
+            )
+        })?;
+        let max_file_mb = match config::var("LOG_MAX_FILE_MB") {
+            Ok(v) => v
+                .parse::<u64>()
+                .context("LOG_MAX_FILE_MB must be a number of megabytes")?,
+            Err(_) => DEFAULT_MAX_FILE_MB,
+        };
+        let max_files = match config::var("LOG_MAX_FILES") {
+            Ok(v) => v
+                .parse::<usize>()
+                .context("LOG_MAX_FILES must be a number of files")?,
+            Err(_) => DEFAULT_MAX_FILES,
+        };
+        Ok(Self {
+            format,
+            filter,
+            file: config::var("LOG_FILE")
+                .ok()
+                .filter(|f| !f.is_empty())
+                .map(PathBuf::from),
+            max_file_bytes: Some(max_file_mb * 1024 * 1024).filter(|b| *b > 0),
+            max_files,
+        })
+    }
+
+    /// Opens `path` for appending, rotated with the same limits as `LOG_FILE`.
+    pub(crate) fn open_rotating(&self, path: &Path) -> Result<RotatingFile> {
+        RotatingFile::open(path, self.max_file_bytes, self.max_files)
+    }
+}
+
+/// The current time in RFC 3339 format, as written in log entries.
+pub(crate) fn timestamp() -> String {
+    let mut timestamp = String::new();
+    let _ = SystemTime.format_time(&mut Writer::new(&mut timestamp));
+    timestamp
+}
+
+/// Installs the global subscriber described by `config`.
+pub(crate) fn init(config: LogConfig) -> Result<()> {
+    let (writer, ansi) = match &config.file {
+        Some(path) => {
+            let file = config.open_rotating(path)?;
+            (BoxMakeWriter::new(Arc::new(file)), false)
+        }
+        None => (BoxMakeWriter::new(io::stderr), io::stderr().is_terminal()),
+    };
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    tracing_subscriber::registry()
+        .with(config.filter)
+        .with(match config.format {
+            LogFormat::Text => layer.boxed(),
+            LogFormat::Pretty => layer.pretty().boxed(),
+            LogFormat::Json => layer.event_format(JsonFormat).boxed(),
+        })
+        .try_init()
+        .context("Failed to install the log subscriber")
+}
+
+/// Formats each event as a JSON object with `timestamp`, `level`, `target`,
+/// and the event's fields, including `message`.
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let mut fields = JsonFields(Map::new());
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        let mut line = json!({
+            "timestamp": timestamp(),
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+        });
+        if let Value::Object(line) = &mut line {
+            line.extend(fields.0);
+        }
+        writeln!(writer, "{}", line)
+    }
+}
+
+struct JsonFields(Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+/// A log file that is renamed to `<file>.1` once it reaches `max_bytes`,
+/// shifting older files up to `<file>.<max_files>` and deleting the oldest.
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    max_files: usize,
+    state: Mutex<RotatingState>,
+}
+
+struct RotatingState {
+    /// `None` between closing a full file and opening the next one.
+    file: Option<File>,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: Option<u64>, max_files: usize) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let file =
+            append(path).with_context(|| format!("Failed to open log file {}", path.display()))?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_files,
+            state: Mutex::new(RotatingState {
+                file: Some(file),
+                written,
+            }),
+        })
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self, state: &mut RotatingState) -> io::Result<()> {
+        // Close the file first: Windows cannot rename a file that is open.
+        if let Some(mut file) = state.file.take() {
+            file.flush()?;
+        }
+        state.written = 0;
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path);
+        }
+        let _ = fs::remove_file(self.rotated_path(self.max_files));
+        for n in (1..self.max_files).rev() {
+            let from = self.rotated_path(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Write for &RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(max_bytes) = self.max_bytes
+            && state.written > 0
+            && state.written + buf.len() as u64 > max_bytes
+        {
+            self.rotate(&mut state)?;
+        }
+        let file = match &mut state.file {
+            Some(file) => file,
+            None => state.file.insert(append(&self.path)?),
+        };
+        let n = file.write(buf)?;
+        state.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match &mut state.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,11 +1,113 @@
-use anyhow::{Context, Result};
+// Connectors are cloned for each connection; without the `tls` feature the
+// connector is `NoTls`, which is `Copy`.
+#![cfg_attr(not(feature = "tls"), allow(clippy::clone_on_copy))]
+
+use access::TableAccess;
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use blob::BlobKind;
+use branch::BranchProvider;
+use catalog::{Catalog, CatalogSettings};
+use chunk::ByteRange;
+use deadpool_postgres::{Object, Pool};
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use serde_json::{Value, json};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::tokenizer::{Token, Tokenizer};
 use std::collections::HashMap;
 use std::env;
-use std::io::{self, BufRead, Write};
-use tokio_postgres::{Client, NoTls};
-use tracing::{error, info};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_postgres::error::ErrorPosition;
+use tokio_postgres::types::{FromSql, Type};
+use tokio_postgres::{CancelToken, Client, Config, Row};
+use tracing::{debug, error, info, warn};
+
+mod access;
+mod attribution;
+mod blob;
+mod branch;
+mod cancel;
+mod catalog;
+mod chaos;
+mod chunk;
+mod classify;
+mod cli;
+mod compat;
+mod compression;
+mod config;
+mod conninfo;
+mod cursor;
+mod deadline;
+mod environment;
+mod export;
+mod fixtures;
+mod flavor;
+mod format;
+mod hooks;
+#[cfg(feature = "http")]
+mod http;
+mod identity;
+mod joins;
+mod logging;
+mod metrics;
+mod outbound;
+mod params;
+mod plugins;
+mod pool;
+mod pooler;
+mod prompts;
+mod queue;
+mod reconnect;
+mod result_limits;
+mod results;
+mod sandbox;
+mod server_info;
+mod server_version;
+mod serverless;
+mod settings;
+mod soft_delete;
+mod spool;
+mod stale_stats;
+mod subscriptions;
+mod telemetry;
+mod tls;
+mod tools;
+mod transaction;
+mod usage;
+mod values;
+mod wide_results;
+mod wirelog;
+
+use attribution::Attribution;
+use cancel::Cancellation;
+use chaos::{Chaos, Fault};
+use cli::{CliArgs, Transport};
+use compat::CompatMode;
+use cursor::Cursors;
+use environment::Environment;
+use flavor::DatabaseFlavor;
+use format::ResultFormat;
+use identity::SessionIdentity;
+use pool::PoolSettings;
+use pooler::Prepared;
+use queue::{QueueLimits, RequestQueue};
+use result_limits::ResultLimits;
+use results::MaterializedResults;
+use sandbox::Sandboxes;
+use server_version::ServerVersion;
+use serverless::Serverless;
+use soft_delete::SoftDeleteFilters;
+use spool::Spool;
+use subscriptions::{SubscriptionSettings, Subscriptions};
+use tls::{Connector, TlsSettings};
+use transaction::Transactions;
+use usage::Usage;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct JsonRpcRequest {
@@ -33,422 +135,2322 @@ struct JsonRpcError {
     data: Option<Value>,
 }
 
+impl JsonRpcResponse {
+    fn success(id: Option<Value>, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    fn error(id: Option<Value>, code: i32, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(JsonRpcError {
+                code,
+                message: message.into(),
+                data: None,
+            }),
+        }
+    }
+
+    /// An error for a request that could not get a connection, marked
+    /// retryable when the database may just be restarting.
+    fn connect_error(id: Option<Value>, e: &anyhow::Error) -> Self {
+        let mut response = Self::error(
+            id,
+            -32603,
+            format!("Failed to connect to database: {:#}", e),
+        );
+        if reconnect::is_retryable(e)
+            && let Some(error) = response.error.as_mut()
+        {
+            error.data = Some(json!({ "type": "connection_error", "retryable": true }));
+        }
+        response
+    }
+
+    /// An error for the failed statement `e`, with PostgreSQL's report of it
+    /// as data when there is one.
+    fn db_error(id: Option<Value>, message: impl Into<String>, e: &tokio_postgres::Error) -> Self {
+        let mut response = Self::error(id, -32603, message);
+        if let Some(error) = response.error.as_mut() {
+            error.data = db_error_data(e);
+        }
+        response
+    }
+}
+
+/// A request rejected by server policy. The error data names the rule, what
+/// matched it, and the configuration change that would allow the request, so
+/// policy can be tuned from the error message alone.
+#[derive(Debug)]
+struct PolicyDenial {
+    rule: &'static str,
+    matched: String,
+    message: String,
+    remedy: String,
+}
+
+impl PolicyDenial {
+    fn into_response(self, id: Option<Value>) -> JsonRpcResponse {
+        let mut response =
+            JsonRpcResponse::error(id, -32603, format!("{}. {}", self.message, self.remedy));
+        if let Some(error) = response.error.as_mut() {
+            error.data = Some(json!({
+                "type": "policy_denied",
+                "rule": self.rule,
+                "matched": self.matched,
+                "remedy": self.remedy,
+            }));
+        }
+        response
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonRpcNotification {
+    jsonrpc: String,
+    method: String,
+    params: Value,
+}
+
+impl JsonRpcNotification {
+    /// Builds an MCP `notifications/message` log notification.
+    fn log(level: &str, data: impl Into<Value>) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            method: "notifications/message".to_string(),
+            params: json!({
+                "level": level,
+                "logger": "postgres-mcp-server",
+                "data": data.into(),
+            }),
+        }
+    }
+
+    /// Builds an MCP `notifications/progress` notification.
+    fn progress(token: Value, progress: usize, total: usize, message: &str) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            method: "notifications/progress".to_string(),
+            params: json!({
+                "progressToken": token,
+                "progress": progress,
+                "total": total,
+                "message": message,
+            }),
+        }
+    }
+
+    /// Builds an MCP `notifications/tools/list_changed` notification.
+    fn tools_list_changed() -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            method: "notifications/tools/list_changed".to_string(),
+            params: json!({}),
+        }
+    }
+
+    /// Builds an MCP `notifications/resources/updated` notification.
+    fn resource_updated(uri: &str) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            method: "notifications/resources/updated".to_string(),
+            params: json!({ "uri": uri }),
+        }
+    }
+}
+
+/// Where notifications are written.
+#[derive(Clone)]
+enum NotificationSink {
+    /// A channel of serialized messages, which the transport writes out.
+    Channel(mpsc::UnboundedSender<String>),
+    /// No client is listening.
+    Discard,
+}
+
+impl NotificationSink {
+    /// Writes a notification to the client between responses.
+    fn send(&self, notification: &JsonRpcNotification) {
+        let NotificationSink::Channel(sender) = self else {
+            return;
+        };
+        wirelog::outbound(notification);
+        match serde_json::to_string(notification) {
+            // The stream closes when its client disconnects; nothing to do.
+            Ok(line) => {
+                let _ = sender.send(line);
+            }
+            Err(e) => error!("Failed to write notification: {}", e),
+        }
+    }
+}
+
 struct McpServer {
     database_url: String,
+    tls_settings: TlsSettings,
+    tls: Connector,
     allow_write_ops: bool,
-    client: Option<Client>,
+    idle_timeout: Option<Duration>,
+    slow_query_threshold: Option<Duration>,
+    /// Time limit for `query` tool statements, and the most `timeout_ms`
+    /// may ask for.
+    query_timeout: Option<Duration>,
+    /// Whether `query` results carry the planner's estimate for the query.
+    cost_estimates: bool,
+    /// Days after which a changed table's statistics count as stale, if
+    /// `query` checks them.
+    stale_stats_days: Option<u64>,
+    /// The most columns a `query` result shows unless it names the columns
+    /// it wants; `None` for no limit.
+    max_result_columns: Option<usize>,
+    /// The most rows and bytes of rows a `query` result returns.
+    result_limits: ResultLimits,
+    export_dir: Option<PathBuf>,
+    /// Where `load_fixtures` reads fixture sets from.
+    fixtures_dir: Option<PathBuf>,
+    /// The `DB_ENVIRONMENT` tag, if the connection has one.
+    environment: Option<Environment>,
+    /// Failures injected on purpose, with `CHAOS_FAULTS`.
+    chaos: Option<Chaos>,
+    /// How `create_branch` copies the database, if it is offered.
+    branching: Option<BranchProvider>,
+    /// How long connecting may take, if the database suspends when idle.
+    serverless: Option<Serverless>,
+    /// How long checkouts retry while the database is unreachable, if they
+    /// do.
+    reconnect_timeout: Option<Duration>,
+    compat_mode: CompatMode,
+    identity: Option<SessionIdentity>,
+    /// Who the request being handled is made by and for.
+    attribution: Attribution,
+    /// The session's tool calls and table accesses, for `usage_stats`.
+    usage: Usage,
+    flavor: DatabaseFlavor,
+    /// `None` until connected, or when the server does not report it.
+    server_version: Option<ServerVersion>,
+    pool: Pool,
+    pool_settings: PoolSettings,
+    /// Whether `pool` is shared with other sessions.
+    shares_pool: bool,
+    soft_delete: SoftDeleteFilters,
+    /// Tables hidden by `ALLOWED_TABLES` and `DENIED_TABLES`.
+    access: TableAccess,
+    /// The flavor and version detected on the session's first connection,
+    /// once made.
+    connected: Arc<OnceLock<(DatabaseFlavor, Option<ServerVersion>)>>,
+    /// The extensions installed in the database, once looked up, which
+    /// decide the extension tools offered.
+    extensions: Arc<Mutex<Option<Vec<String>>>>,
+    /// The connection checked out for the request being handled.
+    client: Option<Object>,
+    /// The progress token of the request being handled, if it asked for
+    /// progress notifications.
+    progress_token: Option<Value>,
+    /// When the transport received the request being handled.
+    received: Instant,
+    /// When the client stops waiting for the request being handled, as the
+    /// transport and then the request's `_meta` set it.
+    deadline: Option<Instant>,
+    /// The failure injected into the request being handled, if any.
+    fault: Option<Fault>,
+    /// Time zone chosen with `set_session_timezone`, applied to each
+    /// connection as it is checked out.
+    session_timezone: Arc<Mutex<Option<String>>>,
+    /// Cursors of paged `query` results, each holding a connection.
+    cursors: Cursors,
+    /// Transactions begun with `begin_transaction`, each holding a
+    /// connection.
+    transactions: Transactions,
+    /// Schemas the session made as sandboxes, dropped when it goes idle.
+    sandboxes: Sandboxes,
+    /// Query results written to disk by `export_query`.
+    spool: Spool,
+    /// Query results kept in memory by `materialize_result`.
+    results: MaterializedResults,
+    /// The smallest result payload compressed for clients that accept it.
+    compression_min_bytes: Option<usize>,
+    /// Tools turned off with `DISABLED_TOOLS`.
+    disabled_tools: Vec<String>,
+    /// Table resources the client subscribed to.
+    subscriptions: Subscriptions,
+    /// URIs of subscribed resources that changed, until the transport takes
+    /// it to forward them to the client.
+    resource_updates: Option<mpsc::UnboundedReceiver<String>>,
+    /// The request being handled, for the client to cancel.
+    cancellation: Cancellation,
+    notifications: NotificationSink,
 }
 
 impl McpServer {
-    fn new() -> Result<Self> {
-        let database_url = env::var("DATABASE_URL")
-            .context("DATABASE_URL environment variable not set")?;
+    fn new(database_url: &str) -> Result<Self> {
+        Self::with_identity(database_url, SessionIdentity::from_env()?, None)
+    }
+
+    /// A server whose connections run as `identity`, rather than as
+    /// `MCP_PRINCIPAL` is mapped, taken from `pool` if given: one shared with
+    /// other sessions of the same identity.
+    fn with_identity(
+        database_url: &str,
+        identity: Option<SessionIdentity>,
+        pool: Option<Pool>,
+    ) -> Result<Self> {
+        let (database_url, tls_settings) = TlsSettings::from_url_and_env(database_url)?;
+        let tls = tls_settings.connector()?;
+
+        let allow_write_ops = config::var("DANGEROUSLY_ALLOW_WRITE_OPS")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
 
-        let allow_write_ops = env::var("DANGEROUSLY_ALLOW_WRITE_OPS")
+        let idle_timeout = match config::var("IDLE_TIMEOUT_SECS") {
+            Ok(v) => match v
+                .parse::<u64>()
+                .context("IDLE_TIMEOUT_SECS must be a number of seconds")?
+            {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            Err(_) => None,
+        };
+
+        let slow_query_threshold = match config::var("SLOW_QUERY_THRESHOLD_MS") {
+            Ok(v) => match v
+                .parse::<u64>()
+                .context("SLOW_QUERY_THRESHOLD_MS must be a number of milliseconds")?
+            {
+                0 => None,
+                ms => Some(Duration::from_millis(ms)),
+            },
+            Err(_) => None,
+        };
+
+        let query_timeout = match config::var("QUERY_TIMEOUT_MS") {
+            Ok(v) => match v
+                .parse::<u64>()
+                .context("QUERY_TIMEOUT_MS must be a number of milliseconds")?
+            {
+                0 => None,
+                ms => Some(Duration::from_millis(ms)),
+            },
+            Err(_) => None,
+        };
+
+        let cost_estimates = config::var("QUERY_COST_ESTIMATES")
             .map(|v| v == "true" || v == "1")
             .unwrap_or(false);
+        let stale_stats_days = stale_stats::max_age_from_env()?;
+        let max_result_columns = wide_results::max_columns_from_env()?;
+        let result_limits = ResultLimits::from_env()?;
+
+        let compat_mode = config::var("COMPAT_MODE").unwrap_or_default();
+        let compat_mode = CompatMode::parse(&compat_mode).with_context(|| {
+            format!(
+                "Unknown COMPAT_MODE '{}' (expected 'native' or 'typescript')",
+                compat_mode
+            )
+        })?;
+
+        let export_dir = config::var("EXPORT_DIR")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+        let fixtures_dir = config::var("FIXTURES_DIR")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+        let environment = Environment::from_env()?;
+        let allow_write_ops = if allow_write_ops && environment == Some(Environment::Production) {
+            warn!(
+                "DANGEROUSLY_ALLOW_WRITE_OPS is ignored, since DB_ENVIRONMENT is production; writes stay disabled"
+            );
+            false
+        } else {
+            allow_write_ops
+        };
+
+        let chaos = Chaos::from_env(environment)?;
+        if let Some(chaos) = &chaos {
+            warn!("Chaos mode: injecting {}", chaos.describe());
+        }
+
+        let branching = BranchProvider::from_env()?;
+
+        let config = connection_config(&database_url, &tls_settings)?;
+        let pool_settings = PoolSettings::from_env()?;
+        let serverless = Serverless::from_env(&config)?;
+        if let Some(serverless) = &serverless {
+            info!(
+                "Serverless database: connecting may take up to {}s while it resumes",
+                serverless.resume_timeout.as_secs()
+            );
+        }
+        let reconnect_timeout = reconnect::timeout_from_env()?;
+        let soft_delete = SoftDeleteFilters::from_env()?;
+        let access = TableAccess::from_env()?;
+        let spool = Spool::from_env()?;
+        let transactions = Transactions::new(transaction::timeout_from_env()?);
+        let results = MaterializedResults::from_env()?;
+        let compression_min_bytes = compression::min_bytes_from_env()?;
+        let disabled_tools = tools::disabled_tools_from_env()?;
+        let (subscriptions, resource_updates) =
+            Subscriptions::new(SubscriptionSettings::from_env()?);
+        let shares_pool = pool.is_some();
+        let pool = match pool {
+            Some(pool) => pool,
+            None => {
+                let pool = pool::build_pool(config, tls.clone(), &pool_settings, identity.clone())?;
+                metrics::register_pool(&pool);
+                pool
+            }
+        };
+
+        let cancellation = Cancellation::new(tls.clone());
 
         Ok(Self {
             database_url,
+            tls_settings,
+            tls,
             allow_write_ops,
+            idle_timeout,
+            slow_query_threshold,
+            query_timeout,
+            cost_estimates,
+            stale_stats_days,
+            max_result_columns,
+            result_limits,
+            export_dir,
+            fixtures_dir,
+            environment,
+            chaos,
+            branching,
+            serverless,
+            reconnect_timeout,
+            compat_mode,
+            identity,
+            attribution: Attribution::default(),
+            usage: Usage::new(),
+            flavor: DatabaseFlavor::PostgreSQL,
+            server_version: None,
+            pool,
+            pool_settings,
+            shares_pool,
+            soft_delete,
+            access,
+            connected: Arc::new(OnceLock::new()),
+            extensions: Arc::new(Mutex::new(None)),
             client: None,
+            progress_token: None,
+            received: Instant::now(),
+            deadline: None,
+            fault: None,
+            session_timezone: Arc::new(Mutex::new(None)),
+            cursors: Cursors::new(),
+            transactions,
+            sandboxes: Sandboxes::from_env(),
+            spool,
+            results,
+            compression_min_bytes,
+            disabled_tools,
+            subscriptions,
+            resource_updates: Some(resource_updates),
+            cancellation,
+            notifications: NotificationSink::Discard,
         })
     }
 
-    async fn connect(&mut self) -> Result<()> {
-        let (client, connection) = tokio_postgres::connect(&self.database_url, NoTls)
-            .await
-            .context("Failed to connect to PostgreSQL")?;
-
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                error!("Connection error: {}", e);
-            }
-        });
-
-        self.client = Some(client);
-        info!("Connected to PostgreSQL");
-        Ok(())
-    }
-
-    async fn handle_request(&mut self, request: JsonRpcRequest) -> JsonRpcResponse {
-        match request.method.as_str() {
-            "initialize" => self.handle_initialize(request.id),
-            "tools/list" => self.handle_tools_list(request.id),
-            "tools/call" => self.handle_tools_call(request.id, request.params).await,
-            "resources/list" => self.handle_resources_list(request.id).await,
-            "resources/read" => self.handle_resources_read(request.id, request.params).await,
-            _ => JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id: request.id,
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32601,
-                    message: format!("Method not found: {}", request.method),
-                    data: None,
-                }),
-            },
+    /// A copy of the server to handle one request alongside others. It
+    /// shares the session's settings, cursors, transactions, sandboxes,
+    /// results, and subscriptions, and checks out a connection of its own.
+    fn for_request(&self) -> Self {
+        Self {
+            database_url: self.database_url.clone(),
+            tls_settings: self.tls_settings.clone(),
+            tls: self.tls.clone(),
+            allow_write_ops: self.allow_write_ops,
+            idle_timeout: self.idle_timeout,
+            slow_query_threshold: self.slow_query_threshold,
+            query_timeout: self.query_timeout,
+            cost_estimates: self.cost_estimates,
+            stale_stats_days: self.stale_stats_days,
+            max_result_columns: self.max_result_columns,
+            result_limits: self.result_limits,
+            export_dir: self.export_dir.clone(),
+            fixtures_dir: self.fixtures_dir.clone(),
+            environment: self.environment,
+            chaos: self.chaos.clone(),
+            branching: self.branching.clone(),
+            serverless: self.serverless,
+            reconnect_timeout: self.reconnect_timeout,
+            compat_mode: self.compat_mode,
+            identity: self.identity.clone(),
+            attribution: self.attribution.clone(),
+            usage: self.usage.clone(),
+            flavor: self.connected.get().map_or(self.flavor, |c| c.0),
+            server_version: self.connected.get().map_or(self.server_version, |c| c.1),
+            pool: self.pool.clone(),
+            pool_settings: self.pool_settings,
+            shares_pool: self.shares_pool,
+            soft_delete: self.soft_delete.clone(),
+            access: self.access.clone(),
+            connected: self.connected.clone(),
+            extensions: self.extensions.clone(),
+            client: None,
+            progress_token: None,
+            received: Instant::now(),
+            deadline: None,
+            fault: None,
+            session_timezone: self.session_timezone.clone(),
+            cursors: self.cursors.clone(),
+            transactions: self.transactions.clone(),
+            sandboxes: self.sandboxes.clone(),
+            spool: self.spool.clone(),
+            results: self.results.clone(),
+            compression_min_bytes: self.compression_min_bytes,
+            disabled_tools: self.disabled_tools.clone(),
+            subscriptions: self.subscriptions.clone(),
+            resource_updates: None,
+            cancellation: self.cancellation.clone(),
+            notifications: self.notifications.clone(),
         }
     }
 
-    fn handle_initialize(&self, id: Option<Value>) -> JsonRpcResponse {
-        JsonRpcResponse {
-            jsonrpc: "2.0".to_string(),
-            id,
-            result: Some(json!({
-                "protocolVersion": "2024-11-05",
-                "capabilities": {
-                    "tools": {},
-                    "resources": {}
-                },
-                "serverInfo": {
-                    "name": "postgres-mcp-server",
-                    "version": "0.1.0"
-                }
-            })),
-            error: None,
+    /// Closes the session's cursors, transactions, and pooled connections,
+    /// for shutdown. Connections still in use close as their requests
+    /// finish.
+    fn close(&self) {
+        let cursors = self.cursors.close_all();
+        let transactions = self.transactions.close_all();
+        self.pool.close();
+        if transactions > 0 {
+            warn!(
+                "Rolled back {} open transaction(s) as the session ended",
+                transactions
+            );
+        }
+        if cursors > 0 {
+            info!(
+                "Closed database connections and {} unfinished query cursor(s)",
+                cursors
+            );
+        } else {
+            info!("Closed database connections");
         }
     }
 
-    fn handle_tools_list(&self, id: Option<Value>) -> JsonRpcResponse {
-        JsonRpcResponse {
-            jsonrpc: "2.0".to_string(),
-            id,
-            result: Some(json!({
-                "tools": [
-                    {
-                        "name": "query",
-                        "description": "Execute a SQL query against the PostgreSQL database",
-                        "inputSchema": {
-                            "type": "object",
-                            "properties": {
-                                "sql": {
-                                    "type": "string",
-                                    "description": "SQL query to execute"
-                                }
-                            },
-                            "required": ["sql"]
-                        }
-                    }
-                ]
-            })),
-            error: None,
+    /// Releases resources held on behalf of an idle session, returning a
+    /// description of what was cleaned up, if anything.
+    async fn release_idle_resources(&mut self) -> Option<String> {
+        let idle_timeout = self.idle_timeout?;
+        let transactions = self.transactions.close_all();
+        let cursors = self.cursors.close_all();
+        let sandboxes = self.drop_sandboxes().await;
+        // A shared pool is trimmed by the transport, once no session needs
+        // its connections.
+        let closed = if self.shares_pool {
+            0
+        } else {
+            pool::trim(&self.pool, self.pool_settings.min_size)
+        };
+
+        let mut released = Vec::new();
+        if transactions > 0 {
+            released.push(format!(
+                "rolled back {} open transaction(s) and closed their connections",
+                transactions
+            ));
+        }
+        if cursors > 0 {
+            released.push(format!(
+                "closed {} unfinished query cursor(s) and their connections",
+                cursors
+            ));
         }
+        if sandboxes > 0 {
+            released.push(format!("dropped {} sandbox schema(s)", sandboxes));
+        }
+        if closed > 0 {
+            released.push(format!(
+                "closed {} database connection(s) (they reopen on the next tool call)",
+                closed
+            ));
+        }
+        let last = released.pop()?;
+        let released = if released.is_empty() {
+            last
+        } else {
+            format!("{}, and {}", released.join(", "), last)
+        };
+        let message = format!("Session idle for {}s: {}", idle_timeout.as_secs(), released);
+        info!("{}", message);
+        Some(message)
     }
 
-    async fn handle_tools_call(
-        &mut self,
-        id: Option<Value>,
-        params: Option<Value>,
-    ) -> JsonRpcResponse {
-        if self.client.is_none() {
-            if let Err(e) = self.connect().await {
-                return JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id,
-                    result: None,
-                    error: Some(JsonRpcError {
-                        code: -32603,
-                        message: format!("Failed to connect to database: {}", e),
-                        data: None,
-                    }),
-                };
-            }
+    /// Drops the session's sandbox schemas, returning how many were dropped.
+    async fn drop_sandboxes(&self) -> usize {
+        if self.sandboxes.is_empty() {
+            return 0;
         }
-
-        let params = match params {
-            Some(p) => p,
-            None => {
-                return JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id,
-                    result: None,
-                    error: Some(JsonRpcError {
-                        code: -32602,
-                        message: "Missing parameters".to_string(),
-                        data: None,
-                    }),
-                }
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Failed to connect to drop sandbox schemas: {}", e);
+                return 0;
             }
         };
+        let dropped = self.sandboxes.drop_all(&client).await;
+        if dropped > 0
+            && let Some(catalog) = Catalog::shared()
+        {
+            catalog.schema_changed();
+        }
+        dropped
+    }
 
-        let tool_name = params
-            .get("name")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-        let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+    /// Whether the session holds connections, transactions, or sandbox
+    /// schemas that idle cleanup would release.
+    fn has_idle_resources(&self) -> bool {
+        (!self.shares_pool && self.pool.status().size > self.pool_settings.min_size)
+            || !self.cursors.is_empty()
+            || self.transactions.len() > 0
+            || !self.sandboxes.is_empty()
+    }
 
-        match tool_name {
-            "query" => self.execute_query(id, arguments).await,
-            _ => JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id,
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32602,
-                    message: format!("Unknown tool: {}", tool_name),
-                    data: None,
-                }),
-            },
-        }
+    /// The receiver of the URIs of subscribed resources that changed, for the
+    /// transport to forward to the client. Can be taken once.
+    fn take_resource_updates(&mut self) -> mpsc::UnboundedReceiver<String> {
+        self.resource_updates
+            .take()
+            .unwrap_or_else(|| mpsc::unbounded_channel().1)
     }
 
-    async fn execute_query(&self, id: Option<Value>, arguments: Value) -> JsonRpcResponse {
-        let sql = match arguments.get("sql").and_then(|v| v.as_str()) {
-            Some(s) => s,
-            None => {
-                return JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id,
-                    result: None,
-                    error: Some(JsonRpcError {
-                        code: -32602,
-                        message: "Missing 'sql' parameter".to_string(),
-                        data: None,
-                    }),
-                }
-            }
+    /// Writes a notification to the client between responses.
+    fn notify(&self, notification: &JsonRpcNotification) {
+        self.notifications.send(notification);
+    }
+
+    /// Awaits `query`, returning `None` if it runs longer than `timeout`, in
+    /// which case the statement is cancelled on the server. The statement is
+    /// also cancelled if the client cancels the request, and not started at
+    /// all, returning `None`, if it already has.
+    async fn with_timeout<T>(
+        &self,
+        cancel_token: CancelToken,
+        timeout: Option<Duration>,
+        query: impl Future<Output = T>,
+    ) -> Option<T> {
+        if !self.cancellation.statement_started(cancel_token.clone()) {
+            return None;
+        }
+        let result = match timeout {
+            None => Some(query.await),
+            Some(timeout) => tokio::time::timeout(timeout, query).await.ok(),
         };
+        self.cancellation.statement_finished();
+        if result.is_none()
+            && let Err(e) = cancel_token.cancel_query(self.tls.clone()).await
+        {
+            error!("Failed to cancel timed out query: {}", e);
+        }
+        result
+    }
 
-        if !self.allow_write_ops && is_write_query(sql) {
-            return JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id,
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32603,
-                    message: "Write operations are not allowed. Set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.".to_string(),
-                    data: None,
-                }),
-            };
+    /// Reports a query that ran longer than the slow query threshold, both as
+    /// a client log notification and in the server log.
+    fn report_slow_query(&self, sql: &str, elapsed: Duration) {
+        let Some(threshold) = self.slow_query_threshold else {
+            return;
+        };
+        if elapsed < threshold {
+            return;
         }
+        let fingerprint = fingerprint_sql(sql);
+        let duration_ms = elapsed.as_millis() as u64;
+        warn!(
+            slow_query = true,
+            duration_ms,
+            threshold_ms = threshold.as_millis() as u64,
+            fingerprint = %fingerprint,
+            "Slow query"
+        );
+        self.report_slow_query_hook(&fingerprint, duration_ms, threshold.as_millis() as u64);
+        // The reference server does not advertise logging, so compatibility
+        // mode only records slow queries in the server log.
+        if self.compat_mode == CompatMode::TypeScript {
+            return;
+        }
+        self.notify(&JsonRpcNotification::log(
+            "warning",
+            json!({
+                "event": "slow_query",
+                "fingerprint": fingerprint,
+                "duration_ms": duration_ms,
+                "threshold_ms": threshold.as_millis() as u64,
+            }),
+        ));
+    }
 
-        let client = self.client.as_ref().unwrap();
+    /// Checks a connection out of the pool for the current request. The
+    /// first connection also detects the database flavor and opens the
+    /// pool's minimum number of connections.
+    async fn connect(&mut self) -> Result<()> {
+        let client = match self.serverless {
+            Some(serverless) => self.checkout_resuming(serverless).await,
+            None => self.checkout_reconnecting().await,
+        }
+        .context("Failed to connect to PostgreSQL")?;
 
-        match client.query(sql, &[]).await {
-            Ok(rows) => {
-                let mut results = Vec::new();
-                for row in rows {
-                    let mut row_map = HashMap::new();
-                    for (idx, column) in row.columns().iter().enumerate() {
-                        let value: Value = match column.type_().name() {
-                            "int4" => row.get::<_, Option<i32>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            "int8" => row.get::<_, Option<i64>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            "text" | "varchar" => row.get::<_, Option<String>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            "bool" => row.get::<_, Option<bool>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            "float4" => row.get::<_, Option<f32>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            "float8" => row.get::<_, Option<f64>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            _ => Value::Null,
-                        };
-                        row_map.insert(column.name().to_string(), value);
-                    }
-                    results.push(row_map);
+        if let Some((flavor, server_version)) = self.connected.get() {
+            self.flavor = *flavor;
+            self.server_version = *server_version;
+        } else {
+            let version: String = pooler::query_one(&client, "SELECT version()", &[])
+                .await
+                .context("Failed to query server version")?
+                .get(0);
+            self.flavor = DatabaseFlavor::detect(&version);
+            // Wire-compatible databases report the PostgreSQL version they
+            // resemble, if any.
+            self.server_version =
+                pooler::query_one(&client, "SELECT current_setting('server_version_num')", &[])
+                    .await
+                    .ok()
+                    .and_then(|row| ServerVersion::parse(row.get(0)));
+            if let Some(identity) = &self.identity {
+                info!(
+                    "Session for principal '{}' runs as role '{}'",
+                    identity.principal, identity.role
+                );
+            }
+            match self.server_version {
+                Some(server_version) if self.flavor == DatabaseFlavor::PostgreSQL => {
+                    info!("Connected to PostgreSQL {}", server_version)
                 }
+                Some(server_version) => info!(
+                    "Connected to {} (PostgreSQL {})",
+                    self.flavor.name(),
+                    server_version
+                ),
+                None => info!("Connected to {}", self.flavor.name()),
+            }
+            let _ = self.connected.set((self.flavor, self.server_version));
 
-                JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id,
-                    result: Some(json!({
-                        "content": [
-                            {
-                                "type": "text",
-                                "text": serde_json::to_string_pretty(&results).unwrap_or_else(|_| "[]".to_string())
-                            }
-                        ]
-                    })),
-                    error: None,
-                }
+            let warm = self.pool_settings.min_size.saturating_sub(1);
+            let opened = futures_util::future::join_all((0..warm).map(|_| self.pool.get())).await;
+            if let Some(Err(e)) = opened.iter().find(|o| o.is_err()) {
+                warn!("Failed to open minimum pool connections: {}", e);
             }
-            Err(e) => JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id,
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32603,
-                    message: format!("Query execution error: {}", e),
-                    data: None,
-                }),
-            },
         }
-    }
 
-    async fn handle_resources_list(&self, id: Option<Value>) -> JsonRpcResponse {
-        if self.client.is_none() {
-            return JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id,
-                result: Some(json!({"resources": []})),
-                error: None,
-            };
+        let timezone = self
+            .session_timezone
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        if let Some(timezone) = &timezone {
+            pooler::execute(
+                &client,
+                "SELECT set_config('TimeZone', $1, false)",
+                &[(timezone, Type::TEXT)],
+            )
+            .await
+            .context("Failed to set the session time zone")?;
+        }
+        // The last request's name is replaced, or reset to the one the
+        // connection started with. With transaction pooling the name is set
+        // per transaction instead, by `pooler::begin`.
+        if !pooler::transaction_pooling() {
+            match self.attribution.application_name() {
+                Some(name) => pooler::execute(
+                    &client,
+                    "SELECT set_config('application_name', $1, false)",
+                    &[(&name, Type::TEXT)],
+                )
+                .await
+                .map(drop),
+                None => client.batch_execute("RESET application_name").await,
+            }
+            .context("Failed to set application_name")?;
         }
 
-        let client = self.client.as_ref().unwrap();
-
-        let query = "SELECT table_name FROM information_schema.tables WHERE table_schema = 'public'";
+        if self.fault == Some(Fault::ConnectionDrop) {
+            // The statement fails as its own connection goes away.
+            let _ = client
+                .simple_query("SELECT pg_terminate_backend(pg_backend_pid())")
+                .await;
+        }
 
-        match client.query(query, &[]).await {
-            Ok(rows) => {
-                let resources: Vec<Value> = rows
-                    .iter()
-                    .filter_map(|row| {
-                        row.get::<_, Option<String>>(0).map(|table_name| {
-                            json!({
-                                "uri": format!("postgres:///{}", table_name),
-                                "name": table_name,
-                                "description": format!("PostgreSQL table: {}", table_name),
-                                "mimeType": "application/json"
-                            })
-                        })
-                    })
-                    .collect();
+        self.client = Some(client);
+        Ok(())
+    }
 
-                JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id,
-                    result: Some(json!({"resources": resources})),
-                    error: None,
+    /// Checks a connection out of the pool of a serverless database, which
+    /// may have to resume first: the client is told if that takes a while,
+    /// and failures a resuming database causes are retried until
+    /// `resume_timeout`.
+    async fn checkout_resuming(&self, serverless: Serverless) -> Result<Object> {
+        let started = Instant::now();
+        let timeouts = self
+            .pool_settings
+            .timeouts_with_create(serverless.resume_timeout);
+        let mut attempt = 0;
+        let mut reported = false;
+        loop {
+            let checkout = self.pool.timeout_get(&timeouts);
+            tokio::pin!(checkout);
+            let result =
+                match tokio::time::timeout(serverless::RESUME_NOTICE_DELAY, &mut checkout).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        if !reported {
+                            self.report_resuming(serverless, started);
+                            reported = true;
+                        }
+                        checkout.await
+                    }
+                };
+            let e = match result {
+                Ok(client) => {
+                    if reported {
+                        info!(
+                            "Database resumed after {:.1}s",
+                            started.elapsed().as_secs_f64()
+                        );
+                    }
+                    return Ok(client);
                 }
+                Err(e) => e,
+            };
+            let remaining = serverless.resume_timeout.saturating_sub(started.elapsed());
+            if !reconnect::is_transient(&e) {
+                return Err(pool::checkout_error(e));
             }
-            Err(e) => JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id,
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32603,
-                    message: format!("Failed to list tables: {}", e),
-                    data: None,
-                }),
-            },
+            if remaining.is_zero() {
+                return Err(
+                    pool::checkout_error(e).context(reconnect::Unreachable(format!(
+                        "The database did not resume within {}s (see DB_RESUME_TIMEOUT_SECS)",
+                        serverless.resume_timeout.as_secs()
+                    ))),
+                );
+            }
+            attempt += 1;
+            debug!("Connection attempt {} failed, retrying: {}", attempt, e);
+            if !reported {
+                self.report_resuming(serverless, started);
+                reported = true;
+            }
+            tokio::time::sleep(reconnect::retry_delay(attempt).min(remaining)).await;
         }
     }
 
-    async fn handle_resources_read(
-        &self,
-        id: Option<Value>,
-        params: Option<Value>,
-    ) -> JsonRpcResponse {
-        let uri = match &params {
-            Some(p) => match p.get("uri").and_then(|u| u.as_str()) {
-                Some(u) => u.to_string(),
-                None => {
-                    return JsonRpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id,
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: -32602,
-                            message: "Missing 'uri' parameter".to_string(),
-                            data: None,
-                        }),
+    /// Checks a connection out of the pool, retrying failures a restarting
+    /// database causes until `reconnect_timeout`.
+    async fn checkout_reconnecting(&self) -> Result<Object> {
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            let e = match self.pool.get().await {
+                Ok(client) => {
+                    if attempt > 0 {
+                        info!(
+                            "Reconnected to the database after {:.1}s",
+                            started.elapsed().as_secs_f64()
+                        );
                     }
+                    return Ok(client);
                 }
-            },
-            None => {
-                return JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id,
-                    result: None,
-                    error: Some(JsonRpcError {
-                        code: -32602,
-                        message: "Missing parameters".to_string(),
-                        data: None,
-                    }),
-                }
+                Err(e) => e,
+            };
+            if !reconnect::is_transient(&e) {
+                return Err(pool::checkout_error(e));
             }
-        };
+            let Some(timeout) = self.reconnect_timeout else {
+                return Err(pool::checkout_error(e).context(reconnect::Unreachable(
+                    "The database is unreachable".to_string(),
+                )));
+            };
+            let remaining = timeout.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                return Err(
+                    pool::checkout_error(e).context(reconnect::Unreachable(format!(
+                        "The database stayed unreachable for {}s (see DB_RECONNECT_TIMEOUT_SECS)",
+                        timeout.as_secs()
+                    ))),
+                );
+            }
+            attempt += 1;
+            if attempt == 1 {
+                warn!(
+                    "Cannot reach the database, retrying for up to {}s: {}",
+                    timeout.as_secs(),
+                    e
+                );
+            } else {
+                debug!("Connection attempt {} failed, retrying: {}", attempt, e);
+            }
+            tokio::time::sleep(reconnect::retry_delay(attempt).min(remaining)).await;
+        }
+    }
 
-        let table_name = uri.trim_start_matches("postgres:///");
+    /// Tells the client the database is resuming, as progress of the request
+    /// if it asked for progress, and as a log message otherwise.
+    fn report_resuming(&self, serverless: Serverless, started: Instant) {
+        let message = format!(
+            "The database is resuming from idle; waiting up to {}s for it to accept connections",
+            serverless.resume_timeout.as_secs()
+        );
+        info!("{}", message);
+        match &self.progress_token {
+            Some(token) => self.notify(&JsonRpcNotification::progress(
+                token.clone(),
+                started.elapsed().as_secs() as usize,
+                serverless.resume_timeout.as_secs() as usize,
+                &message,
+            )),
+            // The reference server does not advertise logging.
+            None if self.compat_mode == CompatMode::TypeScript => {}
+            None => self.notify(&JsonRpcNotification::log(
+                "info",
+                json!({ "event": "database_resuming", "message": message }),
+            )),
+        }
+    }
 
+    async fn ensure_connected(&mut self) -> Result<()> {
         if self.client.is_none() {
-            return JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id,
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32603,
-                    message: "Not connected to database".to_string(),
-                    data: None,
-                }),
-            };
+            self.connect().await?;
         }
+        Ok(())
+    }
 
-        let client = self.client.as_ref().unwrap();
-        let query = format!("SELECT * FROM {} LIMIT 100", table_name);
-
-        match client.query(&query, &[]).await {
-            Ok(rows) => {
-                let mut results = Vec::new();
-                for row in rows {
-                    let mut row_map = HashMap::new();
-                    for (idx, column) in row.columns().iter().enumerate() {
-                        let value: Value = match column.type_().name() {
-                            "int4" => row.get::<_, Option<i32>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            "int8" => row.get::<_, Option<i64>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            "text" | "varchar" => row.get::<_, Option<String>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            "bool" => row.get::<_, Option<bool>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            "float4" => row.get::<_, Option<f32>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            "float8" => row.get::<_, Option<f64>>(idx).map(|v| json!(v)).unwrap_or(Value::Null),
-                            _ => Value::Null,
-                        };
-                        row_map.insert(column.name().to_string(), value);
+    async fn handle_request(&mut self, request: JsonRpcRequest) -> JsonRpcResponse {
+        telemetry::record_request(&request);
+        metrics::record_request(&request.method);
+        let summary = hooks::summarize_request(&request);
+        let id = request.id.clone();
+        self.progress_token = request
+            .params
+            .as_ref()
+            .and_then(|p| p.get("_meta"))
+            .and_then(|m| m.get("progressToken"))
+            .cloned();
+        self.attribution.set_request(request.params.as_ref());
+        if request.method == "initialize" {
+            self.attribution.set_client(request.params.as_ref());
+            self.usage.record_initialize();
+        }
+        let call = (request.method == "tools/call")
+            .then(|| usage::tool_call(request.params.as_ref()))
+            .flatten();
+        let deadline = deadline::from_meta(request.params.as_ref(), self.received)
+            .map(|deadline| deadline::earliest(self.deadline, deadline));
+        self.fault = self.chaos.as_ref().and_then(|c| c.pick(&request.method));
+        if let Some(fault) = self.fault {
+            warn!(
+                "Chaos mode: injecting {} into {}",
+                fault.name(),
+                request.method
+            );
+        }
+        self.cancellation.start(&id);
+        let application_name = self.attribution.application_name();
+        let mut response = match deadline {
+            Err(message) => JsonRpcResponse::error(id.clone(), -32602, message),
+            Ok(Some(deadline)) if deadline <= Instant::now() => {
+                deadline::exceeded_response(id.clone())
+            }
+            Ok(Some(deadline)) => {
+                let dispatch = pooler::labelled(application_name, self.dispatch(request));
+                match tokio::time::timeout_at(deadline.into(), dispatch).await {
+                    Ok(response) if Instant::now() < deadline => response,
+                    // Finished too late to be of use; answering with the
+                    // error spares serializing the result.
+                    Ok(_) => deadline::exceeded_response(id.clone()),
+                    Err(_) => {
+                        self.cancellation.interrupt();
+                        deadline::exceeded_response(id.clone())
                     }
-                    results.push(row_map);
-                }
-
-                JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id,
-                    result: Some(json!({
-                        "contents": [
-                            {
-                                "uri": uri,
-                                "mimeType": "application/json",
-                                "text": serde_json::to_string_pretty(&results).unwrap_or_else(|_| "[]".to_string())
-                            }
-                        ]
-                    })),
-                    error: None,
                 }
             }
-            Err(e) => JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id,
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32603,
-                    message: format!("Failed to read table: {}", e),
-                    data: None,
-                }),
-            },
+            Ok(None) => pooler::labelled(application_name, self.dispatch(request)).await,
+        };
+        if self.cancellation.finish() {
+            response = cancel::cancelled_response(id);
         }
+        self.usage.record_call(call, &response);
+        // Return the connection to the pool between requests.
+        self.client = None;
+        telemetry::record_response(&response);
+        self.report_policy_denial(summary, &response);
+        response
     }
-}
 
-fn is_write_query(sql: &str) -> bool {
-    let sql_upper = sql.trim().to_uppercase();
-    sql_upper.starts_with("INSERT")
-        || sql_upper.starts_with("UPDATE")
-        || sql_upper.starts_with("DELETE")
-        || sql_upper.starts_with("DROP")
-        || sql_upper.starts_with("CREATE")
-        || sql_upper.starts_with("ALTER")
-        || sql_upper.starts_with("TRUNCATE")
-}
+    async fn dispatch(&mut self, request: JsonRpcRequest) -> JsonRpcResponse {
+        if self.compat_mode == CompatMode::TypeScript {
+            return self.handle_ts_compat_request(request).await;
+        }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+        if let (Some(chaos), Some(Fault::SlowQuery)) = (&self.chaos, self.fault) {
+            tokio::time::sleep(chaos.delay).await;
+        }
+
+        let encoding = compression::accepted(request.params.as_ref());
+        let mut response = match request.method.as_str() {
+            "initialize" => self.handle_initialize(request.id),
+            "tools/list" => self.handle_tools_list(request.id).await,
+            "tools/call" => self.handle_tools_call(request.id, request.params).await,
+            "resources/list" => self.handle_resources_list(request.id).await,
+            "resources/read" => self.handle_resources_read(request.id, request.params).await,
+            "resources/subscribe" => {
+                self.handle_resources_subscribe(request.id, request.params)
+                    .await
+            }
+            "resources/unsubscribe" => {
+                self.handle_resources_unsubscribe(request.id, request.params)
+            }
+            "prompts/list" => self.handle_prompts_list(request.id),
+            "prompts/get" => self.handle_prompts_get(request.id, request.params).await,
+            _ => JsonRpcResponse::error(
+                request.id,
+                -32601,
+                format!("Method not found: {}", request.method),
+            ),
+        };
+        if let (Some(chaos), Some(fault), Some(result)) =
+            (&self.chaos, self.fault, response.result.as_mut())
+        {
+            chaos.corrupt(fault, result);
+        }
+        if let (Some(encoding), Some(min_bytes), Some(result)) = (
+            encoding,
+            self.compression_min_bytes,
+            response.result.as_mut(),
+        ) {
+            compression::compress_result(result, encoding, min_bytes);
+        }
+        response
+    }
+
+    fn handle_initialize(&self, id: Option<Value>) -> JsonRpcResponse {
+        JsonRpcResponse::success(
+            id,
+            json!({
+                "protocolVersion": "2024-11-05",
+                "capabilities": {
+                    "tools": { "listChanged": true },
+                    "resources": { "subscribe": !pooler::transaction_pooling() },
+                    "prompts": {},
+                    "logging": {}
+                },
+                "serverInfo": {
+                    "name": "postgres-mcp-server",
+                    "version": "0.1.0"
+                }
+            }),
+        )
+    }
+
+    async fn handle_resources_list(&mut self, id: Option<Value>) -> JsonRpcResponse {
+        // Tables are only listed while connections are open; reading server
+        // info connects on demand, so it is listed even before then.
+        let own_resources: Vec<Value> = std::iter::once(server_info::server_info_resource())
+            .chain(self.spool.resources())
+            .chain(self.results.resources())
+            .collect();
+        let cached_tables = Catalog::shared().and_then(Catalog::relations);
+        if let Some(tables) = cached_tables {
+            let resources: Vec<Value> = own_resources
+                .into_iter()
+                .chain(self.table_resources(tables))
+                .collect();
+            return JsonRpcResponse::success(id, json!({ "resources": resources }));
+        }
+        if self.pool.status().size == 0 {
+            return JsonRpcResponse::success(id, json!({ "resources": own_resources }));
+        }
+        if let Err(e) = self.ensure_connected().await {
+            return JsonRpcResponse::connect_error(id, &e);
+        }
+
+        let client = self.client.as_ref().unwrap();
+
+        match pooler::query(client, catalog::RELATIONS_QUERY, &[]).await {
+            Ok(rows) => {
+                let tables = rows
+                    .iter()
+                    .map(|row| (row.get(0), row.get(1), row.get(2)))
+                    .collect();
+                let resources: Vec<Value> = own_resources
+                    .into_iter()
+                    .chain(self.table_resources(tables))
+                    .collect();
+
+                JsonRpcResponse::success(id, json!({"resources": resources}))
+            }
+            Err(e) => JsonRpcResponse::db_error(
+                id,
+                format!("Failed to list tables: {}", db_error_message(&e)),
+                &e,
+            ),
+        }
+    }
+
+    /// The `resources/list` entries of the relations given by schema, name,
+    /// and kind that the access lists do not hide, public ones first.
+    /// Relations of temporary schemas, and of schemas whose URIs spool files
+    /// and materialized results use, are left out.
+    fn table_resources(&self, mut tables: Vec<(String, String, String)>) -> Vec<Value> {
+        tables.retain(|(schema, table, _)| {
+            !schema.starts_with("pg_")
+                && !SHADOWED_SCHEMAS.contains(&schema.as_str())
+                && self.access.allows(schema, table)
+        });
+        tables.sort_by(|a, b| (a.0 != "public", a).cmp(&(b.0 != "public", b)));
+        tables
+            .iter()
+            .flat_map(|(schema, table, kind)| table_resources(schema, table, kind))
+            .collect()
+    }
+
+    async fn handle_resources_read(
+        &mut self,
+        id: Option<Value>,
+        params: Option<Value>,
+    ) -> JsonRpcResponse {
+        let uri = match uri_param(params.as_ref()) {
+            Ok(uri) => uri,
+            Err(message) => return JsonRpcResponse::error(id, -32602, message),
+        };
+        let range = match ByteRange::from_params(params.as_ref()) {
+            Ok(range) => range,
+            Err(message) => return JsonRpcResponse::error(id, -32602, message),
+        };
+
+        // Spool files are read a chunk at a time from disk; other resources
+        // are built whole and then cut to the range.
+        if let Some(name) = uri.strip_prefix(spool::SPOOL_URI_PREFIX) {
+            return self.read_spool_file(id, &uri, name, range).await;
+        }
+        // A table named "results" keeps its definition URI.
+        let result_name = uri
+            .strip_prefix(results::RESULTS_URI_PREFIX)
+            .filter(|rest| !rest.starts_with(SCHEMA_SEGMENT))
+            .map(str::to_string);
+        let mut response = if uri == server_info::SERVER_INFO_URI {
+            self.read_server_info(id).await
+        } else if let Some(rest) = result_name {
+            self.read_materialized_result(id, &uri, &rest)
+        } else {
+            self.read_table_resource(id, uri).await
+        };
+        if let (Some(range), Some(result)) = (range, response.result.as_mut())
+            && let Err(message) = chunk::apply(result, range)
+        {
+            return JsonRpcResponse::error(response.id, -32602, message);
+        }
+        response
+    }
+
+    async fn handle_resources_subscribe(
+        &mut self,
+        id: Option<Value>,
+        params: Option<Value>,
+    ) -> JsonRpcResponse {
+        let uri = match uri_param(params.as_ref()) {
+            Ok(uri) => uri,
+            Err(message) => return JsonRpcResponse::error(id, -32602, message),
+        };
+        // Server info is built afresh whenever it is read, and spool files
+        // and materialized results never change.
+        let table_uri = (uri != server_info::SERVER_INFO_URI
+            && !uri.starts_with(spool::SPOOL_URI_PREFIX)
+            && !uri.starts_with(results::RESULTS_URI_PREFIX))
+        .then(|| parse_table_uri(&uri).ok())
+        .flatten()
+        .filter(|table_uri| !table_uri.definition);
+        let Some(TableUri { schema, table, .. }) = table_uri else {
+            return JsonRpcResponse::error(
+                id,
+                -32602,
+                format!("Only table resources can be subscribed to, not {}", uri),
+            );
+        };
+        if pooler::transaction_pooling() {
+            return JsonRpcResponse::error(
+                id,
+                -32603,
+                "Subscriptions are not available with DB_POOLER=pgbouncer, since LISTEN needs a connection of its own",
+            );
+        }
+        if let Err(e) = self.ensure_connected().await {
+            return JsonRpcResponse::connect_error(id, &e);
+        }
+        let kind = match self.relation_kind(&schema, &table).await {
+            Ok(Some(kind)) => kind,
+            Ok(None) => {
+                return JsonRpcResponse::error(
+                    id,
+                    -32602,
+                    format!("Table '{}' not found in schema {}", table, schema),
+                );
+            }
+            Err(e) => {
+                return JsonRpcResponse::db_error(
+                    id,
+                    format!("Failed to subscribe: {}", db_error_message(&e)),
+                    &e,
+                );
+            }
+        };
+        if self.subscriptions.install_triggers() && kind.contains("view") {
+            return JsonRpcResponse::error(
+                id,
+                -32602,
+                format!(
+                    "SUBSCRIPTION_TRIGGERS cannot install a change trigger on {} '{}'; announce its changes with NOTIFY instead",
+                    kind, table
+                ),
+            );
+        }
+        if self.subscriptions.install_triggers() {
+            let client = self.client.as_ref().unwrap();
+            let qualified = format!("{}.{}", quote_ident(&schema), quote_ident(&table));
+            if let Err(e) =
+                subscriptions::install_trigger(client, &qualified, self.subscriptions.channel())
+                    .await
+            {
+                return JsonRpcResponse::db_error(
+                    id,
+                    format!(
+                        "Failed to install the change trigger on table '{}' (SUBSCRIPTION_TRIGGERS needs the privileges of its owner): {}",
+                        table,
+                        db_error_message(&e)
+                    ),
+                    &e,
+                );
+            }
+        }
+        let listening = match connection_config(&self.database_url, &self.tls_settings) {
+            Ok(config) => self.subscriptions.listen(&config, self.tls.clone()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = listening {
+            return JsonRpcResponse::error(id, -32603, format!("{:#}", e));
+        }
+        self.subscriptions.add(uri, schema, table);
+        JsonRpcResponse::success(id, json!({}))
+    }
+
+    fn handle_resources_unsubscribe(
+        &mut self,
+        id: Option<Value>,
+        params: Option<Value>,
+    ) -> JsonRpcResponse {
+        match uri_param(params.as_ref()) {
+            Ok(uri) => {
+                self.subscriptions.remove(&uri);
+                JsonRpcResponse::success(id, json!({}))
+            }
+            Err(message) => JsonRpcResponse::error(id, -32602, message),
+        }
+    }
+
+    /// The kind of `table` in `schema`, as `resources/list` gives it, if it
+    /// is a relation offered as a resource that the access lists do not
+    /// hide. Requires a connection.
+    async fn relation_kind(
+        &self,
+        schema: &str,
+        table: &str,
+    ) -> Result<Option<String>, tokio_postgres::Error> {
+        if !self.access.allows(schema, table) {
+            return Ok(None);
+        }
+        let client = self.client.as_ref().unwrap();
+        // A schema and name pick out one relation at most.
+        let rows = pooler::query(
+            client,
+            &format!(
+                "{} AND n.nspname = $1 AND c.relname = $2",
+                catalog::RELATIONS_QUERY
+            ),
+            &[(&schema, Type::TEXT), (&table, Type::TEXT)],
+        )
+        .await?;
+        Ok(rows.first().map(|row| row.get(2)))
+    }
+
+    async fn read_table_resource(&mut self, id: Option<Value>, uri: String) -> JsonRpcResponse {
+        let table_uri = match parse_table_uri(&uri) {
+            Ok(t) => t,
+            Err(e) => return JsonRpcResponse::error(id, -32602, e.to_string()),
+        };
+
+        if self.pool.status().size == 0 {
+            return JsonRpcResponse::error(id, -32603, "Not connected to database");
+        }
+        if let Err(e) = self.ensure_connected().await {
+            return JsonRpcResponse::connect_error(id, &e);
+        }
+
+        // The name is quoted wherever it reaches SQL, and must also be one of
+        // the tables `resources/list` offers.
+        let kind = match self
+            .relation_kind(&table_uri.schema, &table_uri.table)
+            .await
+        {
+            Ok(Some(kind)) => {
+                self.usage
+                    .record_table_read(&table_uri.schema, &table_uri.table);
+                kind
+            }
+            Ok(None) => {
+                return JsonRpcResponse::error(
+                    id,
+                    -32602,
+                    format!(
+                        "Table '{}' not found in schema {}",
+                        table_uri.table, table_uri.schema
+                    ),
+                );
+            }
+            Err(e) => {
+                return JsonRpcResponse::db_error(
+                    id,
+                    format!("Failed to read table: {}", db_error_message(&e)),
+                    &e,
+                );
+            }
+        };
+        if table_uri.definition {
+            return self
+                .read_table_definition(id, &uri, &table_uri.schema, &table_uri.table)
+                .await;
+        }
+        let client = self.client.as_ref().unwrap();
+        let qualified = format!(
+            "{}.{}",
+            quote_ident(&table_uri.schema),
+            quote_ident(&table_uri.table)
+        );
+
+        let primary_key = match primary_key_columns(client, &qualified).await {
+            Ok(pk) => pk,
+            Err(e) => {
+                return JsonRpcResponse::db_error(
+                    id,
+                    format!("Failed to read table: {}", db_error_message(&e)),
+                    &e,
+                );
+            }
+        };
+        let column_types = match column_types(client, &qualified).await {
+            Ok(columns) => columns,
+            Err(e) => {
+                return JsonRpcResponse::db_error(
+                    id,
+                    format!("Failed to read table: {}", db_error_message(&e)),
+                    &e,
+                );
+            }
+        };
+        let blob_columns: HashMap<&str, BlobKind> = column_types
+            .iter()
+            .filter_map(|(name, type_name)| {
+                BlobKind::from_type(type_name).map(|kind| (name.as_str(), kind))
+            })
+            .collect();
+        let unknown = table_uri
+            .columns
+            .iter()
+            .chain(&table_uri.order_by)
+            .find(|c| !column_types.iter().any(|(name, _)| name == *c));
+        if let Some(unknown) = unknown {
+            return JsonRpcResponse::error(
+                id,
+                -32602,
+                format!(
+                    "Column '{}' not found in table {}",
+                    unknown, table_uri.table
+                ),
+            );
+        }
+
+        if let Some(blob_path) = &table_uri.blob {
+            let [key_column] = primary_key.as_slice() else {
+                return JsonRpcResponse::error(
+                    id,
+                    -32602,
+                    format!(
+                        "Blob resources require a single-column primary key on {}",
+                        table_uri.table
+                    ),
+                );
+            };
+            let Some(kind) = blob_columns.get(blob_path.column.as_str()) else {
+                return JsonRpcResponse::error(
+                    id,
+                    -32602,
+                    format!(
+                        "{} is not a bytea or large object column of {}",
+                        blob_path.column, table_uri.table
+                    ),
+                );
+            };
+            return match blob::read_blob(
+                client,
+                &qualified,
+                key_column,
+                &blob_path.key,
+                &blob_path.column,
+                *kind,
+            )
+            .await
+            {
+                Ok(data) => JsonRpcResponse::success(
+                    id,
+                    json!({
+                        "contents": [
+                            {
+                                "uri": uri,
+                                "mimeType": blob::detect_mime_type(&data),
+                                "blob": BASE64_STANDARD.encode(&data),
+                            }
+                        ]
+                    }),
+                ),
+                Err(e) => JsonRpcResponse::error(id, -32603, format!("{:#}", e)),
+            };
+        }
+
+        // Pages are only stable under a total order, so fall back to the
+        // primary key, then to physical order, when none was requested.
+        // Views and foreign tables have no physical order to fall back to.
+        let (order_columns, order_source) = if !table_uri.order_by.is_empty() {
+            (table_uri.order_by.clone(), "requested")
+        } else if !primary_key.is_empty() {
+            (primary_key.clone(), "primary_key")
+        } else if kind == "view" || kind == "foreign table" {
+            (Vec::new(), "none")
+        } else {
+            (vec!["ctid".to_string()], "physical")
+        };
+        let order_clause = match order_source {
+            "none" => String::new(),
+            "physical" => " ORDER BY ctid".to_string(),
+            _ => format!(
+                " ORDER BY {}",
+                order_columns
+                    .iter()
+                    .map(|c| quote_ident(c))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+
+        // Binary columns are summarized rather than transferred; with a
+        // single-column key each one also gets a blob resource URI.
+        let selected: Vec<String> = if table_uri.columns.is_empty() {
+            column_types.iter().map(|(name, _)| name.clone()).collect()
+        } else {
+            table_uri.columns.clone()
+        };
+        let blob_key = match primary_key.as_slice() {
+            [key]
+                if selected
+                    .iter()
+                    .any(|c| blob_columns.contains_key(c.as_str())) =>
+            {
+                Some(key)
+            }
+            _ => None,
+        };
+        let mut select_list: Vec<String> = selected
+            .iter()
+            .map(|c| match blob_columns.get(c.as_str()) {
+                Some(kind) => format!("{} AS {}", kind.summary_expr(c), quote_ident(c)),
+                None => quote_ident(c),
+            })
+            .collect();
+        if let Some(key) = blob_key {
+            select_list.push(format!("{}::text AS {}", quote_ident(key), BLOB_KEY_COLUMN));
+        }
+        let select_list = if select_list.is_empty() {
+            "*".to_string()
+        } else {
+            select_list.join(", ")
+        };
+        let soft_delete_filter = self
+            .soft_delete
+            .filter(&table_uri.schema, &table_uri.table)
+            .filter(|_| !table_uri.include_deleted);
+        let where_clause = soft_delete_filter
+            .map(|predicate| format!(" WHERE ({})", predicate))
+            .unwrap_or_default();
+        // One extra row tells us whether there is a next page.
+        let query = format!(
+            "SELECT {} FROM {}{}{} LIMIT {} OFFSET {}",
+            select_list,
+            qualified,
+            where_clause,
+            order_clause,
+            table_uri.limit + 1,
+            table_uri.offset
+        );
+
+        // Preparing first gives the column order even when the page is
+        // empty; unprepared, only the rows give it.
+        let result = match Prepared::new(client, &query).await {
+            Ok(statement) => statement
+                .query(client, &[])
+                .await
+                .map(|rows| (statement, rows)),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok((statement, rows)) => {
+                let has_more = rows.len() as i64 > table_uri.limit;
+                let columns: Vec<String> = statement
+                    .columns()
+                    .or_else(|| rows.first().map(Row::columns))
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|c| c.name().to_string())
+                    .filter(|c| c != BLOB_KEY_COLUMN)
+                    .collect();
+                let results: Vec<_> = rows
+                    .iter()
+                    .take(table_uri.limit as usize)
+                    .map(|row| {
+                        let mut map = row_to_map(row);
+                        let key = map.remove(BLOB_KEY_COLUMN);
+                        for (column, kind) in &blob_columns {
+                            if let Some(value) = map.get_mut(*column)
+                                && !value.is_null()
+                            {
+                                let mut reference = json!({ kind.summary_key(): value.clone() });
+                                if let Some(Value::String(key)) = &key {
+                                    reference["uri"] = json!(table_uri.blob_uri(key, column));
+                                }
+                                *value = reference;
+                            }
+                        }
+                        map
+                    })
+                    .collect();
+                let next_uri =
+                    has_more.then(|| table_uri.page_uri(table_uri.offset + table_uri.limit));
+
+                JsonRpcResponse::success(
+                    id,
+                    json!({
+                        "contents": [
+                            {
+                                "uri": uri,
+                                "mimeType": table_uri.format.mime_type(),
+                                "text": table_uri.format.render(&columns, &results),
+                                "_meta": {
+                                    "kind": kind,
+                                    "ordering": {
+                                        "columns": order_columns,
+                                        "source": order_source,
+                                    },
+                                    "offset": table_uri.offset,
+                                    "limit": table_uri.limit,
+                                    "nextUri": next_uri,
+                                    "softDeleteFilter": soft_delete_filter,
+                                }
+                            }
+                        ]
+                    }),
+                )
+            }
+            Err(e) => JsonRpcResponse::db_error(
+                id,
+                format!("Failed to read table: {}", db_error_message(&e)),
+                &e,
+            ),
+        }
+    }
+
+    /// Reads a `postgres:///[<schema>/]<table>/schema` resource: the table's
+    /// columns, constraints, and indexes, as `describe_table` returns them.
+    async fn read_table_definition(
+        &self,
+        id: Option<Value>,
+        uri: &str,
+        schema: &str,
+        table: &str,
+    ) -> JsonRpcResponse {
+        let described = self
+            .describe_relations(&[(schema.to_string(), table.to_string())])
+            .await;
+        match described.map(|mut descriptions| descriptions.pop()) {
+            Ok(Some(description)) => JsonRpcResponse::success(
+                id,
+                json!({
+                    "contents": [
+                        {
+                            "uri": uri,
+                            "mimeType": "application/json",
+                            "text": serde_json::to_string_pretty(&description).unwrap_or_default(),
+                        }
+                    ]
+                }),
+            ),
+            Ok(None) => JsonRpcResponse::error(
+                id,
+                -32602,
+                format!("Table '{}' not found in schema {}", table, schema),
+            ),
+            Err(e) => e.into_response(id),
+        }
+    }
+}
+
+/// The `uri` parameter of a `resources/*` request.
+fn uri_param(params: Option<&Value>) -> Result<String, &'static str> {
+    let params = params.ok_or("Missing parameters")?;
+    params
+        .get("uri")
+        .and_then(|u| u.as_str())
+        .map(str::to_string)
+        .ok_or("Missing 'uri' parameter")
+}
+
+/// The `resources/list` entries of a table: its rows, and its definition.
+/// Tables outside the public schema are named with their schema.
+fn table_resources(schema: &str, table: &str, kind: &str) -> [Value; 2] {
+    let name = match schema {
+        "public" => table.to_string(),
+        schema => format!("{}.{}", schema, table),
+    };
+    let uri = table_path_uri(schema, table);
+    [
+        json!({
+            "uri": uri,
+            "name": name,
+            "description": format!("PostgreSQL {}: {}", kind, name),
+            "mimeType": "application/json"
+        }),
+        json!({
+            "uri": format!("{}/{}", uri, SCHEMA_SEGMENT),
+            "name": format!("{} schema", name),
+            "description": format!(
+                "Columns, constraints, and indexes of PostgreSQL {}: {}",
+                kind, name
+            ),
+            "mimeType": "application/json"
+        }),
+    ]
+}
+
+/// The URI of the rows of `table` in `schema`: `postgres:///<table>` in the
+/// public schema, and `postgres:///<schema>/<table>` in others.
+fn table_path_uri(schema: &str, table: &str) -> String {
+    match schema {
+        "public" => format!("postgres:///{}", percent_encode(table)),
+        schema => format!(
+            "postgres:///{}/{}",
+            percent_encode(schema),
+            percent_encode(table)
+        ),
+    }
+}
+
+/// Rows returned by a table resource read when the URI sets no `limit`.
+const DEFAULT_RESOURCE_LIMIT: i64 = 100;
+/// Upper bound on the `limit` a table resource URI may request.
+const MAX_RESOURCE_LIMIT: i64 = 1000;
+
+/// Column alias carrying the row key used to build blob resource URIs.
+const BLOB_KEY_COLUMN: &str = "__blob_key";
+
+/// The last path segment of a table's definition resource.
+const SCHEMA_SEGMENT: &str = "schema";
+
+/// Schemas whose tables would have the URIs of spool files and materialized
+/// results, and so are not offered as resources.
+const SHADOWED_SCHEMAS: &[&str] = &["spool", "results"];
+
+/// A parsed table resource URI: `postgres:///<table>?<options>`,
+/// `postgres:///<table>/schema`, or `postgres:///<table>/<key>/<column>`
+/// for a table in the public schema, and the same with the path starting
+/// `<schema>/` for a table in another.
+struct TableUri {
+    schema: String,
+    table: String,
+    /// Whether the URI names the table's definition rather than its rows.
+    definition: bool,
+    blob: Option<BlobPath>,
+    limit: i64,
+    offset: i64,
+    order_by: Vec<String>,
+    columns: Vec<String>,
+    format: ResultFormat,
+    /// Whether rows the table's soft-delete filter rejects are included.
+    include_deleted: bool,
+}
+
+/// The row key and column of a blob resource.
+struct BlobPath {
+    key: String,
+    column: String,
+}
+
+impl TableUri {
+    /// The URI of another page of the same read.
+    fn page_uri(&self, offset: i64) -> String {
+        let mut query = vec![
+            format!("offset={}", offset),
+            format!("limit={}", self.limit),
+        ];
+        if !self.order_by.is_empty() {
+            query.push(format!(
+                "order_by={}",
+                percent_encode(&self.order_by.join(","))
+            ));
+        }
+        if !self.columns.is_empty() {
+            query.push(format!(
+                "columns={}",
+                percent_encode(&self.columns.join(","))
+            ));
+        }
+        if self.format != ResultFormat::Json {
+            query.push(format!("format={}", self.format.name()));
+        }
+        if self.include_deleted {
+            query.push("include_deleted=true".to_string());
+        }
+        format!(
+            "{}?{}",
+            table_path_uri(&self.schema, &self.table),
+            query.join("&")
+        )
+    }
+
+    /// The URI of the blob in `column` of the row with primary key `key`.
+    fn blob_uri(&self, key: &str, column: &str) -> String {
+        let path = if self.schema == "public" && column == SCHEMA_SEGMENT {
+            // Without its schema, it would be taken for the definition of a
+            // table in a schema named for this one.
+            format!("postgres:///public/{}", percent_encode(&self.table))
+        } else {
+            table_path_uri(&self.schema, &self.table)
+        };
+        format!(
+            "{}/{}/{}",
+            path,
+            percent_encode(key),
+            percent_encode(column)
+        )
+    }
+}
+
+/// Parses a table resource URI. Supported query parameters are `limit`,
+/// `offset`, `format` (or `accept` with a MIME type), `include_deleted`, and
+/// the comma-separated column lists `order_by` and `columns`.
+fn parse_table_uri(uri: &str) -> Result<TableUri> {
+    let rest = uri
+        .strip_prefix("postgres:///")
+        .with_context(|| format!("Unsupported resource URI: {}", uri))?;
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let segments: Vec<String> = path.split('/').map(percent_decode).collect();
+    let public = || "public".to_string();
+    let blob = |key: &String, column: &String| {
+        Some(BlobPath {
+            key: key.clone(),
+            column: column.clone(),
+        })
+    };
+    // A last segment of "schema" names a definition, so a public table's
+    // blob column of that name needs the schema spelled out.
+    let (schema, table, definition, blob) = match segments.as_slice() {
+        [table] => (public(), table.clone(), false, None),
+        [table, segment] if segment == SCHEMA_SEGMENT => (public(), table.clone(), true, None),
+        [schema, table] => (schema.clone(), table.clone(), false, None),
+        [schema, table, segment] if segment == SCHEMA_SEGMENT => {
+            (schema.clone(), table.clone(), true, None)
+        }
+        [table, key, column] => (public(), table.clone(), false, blob(key, column)),
+        [schema, table, key, column] => (schema.clone(), table.clone(), false, blob(key, column)),
+        _ => anyhow::bail!("Unsupported resource path: {}", uri),
+    };
+    if schema.is_empty() || table.is_empty() {
+        anyhow::bail!("Resource URI names no table: {}", uri);
+    }
+    if definition && !query.is_empty() {
+        anyhow::bail!("Table schema resources take no parameters: {}", uri);
+    }
+
+    let mut table_uri = TableUri {
+        schema,
+        table,
+        definition,
+        blob,
+        limit: DEFAULT_RESOURCE_LIMIT,
+        offset: 0,
+        order_by: Vec::new(),
+        columns: Vec::new(),
+        format: ResultFormat::Json,
+        include_deleted: false,
+    };
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value);
+        match key {
+            "limit" => {
+                table_uri.limit = value
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|l| (1..=MAX_RESOURCE_LIMIT).contains(l))
+                    .with_context(|| {
+                        format!("'limit' must be between 1 and {}", MAX_RESOURCE_LIMIT)
+                    })?;
+            }
+            "offset" => {
+                table_uri.offset = value
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|o| *o >= 0)
+                    .context("'offset' must be a non-negative integer")?;
+            }
+            "order_by" => table_uri.order_by = split_column_list(&value),
+            "columns" => table_uri.columns = split_column_list(&value),
+            "format" => {
+                table_uri.format = ResultFormat::parse(&value).with_context(|| {
+                    format!(
+                        "Unknown format '{}' (expected 'json', 'csv', or 'markdown')",
+                        value
+                    )
+                })?;
+            }
+            "accept" => {
+                table_uri.format = ResultFormat::from_mime_type(&value).with_context(|| {
+                    format!(
+                        "Unsupported accept type '{}' (expected 'application/json', 'text/csv', or 'text/markdown')",
+                        value
+                    )
+                })?;
+            }
+            "include_deleted" => {
+                table_uri.include_deleted = match value.as_str() {
+                    "true" => true,
+                    "false" => false,
+                    _ => anyhow::bail!("'include_deleted' must be true or false"),
+                };
+            }
+            _ => anyhow::bail!("Unknown resource URI parameter '{}'", key),
+        }
+    }
+    Ok(table_uri)
+}
+
+fn split_column_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty())
+        .collect()
+}
+
+/// Decodes `%XX` escapes; malformed escapes are kept as-is.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = value
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Escapes everything but unreserved URI characters and commas.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b',' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn row_to_map(row: &Row) -> HashMap<String, Value> {
+    let mut row_map = HashMap::new();
+    for (idx, column) in row.columns().iter().enumerate() {
+        let value: Value = match column.type_().name() {
+            "int2" => row
+                .get::<_, Option<i16>>(idx)
+                .map(|v| json!(v))
+                .unwrap_or(Value::Null),
+            "int4" => row
+                .get::<_, Option<i32>>(idx)
+                .map(|v| json!(v))
+                .unwrap_or(Value::Null),
+            "int8" => row
+                .get::<_, Option<i64>>(idx)
+                .map(values::int8)
+                .unwrap_or(Value::Null),
+            "numeric" => row
+                .get::<_, Option<values::Numeric>>(idx)
+                .map(|v| values::numeric(&v))
+                .unwrap_or(Value::Null),
+            "text" | "varchar" => row
+                .get::<_, Option<String>>(idx)
+                .map(|v| json!(v))
+                .unwrap_or(Value::Null),
+            "bool" => row
+                .get::<_, Option<bool>>(idx)
+                .map(|v| json!(v))
+                .unwrap_or(Value::Null),
+            "float4" => row
+                .get::<_, Option<f32>>(idx)
+                .map(values::float4)
+                .unwrap_or(Value::Null),
+            "float8" => row
+                .get::<_, Option<f64>>(idx)
+                .map(values::float8)
+                .unwrap_or(Value::Null),
+            "date" | "timestamp" | "timestamptz" => row
+                .get::<_, Option<values::DateTime>>(idx)
+                .map(|v| json!(v.0))
+                .unwrap_or(Value::Null),
+            "interval" => row
+                .get::<_, Option<values::Interval>>(idx)
+                .map(|v| json!(v.0))
+                .unwrap_or(Value::Null),
+            "bytea" => row
+                .get::<_, Option<&[u8]>>(idx)
+                .map(values::bytea)
+                .unwrap_or(Value::Null),
+            "uuid" => row
+                .get::<_, Option<values::Uuid>>(idx)
+                .map(|v| json!(v.0))
+                .unwrap_or(Value::Null),
+            "vector" => row
+                .get::<_, Option<values::Vector>>(idx)
+                .map(|v| v.0)
+                .unwrap_or(Value::Null),
+            // Arrays and values of other types may hold values of types
+            // without a text form to fall back on, which are left null.
+            _ if values::Array::accepts(column.type_()) => row
+                .try_get::<_, Option<values::Array>>(idx)
+                .ok()
+                .flatten()
+                .map(|v| v.0)
+                .unwrap_or(Value::Null),
+            _ => row
+                .try_get::<_, Option<values::Text>>(idx)
+                .ok()
+                .flatten()
+                .map(|v| json!(v.0))
+                .unwrap_or(Value::Null),
+        };
+        row_map.insert(column.name().to_string(), value);
+    }
+    row_map
+}
+
+/// Extracts the server's message from a database error.
+fn db_error_message(e: &tokio_postgres::Error) -> String {
+    e.as_db_error()
+        .map(|db| db.message().to_string())
+        .unwrap_or_else(|| e.to_string())
+}
+
+/// PostgreSQL's report of a failed statement, as JSON-RPC error data from
+/// which clients can locate and fix the problem in their SQL. `position` is
+/// the 1-based character offset in the statement the error points at;
+/// `internal_position` points into `internal_query`, a statement run on the
+/// client's behalf, such as one inside a function. `retryable` tells whether
+/// running it again may succeed, as after a serialization failure. A lost
+/// connection has data of its own, marked retryable; `None` for other errors
+/// the server did not report.
+fn db_error_data(e: &tokio_postgres::Error) -> Option<Value> {
+    if reconnect::is_connection_lost(e) {
+        return Some(json!({ "type": "connection_error", "retryable": true }));
+    }
+    let db = e.as_db_error()?;
+    let (position, internal_position, internal_query) = match db.position() {
+        Some(ErrorPosition::Original(position)) => (Some(*position), None, None),
+        Some(ErrorPosition::Internal { position, query }) => {
+            (None, Some(*position), Some(query.as_str()))
+        }
+        None => (None, None, None),
+    };
+    Some(json!({
+        "type": "database_error",
+        "sqlstate": db.code().code(),
+        "severity": db.severity(),
+        "message": db.message(),
+        "detail": db.detail(),
+        "hint": db.hint(),
+        "position": position,
+        "internal_position": internal_position,
+        "internal_query": internal_query,
+        "where": db.where_(),
+        "schema": db.schema(),
+        "table": db.table(),
+        "column": db.column(),
+        "data_type": db.datatype(),
+        "constraint": db.constraint(),
+        "retryable": reconnect::is_retryable_state(db.code()),
+    }))
+}
+
+/// The error for a statement cancelled by its time limit.
+fn timeout_message(timeout: Duration) -> String {
+    format!(
+        "Query cancelled after running longer than its {} ms time limit",
+        timeout.as_millis()
+    )
+}
+
+/// Returns the columns of a (quoted, qualified) table with their type names,
+/// in column order.
+async fn column_types(
+    client: &Client,
+    qualified: &str,
+) -> Result<Vec<(String, String)>, tokio_postgres::Error> {
+    let rows = pooler::query(
+        client,
+        "SELECT attname::text, atttypid::regtype::text FROM pg_attribute \
+             WHERE attrelid = to_regclass($1) AND attnum > 0 AND NOT attisdropped \
+             ORDER BY attnum",
+        &[(&qualified, Type::TEXT)],
+    )
+    .await?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+/// The `application_name` of connections whose `DATABASE_URL` sets none,
+/// kept by requests attributed to no one.
+const DEFAULT_APPLICATION_NAME: &str = "postgres-mcp-server";
+
+/// The connection settings every pool uses: the connection string without
+/// its TLS parameters, the TLS mode, `DB_TIMEZONE`, and the default
+/// `application_name`.
+fn connection_config(database_url: &str, tls_settings: &TlsSettings) -> Result<Config> {
+    let mut config = conninfo::parse(database_url)?;
+    config.ssl_mode(tls_settings.pg_ssl_mode());
+    if config.get_application_name().is_none() {
+        config.application_name(DEFAULT_APPLICATION_NAME);
+    }
+    if let Some(timezone) = config::var("DB_TIMEZONE").ok().filter(|v| !v.is_empty()) {
+        if timezone.contains(char::is_whitespace) {
+            bail!("DB_TIMEZONE must be a time zone name such as UTC or Europe/Berlin");
+        }
+        let options = config.get_options().unwrap_or_default();
+        config.options(format!("{} -c TimeZone={}", options, timezone).trim_start());
+    }
+    Ok(config)
+}
+
+/// Starts building the catalog cache, if `CATALOG_WARMUP` asks for it, on a
+/// one-connection pool of its own.
+fn start_catalog(database_url: &str) -> Result<()> {
+    let Some(settings) = CatalogSettings::from_env()? else {
+        return Ok(());
+    };
+    let (database_url, tls_settings) = TlsSettings::from_url_and_env(database_url)?;
+    let pool = pool::build_pool(
+        connection_config(&database_url, &tls_settings)?,
+        tls_settings.connector()?,
+        &PoolSettings::from_env()?.with_max_size(1),
+        SessionIdentity::from_env()?,
+    )?;
+    catalog::start(pool, settings);
+    Ok(())
+}
+
+/// Returns the primary key columns of a (quoted, qualified) table in key order.
+async fn primary_key_columns(
+    client: &Client,
+    qualified: &str,
+) -> Result<Vec<String>, tokio_postgres::Error> {
+    let rows = pooler::query(
+        client,
+        "SELECT a.attname::text \
+             FROM pg_index i \
+             JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey) \
+             WHERE i.indrelid = to_regclass($1) AND i.indisprimary \
+             ORDER BY array_position(i.indkey::int2[], a.attnum)",
+        &[(&qualified, Type::TEXT)],
+    )
+    .await?;
+    Ok(rows.iter().map(|r| r.get(0)).collect())
+}
+
+/// Normalizes SQL for grouping similar statements: literals, including
+/// numbers, escape strings, and dollar-quoted strings, become `?`, comments
+/// are dropped, and whitespace is collapsed. SQL that cannot be tokenized,
+/// such as SQL with an unterminated string, becomes a lone `?`, since any of
+/// it may be a literal.
+fn fingerprint_sql(sql: &str) -> String {
+    let Ok(tokens) = Tokenizer::new(&PostgreSqlDialect {}, sql).tokenize() else {
+        return "?".to_string();
+    };
+    let mut out = String::with_capacity(sql.len());
+    for token in tokens {
+        match token {
+            Token::Whitespace(_) => {
+                if !out.is_empty() && !out.ends_with(' ') {
+                    out.push(' ');
+                }
+            }
+            Token::Number(..)
+            | Token::SingleQuotedString(_)
+            | Token::TripleSingleQuotedString(_)
+            | Token::TripleDoubleQuotedString(_)
+            | Token::DollarQuotedString(_)
+            | Token::SingleQuotedByteStringLiteral(_)
+            | Token::DoubleQuotedByteStringLiteral(_)
+            | Token::TripleSingleQuotedByteStringLiteral(_)
+            | Token::TripleDoubleQuotedByteStringLiteral(_)
+            | Token::SingleQuotedRawStringLiteral(_)
+            | Token::DoubleQuotedRawStringLiteral(_)
+            | Token::TripleSingleQuotedRawStringLiteral(_)
+            | Token::TripleDoubleQuotedRawStringLiteral(_)
+            | Token::NationalStringLiteral(_)
+            | Token::EscapedStringLiteral(_)
+            | Token::UnicodeStringLiteral(_)
+            | Token::HexStringLiteral(_) => out.push('?'),
+            token => out.push_str(&token.to_string()),
+        }
+    }
+    out.trim_end().to_string()
+}
+
+/// Quotes an identifier the way PostgreSQL's `quote_ident` does.
+pub(crate) fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Serializes a tool result as the pretty-printed text content of the
+/// response and, for object results, as its `structuredContent`.
+fn tool_result(value: impl Serialize) -> Value {
+    let mut result = json!({
+        "content": [
+            {
+                "type": "text",
+                "text": serde_json::to_string_pretty(&value).unwrap_or_else(|_| "[]".to_string())
+            }
+        ]
+    });
+    // Converting to a Value sorts the keys, so the text is rendered from the
+    // typed result to keep its fields in declaration order.
+    if let Ok(structured @ Value::Object(_)) = serde_json::to_value(&value) {
+        result["structuredContent"] = structured;
+    }
+    result
+}
+
+/// Resolves when the process is asked to stop: Ctrl-C everywhere, plus
+/// SIGTERM on Unix and Ctrl-Break on Windows.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(windows)]
+    let terminate = async {
+        match tokio::signal::windows::ctrl_break() {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Cannot listen for Ctrl-Break: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(any(unix, windows)))]
+    let terminate = std::future::pending::<()>();
+
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Cannot listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+fn main() -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start the async runtime")?;
+    let served = runtime.block_on(run());
+    // Reading stdin blocks a runtime thread in a way that cannot be
+    // cancelled, so waiting for it would hold the exit until the client
+    // writes another line.
+    runtime.shutdown_background();
+    served
+}
+
+async fn run() -> Result<()> {
+    let args = CliArgs::parse(env::args().skip(1))?;
+    config::load(args.config.as_deref())?;
+    if let Some(format) = args.export_config {
+        print!("{}", config::export(format)?);
+        return Ok(());
+    }
+
+    let log_config = logging::LogConfig::from_env()?;
+    logging::init(log_config.clone())?;
+    wirelog::init(&log_config)?;
+    spool::check_encryption()?;
+    values::init()?;
+    pooler::init()?;
 
     info!("Starting PostgreSQL MCP Server");
 
-    let mut server = McpServer::new()?;
+    // Like the reference TypeScript server, accept the connection string as
+    // the first command-line argument when DATABASE_URL is not set.
+    let database_url = conninfo::database_url(args.database_url)?;
+    conninfo::check(&database_url).await?;
+    plugins::load(&database_url).await?;
+    hooks::init()?;
+    if let Some(dir) = &args.load_fixtures {
+        return load_fixtures_command(&database_url, Path::new(dir), args.keep_existing).await;
+    }
+    metrics::init().await?;
+    start_catalog(&database_url)?;
+
+    let served = match args.transport {
+        Transport::Stdio => {
+            telemetry::init("stdio")?;
+            serve_stdio(&database_url).await
+        }
+        #[cfg(feature = "http")]
+        Transport::Http(addr) => {
+            telemetry::init("http")?;
+            http::serve(addr, database_url).await
+        }
+    };
+    telemetry::flush().await;
+    served
+}
+
+/// Runs `load-fixtures`: loads the fixture files in `dir` as the
+/// `load_fixtures` tool does, with the same safeguards, and prints the
+/// report.
+async fn load_fixtures_command(database_url: &str, dir: &Path, keep_existing: bool) -> Result<()> {
+    let mut server = McpServer::new(database_url)?;
+    server.connect().await?;
+    let report = server
+        .load_fixtures(dir, "public", keep_existing)
+        .await
+        .map_err(|e| anyhow::anyhow!(e.message()))?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    server.close();
+    Ok(())
+}
+
+/// Serves one client over stdin and stdout until either is closed or the
+/// process is asked to stop. Each request is handled in a task of its own,
+/// and responses are written as they are ready, in whatever order.
+async fn serve_stdio(database_url: &str) -> Result<()> {
+    let mut server = McpServer::new(database_url)?;
+    let mut queue = RequestQueue::spawn_stdin_reader(QueueLimits::from_env()?);
+    let mut resource_updates = server.take_resource_updates();
+    let mut cancellations = queue.take_cancellations();
+    // Responses and notifications, serialized, on their way to stdout.
+    let (outgoing, mut lines) = mpsc::unbounded_channel();
+    server.notifications = NotificationSink::Channel(outgoing.clone());
+    let mut handling = JoinSet::new();
 
-    let stdin = io::stdin();
     let mut stdout = io::stdout();
-    let reader = stdin.lock();
 
-    for line in reader.lines() {
-        let line = line?;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        let idle_timeout = server
+            .idle_timeout
+            .filter(|_| handling.is_empty() && server.has_idle_resources());
+        let received = tokio::select! {
+            _ = &mut shutdown => {
+                info!("Shutdown requested, exiting");
+                break;
+            }
+            Some(line) = lines.recv() => {
+                write_line(&mut stdout, &line)?;
+                continue;
+            }
+            Some(uri) = resource_updates.recv() => {
+                server.notify(&JsonRpcNotification::resource_updated(&uri));
+                continue;
+            }
+            Some(params) = cancellations.recv() => {
+                server.cancellation.cancel(params.as_ref());
+                continue;
+            }
+            Some(handled) = handling.join_next() => {
+                if let Err(e) = handled {
+                    error!("Request handler failed: {}", e);
+                }
+                continue;
+            }
+            received = queue.recv_timeout(idle_timeout) => received,
+        };
+        let line = match received {
+            Ok(line) => line,
+            Err(_) => {
+                if let Some(message) = server.release_idle_resources().await {
+                    server.notify(&JsonRpcNotification::log("info", message));
+                }
+                continue;
+            }
+        };
+
+        let Some(queued) = line else {
+            info!("Client closed stdin, exiting");
+            break;
+        };
+        let line = match queued.line {
+            Ok(line) => line,
+            // A client writing in a legacy code page (as Windows consoles do by
+            // default) should cost one request, not the session.
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                error!("Discarding request that is not valid UTF-8");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         if line.trim().is_empty() {
             continue;
@@ -462,12 +2464,185 @@ async fn main() -> Result<()> {
             }
         };
 
-        let response = server.handle_request(request).await;
-        let response_str = serde_json::to_string(&response)?;
+        if let Some(busy) = queue.check_wait(queued.received, queued.priority, &request.id) {
+            wirelog::outbound(&busy);
+            write_line(&mut stdout, &serde_json::to_string(&busy)?)?;
+            continue;
+        }
+        let mut handler = server.for_request();
+        handler.received = queued.received;
+        let outgoing = outgoing.clone();
+        let permit = queued.permit;
+        handling.spawn(async move {
+            let response = handler.handle_request(request).await;
+            drop(permit);
+            wirelog::outbound(&response);
+            match serde_json::to_string(&response) {
+                Ok(line) => {
+                    let _ = outgoing.send(line);
+                }
+                Err(e) => error!("Failed to serialize response: {}", e),
+            }
+        });
+    }
 
-        writeln!(stdout, "{}", response_str)?;
-        stdout.flush()?;
+    // Answer the requests still being handled before exiting; requests not
+    // yet started are dropped. A further signal cancels those still running.
+    if !handling.is_empty() {
+        info!("Waiting for {} request(s) to finish", handling.len());
+    }
+    let cancel = shutdown_signal();
+    tokio::pin!(cancel);
+    let mut cancelled = false;
+    while !handling.is_empty() {
+        tokio::select! {
+            Some(line) = lines.recv() => write_line(&mut stdout, &line)?,
+            _ = handling.join_next() => {}
+            _ = &mut cancel, if !cancelled => {
+                cancelled = true;
+                let count = server.cancellation.cancel_all();
+                info!("Shutdown requested again, cancelling {} request(s)", count);
+            }
+        }
+    }
+    while let Ok(line) = lines.try_recv() {
+        write_line(&mut stdout, &line)?;
     }
+    server.close();
 
     Ok(())
 }
+
+fn write_line(stdout: &mut io::Stdout, line: &str) -> io::Result<()> {
+    writeln!(stdout, "{}", line)?;
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_replace_literals() {
+        assert_eq!(
+            fingerprint_sql("SELECT * FROM t WHERE a = 'x''y' AND b = 42.5 AND c = $1"),
+            "SELECT * FROM t WHERE a = ? AND b = ? AND c = $1"
+        );
+    }
+
+    #[test]
+    fn fingerprints_replace_dollar_quoted_strings() {
+        assert_eq!(fingerprint_sql("SELECT $$s3cret$$"), "SELECT ?");
+        assert_eq!(
+            fingerprint_sql("SELECT $tag$it's $$ s3cret$tag$ AS x"),
+            "SELECT ? AS x"
+        );
+    }
+
+    #[test]
+    fn fingerprints_replace_escape_strings() {
+        assert_eq!(
+            fingerprint_sql(r"SELECT E'a\'b secret' FROM t"),
+            "SELECT ? FROM t"
+        );
+        assert_eq!(fingerprint_sql("SELECT x'1f', U&'d\\0061'"), "SELECT ?, ?");
+    }
+
+    #[test]
+    fn fingerprints_drop_comments() {
+        assert_eq!(
+            fingerprint_sql("SELECT /* s3cret */ 1 -- also s3cret\nFROM t"),
+            "SELECT ? FROM t"
+        );
+    }
+
+    #[test]
+    fn fingerprints_keep_digits_in_names() {
+        assert_eq!(
+            fingerprint_sql("SELECT col1, \"t 2\".x3 FROM t2 WHERE y=7"),
+            "SELECT col1, \"t 2\".x3 FROM t2 WHERE y=?"
+        );
+    }
+
+    #[test]
+    fn unterminated_sql_fingerprints_to_nothing() {
+        assert_eq!(fingerprint_sql("SELECT 'open s3cret"), "?");
+    }
+
+    #[test]
+    fn table_uris() {
+        let uri = parse_table_uri("postgres:///orders").unwrap();
+        assert_eq!(
+            (uri.schema.as_str(), uri.table.as_str()),
+            ("public", "orders")
+        );
+        assert!(!uri.definition && uri.blob.is_none());
+        assert_eq!((uri.limit, uri.offset), (DEFAULT_RESOURCE_LIMIT, 0));
+
+        let uri = parse_table_uri("postgres:///sales/orders/schema").unwrap();
+        assert_eq!(
+            (uri.schema.as_str(), uri.table.as_str()),
+            ("sales", "orders")
+        );
+        assert!(uri.definition);
+
+        let uri = parse_table_uri("postgres:///orders/schema").unwrap();
+        assert_eq!(
+            (uri.schema.as_str(), uri.table.as_str()),
+            ("public", "orders")
+        );
+        assert!(uri.definition);
+
+        let uri = parse_table_uri("postgres:///My%20Schema/My%2FTable").unwrap();
+        assert_eq!(
+            (uri.schema.as_str(), uri.table.as_str()),
+            ("My Schema", "My/Table")
+        );
+    }
+
+    #[test]
+    fn blob_uris() {
+        let uri = parse_table_uri("postgres:///files/42/data").unwrap();
+        let blob = uri.blob.unwrap();
+        assert_eq!(uri.table, "files");
+        assert_eq!((blob.key.as_str(), blob.column.as_str()), ("42", "data"));
+
+        // A public table's blob column named "schema" needs the schema.
+        let uri = parse_table_uri("postgres:///public/files/42/schema").unwrap();
+        assert_eq!(uri.blob.unwrap().column, "schema");
+    }
+
+    #[test]
+    fn table_uri_parameters() {
+        let uri = parse_table_uri(
+            "postgres:///orders?limit=10&offset=20&order_by=id,%20name&columns=id&format=csv&include_deleted=true",
+        )
+        .unwrap();
+        assert_eq!((uri.limit, uri.offset), (10, 20));
+        assert_eq!(uri.order_by, ["id", "name"]);
+        assert_eq!(uri.columns, ["id"]);
+        assert_eq!(uri.format, ResultFormat::Csv);
+        assert!(uri.include_deleted);
+
+        let uri = parse_table_uri("postgres:///orders?accept=text/markdown").unwrap();
+        assert_eq!(uri.format, ResultFormat::Markdown);
+    }
+
+    #[test]
+    fn bad_table_uris() {
+        for uri in [
+            "http:///orders",
+            "postgres:///",
+            "postgres:///a/b/c/d/e",
+            "postgres:///orders/schema?limit=5",
+            "postgres:///orders?limit=0",
+            "postgres:///orders?limit=100000",
+            "postgres:///orders?offset=-1",
+            "postgres:///orders?format=xml",
+            "postgres:///orders?include_deleted=yes",
+            "postgres:///orders?color=red",
+        ] {
+            assert!(parse_table_uri(uri).is_err(), "{}", uri);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Prometheus metrics, served over HTTP when `METRICS_ADDR` is set.
+//!
+//! `GET /metrics` on that address returns, in the Prometheus text format:
+//!
+//! - `postgres_mcp_requests_total{method}`: MCP requests handled, by method
+//!   (`other` for methods the server does not implement)
+//! - `postgres_mcp_queries_total`: statements run by the `query`,
+//!   `export_query`, and `materialize_result` tools
+//! - `postgres_mcp_query_duration_seconds`: a histogram of their run time
+//! - `postgres_mcp_rows_returned_total`: rows they returned, including later
+//!   pages of paged results
+//! - `postgres_mcp_query_errors_total{sqlstate}`: the ones that failed, by
+//!   SQLSTATE (`57014` for timeouts, `none` for errors without one)
+//! - `postgres_mcp_connections{state}`: pooled database connections `in_use`
+//!   and `idle`, across all sessions
+//!
+//! Nothing is recorded while `METRICS_ADDR` is unset. The listener answers
+//! every other path with 404 and has no authentication, so bind it to an
+//! address only the scraper can reach.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use deadpool_postgres::{Pool, WeakPool};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+use crate::config;
+
+/// Methods counted under their own name.
+const METHODS: &[&str] = &[
+    "initialize",
+    "ping",
+    "tools/list",
+    "tools/call",
+    "resources/list",
+    "resources/read",
+    "resources/subscribe",
+    "resources/unsubscribe",
+    "prompts/list",
+    "prompts/get",
+];
+/// Upper bounds of the query duration buckets, in seconds.
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+/// The SQLSTATE of a cancelled statement, also used for timeouts.
+pub(crate) const QUERY_CANCELED: &str = "57014";
+/// The most a scrape request may send before its headers end.
+const MAX_REQUEST_BYTES: usize = 8192;
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+#[derive(Default)]
+struct Metrics {
+    requests: Mutex<BTreeMap<&'static str, u64>>,
+    queries: AtomicU64,
+    rows_returned: AtomicU64,
+    /// Queries per duration bucket, with one more for those over the last.
+    duration_buckets: Mutex<Vec<u64>>,
+    /// Total query time, in microseconds.
+    duration_sum_micros: AtomicU64,
+    errors: Mutex<BTreeMap<String, u64>>,
+    pools: Mutex<Vec<WeakPool>>,
+}
+
+/// Starts serving metrics on `METRICS_ADDR`, if it is set.
+pub(crate) async fn init() -> Result<()> {
+    let Some(addr) = config::var("METRICS_ADDR").ok().filter(|v| !v.is_empty()) else {
+        return Ok(());
+    };
+    let addr: SocketAddr = addr
+        .parse()
+        .context("METRICS_ADDR must be an address and port, such as 127.0.0.1:9187")?;
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen for metrics scrapes on {}", addr))?;
+    METRICS.get_or_init(|| Metrics {
+        duration_buckets: Mutex::new(vec![0; DURATION_BUCKETS.len() + 1]),
+        ..Metrics::default()
+    });
+    info!("Serving metrics on http://{}/metrics", addr);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(answer_scrape(stream));
+                }
+                Err(e) => warn!("Failed to accept a metrics scrape: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Includes `pool`'s connections in the connection gauges while it lives.
+pub(crate) fn register_pool(pool: &Pool) {
+    if let Some(metrics) = METRICS.get() {
+        lock(&metrics.pools).push(pool.weak());
+    }
+}
+
+/// Counts a request for `method`.
+pub(crate) fn record_request(method: &str) {
+    if let Some(metrics) = METRICS.get() {
+        let method = METHODS
+            .iter()
+            .find(|m| **m == method)
+            .copied()
+            .unwrap_or("other");
+        *lock(&metrics.requests).entry(method).or_default() += 1;
+    }
+}
+
+/// Counts a statement that ran for `elapsed` and returned `rows` rows.
+pub(crate) fn record_query(elapsed: Duration, rows: usize) {
+    if let Some(metrics) = METRICS.get() {
+        metrics.observe(elapsed);
+        metrics
+            .rows_returned
+            .fetch_add(rows as u64, Ordering::Relaxed);
+    }
+}
+
+/// Counts a statement that ran for `elapsed` and failed with `sqlstate`.
+pub(crate) fn record_query_error(elapsed: Duration, sqlstate: Option<&str>) {
+    if let Some(metrics) = METRICS.get() {
+        metrics.observe(elapsed);
+        *lock(&metrics.errors)
+            .entry(sqlstate.unwrap_or("none").to_string())
+            .or_default() += 1;
+    }
+}
+
+/// Counts rows returned by a later page of a paged result.
+pub(crate) fn record_rows(rows: usize) {
+    if let Some(metrics) = METRICS.get() {
+        metrics
+            .rows_returned
+            .fetch_add(rows as u64, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    fn observe(&self, elapsed: Duration) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        self.duration_sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        let seconds = elapsed.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        lock(&self.duration_buckets)[bucket] += 1;
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP postgres_mcp_requests_total MCP requests handled, by method.\n");
+        out.push_str("# TYPE postgres_mcp_requests_total counter\n");
+        for (method, count) in lock(&self.requests).iter() {
+            let _ = writeln!(
+                out,
+                "postgres_mcp_requests_total{{method=\"{}\"}} {}",
+                method, count
+            );
+        }
+
+        out.push_str("# HELP postgres_mcp_queries_total Statements run by the query tools.\n");
+        out.push_str("# TYPE postgres_mcp_queries_total counter\n");
+        let queries = self.queries.load(Ordering::Relaxed);
+        let _ = writeln!(out, "postgres_mcp_queries_total {}", queries);
+
+        out.push_str(
+            "# HELP postgres_mcp_query_duration_seconds Run time of statements run by the query tools.\n",
+        );
+        out.push_str("# TYPE postgres_mcp_query_duration_seconds histogram\n");
+        let buckets = lock(&self.duration_buckets).clone();
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "postgres_mcp_query_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        cumulative += buckets.last().copied().unwrap_or(0);
+        let _ = writeln!(
+            out,
+            "postgres_mcp_query_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            cumulative
+        );
+        let _ = writeln!(
+            out,
+            "postgres_mcp_query_duration_seconds_sum {}",
+            self.duration_sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(
+            out,
+            "postgres_mcp_query_duration_seconds_count {}",
+            cumulative
+        );
+
+        out.push_str("# HELP postgres_mcp_rows_returned_total Rows returned by the query tools.\n");
+        out.push_str("# TYPE postgres_mcp_rows_returned_total counter\n");
+        let _ = writeln!(
+            out,
+            "postgres_mcp_rows_returned_total {}",
+            self.rows_returned.load(Ordering::Relaxed)
+        );
+
+        out.push_str(
+            "# HELP postgres_mcp_query_errors_total Failed statements run by the query tools, by SQLSTATE.\n",
+        );
+        out.push_str("# TYPE postgres_mcp_query_errors_total counter\n");
+        for (sqlstate, count) in lock(&self.errors).iter() {
+            let _ = writeln!(
+                out,
+                "postgres_mcp_query_errors_total{{sqlstate=\"{}\"}} {}",
+                sqlstate, count
+            );
+        }
+
+        let (mut in_use, mut idle) = (0, 0);
+        lock(&self.pools).retain(|pool| match pool.upgrade() {
+            Some(pool) => {
+                let status = pool.status();
+                in_use += status.size.saturating_sub(status.available);
+                idle += status.available;
+                true
+            }
+            None => false,
+        });
+        out.push_str("# HELP postgres_mcp_connections Pooled database connections.\n");
+        out.push_str("# TYPE postgres_mcp_connections gauge\n");
+        let _ = writeln!(
+            out,
+            "postgres_mcp_connections{{state=\"in_use\"}} {}",
+            in_use
+        );
+        let _ = writeln!(out, "postgres_mcp_connections{{state=\"idle\"}} {}", idle);
+        out
+    }
+}
+
+/// Answers one scrape on `stream`.
+async fn answer_scrape(mut stream: TcpStream) {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_BYTES {
+        match tokio::time::timeout(Duration::from_secs(10), stream.read(&mut buf)).await {
+            Ok(Ok(0)) | Ok(Err(_)) | Err(_) => return,
+            Ok(Ok(n)) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+    let request_line = request.split(|b| *b == b'\n').next().unwrap_or_default();
+    let is_scrape =
+        request_line.starts_with(b"GET /metrics ") || request_line.starts_with(b"GET /metrics?");
+    let (status, body) = match METRICS.get() {
+        Some(metrics) if is_scrape => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,88 @@
+//! Outbound HTTP requests: `HOOK_URL` webhooks, `TELEMETRY_ENDPOINT`
+//! reports, and the Neon API for `BRANCH_PROVIDER=neon`.
+//!
+//! They need the `http-client` feature. Without it, settings that would make
+//! a request are refused at startup.
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+
+/// Why a POST failed.
+#[derive(Debug)]
+pub(crate) enum PostError {
+    /// The server answered with an error status and this body.
+    #[cfg_attr(not(feature = "http-client"), allow(dead_code))]
+    Status(u16, String),
+    /// The request could not be made.
+    Failed(String),
+}
+
+impl fmt::Display for PostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Status(status, _) => write!(f, "the server answered with status {}", status),
+            Self::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Refuses `setting` when this build cannot make requests.
+pub(crate) fn require(setting: &str) -> Result<()> {
+    if !cfg!(feature = "http-client") {
+        bail!(
+            "{} needs the 'http-client' feature, which this build does not include",
+            setting
+        );
+    }
+    Ok(())
+}
+
+/// POSTs the JSON `body` to `url`, with `authorization` as the
+/// `Authorization` header if given, and returns the answer's body.
+#[cfg(feature = "http-client")]
+pub(crate) async fn post_json(
+    url: String,
+    authorization: Option<String>,
+    body: String,
+    timeout: Duration,
+) -> Result<String, PostError> {
+    let sent = tokio::task::spawn_blocking(move || {
+        let mut request = ureq::AgentBuilder::new()
+            .timeout(timeout)
+            .build()
+            .post(&url)
+            .set("Accept", "application/json")
+            .set("Content-Type", "application/json");
+        if let Some(authorization) = &authorization {
+            request = request.set("Authorization", authorization);
+        }
+        match request.send_string(&body) {
+            Ok(response) => response
+                .into_string()
+                .map_err(|e| PostError::Failed(e.to_string())),
+            Err(ureq::Error::Status(status, response)) => Err(PostError::Status(
+                status,
+                response.into_string().unwrap_or_default(),
+            )),
+            Err(e) => Err(PostError::Failed(e.to_string())),
+        }
+    })
+    .await;
+    sent.unwrap_or_else(|e| Err(PostError::Failed(e.to_string())))
+}
+
+/// Without the `http-client` feature, `require` refuses every setting that
+/// would get here.
+#[cfg(not(feature = "http-client"))]
+pub(crate) async fn post_json(
+    _url: String,
+    _authorization: Option<String>,
+    _body: String,
+    _timeout: Duration,
+) -> Result<String, PostError> {
+    Err(PostError::Failed(
+        "this build does not include the 'http-client' feature".to_string(),
+    ))
+}
@@ -0,0 +1,75 @@
+//! Query parameters given as JSON.
+//!
+//! Each value is sent in PostgreSQL's text format and converted to the
+//! parameter's type by the server, so any type with a text representation can
+//! be bound, and a value that does not fit its parameter is rejected with the
+//! server's own message (`invalid input syntax for type integer`). Values are
+//! bound, never interpolated into the SQL.
+
+use std::error::Error;
+
+use bytes::BytesMut;
+use serde_json::Value;
+use tokio_postgres::types::{Format, IsNull, Kind, ToSql, Type, to_sql_checked};
+
+/// A parameter value in text format; `None` binds NULL.
+#[derive(Debug)]
+pub(crate) struct TextParam(Option<String>);
+
+impl TextParam {
+    /// Converts `value` for a parameter of type `ty`: JSON arrays become array
+    /// literals for array parameters and JSON text otherwise, as objects do.
+    /// A parameter the server types itself (see `pooler`) takes arrays as
+    /// array literals too, so a JSON array for a `jsonb` one is passed as a
+    /// string.
+    pub(crate) fn from_json(value: &Value, ty: &Type) -> Self {
+        Self(match (value, ty.kind()) {
+            (Value::Null, _) => None,
+            (Value::String(s), _) => Some(s.clone()),
+            (Value::Array(items), Kind::Array(_) | Kind::Pseudo) => Some(array_literal(items)),
+            (value, _) => Some(value.to_string()),
+        })
+    }
+}
+
+impl ToSql for TextParam {
+    fn to_sql(&self, _: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        match &self.0 {
+            Some(text) => {
+                out.extend_from_slice(text.as_bytes());
+                Ok(IsNull::No)
+            }
+            None => Ok(IsNull::Yes),
+        }
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    fn encode_format(&self, _: &Type) -> Format {
+        Format::Text
+    }
+
+    to_sql_checked!();
+}
+
+/// Renders a JSON array as a PostgreSQL array literal such as
+/// `{1,NULL,"a \"b\""}`.
+fn array_literal(items: &[Value]) -> String {
+    let elements: Vec<String> = items
+        .iter()
+        .map(|item| match item {
+            Value::Null => "NULL".to_string(),
+            Value::Array(nested) => array_literal(nested),
+            Value::String(s) => quote_element(s),
+            Value::Object(_) => quote_element(&item.to_string()),
+            other => other.to_string(),
+        })
+        .collect();
+    format!("{{{}}}", elements.join(","))
+}
+
+fn quote_element(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,248 @@
+//! Tools provided by external executables.
+//!
+//! `TOOL_PLUGINS` lists executables, separated by commas, that each add one
+//! or more tools. A plugin is run once per request, with a JSON request on
+//! stdin, and answers with one JSON object on stdout:
+//!
+//! - `{"request": "describe"}`, sent to every plugin at startup, is answered
+//!   with `{"tools": [{"name": ..., "description": ..., "inputSchema": ...}]}`;
+//!   the tools are listed in `tools/list` after the built-in ones.
+//! - `{"request": "call", "tool": ..., "arguments": {...}, "allow_write_ops":
+//!   false}` is answered with `{"result": ...}`, returned as the tool result,
+//!   or `{"error": "message"}`.
+//!
+//! Plugins find the database in their `DATABASE_URL` environment variable,
+//! which is set to the server's connection string. A plugin that exits with
+//! an error, prints something other than JSON, or outlives
+//! `PLUGIN_TIMEOUT_SECS` fails the request, and its stderr is logged.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde_json::{Value, json};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+use crate::config;
+use crate::tools::{ToolError, tool_names};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+static PLUGINS: OnceLock<Plugins> = OnceLock::new();
+
+struct Plugins {
+    tools: Vec<PluginTool>,
+    database_url: String,
+    timeout: Duration,
+}
+
+/// A tool and the plugin that provides it.
+struct PluginTool {
+    command: PathBuf,
+    /// The tool's `tools/list` entry.
+    definition: Value,
+}
+
+/// Starts every plugin in `TOOL_PLUGINS` to learn the tools it provides.
+pub(crate) async fn load(database_url: &str) -> Result<()> {
+    let commands: Vec<PathBuf> = config::var("TOOL_PLUGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    if commands.is_empty() {
+        return Ok(());
+    }
+    let timeout = match config::var("PLUGIN_TIMEOUT_SECS") {
+        Ok(v) => Duration::from_secs(
+            v.parse::<u64>()
+                .context("PLUGIN_TIMEOUT_SECS must be a number of seconds")?,
+        ),
+        Err(_) => Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+    };
+    let mut plugins = Plugins {
+        tools: Vec::new(),
+        database_url: database_url.to_string(),
+        timeout,
+    };
+    let built_in = tool_names();
+    for command in commands {
+        let answer = plugins
+            .run(&command, &json!({ "request": "describe" }))
+            .await
+            .map_err(|e| anyhow!(e))
+            .with_context(|| format!("Plugin {} could not be described", command.display()))?;
+        let Some(Value::Array(tools)) = answer.get("tools") else {
+            bail!(
+                "Plugin {} must describe its tools as {{\"tools\": [...]}}",
+                command.display()
+            );
+        };
+        for tool in tools {
+            let name = tool.get("name").and_then(Value::as_str).unwrap_or("");
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                bail!(
+                    "Plugin {} describes a tool named '{}'; names may use letters, digits, '_', and '-'",
+                    command.display(),
+                    name
+                );
+            }
+            if built_in.iter().any(|b| b == name) || plugins.find(name).is_some() {
+                bail!(
+                    "Plugin {} describes a tool named '{}', which already exists",
+                    command.display(),
+                    name
+                );
+            }
+            let mut definition = json!({
+                "name": name,
+                "description": tool.get("description").cloned().unwrap_or(json!("")),
+                "inputSchema": tool
+                    .get("inputSchema")
+                    .cloned()
+                    .unwrap_or(json!({ "type": "object" })),
+            });
+            if let Some(output_schema) = tool.get("outputSchema") {
+                definition["outputSchema"] = output_schema.clone();
+            }
+            plugins.tools.push(PluginTool {
+                command: command.clone(),
+                definition,
+            });
+        }
+        info!(
+            "Plugin {} provides {} tool(s)",
+            command.display(),
+            tools.len()
+        );
+    }
+    let _ = PLUGINS.set(plugins);
+    Ok(())
+}
+
+/// The `tools/list` entries of the plugins' tools.
+pub(crate) fn definitions() -> Vec<Value> {
+    PLUGINS.get().map_or_else(Vec::new, |plugins| {
+        plugins
+            .tools
+            .iter()
+            .map(|tool| tool.definition.clone())
+            .collect()
+    })
+}
+
+/// Whether a plugin provides the tool `name`.
+pub(crate) fn provides(name: &str) -> bool {
+    PLUGINS
+        .get()
+        .is_some_and(|plugins| plugins.find(name).is_some())
+}
+
+/// Runs the plugin tool `name` with `arguments`, returning its result.
+pub(crate) async fn call(
+    name: &str,
+    arguments: Value,
+    allow_write_ops: bool,
+) -> Result<Value, ToolError> {
+    let Some((plugins, tool)) = PLUGINS
+        .get()
+        .and_then(|plugins| Some((plugins, plugins.find(name)?)))
+    else {
+        return Err(ToolError::InvalidArguments(format!(
+            "Unknown tool: {}",
+            name
+        )));
+    };
+    let request = json!({
+        "request": "call",
+        "tool": name,
+        "arguments": arguments,
+        "allow_write_ops": allow_write_ops,
+    });
+    let answer = plugins
+        .run(&tool.command, &request)
+        .await
+        .map_err(ToolError::Failed)?;
+    match (answer.get("result"), answer.get("error")) {
+        (_, Some(error)) => Err(ToolError::Failed(match error {
+            Value::String(message) => message.clone(),
+            other => other.to_string(),
+        })),
+        (Some(result), None) => Ok(result.clone()),
+        (None, None) => Err(ToolError::Failed(format!(
+            "Plugin {} answered with neither a result nor an error",
+            tool.command.display()
+        ))),
+    }
+}
+
+impl Plugins {
+    fn find(&self, name: &str) -> Option<&PluginTool> {
+        self.tools
+            .iter()
+            .find(|tool| tool.definition["name"] == name)
+    }
+
+    /// Runs `command` with `request` on its stdin and parses what it prints.
+    async fn run(&self, command: &Path, request: &Value) -> Result<Value, String> {
+        let mut child = Command::new(command)
+            .env("DATABASE_URL", &self.database_url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("Failed to start plugin {}: {}", command.display(), e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let line = format!("{}\n", request);
+            // A plugin that exits without reading its request is judged by
+            // its exit status and output instead.
+            let _ = stdin.write_all(line.as_bytes()).await;
+        }
+        let output = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                format!(
+                    "Plugin {} did not answer within {}s",
+                    command.display(),
+                    self.timeout.as_secs()
+                )
+            })?
+            .map_err(|e| format!("Failed to run plugin {}: {}", command.display(), e))?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
+        if !output.status.success() {
+            warn!("Plugin {} failed: {}", command.display(), stderr);
+            return Err(format!(
+                "Plugin {} exited with {}{}",
+                command.display(),
+                output.status,
+                stderr
+                    .lines()
+                    .last()
+                    .map(|line| format!(": {}", line))
+                    .unwrap_or_default()
+            ));
+        }
+        if !stderr.is_empty() {
+            info!("Plugin {}: {}", command.display(), stderr);
+        }
+        serde_json::from_slice(&output.stdout).map_err(|e| {
+            format!(
+                "Plugin {} did not answer with JSON: {}",
+                command.display(),
+                e
+            )
+        })
+    }
+}
@@ -0,0 +1,181 @@
+//! Database connection pool.
+//!
+//! Each request checks a connection out of the pool and returns it when the
+//! response is written, so a connection the server dropped costs one failed
+//! request: it is discarded when it comes back, and the next request gets a
+//! fresh one. Before a pooled connection is reused it is health-checked, by
+//! default with a round trip to the server.
+
+use std::borrow::Cow;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use deadpool_postgres::{
+    Hook, HookError, Manager, ManagerConfig, Pool, PoolError, RecyclingMethod, Runtime, Timeouts,
+};
+use tokio_postgres::Config;
+
+use crate::config;
+use crate::db_error_message;
+use crate::identity::SessionIdentity;
+use crate::tls::Connector;
+
+const DEFAULT_MAX_SIZE: usize = 4;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HealthCheck {
+    /// Only check that the connection has not been closed.
+    Fast,
+    /// Also run an empty statement on the server.
+    Verified,
+}
+
+impl HealthCheck {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "fast" => Some(Self::Fast),
+            "" | "verified" => Some(Self::Verified),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PoolSettings {
+    pub(crate) max_size: usize,
+    /// Connections opened on first use and kept through idle periods.
+    pub(crate) min_size: usize,
+    /// How long a request waits for a free connection, or for a new one to
+    /// be established.
+    timeout: Duration,
+    health_check: HealthCheck,
+}
+
+impl PoolSettings {
+    /// Reads `DB_POOL_MAX_SIZE`, `DB_POOL_MIN_SIZE`, `DB_POOL_TIMEOUT_SECS`,
+    /// and `DB_POOL_HEALTH_CHECK`.
+    pub(crate) fn from_env() -> Result<Self> {
+        let max_size = match config::var("DB_POOL_MAX_SIZE") {
+            Ok(v) => v
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .context("DB_POOL_MAX_SIZE must be a positive number of connections")?,
+            Err(_) => DEFAULT_MAX_SIZE,
+        };
+        let min_size = match config::var("DB_POOL_MIN_SIZE") {
+            Ok(v) => v
+                .parse::<usize>()
+                .context("DB_POOL_MIN_SIZE must be a number of connections")?,
+            Err(_) => 0,
+        };
+        if min_size > max_size {
+            bail!(
+                "DB_POOL_MIN_SIZE ({}) exceeds DB_POOL_MAX_SIZE ({})",
+                min_size,
+                max_size
+            );
+        }
+        let timeout = match config::var("DB_POOL_TIMEOUT_SECS") {
+            Ok(v) => v
+                .parse::<u64>()
+                .context("DB_POOL_TIMEOUT_SECS must be a number of seconds")?,
+            Err(_) => DEFAULT_TIMEOUT_SECS,
+        };
+        let health_check = config::var("DB_POOL_HEALTH_CHECK").unwrap_or_default();
+        let health_check = HealthCheck::parse(&health_check).with_context(|| {
+            format!(
+                "Unknown DB_POOL_HEALTH_CHECK '{}' (expected 'fast' or 'verified')",
+                health_check
+            )
+        })?;
+        Ok(Self {
+            max_size,
+            min_size,
+            timeout: Duration::from_secs(timeout),
+            health_check,
+        })
+    }
+
+    /// The pool's timeouts, but allowing `create` to establish a connection.
+    pub(crate) fn timeouts_with_create(&self, create: Duration) -> Timeouts {
+        Timeouts {
+            wait: Some(self.timeout),
+            create: Some(create),
+            recycle: Some(self.timeout),
+        }
+    }
+
+    /// These settings with at most `max_size` connections.
+    pub(crate) fn with_max_size(self, max_size: usize) -> Self {
+        Self {
+            max_size,
+            min_size: self.min_size.min(max_size),
+            ..self
+        }
+    }
+}
+
+/// Builds the pool. No connection is opened until the first checkout; every
+/// new connection is switched to the session's role before it is handed out.
+pub(crate) fn build_pool(
+    config: Config,
+    tls: Connector,
+    settings: &PoolSettings,
+    identity: Option<SessionIdentity>,
+) -> Result<Pool> {
+    let recycling_method = match settings.health_check {
+        HealthCheck::Fast => RecyclingMethod::Fast,
+        HealthCheck::Verified => RecyclingMethod::Verified,
+    };
+    let manager = Manager::from_config(config, tls, ManagerConfig { recycling_method });
+    let mut builder = Pool::builder(manager)
+        .max_size(settings.max_size)
+        .runtime(Runtime::Tokio1)
+        .wait_timeout(Some(settings.timeout))
+        .create_timeout(Some(settings.timeout))
+        .recycle_timeout(Some(settings.timeout));
+    if let Some(identity) = identity {
+        builder = builder.post_create(Hook::async_fn(move |client, _| {
+            let identity = identity.clone();
+            Box::pin(async move {
+                client
+                    .batch_execute(&identity.statement())
+                    .await
+                    .map_err(|e| {
+                        HookError::Message(Cow::Owned(format!(
+                            "Failed to switch to role '{}' for principal '{}': {}",
+                            identity.role,
+                            identity.principal,
+                            db_error_message(&e)
+                        )))
+                    })
+            })
+        }));
+    }
+    builder.build().context("Failed to create connection pool")
+}
+
+/// Closes the pool's idle connections beyond the first `keep`, returning how
+/// many were closed.
+pub(crate) fn trim(pool: &Pool, mut keep: usize) -> usize {
+    pool.retain(|_, _| {
+        let kept = keep > 0;
+        keep = keep.saturating_sub(1);
+        kept
+    })
+    .removed
+    .len()
+}
+
+/// Describes a failed checkout without repeating the underlying error, which
+/// `PoolError` both displays and reports as its source.
+pub(crate) fn checkout_error(e: PoolError) -> anyhow::Error {
+    match e {
+        PoolError::Backend(e) | PoolError::PostCreateHook(HookError::Backend(e)) => e.into(),
+        PoolError::PostCreateHook(HookError::Message(message)) => anyhow!(message),
+        PoolError::Timeout(_) => anyhow!("{} (see DB_POOL_TIMEOUT_SECS)", e),
+        e => anyhow!(e.to_string()),
+    }
+}
@@ -0,0 +1,331 @@
+//! Connecting through a connection pooler in transaction pooling mode.
+//!
+//! A pooler such as PgBouncer with `pool_mode = transaction` hands a client
+//! a server connection for one transaction at a time, so anything a session
+//! keeps between transactions may be gone, or belong to another client, by
+//! the next statement. `DB_POOLER=pgbouncer` tells the server it connects
+//! through one, and it then avoids session state:
+//!
+//! - statements run unnamed, their parameters sent along with them, rather
+//!   than as named prepared statements, which the pooler may send to a
+//!   server connection that never saw them
+//! - `ROLE_MAP` is refused, as `SET ROLE` would apply to whichever client
+//!   gets the server connection next
+//! - `set_session_timezone` is refused for the same reason
+//! - a request's `application_name` is set within the transactions the
+//!   server begins, rather than for the session; statements run outside
+//!   one keep the name the connection started with
+//! - resource subscriptions are not offered, since `LISTEN` holds a server
+//!   connection the pooler shares
+//!
+//! Unnamed statements are not described before they run, so `query`
+//! parameters are sent as untyped text for the server to infer their types,
+//! the parameter count is checked by the server rather than beforehand, and
+//! a result with no rows names no columns. `DB_POOLER=none`, the default,
+//! prepares statements as usual.
+
+use std::fmt;
+use std::sync::OnceLock;
+
+use anyhow::{Result, bail};
+use futures_util::TryStreamExt;
+use serde_json::Value;
+use tokio_postgres::types::{Kind, ToSql, Type};
+use tokio_postgres::{Client, Column, Error, Row, RowStream, Statement};
+
+use crate::config;
+use crate::params::TextParam;
+
+static TRANSACTION_POOLING: OnceLock<bool> = OnceLock::new();
+
+tokio::task_local! {
+    /// The `application_name` of the request being handled.
+    static APPLICATION_NAME: Option<String>;
+}
+
+/// Reads `DB_POOLER`, refusing `ROLE_MAP` with transaction pooling.
+pub(crate) fn init() -> Result<()> {
+    let value = config::var("DB_POOLER").unwrap_or_default();
+    let transaction_pooling = match value.trim().to_ascii_lowercase().as_str() {
+        "" | "none" => false,
+        "pgbouncer" => true,
+        _ => bail!(
+            "Unknown DB_POOLER '{}' (expected 'none' or 'pgbouncer')",
+            value
+        ),
+    };
+    if transaction_pooling && config::var("ROLE_MAP").is_ok_and(|v| !v.is_empty()) {
+        bail!(
+            "ROLE_MAP cannot be used with DB_POOLER=pgbouncer, since SET ROLE through the pooler would carry over to other clients"
+        );
+    }
+    let _ = TRANSACTION_POOLING.set(transaction_pooling);
+    Ok(())
+}
+
+/// Whether connections go through a pooler in transaction pooling mode.
+pub(crate) fn transaction_pooling() -> bool {
+    TRANSACTION_POOLING.get().copied().unwrap_or(false)
+}
+
+/// Handles a request, run as `request`, whose transactions are labelled
+/// with `application_name`.
+pub(crate) async fn labelled<F: Future>(application_name: Option<String>, request: F) -> F::Output {
+    APPLICATION_NAME.scope(application_name, request).await
+}
+
+/// Starts a transaction with `begin`, such as `BEGIN TRANSACTION READ ONLY`.
+/// With transaction pooling, the request's `application_name` is set for the
+/// transaction alone, as a session setting would stay with the server
+/// connection for the pooler's next client.
+pub(crate) async fn begin(client: &Client, begin: &str) -> Result<(), Error> {
+    client.batch_execute(begin).await?;
+    if !transaction_pooling() {
+        return Ok(());
+    }
+    if let Ok(Some(name)) = APPLICATION_NAME.try_with(Clone::clone) {
+        execute(
+            client,
+            "SELECT set_config('application_name', $1, true)",
+            &[(&name, Type::TEXT)],
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// A parameter value and the type it is sent as.
+pub(crate) type Param<'a> = (&'a (dyn ToSql + Sync), Type);
+
+/// The type of a parameter whose type the server infers from the statement.
+pub(crate) fn unknown() -> Type {
+    Type::new(
+        "unknown".to_string(),
+        0,
+        Kind::Pseudo,
+        "pg_catalog".to_string(),
+    )
+}
+
+/// Runs `sql` and returns its rows.
+pub(crate) async fn query(
+    client: &Client,
+    sql: &str,
+    params: &[Param<'_>],
+) -> Result<Vec<Row>, Error> {
+    if transaction_pooling() {
+        client.query_typed(sql, params).await
+    } else {
+        client.query(sql, &values(params)).await
+    }
+}
+
+/// The error of a statement expected to return one row, or at most one.
+#[derive(Debug)]
+pub(crate) enum RowError {
+    Statement(Error),
+    /// The statement returned this many rows instead.
+    Count(usize),
+}
+
+impl From<Error> for RowError {
+    fn from(e: Error) -> Self {
+        Self::Statement(e)
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Statement(e) => e.fmt(f),
+            Self::Count(rows) => {
+                write!(f, "query returned an unexpected number of rows ({})", rows)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Statement(e) => Some(e),
+            Self::Count(_) => None,
+        }
+    }
+}
+
+/// Runs `sql`, which returns exactly one row.
+pub(crate) async fn query_one(
+    client: &Client,
+    sql: &str,
+    params: &[Param<'_>],
+) -> Result<Row, RowError> {
+    one(query(client, sql, params).await?)
+}
+
+/// Runs `sql`, which returns at most one row.
+pub(crate) async fn query_opt(
+    client: &Client,
+    sql: &str,
+    params: &[Param<'_>],
+) -> Result<Option<Row>, RowError> {
+    let mut rows = query(client, sql, params).await?;
+    match rows.len() {
+        0 | 1 => Ok(rows.pop()),
+        count => Err(RowError::Count(count)),
+    }
+}
+
+/// The one row of `rows`. The rows are counted here rather than by
+/// tokio-postgres, whose `query_one` would prepare a named statement.
+fn one(mut rows: Vec<Row>) -> Result<Row, RowError> {
+    match rows.len() {
+        1 => Ok(rows.remove(0)),
+        count => Err(RowError::Count(count)),
+    }
+}
+
+/// Runs `sql` and returns the number of rows it affected.
+pub(crate) async fn execute(
+    client: &Client,
+    sql: &str,
+    params: &[Param<'_>],
+) -> Result<u64, Error> {
+    if transaction_pooling() {
+        let stream = client
+            .query_typed_raw(sql, params.iter().map(|(value, ty)| (*value, ty.clone())))
+            .await?;
+        futures_util::pin_mut!(stream);
+        while stream.try_next().await?.is_some() {}
+        Ok(stream.rows_affected().unwrap_or(0))
+    } else {
+        client.execute(sql, &values(params)).await
+    }
+}
+
+fn values<'a>(params: &[Param<'a>]) -> Vec<&'a (dyn ToSql + Sync)> {
+    params.iter().map(|(value, _)| *value).collect()
+}
+
+/// A statement to run, possibly more than once: prepared, or with
+/// transaction pooling kept as text and sent with its parameters each time.
+/// Its parameters are `TextParam`s, which the server converts either way.
+pub(crate) struct Prepared {
+    sql: String,
+    statement: Option<Statement>,
+}
+
+impl Prepared {
+    pub(crate) async fn new(client: &Client, sql: &str) -> Result<Self, Error> {
+        Self::with_types(client, sql, &[]).await
+    }
+
+    /// Prepares `sql`, giving the types of its first parameters, such as
+    /// those of the statement it wraps.
+    pub(crate) async fn with_types(
+        client: &Client,
+        sql: &str,
+        types: &[Type],
+    ) -> Result<Self, Error> {
+        let statement = if transaction_pooling() {
+            None
+        } else {
+            Some(client.prepare_typed(sql, types).await?)
+        };
+        Ok(Self {
+            sql: sql.to_string(),
+            statement,
+        })
+    }
+
+    /// The parameter types; `None` when the statement is not prepared.
+    pub(crate) fn params(&self) -> Option<&[Type]> {
+        self.statement.as_ref().map(Statement::params)
+    }
+
+    /// The result columns; `None` when the statement is not prepared.
+    pub(crate) fn columns(&self) -> Option<&[Column]> {
+        self.statement.as_ref().map(Statement::columns)
+    }
+
+    /// Whether the statement may return rows: `false` only for a prepared
+    /// statement without result columns.
+    pub(crate) fn returns_rows(&self) -> bool {
+        self.columns().is_none_or(|columns| !columns.is_empty())
+    }
+
+    /// The parameter types to prepare a statement that wraps this one with.
+    pub(crate) fn param_types(&self) -> &[Type] {
+        self.params().unwrap_or_default()
+    }
+
+    /// Converts JSON `values` to this statement's parameters.
+    pub(crate) fn text_params<'a>(
+        &self,
+        values: impl IntoIterator<Item = &'a Value>,
+    ) -> Vec<TextParam> {
+        let unknown = unknown();
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let ty = self
+                    .params()
+                    .and_then(|types| types.get(index))
+                    .unwrap_or(&unknown);
+                TextParam::from_json(value, ty)
+            })
+            .collect()
+    }
+
+    pub(crate) async fn query(
+        &self,
+        client: &Client,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, Error> {
+        match &self.statement {
+            Some(statement) => client.query(statement, params).await,
+            None => client.query_typed(&self.sql, &self.untyped(params)).await,
+        }
+    }
+
+    pub(crate) async fn query_one(
+        &self,
+        client: &Client,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Row, RowError> {
+        one(self.query(client, params).await?)
+    }
+
+    pub(crate) async fn execute(
+        &self,
+        client: &Client,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, Error> {
+        match &self.statement {
+            Some(statement) => client.execute(statement, params).await,
+            None => execute(client, &self.sql, &self.untyped(params)).await,
+        }
+    }
+
+    /// Runs the statement, streaming its rows.
+    pub(crate) async fn query_raw(
+        &self,
+        client: &Client,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<RowStream, Error> {
+        match &self.statement {
+            Some(statement) => client.query_raw(statement, params.iter().copied()).await,
+            None => {
+                client
+                    .query_typed_raw(&self.sql, self.untyped(params))
+                    .await
+            }
+        }
+    }
+
+    /// `params` sent as text for the server to type.
+    fn untyped<'a>(&self, params: &[&'a (dyn ToSql + Sync)]) -> Vec<Param<'a>> {
+        params.iter().map(|value| (*value, unknown())).collect()
+    }
+}
@@ -0,0 +1,320 @@
+//! Built-in prompts, filled in with live schema context.
+//!
+//! `prompts/list` offers a few prompts for common database tasks, and
+//! `prompts/get` fills one in with what the catalogs say now, so the model
+//! starts from the actual definitions and statistics instead of querying for
+//! them first:
+//!
+//! - `analyze-table-stats` (`table`, `schema`): the table's definition, size,
+//!   activity, index usage, and planner statistics.
+//! - `suggest-indexes-for-query` (`query`): the query's estimated plan and the
+//!   definitions, indexes, and row estimates of the tables it reads.
+//! - `summarize-schema` (`schema`): every table of the schema with its
+//!   columns, keys, and row estimate.
+//!
+//! Tables the access lists hide are refused or left out, as elsewhere.
+
+use std::collections::HashMap;
+
+use serde_json::{Value, json};
+use tokio_postgres::types::Type;
+
+use crate::access::{referenced_tables, resolve_tables};
+use crate::catalog::{Catalog, TableDescription};
+use crate::tools::{ToolError, failed};
+use crate::{JsonRpcResponse, McpServer, pooler, quote_ident};
+
+/// The most tables `summarize-schema` includes.
+const MAX_SUMMARY_TABLES: usize = 200;
+
+fn prompt_definitions() -> Value {
+    json!([
+        {
+            "name": "analyze-table-stats",
+            "description": "Review a table's size, activity, index usage, and planner statistics, and recommend maintenance or indexing changes",
+            "arguments": [
+                { "name": "table", "description": "Table to analyze", "required": true },
+                { "name": "schema", "description": "Schema containing the table (default public)", "required": false }
+            ]
+        },
+        {
+            "name": "suggest-indexes-for-query",
+            "description": "Suggest indexes that would speed up a query, given its estimated plan and the existing indexes of the tables it reads",
+            "arguments": [
+                { "name": "query", "description": "The SQL query to speed up", "required": true }
+            ]
+        },
+        {
+            "name": "summarize-schema",
+            "description": "Summarize the tables of a schema, their columns, and how they relate",
+            "arguments": [
+                { "name": "schema", "description": "Schema to summarize (default public)", "required": false }
+            ]
+        }
+    ])
+}
+
+impl McpServer {
+    pub(crate) fn handle_prompts_list(&self, id: Option<Value>) -> JsonRpcResponse {
+        JsonRpcResponse::success(id, json!({ "prompts": prompt_definitions() }))
+    }
+
+    pub(crate) async fn handle_prompts_get(
+        &mut self,
+        id: Option<Value>,
+        params: Option<Value>,
+    ) -> JsonRpcResponse {
+        let params = params.unwrap_or(Value::Null);
+        let Some(name) = params.get("name").and_then(Value::as_str) else {
+            return JsonRpcResponse::error(id, -32602, "Missing 'name' parameter");
+        };
+        let arguments: HashMap<String, String> = match params.get("arguments") {
+            None | Some(Value::Null) => HashMap::new(),
+            Some(arguments) => match serde_json::from_value(arguments.clone()) {
+                Ok(arguments) => arguments,
+                Err(_) => {
+                    return JsonRpcResponse::error(
+                        id,
+                        -32602,
+                        "Prompt arguments must be an object of strings",
+                    );
+                }
+            },
+        };
+        if !prompt_definitions()
+            .as_array()
+            .is_some_and(|prompts| prompts.iter().any(|p| p["name"] == name))
+        {
+            return JsonRpcResponse::error(id, -32602, format!("Unknown prompt: {}", name));
+        }
+        if let Err(e) = self.ensure_connected().await {
+            return JsonRpcResponse::connect_error(id, &e);
+        }
+
+        let argument = |name: &str| arguments.get(name).filter(|v| !v.is_empty());
+        let required = |name: &str| {
+            argument(name)
+                .ok_or_else(|| ToolError::InvalidArguments(format!("Missing '{}' argument", name)))
+        };
+        let schema = argument("schema").map_or("public", String::as_str);
+        let prompt = match name {
+            "analyze-table-stats" => match required("table") {
+                Ok(table) => self.analyze_table_stats(schema, table).await,
+                Err(e) => Err(e),
+            },
+            "suggest-indexes-for-query" => match required("query") {
+                Ok(query) => self.suggest_indexes(query).await,
+                Err(e) => Err(e),
+            },
+            _ => self.summarize_schema(schema).await,
+        };
+        match prompt {
+            Ok((description, text)) => JsonRpcResponse::success(
+                id,
+                json!({
+                    "description": description,
+                    "messages": [
+                        { "role": "user", "content": { "type": "text", "text": text } }
+                    ]
+                }),
+            ),
+            Err(e) => e.into_response(id),
+        }
+    }
+
+    async fn analyze_table_stats(
+        &self,
+        schema: &str,
+        table: &str,
+    ) -> Result<(String, String), ToolError> {
+        self.check_table_access(schema, table)?;
+        let description = self
+            .describe_relations(&[(schema.to_string(), table.to_string())])
+            .await?
+            .pop()
+            .ok_or_else(|| {
+                ToolError::InvalidArguments(format!("Table '{}.{}' not found", schema, table))
+            })?;
+        let qualified = format!("{}.{}", quote_ident(schema), quote_ident(table));
+        let client = self.client.as_ref().unwrap();
+        let row = pooler::query_one(
+            client,
+            "SELECT jsonb_build_object( \
+                     'estimated_rows', CASE WHEN c.reltuples >= 0 THEN c.reltuples::int8 END, \
+                     'total_bytes', pg_total_relation_size(c.oid), \
+                     'table_bytes', pg_relation_size(c.oid), \
+                     'index_bytes', pg_indexes_size(c.oid), \
+                     'activity', (SELECT to_jsonb(s) - 'relid' - 'schemaname' - 'relname' \
+                                  FROM pg_stat_all_tables s WHERE s.relid = c.oid), \
+                     'index_usage', (SELECT coalesce(jsonb_agg(jsonb_build_object( \
+                                         'index', i.indexrelname, 'scans', i.idx_scan, \
+                                         'tuples_read', i.idx_tup_read, \
+                                         'bytes', pg_relation_size(i.indexrelid)) \
+                                         ORDER BY i.indexrelname), '[]') \
+                                     FROM pg_stat_all_indexes i WHERE i.relid = c.oid), \
+                     'column_statistics', (SELECT coalesce(jsonb_agg(jsonb_build_object( \
+                                               'column', st.attname, 'null_frac', st.null_frac, \
+                                               'n_distinct', st.n_distinct, \
+                                               'avg_width', st.avg_width, \
+                                               'correlation', st.correlation) \
+                                               ORDER BY st.attname), '[]') \
+                                           FROM pg_stats st \
+                                           WHERE st.schemaname = $2 AND st.tablename = $3) \
+                 )::text \
+                 FROM pg_class c WHERE c.oid = to_regclass($1)",
+            &[
+                (&qualified, Type::TEXT),
+                (&schema, Type::TEXT),
+                (&table, Type::TEXT),
+            ],
+        )
+        .await
+        .map_err(|e| failed("Failed to read table statistics", e))?;
+        let statistics: Value = serde_json::from_str(row.get(0)).unwrap_or(Value::Null);
+
+        let text = format!(
+            "Analyze the statistics of the PostgreSQL table {schema}.{table} below. Point out \
+             anything that needs attention, such as dead tuples and how long ago the table was \
+             vacuumed and analyzed, indexes that are rarely or never scanned, sequential scans \
+             of a large table, and columns whose statistics look skewed or stale. Recommend \
+             concrete actions, each with the SQL that carries it out.\n\n\
+             Table definition:\n{}\n\n\
+             Statistics from pg_class, pg_stat_all_tables, pg_stat_all_indexes, and pg_stats:\n{}",
+            json_block(&description),
+            json_block(&statistics),
+        );
+        Ok((format!("Statistics of {}.{}", schema, table), text))
+    }
+
+    async fn suggest_indexes(&self, query: &str) -> Result<(String, String), ToolError> {
+        let plan = self.estimated_plan(query).await?;
+        let tables = referenced_tables(query).map_err(ToolError::InvalidArguments)?;
+        let mut relations = resolve_tables(self.client.as_ref().unwrap(), &tables)
+            .await
+            .map_err(|e| failed("Failed to resolve the tables the query refers to", e))?;
+        relations.sort();
+        relations.dedup();
+        let descriptions = self.describe_relations(&relations).await?;
+        let tables = self.with_row_estimates(descriptions).await?;
+
+        let text = format!(
+            "Suggest indexes that would make this PostgreSQL query faster. For each, give the \
+             CREATE INDEX statement, the part of the plan it would improve, and what it would \
+             cost writes to the table. If the existing indexes already serve the query well, \
+             say so rather than suggesting more.\n\n\
+             Query:\n```sql\n{}\n```\n\n\
+             Estimated plan (EXPLAIN, FORMAT JSON):\n{}\n\n\
+             Tables the query reads, with their existing indexes and estimated row counts:\n{}",
+            query.trim(),
+            json_block(&plan),
+            json_block(&tables),
+        );
+        Ok(("Index suggestions for a query".to_string(), text))
+    }
+
+    async fn summarize_schema(&self, schema: &str) -> Result<(String, String), ToolError> {
+        let names = match Catalog::shared().and_then(|c| c.table_names(schema)) {
+            Some(names) => names,
+            None => {
+                let client = self.client.as_ref().unwrap();
+                pooler::query(
+                    client,
+                    "SELECT table_name::text FROM information_schema.tables \
+                         WHERE table_schema = $1 ORDER BY table_name",
+                    &[(&schema, Type::TEXT)],
+                )
+                .await
+                .map_err(|e| failed("Failed to list tables", e))?
+                .iter()
+                .map(|row| row.get(0))
+                .collect()
+            }
+        };
+        let mut names: Vec<String> = names
+            .into_iter()
+            .filter(|table| self.access.allows(schema, table))
+            .collect();
+        names.sort();
+        if names.is_empty() {
+            return Err(ToolError::InvalidArguments(format!(
+                "Schema '{}' has no tables this server can show",
+                schema
+            )));
+        }
+        let omitted = names.len().saturating_sub(MAX_SUMMARY_TABLES);
+        let relations: Vec<(String, String)> = names
+            .into_iter()
+            .take(MAX_SUMMARY_TABLES)
+            .map(|table| (schema.to_string(), table))
+            .collect();
+        let mut descriptions = self.describe_relations(&relations).await?;
+        descriptions.sort_by_key(TableDescription::qualified_name);
+        let summaries: Vec<Value> = descriptions.iter().map(TableDescription::summary).collect();
+        let tables = self.with_row_estimates_of(summaries, &descriptions).await?;
+
+        let mut text = format!(
+            "Summarize the PostgreSQL schema {schema} for someone new to this database: what the \
+             data is about, the main entities and how they relate through foreign keys, and any \
+             naming or design conventions worth knowing.\n\n\
+             Tables, with their columns, keys, and estimated row counts:\n{}",
+            json_block(&tables),
+        );
+        if omitted > 0 {
+            text.push_str(&format!("\n\n{} more tables are not shown.", omitted));
+        }
+        Ok((format!("Summary of schema {}", schema), text))
+    }
+
+    /// `descriptions` as JSON, each with its `estimated_rows`.
+    async fn with_row_estimates(
+        &self,
+        descriptions: Vec<TableDescription>,
+    ) -> Result<Vec<Value>, ToolError> {
+        let values = descriptions
+            .iter()
+            .map(|d| serde_json::to_value(d).unwrap_or(Value::Null))
+            .collect();
+        self.with_row_estimates_of(values, &descriptions).await
+    }
+
+    /// Adds the `estimated_rows` of the table each of `descriptions` describes
+    /// to the matching entry of `values`.
+    async fn with_row_estimates_of(
+        &self,
+        mut values: Vec<Value>,
+        descriptions: &[TableDescription],
+    ) -> Result<Vec<Value>, ToolError> {
+        let names: Vec<String> = descriptions
+            .iter()
+            .map(TableDescription::qualified_name)
+            .collect();
+        let client = self.client.as_ref().unwrap();
+        let rows = pooler::query(
+            client,
+            "SELECT r.name, c.reltuples::float8 \
+                 FROM unnest($1::text[]) AS r(name) \
+                 JOIN pg_class c ON c.oid = to_regclass(r.name)",
+            &[(&names, Type::TEXT_ARRAY)],
+        )
+        .await
+        .map_err(|e| failed("Failed to read row estimates", e))?;
+        let estimates: HashMap<String, f64> =
+            rows.iter().map(|row| (row.get(0), row.get(1))).collect();
+        for (value, name) in values.iter_mut().zip(&names) {
+            if let (Value::Object(value), Some(estimate)) = (value, estimates.get(name)) {
+                // PostgreSQL reports -1 for tables never vacuumed or analyzed.
+                let estimate = (*estimate >= 0.0).then_some(*estimate as i64);
+                value.insert("estimated_rows".to_string(), json!(estimate));
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// `value` as a fenced JSON block.
+fn json_block(value: &impl serde::Serialize) -> String {
+    format!(
+        "```json\n{}\n```",
+        serde_json::to_string_pretty(value).unwrap_or_else(|_| "null".to_string())
+    )
+}
@@ -0,0 +1,352 @@
+//! Bounded queueing of incoming requests.
+//!
+//! Up to `QUEUE_MAX_CONCURRENT` requests (4 by default) are handled at once,
+//! each on its own connection; while that many slow tool calls run, later
+//! requests wait in the queue. `QUEUE_MAX_DEPTH` caps how many may wait and
+//! `QUEUE_MAX_WAIT_MS` how long; requests beyond either limit are answered
+//! immediately with a structured "server busy" error instead of piling up.
+//!
+//! Cheap metadata requests such as `tools/list` travel in a separate priority
+//! lane: they are taken ahead of queued tool calls, need none of the
+//! concurrent slots, and are never rejected, so the server stays responsive
+//! to them however much work is queued.
+//! Cancellations (`notifications/cancelled`) bypass both lanes, to reach the
+//! request they cancel while it is being handled.
+//!
+//! Over HTTP, the sessions' tool calls share the concurrent slots through a
+//! `FairQueue`, under the same limits. Each session waits in a lane of its
+//! own and a freed slot goes to the lanes in turn, so a session sending many
+//! requests at once cannot hold up the others' for longer than one of its
+//! requests per slot.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
+use tokio::time::error::Elapsed;
+use tracing::{error, warn};
+
+use crate::{JsonRpcResponse, config, wirelog};
+
+#[cfg(feature = "http")]
+mod fair;
+
+#[cfg(feature = "http")]
+pub(crate) use fair::FairQueue;
+
+/// JSON-RPC error code for requests rejected because the server is busy.
+const SERVER_BUSY: i32 = -32000;
+
+const DEFAULT_MAX_CONCURRENT: usize = 4;
+
+/// Methods answered without running queries against user data.
+const PRIORITY_METHODS: &[&str] = &[
+    "initialize",
+    "ping",
+    "tools/list",
+    "resources/list",
+    "prompts/list",
+];
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct QueueLimits {
+    max_concurrent: usize,
+    max_depth: Option<usize>,
+    max_wait: Option<Duration>,
+}
+
+impl QueueLimits {
+    /// Reads `QUEUE_MAX_CONCURRENT`, `QUEUE_MAX_DEPTH`, and
+    /// `QUEUE_MAX_WAIT_MS`; for the last two, unset or 0 means no limit.
+    pub(crate) fn from_env() -> Result<Self> {
+        let max_concurrent = match config::var("QUEUE_MAX_CONCURRENT") {
+            Ok(v) => v
+                .parse::<usize>()
+                .context("QUEUE_MAX_CONCURRENT must be a number of requests")?,
+            Err(_) => DEFAULT_MAX_CONCURRENT,
+        };
+        if max_concurrent == 0 {
+            bail!("QUEUE_MAX_CONCURRENT must be at least 1");
+        }
+        let max_depth = match config::var("QUEUE_MAX_DEPTH") {
+            Ok(v) => Some(
+                v.parse::<usize>()
+                    .context("QUEUE_MAX_DEPTH must be a number of requests")?,
+            )
+            .filter(|d| *d > 0),
+            Err(_) => None,
+        };
+        let max_wait = match config::var("QUEUE_MAX_WAIT_MS") {
+            Ok(v) => Some(
+                v.parse::<u64>()
+                    .context("QUEUE_MAX_WAIT_MS must be a number of milliseconds")?,
+            )
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis),
+            Err(_) => None,
+        };
+        Ok(Self {
+            max_concurrent,
+            max_depth,
+            max_wait,
+        })
+    }
+}
+
+/// A line read from stdin, when it arrived, and whether it was in the
+/// priority lane.
+pub(crate) struct QueuedLine {
+    pub(crate) line: io::Result<String>,
+    pub(crate) received: Instant,
+    pub(crate) priority: bool,
+    /// The concurrent slot to hold while the request is handled; priority
+    /// requests take none.
+    pub(crate) permit: Option<OwnedSemaphorePermit>,
+}
+
+/// The receiving end of the request queue.
+pub(crate) struct RequestQueue {
+    rx: mpsc::UnboundedReceiver<QueuedLine>,
+    priority_rx: mpsc::UnboundedReceiver<QueuedLine>,
+    /// The parameters of `notifications/cancelled` notifications, until the
+    /// main loop takes them.
+    cancel_rx: Option<mpsc::UnboundedReceiver<Option<Value>>>,
+    depth: Arc<AtomicUsize>,
+    /// Slots for requests being handled at once.
+    permits: Arc<Semaphore>,
+    limits: QueueLimits,
+}
+
+impl RequestQueue {
+    /// Reads stdin in a task of its own so the main loop can notice when the
+    /// session has gone idle while waiting for the next request. Requests that
+    /// would exceed the maximum depth are rejected by the reader.
+    pub(crate) fn spawn_stdin_reader(limits: QueueLimits) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (priority_tx, priority_rx) = mpsc::unbounded_channel();
+        let (cancel_tx, cancel_rx) = mpsc::unbounded_channel();
+        let depth = Arc::new(AtomicUsize::new(0));
+        let reader_depth = depth.clone();
+        tokio::spawn(async move {
+            let mut stdin = BufReader::new(tokio::io::stdin());
+            let mut buf = Vec::new();
+            while let Some(line) = read_line(&mut stdin, &mut buf).await {
+                let failed = line
+                    .as_ref()
+                    .is_err_and(|e| e.kind() != io::ErrorKind::InvalidData);
+                if let Ok(line) = &line
+                    && !line.trim().is_empty()
+                {
+                    wirelog::inbound(line);
+                }
+                let request = line
+                    .as_ref()
+                    .ok()
+                    .and_then(|l| serde_json::from_str::<Value>(l).ok());
+                if let Some(request) = &request
+                    && request.get("method").and_then(|m| m.as_str())
+                        == Some("notifications/cancelled")
+                {
+                    if cancel_tx.send(request.get("params").cloned()).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                if request.as_ref().is_some_and(is_priority) {
+                    let queued = QueuedLine {
+                        line,
+                        received: Instant::now(),
+                        priority: true,
+                        permit: None,
+                    };
+                    if priority_tx.send(queued).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+
+                if let (Some(max_depth), Some(request)) = (limits.max_depth, &request) {
+                    let queued = reader_depth.load(Ordering::SeqCst);
+                    if queued >= max_depth
+                        && let Some(id) = request.get("id").filter(|id| !id.is_null())
+                    {
+                        write_response(&queue_full(Some(id.clone()), queued, max_depth));
+                        continue;
+                    }
+                }
+                reader_depth.fetch_add(1, Ordering::SeqCst);
+                let queued = QueuedLine {
+                    line,
+                    received: Instant::now(),
+                    priority: false,
+                    permit: None,
+                };
+                if tx.send(queued).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Self {
+            rx,
+            priority_rx,
+            cancel_rx: Some(cancel_rx),
+            depth,
+            permits: Arc::new(Semaphore::new(limits.max_concurrent)),
+            limits,
+        }
+    }
+
+    /// Returns the next request, taking the priority lane first. Other
+    /// requests are only taken once a concurrent slot is free for them.
+    pub(crate) async fn recv(&mut self) -> Option<QueuedLine> {
+        let Self {
+            rx,
+            priority_rx,
+            depth,
+            permits,
+            ..
+        } = self;
+        tokio::select! {
+            biased;
+            Some(queued) = priority_rx.recv() => Some(queued),
+            Some(queued) = async {
+                let permit = permits.clone().acquire_owned().await.ok()?;
+                let mut queued = rx.recv().await?;
+                depth.fetch_sub(1, Ordering::SeqCst);
+                queued.permit = Some(permit);
+                Some(queued)
+            } => Some(queued),
+            else => None,
+        }
+    }
+
+    /// The receiver of the parameters of cancellations. Can be taken once.
+    pub(crate) fn take_cancellations(&mut self) -> mpsc::UnboundedReceiver<Option<Value>> {
+        self.cancel_rx
+            .take()
+            .unwrap_or_else(|| mpsc::unbounded_channel().1)
+    }
+
+    /// Like `recv`, but gives up once `timeout` elapses without a request.
+    pub(crate) async fn recv_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<QueuedLine>, Elapsed> {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.recv()).await,
+            None => Ok(self.recv().await),
+        }
+    }
+
+    /// Returns a "server busy" response if a request received at `received`
+    /// waited longer than the maximum wait before it could be handled.
+    /// Priority requests are never rejected.
+    pub(crate) fn check_wait(
+        &self,
+        received: Instant,
+        priority: bool,
+        id: &Option<Value>,
+    ) -> Option<JsonRpcResponse> {
+        let max_wait = self.limits.max_wait?;
+        let waited = received.elapsed();
+        if waited <= max_wait || priority || id.is_none() {
+            return None;
+        }
+        Some(queue_timeout(id.clone(), waited, max_wait))
+    }
+}
+
+/// Reads the next line from `reader` into `buf`, without its line ending, or
+/// returns `None` at the end of the input. A line that is not valid UTF-8
+/// is an `InvalidData` error, after which reading can go on.
+async fn read_line<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    buf: &mut Vec<u8>,
+) -> Option<io::Result<String>> {
+    buf.clear();
+    match reader.read_until(b'\n', buf).await {
+        Ok(0) => None,
+        Ok(_) => {
+            if buf.ends_with(b"\n") {
+                buf.pop();
+                if buf.ends_with(b"\r") {
+                    buf.pop();
+                }
+            }
+            Some(
+                String::from_utf8(std::mem::take(buf))
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            )
+        }
+        Err(e) => Some(Err(e)),
+    }
+}
+
+/// Whether a request belongs in the priority lane: a metadata method or a
+/// notification, which expects no response and does no work.
+fn is_priority(request: &Value) -> bool {
+    is_priority_method(request.get("method").and_then(|m| m.as_str()).unwrap_or(""))
+}
+
+/// Whether requests for `method` skip the queue.
+pub(crate) fn is_priority_method(method: &str) -> bool {
+    PRIORITY_METHODS.contains(&method) || method.starts_with("notifications/")
+}
+
+fn queue_full(id: Option<Value>, queued: usize, max_depth: usize) -> JsonRpcResponse {
+    warn!("Request queue full ({} waiting), rejecting request", queued);
+    server_busy(
+        id,
+        format!("Server busy: {} requests are already queued", queued),
+        json!({
+            "reason": "queue_full",
+            "queue_depth": queued,
+            "max_queue_depth": max_depth,
+        }),
+    )
+}
+
+fn queue_timeout(id: Option<Value>, waited: Duration, max_wait: Duration) -> JsonRpcResponse {
+    warn!(
+        "Request waited {}ms in the queue, rejecting it",
+        waited.as_millis()
+    );
+    server_busy(
+        id,
+        format!(
+            "Server busy: request waited {}ms in the queue",
+            waited.as_millis()
+        ),
+        json!({
+            "reason": "queue_timeout",
+            "waited_ms": waited.as_millis() as u64,
+            "max_wait_ms": max_wait.as_millis() as u64,
+        }),
+    )
+}
+
+pub(crate) fn server_busy(id: Option<Value>, message: String, mut data: Value) -> JsonRpcResponse {
+    let mut response = JsonRpcResponse::error(id, SERVER_BUSY, message);
+    data["type"] = json!("server_busy");
+    if let Some(error) = response.error.as_mut() {
+        error.data = Some(data);
+    }
+    response
+}
+
+fn write_response(response: &JsonRpcResponse) {
+    wirelog::outbound(response);
+    let mut stdout = io::stdout().lock();
+    let written = serde_json::to_string(response)
+        .map_err(io::Error::other)
+        .and_then(|line| writeln!(stdout, "{}", line))
+        .and_then(|_| stdout.flush());
+    if let Err(e) = written {
+        error!("Failed to write response: {}", e);
+    }
+}
@@ -0,0 +1,222 @@
+//! The queue the HTTP transport's sessions share.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+use super::{QueueLimits, queue_full, queue_timeout};
+use crate::JsonRpcResponse;
+
+/// The concurrent slots shared by the requests of many sessions, each
+/// session waiting for them in a lane of its own.
+pub(crate) struct FairQueue {
+    lanes: Arc<Mutex<Lanes>>,
+    limits: QueueLimits,
+}
+
+struct Lanes {
+    /// Slots no request holds.
+    free: usize,
+    /// The sessions with requests waiting, in the order they are served,
+    /// and their requests in the order they arrived.
+    waiting: VecDeque<(String, VecDeque<oneshot::Sender<()>>)>,
+    /// How many requests wait.
+    depth: usize,
+}
+
+impl Lanes {
+    /// Gives a slot that was freed to the first request of the next lane,
+    /// which then goes to the back of the line, or keeps it if none waits.
+    fn release(&mut self) {
+        while let Some((session, mut requests)) = self.waiting.pop_front() {
+            let next = requests.pop_front();
+            if !requests.is_empty() {
+                self.waiting.push_back((session, requests));
+            }
+            let Some(next) = next else {
+                continue;
+            };
+            self.depth -= 1;
+            // A request that gave up waiting has dropped its receiver.
+            if next.send(()).is_ok() {
+                return;
+            }
+        }
+        self.free += 1;
+    }
+
+    /// Forgets the requests that gave up waiting.
+    fn forget_abandoned(&mut self) {
+        for (_, requests) in &mut self.waiting {
+            let before = requests.len();
+            requests.retain(|request| !request.is_closed());
+            self.depth -= before - requests.len();
+        }
+        self.waiting.retain(|(_, requests)| !requests.is_empty());
+    }
+}
+
+/// A concurrent slot, given back to the queue when dropped.
+pub(crate) struct Slot(Arc<Mutex<Lanes>>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        lock(&self.0).release();
+    }
+}
+
+impl FairQueue {
+    pub(crate) fn new(limits: QueueLimits) -> Self {
+        Self {
+            lanes: Arc::new(Mutex::new(Lanes {
+                free: limits.max_concurrent,
+                waiting: VecDeque::new(),
+                depth: 0,
+            })),
+            limits,
+        }
+    }
+
+    /// Waits in `session`'s lane for a slot for the request `id`, received
+    /// at `received`. Returns a "server busy" response instead when the
+    /// queue is full or the request waits past the maximum wait.
+    pub(crate) async fn acquire(
+        &self,
+        session: &str,
+        received: Instant,
+        id: &Option<Value>,
+    ) -> Result<Slot, JsonRpcResponse> {
+        let mut granted = {
+            let mut lanes = lock(&self.lanes);
+            if lanes.free > 0 {
+                lanes.free -= 1;
+                return Ok(Slot(self.lanes.clone()));
+            }
+            if let Some(max_depth) = self.limits.max_depth
+                && lanes.depth >= max_depth
+            {
+                return Err(queue_full(id.clone(), lanes.depth, max_depth));
+            }
+            let (sender, granted) = oneshot::channel();
+            match lanes.waiting.iter_mut().find(|(lane, _)| lane == session) {
+                Some((_, requests)) => requests.push_back(sender),
+                None => lanes
+                    .waiting
+                    .push_back((session.to_string(), VecDeque::from([sender]))),
+            }
+            lanes.depth += 1;
+            granted
+        };
+        let waited = match self.limits.max_wait {
+            Some(max_wait) => {
+                let left = max_wait.saturating_sub(received.elapsed());
+                tokio::time::timeout(left, &mut granted).await.ok()
+            }
+            None => Some((&mut granted).await),
+        };
+        match waited {
+            Some(Ok(())) => Ok(Slot(self.lanes.clone())),
+            // The lanes outlive the requests waiting in them, so only
+            // running out of time ends a wait.
+            _ => {
+                let mut lanes = lock(&self.lanes);
+                // A slot given as the wait ran out goes to the next request.
+                granted.close();
+                if granted.try_recv().is_ok() {
+                    lanes.release();
+                }
+                lanes.forget_abandoned();
+                let max_wait = self.limits.max_wait.unwrap_or_default();
+                Err(queue_timeout(id.clone(), received.elapsed(), max_wait))
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    fn limits(max_depth: Option<usize>, max_wait: Option<Duration>) -> QueueLimits {
+        QueueLimits {
+            max_concurrent: 1,
+            max_depth,
+            max_wait,
+        }
+    }
+
+    fn reason(busy: JsonRpcResponse) -> Value {
+        busy.error.and_then(|e| e.data).unwrap()["reason"].clone()
+    }
+
+    /// Lets spawned requests run until `depth` of them wait.
+    async fn wait_for_depth(queue: &FairQueue, depth: usize) {
+        while lock(&queue.lanes).depth < depth {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn flooding_sessions_take_turns_with_others() {
+        let queue = Arc::new(FairQueue::new(limits(None, None)));
+        let held = queue.acquire("flood", Instant::now(), &None).await.unwrap();
+        let (served, mut order) = mpsc::unbounded_channel();
+        let sessions = std::iter::repeat_n("flood", 10).chain(["other"]);
+        for session in sessions {
+            let queue = queue.clone();
+            let served = served.clone();
+            tokio::spawn(async move {
+                let _slot = queue.acquire(session, Instant::now(), &None).await;
+                served.send(session).unwrap();
+            });
+        }
+        drop(served);
+        wait_for_depth(&queue, 11).await;
+        drop(held);
+
+        let mut sessions = Vec::new();
+        while let Some(session) = order.recv().await {
+            sessions.push(session);
+        }
+        assert_eq!(sessions.len(), 11);
+        assert_eq!(&sessions[..2], ["flood", "other"]);
+    }
+
+    #[tokio::test]
+    async fn full_queues_turn_requests_away() {
+        let queue = Arc::new(FairQueue::new(limits(Some(1), None)));
+        let held = queue.acquire("a", Instant::now(), &None).await.unwrap();
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire("a", Instant::now(), &None).await.is_ok() }
+        });
+        wait_for_depth(&queue, 1).await;
+        let busy = queue.acquire("b", Instant::now(), &Some(json!(2))).await;
+        assert_eq!(reason(busy.err().unwrap()), "queue_full");
+        drop(held);
+        assert!(waiting.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn requests_waiting_too_long_are_turned_away() {
+        let queue = FairQueue::new(limits(None, Some(Duration::from_millis(10))));
+        let held = queue.acquire("a", Instant::now(), &None).await.unwrap();
+        let busy = queue.acquire("b", Instant::now(), &Some(json!(2))).await;
+        assert_eq!(reason(busy.err().unwrap()), "queue_timeout");
+        assert_eq!(lock(&queue.lanes).depth, 0);
+        drop(held);
+        assert!(queue.acquire("b", Instant::now(), &None).await.is_ok());
+    }
+}
@@ -0,0 +1,132 @@
+//! Reconnecting when the database restarts or fails over.
+//!
+//! A restart or failover closes every connection to the database. The pool
+//! discards a closed connection when it comes back, but until the database
+//! accepts connections again, opening a new one fails as well. So a checkout
+//! that fails the way one to a restarting database does (refused, reset,
+//! closed, timed out, or rejected with `cannot_connect_now` or
+//! `admin_shutdown`) is retried with exponential backoff and jitter for up to
+//! `DB_RECONNECT_TIMEOUT_SECS` (30 by default; 0 turns retrying off). The
+//! `LISTEN` connection of resource subscriptions is reopened the same way
+//! for as long as the session lasts.
+//!
+//! A request that fails for a reason a later attempt may get past, such as a
+//! connection lost while its statement ran, a database that stayed
+//! unreachable, or a serialization failure or deadlock, carries
+//! `"retryable": true` in its error data.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use deadpool_postgres::{PoolError, TimeoutType};
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio_postgres::error::SqlState;
+
+use crate::config;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(250);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(4);
+
+/// Reads `DB_RECONNECT_TIMEOUT_SECS`, how long a checkout may keep retrying
+/// while the database is unreachable; `None` when it is 0, which turns
+/// retrying off.
+pub(crate) fn timeout_from_env() -> Result<Option<Duration>> {
+    match config::var("DB_RECONNECT_TIMEOUT_SECS") {
+        Ok(v) => match v
+            .parse::<u64>()
+            .context("DB_RECONNECT_TIMEOUT_SECS must be a number of seconds")?
+        {
+            0 => Ok(None),
+            secs => Ok(Some(Duration::from_secs(secs))),
+        },
+        Err(_) => Ok(Some(Duration::from_secs(DEFAULT_TIMEOUT_SECS))),
+    }
+}
+
+/// Why a checkout that a later one may get past failed, such as the database
+/// not coming back within the reconnect timeout.
+#[derive(Debug)]
+pub(crate) struct Unreachable(pub(crate) String);
+
+impl fmt::Display for Unreachable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Whether the failed checkout `e` may succeed once the database accepts
+/// connections again.
+pub(crate) fn is_transient(e: &PoolError) -> bool {
+    match e {
+        PoolError::Timeout(TimeoutType::Create) => true,
+        PoolError::Backend(e) => match e.code() {
+            Some(code) => {
+                code == &SqlState::CANNOT_CONNECT_NOW
+                    || code == &SqlState::ADMIN_SHUTDOWN
+                    || code.code().starts_with("08")
+                    // Neon's proxy reports a compute that did not start in
+                    // time as an internal error.
+                    || (code == &SqlState::INTERNAL_ERROR
+                        && e.as_db_error()
+                            .is_some_and(|db| db.message().contains("compute node")))
+            }
+            None => is_connection_lost(e),
+        },
+        _ => false,
+    }
+}
+
+/// Whether `e` is the connection closing or failing, rather than the server
+/// reporting an error.
+pub(crate) fn is_connection_lost(e: &tokio_postgres::Error) -> bool {
+    e.is_closed()
+        || e.source()
+            .and_then(|source| source.downcast_ref::<io::Error>())
+            .is_some_and(|io| {
+                matches!(
+                    io.kind(),
+                    io::ErrorKind::ConnectionRefused
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::UnexpectedEof
+                )
+            })
+}
+
+/// Whether running the statement again may succeed where it failed with the
+/// server error `code`: after a serialization failure or deadlock, or once
+/// the database that shut down or refused the connection is back.
+pub(crate) fn is_retryable_state(code: &SqlState) -> bool {
+    code == &SqlState::T_R_SERIALIZATION_FAILURE
+        || code == &SqlState::T_R_DEADLOCK_DETECTED
+        || code == &SqlState::ADMIN_SHUTDOWN
+        || code == &SqlState::CRASH_SHUTDOWN
+        || code == &SqlState::CANNOT_CONNECT_NOW
+        || code.code().starts_with("08")
+}
+
+/// Whether the failed connection attempt `e` may succeed later.
+pub(crate) fn is_retryable(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<Unreachable>().is_some()
+}
+
+/// How long to wait before retrying after `attempt` failed attempts: twice
+/// as long as the time before, up to a cap, less a random part of up to half
+/// so that clients cut off together do not all retry together.
+pub(crate) fn retry_delay(attempt: u32) -> Duration {
+    let delay = FIRST_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY);
+    let mut random = [0u8; 2];
+    if SystemRandom::new().fill(&mut random).is_err() {
+        return delay;
+    }
+    let fraction = f64::from(u16::from_le_bytes(random)) / f64::from(u16::MAX);
+    delay.mul_f64(1.0 - fraction / 2.0)
+}
@@ -0,0 +1,131 @@
+//! Capping how much a `query` result returns.
+//!
+//! A `SELECT * FROM events` can return millions of rows, hundreds of
+//! megabytes that the client has to take in and no agent can read. So a
+//! result stops at `MAX_RESULT_ROWS` rows (1000 by default) or once its rows
+//! take `MAX_RESULT_BYTES` of JSON (10 MiB by default), whichever comes first;
+//! 0 turns either limit off. A query that returns more rows than the limit is
+//! paged like one run with `max_rows`, so only the rows returned are read from
+//! the database, and the rest are a `cursor` away. Statements that cannot be
+//! paged, such as `INSERT ... RETURNING` or those run in a transaction, are cut
+//! short with no cursor.
+//!
+//! A truncated result says so in its `_meta.truncated` and in a text item:
+//! how many rows it returned, how many the statement returned in all when that
+//! is known (a paged query's rows are not counted ahead), and the cursor to
+//! continue from. A result always holds at least one row, however large.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config;
+
+const DEFAULT_MAX_ROWS: usize = 1000;
+const DEFAULT_MAX_BYTES: usize = 10 * 1024 * 1024;
+
+/// The most rows and bytes of rows a `query` result returns.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ResultLimits {
+    pub(crate) max_rows: Option<usize>,
+    pub(crate) max_bytes: Option<usize>,
+}
+
+impl ResultLimits {
+    /// Reads `MAX_RESULT_ROWS` and `MAX_RESULT_BYTES`; either is `None` when
+    /// it is 0, which turns the limit off.
+    pub(crate) fn from_env() -> Result<Self> {
+        Ok(Self {
+            max_rows: limit_from_env("MAX_RESULT_ROWS", DEFAULT_MAX_ROWS, "a number of rows")?,
+            max_bytes: limit_from_env("MAX_RESULT_BYTES", DEFAULT_MAX_BYTES, "a number of bytes")?,
+        })
+    }
+
+    /// How many of `rows` fit within `MAX_RESULT_BYTES`, if not all of them.
+    pub(crate) fn fit_bytes(&self, rows: &[HashMap<String, Value>]) -> Option<usize> {
+        let max_bytes = self.max_bytes?;
+        let mut bytes = 0;
+        for (index, row) in rows.iter().enumerate() {
+            // The row and the comma separating it from the next.
+            bytes += serde_json::to_vec(row).map_or(0, |json| json.len()) + 1;
+            if bytes > max_bytes {
+                return Some(index.max(1)).filter(|&fit| fit < rows.len());
+            }
+        }
+        None
+    }
+
+    /// Cuts `rows`, all the rows of a statement, down to the limits,
+    /// returning what was cut if anything was.
+    pub(crate) fn truncate(&self, rows: &mut Vec<HashMap<String, Value>>) -> Option<Truncation> {
+        let available = rows.len();
+        let (fit, limit) = match (self.fit_bytes(rows), self.max_rows) {
+            (Some(fit), Some(max_rows)) if max_rows < fit => (max_rows, "MAX_RESULT_ROWS"),
+            (Some(fit), _) => (fit, "MAX_RESULT_BYTES"),
+            (None, Some(max_rows)) if max_rows < available => (max_rows, "MAX_RESULT_ROWS"),
+            (None, _) => return None,
+        };
+        rows.truncate(fit);
+        Some(Truncation {
+            limit,
+            rows_returned: fit,
+            rows_available: Some(available),
+            next_cursor: None,
+        })
+    }
+}
+
+fn limit_from_env(name: &str, default: usize, unit: &str) -> Result<Option<usize>> {
+    match config::var(name) {
+        Ok(v) => match v
+            .parse::<usize>()
+            .with_context(|| format!("{} must be {}", name, unit))?
+        {
+            0 => Ok(None),
+            limit => Ok(Some(limit)),
+        },
+        Err(_) => Ok(Some(default)),
+    }
+}
+
+/// How a `query` result was cut short, as listed in its `_meta.truncated`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Truncation {
+    /// The setting that cut it short: "MAX_RESULT_ROWS" or "MAX_RESULT_BYTES".
+    pub(crate) limit: &'static str,
+    pub(crate) rows_returned: usize,
+    /// How many rows the statement returned in all; `None` for a paged query,
+    /// whose remaining rows have not been read.
+    pub(crate) rows_available: Option<usize>,
+    /// The cursor to pass to fetch the rest, if they can be fetched.
+    pub(crate) next_cursor: Option<String>,
+}
+
+impl Truncation {
+    /// The text item telling the caller what was left out.
+    pub(crate) fn note(&self) -> String {
+        let shown = match self.rows_available {
+            Some(available) => format!(
+                "The result was cut short at {}: {} of its {} rows are shown.",
+                self.limit, self.rows_returned, available
+            ),
+            None => format!(
+                "The result was cut short at {}: the first {} rows are shown, and more follow.",
+                self.limit, self.rows_returned
+            ),
+        };
+        match &self.next_cursor {
+            Some(cursor) => format!(
+                "{} Pass \"cursor\": \"{}\" to continue, or narrow the query with WHERE, LIMIT, or fewer columns",
+                shown, cursor
+            ),
+            None => format!(
+                "{} The rest cannot be fetched with a cursor: narrow the query with WHERE, LIMIT, or fewer columns",
+                shown
+            ),
+        }
+    }
+}
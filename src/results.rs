@@ -0,0 +1,300 @@
+//! Query results kept on the server for a while, to be read again.
+//!
+//! `materialize_result` runs a read-only query and keeps its rows in the
+//! session's memory as a `postgres:///results/<name>` resource, so an agent
+//! can page through a large intermediate result as often as it needs without
+//! running the query again or carrying the rows in its context. Each result
+//! lasts for the `ttl_secs` it was made with and at most as long as the
+//! session. The session's results together may hold `MATERIALIZE_MAX_BYTES`
+//! of row data; keeping a new one discards the oldest until it fits.
+//!
+//! Reads take `limit` (100 rows by default, at most 1000), `offset`, and
+//! `format` (or `accept`) query parameters like table resources, for example
+//! `postgres:///results/<name>?offset=200&limit=100&format=csv`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use futures_util::{TryStreamExt, pin_mut};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{Value, json};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, Column};
+
+use crate::format::ResultFormat;
+use crate::pooler::Prepared;
+use crate::{
+    DEFAULT_RESOURCE_LIMIT, JsonRpcResponse, MAX_RESOURCE_LIMIT, McpServer, config, percent_decode,
+    row_to_map,
+};
+
+/// The URI prefix of materialized result resources.
+pub(crate) const RESULTS_URI_PREFIX: &str = "postgres:///results/";
+
+const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// A materialized query result.
+pub(crate) struct MaterializedResult {
+    pub(crate) name: String,
+    pub(crate) columns: Vec<String>,
+    pub(crate) rows: Vec<HashMap<String, Value>>,
+    /// The rows' size as JSON.
+    pub(crate) bytes: u64,
+    pub(crate) expires: Instant,
+}
+
+impl MaterializedResult {
+    pub(crate) fn uri(&self) -> String {
+        format!("{}{}", RESULTS_URI_PREFIX, self.name)
+    }
+
+    /// The result's `resources/list` entry.
+    fn resource(&self) -> Value {
+        json!({
+            "uri": self.uri(),
+            "name": self.name,
+            "description": format!(
+                "Materialized query result ({} rows, expires in {}s)",
+                self.rows.len(),
+                self.expires.saturating_duration_since(Instant::now()).as_secs()
+            ),
+            "mimeType": "application/json",
+        })
+    }
+}
+
+/// The session's materialized results, oldest first. Clones share them.
+#[derive(Clone)]
+pub(crate) struct MaterializedResults {
+    max_bytes: u64,
+    results: Arc<Mutex<Vec<MaterializedResult>>>,
+    random: SystemRandom,
+}
+
+impl MaterializedResults {
+    /// Reads `MATERIALIZE_MAX_BYTES`; a quota of 0 disables materializing.
+    pub(crate) fn from_env() -> Result<Self> {
+        let max_bytes = match config::var("MATERIALIZE_MAX_BYTES") {
+            Ok(v) => v
+                .parse::<u64>()
+                .context("MATERIALIZE_MAX_BYTES must be a number of bytes")?,
+            Err(_) => DEFAULT_MAX_BYTES,
+        };
+        Ok(Self {
+            max_bytes,
+            results: Arc::new(Mutex::new(Vec::new())),
+            random: SystemRandom::new(),
+        })
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    /// The most row data one result may hold.
+    pub(crate) fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Keeps `rows` for `ttl`, discarding the oldest results until they fit,
+    /// and returns the new result's URI.
+    pub(crate) fn add(
+        &self,
+        columns: Vec<String>,
+        rows: Vec<HashMap<String, Value>>,
+        bytes: u64,
+        ttl: Duration,
+    ) -> Result<String> {
+        let mut results = self.unexpired();
+        while results.iter().map(|r| r.bytes).sum::<u64>() + bytes > self.max_bytes
+            && !results.is_empty()
+        {
+            results.remove(0);
+        }
+        let mut token = [0u8; 12];
+        self.random
+            .fill(&mut token)
+            .map_err(|_| anyhow!("no random numbers for a result name"))?;
+        let result = MaterializedResult {
+            name: token.iter().map(|b| format!("{:02x}", b)).collect(),
+            columns,
+            rows,
+            bytes,
+            expires: Instant::now() + ttl,
+        };
+        let uri = result.uri();
+        results.push(result);
+        Ok(uri)
+    }
+
+    /// The `resources/list` entries of the results that have not expired.
+    pub(crate) fn resources(&self) -> Vec<Value> {
+        self.unexpired().iter().map(|r| r.resource()).collect()
+    }
+
+    /// The results that have not expired, locked.
+    fn unexpired(&self) -> MutexGuard<'_, Vec<MaterializedResult>> {
+        let mut results = self.results.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        results.retain(|r| r.expires > now);
+        results
+    }
+}
+
+/// Runs `statement` and collects its rows with their size as JSON and their
+/// column names, or returns `None` as soon as they grow past `max_bytes`.
+pub(crate) async fn collect_rows(
+    client: &Client,
+    statement: &Prepared,
+    params: &[&(dyn ToSql + Sync)],
+    max_bytes: u64,
+) -> Result<Option<(Vec<HashMap<String, Value>>, Vec<String>, u64)>, tokio_postgres::Error> {
+    let stream = statement.query_raw(client, params).await?;
+    pin_mut!(stream);
+    let mut rows = Vec::new();
+    let mut columns = statement.columns().map(column_names);
+    let mut bytes = 0;
+    while let Some(row) = stream.try_next().await? {
+        if columns.is_none() {
+            columns = Some(column_names(row.columns()));
+        }
+        let row = row_to_map(&row);
+        bytes += serde_json::to_vec(&row).map_or(0, |json| json.len() as u64);
+        if bytes > max_bytes {
+            return Ok(None);
+        }
+        rows.push(row);
+    }
+    Ok(Some((rows, columns.unwrap_or_default(), bytes)))
+}
+
+fn column_names(columns: &[Column]) -> Vec<String> {
+    columns.iter().map(|c| c.name().to_string()).collect()
+}
+
+impl McpServer {
+    /// Reads a page of a materialized result, `rest` being the URI after
+    /// `RESULTS_URI_PREFIX`. Needs no connection.
+    pub(crate) fn read_materialized_result(
+        &self,
+        id: Option<Value>,
+        uri: &str,
+        rest: &str,
+    ) -> JsonRpcResponse {
+        let (name, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let page = match Page::parse(query) {
+            Ok(page) => page,
+            Err(e) => return JsonRpcResponse::error(id, -32602, e.to_string()),
+        };
+        let results = self.results.unexpired();
+        let Some(result) = results.iter().find(|r| r.name == name) else {
+            return JsonRpcResponse::error(
+                id,
+                -32602,
+                format!(
+                    "No materialized result '{}': it expired, or was made by another session",
+                    name
+                ),
+            );
+        };
+        let rows: Vec<HashMap<String, Value>> = result
+            .rows
+            .iter()
+            .skip(page.offset)
+            .take(page.limit)
+            .cloned()
+            .collect();
+        let next_offset = page.offset + page.limit;
+        let next_uri = (next_offset < result.rows.len()).then(|| {
+            let mut next = format!(
+                "{}?offset={}&limit={}",
+                result.uri(),
+                next_offset,
+                page.limit
+            );
+            if page.format != ResultFormat::Json {
+                next.push_str(&format!("&format={}", page.format.name()));
+            }
+            next
+        });
+        JsonRpcResponse::success(
+            id,
+            json!({
+                "contents": [
+                    {
+                        "uri": uri,
+                        "mimeType": page.format.mime_type(),
+                        "text": page.format.render(&result.columns, &rows),
+                        "_meta": {
+                            "offset": page.offset,
+                            "limit": page.limit,
+                            "totalRows": result.rows.len(),
+                            "nextUri": next_uri,
+                            "expiresInSecs": result
+                                .expires
+                                .saturating_duration_since(Instant::now())
+                                .as_secs(),
+                        }
+                    }
+                ]
+            }),
+        )
+    }
+}
+
+/// The rows and format a read of a materialized result asks for.
+struct Page {
+    limit: usize,
+    offset: usize,
+    format: ResultFormat,
+}
+
+impl Page {
+    fn parse(query: &str) -> Result<Self> {
+        let mut page = Page {
+            limit: DEFAULT_RESOURCE_LIMIT as usize,
+            offset: 0,
+            format: ResultFormat::Json,
+        };
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(value);
+            match key {
+                "limit" => {
+                    page.limit = value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|l| (1..=MAX_RESOURCE_LIMIT as usize).contains(l))
+                        .with_context(|| {
+                            format!("'limit' must be between 1 and {}", MAX_RESOURCE_LIMIT)
+                        })?;
+                }
+                "offset" => {
+                    page.offset = value
+                        .parse::<usize>()
+                        .context("'offset' must be a non-negative integer")?;
+                }
+                "format" => {
+                    page.format = ResultFormat::parse(&value).with_context(|| {
+                        format!(
+                            "Unknown format '{}' (expected 'json', 'csv', or 'markdown')",
+                            value
+                        )
+                    })?;
+                }
+                "accept" => {
+                    page.format = ResultFormat::from_mime_type(&value).with_context(|| {
+                        format!(
+                            "Unsupported accept type '{}' (expected 'application/json', 'text/csv', or 'text/markdown')",
+                            value
+                        )
+                    })?;
+                }
+                _ => anyhow::bail!("Unknown resource URI parameter '{}'", key),
+            }
+        }
+        Ok(page)
+    }
+}
@@ -0,0 +1,106 @@
+//! Sandbox schemas, dropped once the session goes idle.
+//!
+//! An agent trying something out often needs tables of its own, which are
+//! easy to forget once it is done. With `SANDBOX_SCHEMA_PREFIX` set, a
+//! schema the session creates with `CREATE SCHEMA` whose name starts with
+//! the prefix, such as `sandbox_orders` for `sandbox_`, is marked ephemeral:
+//! when the session goes idle for `IDLE_TIMEOUT_SECS`, it is dropped with
+//! everything in it. Schemas created otherwise, with `IF NOT EXISTS`, or by
+//! other sessions are never dropped.
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use sqlparser::ast::{SchemaName, Statement};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use tokio_postgres::Client;
+use tracing::warn;
+
+use crate::{config, quote_ident};
+
+/// The session's ephemeral sandbox schemas. Clones share them.
+#[derive(Clone)]
+pub(crate) struct Sandboxes {
+    prefix: Option<String>,
+    schemas: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl Sandboxes {
+    /// Reads `SANDBOX_SCHEMA_PREFIX`; unset or empty, no schema is a
+    /// sandbox.
+    pub(crate) fn from_env() -> Self {
+        Self {
+            prefix: config::var("SANDBOX_SCHEMA_PREFIX")
+                .ok()
+                .filter(|v| !v.is_empty()),
+            schemas: Arc::default(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        lock(&self.schemas).is_empty()
+    }
+
+    /// Marks the sandbox schemas that `sql`, which ran, created.
+    pub(crate) fn record(&self, sql: &str) {
+        let Some(prefix) = &self.prefix else {
+            return;
+        };
+        let Ok(statements) = Parser::parse_sql(&PostgreSqlDialect {}, sql) else {
+            return;
+        };
+        let mut schemas = lock(&self.schemas);
+        for statement in statements {
+            // A schema that may have been there before is not the
+            // session's to drop.
+            let Statement::CreateSchema {
+                schema_name,
+                if_not_exists: false,
+            } = statement
+            else {
+                continue;
+            };
+            let ident = match schema_name {
+                SchemaName::Simple(name) | SchemaName::NamedAuthorization(name, _) => {
+                    match <[_; 1]>::try_from(name.0) {
+                        Ok([ident]) => ident,
+                        Err(_) => continue,
+                    }
+                }
+                SchemaName::UnnamedAuthorization(role) => role,
+            };
+            // PostgreSQL folds unquoted names to lower case.
+            let name = match ident.quote_style {
+                Some(_) => ident.value,
+                None => ident.value.to_lowercase(),
+            };
+            if name.starts_with(prefix.as_str()) {
+                schemas.insert(name);
+            }
+        }
+    }
+
+    /// Drops every sandbox schema on `client`, returning how many were
+    /// dropped. One that could not be is logged and left marked, to be
+    /// tried again.
+    pub(crate) async fn drop_all(&self, client: &Client) -> usize {
+        let schemas: Vec<String> = lock(&self.schemas).iter().cloned().collect();
+        let mut dropped = 0;
+        for schema in schemas {
+            let sql = format!("DROP SCHEMA IF EXISTS {} CASCADE", quote_ident(&schema));
+            match client.batch_execute(&sql).await {
+                Ok(()) => {
+                    lock(&self.schemas).remove(&schema);
+                    dropped += 1;
+                }
+                Err(e) => warn!("Failed to drop sandbox schema {}: {}", schema, e),
+            }
+        }
+        dropped
+    }
+}
+
+fn lock(schemas: &Mutex<BTreeSet<String>>) -> MutexGuard<'_, BTreeSet<String>> {
+    schemas.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,254 @@
+//! The `postgres:///server-info` resource: an authoritative summary of the
+//! connected database for questions like "what database am I connected to?".
+//!
+//! Each figure comes from its own query, so a database lacking one of the
+//! catalog functions (CockroachDB has no `pg_is_in_recovery`, for example)
+//! still gets a summary with that field left `null`.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use tokio_postgres::types::Type;
+use tokio_postgres::{Client, Row};
+use tracing::debug;
+
+use crate::environment::Environment;
+use crate::{JsonRpcResponse, McpServer, blob, db_error_message, pooler};
+
+pub(crate) const SERVER_INFO_URI: &str = "postgres:///server-info";
+
+/// Number of tables listed under `largest_tables`.
+const LARGEST_TABLES: i64 = 5;
+
+#[derive(Debug, Serialize)]
+struct ServerInfo {
+    product: &'static str,
+    version: String,
+    /// `server_version_num`, such as 150004 for 15.4.
+    server_version_num: Option<u32>,
+    /// Features added in later PostgreSQL releases, and whether the server
+    /// has them; `null` when its version is unknown.
+    features: Option<BTreeMap<&'static str, bool>>,
+    database: String,
+    user: String,
+    /// The `DB_ENVIRONMENT` tag, if set.
+    environment: Option<&'static str>,
+    size_bytes: Option<i64>,
+    size: Option<String>,
+    /// Schemas other than `pg_catalog`, `information_schema`, and `pg_*`.
+    schema_count: Option<i64>,
+    table_count: Option<i64>,
+    largest_tables: Vec<TableSize>,
+    read_only: bool,
+    /// Why writes would be refused; empty when `read_only` is false.
+    read_only_reasons: Vec<&'static str>,
+    tls: TlsStatus,
+    limits: Limits,
+}
+
+#[derive(Debug, Serialize)]
+struct TableSize {
+    schema: String,
+    table: String,
+    /// Size including indexes and TOAST data.
+    size_bytes: i64,
+    size: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TlsStatus {
+    sslmode: &'static str,
+    encrypted: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct Limits {
+    statement_timeout: Option<String>,
+    idle_timeout_secs: Option<u64>,
+    pool_max_size: usize,
+    pool_min_size: usize,
+    slow_query_threshold_ms: Option<u64>,
+    query_timeout_ms: Option<u64>,
+    max_blob_bytes: i64,
+    exports_enabled: bool,
+    /// Disk quota for `export_query` spool files; 0 when spooling is off.
+    spool_max_bytes: u64,
+}
+
+impl McpServer {
+    /// Reads the server info resource, connecting first if necessary.
+    pub(crate) async fn read_server_info(&mut self, id: Option<Value>) -> JsonRpcResponse {
+        if let Err(e) = self.ensure_connected().await {
+            return JsonRpcResponse::connect_error(id, &e);
+        }
+        let client = self.client.as_ref().unwrap();
+
+        let identity = match pooler::query_one(
+            client,
+            "SELECT version(), current_database()::text, current_user::text",
+            &[],
+        )
+        .await
+        {
+            Ok(row) => row,
+            Err(pooler::RowError::Statement(e)) => {
+                return JsonRpcResponse::db_error(
+                    id,
+                    format!("Failed to read server info: {}", db_error_message(&e)),
+                    &e,
+                );
+            }
+            Err(e) => {
+                return JsonRpcResponse::error(
+                    id,
+                    -32603,
+                    format!("Failed to read server info: {}", e),
+                );
+            }
+        };
+
+        let size = optional_row(
+            client,
+            "SELECT pg_database_size(current_database())::int8, \
+             pg_size_pretty(pg_database_size(current_database()))",
+        )
+        .await;
+        let schema_count = optional_row(
+            client,
+            "SELECT count(*)::int8 FROM pg_namespace \
+             WHERE nspname <> 'information_schema' AND nspname NOT LIKE 'pg\\_%'",
+        )
+        .await
+        .map(|row| row.get(0));
+        let table_count = optional_row(
+            client,
+            "SELECT count(*)::int8 FROM information_schema.tables \
+             WHERE table_type = 'BASE TABLE' \
+             AND table_schema NOT IN ('pg_catalog', 'information_schema')",
+        )
+        .await
+        .map(|row| row.get(0));
+        let largest_tables = pooler::query(
+            client,
+            "SELECT n.nspname::text, c.relname::text, pg_total_relation_size(c.oid)::int8, \
+                 pg_size_pretty(pg_total_relation_size(c.oid)) \
+                 FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+                 WHERE c.relkind IN ('r', 'm') \
+                 AND n.nspname <> 'information_schema' AND n.nspname NOT LIKE 'pg\\_%' \
+                 ORDER BY 3 DESC LIMIT $1",
+            &[(&LARGEST_TABLES, Type::INT8)],
+        )
+        .await
+        .map(|rows| {
+            rows.iter()
+                .filter(|row| self.access.allows(row.get(0), row.get(1)))
+                .map(|row| TableSize {
+                    schema: row.get(0),
+                    table: row.get(1),
+                    size_bytes: row.get(2),
+                    size: row.get(3),
+                })
+                .collect()
+        })
+        .unwrap_or_else(|e| {
+            debug!("Largest tables unavailable: {}", e);
+            Vec::new()
+        });
+        let in_recovery: Option<bool> = optional_row(client, "SELECT pg_is_in_recovery()")
+            .await
+            .map(|row| row.get(0));
+        let transaction_read_only = optional_row(client, "SHOW transaction_read_only")
+            .await
+            .map(|row| row.get::<_, String>(0) == "on");
+        let statement_timeout = optional_row(client, "SHOW statement_timeout")
+            .await
+            .map(|row| row.get(0));
+        let encrypted = optional_row(
+            client,
+            "SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()",
+        )
+        .await
+        .map(|row| row.get(0));
+
+        let mut read_only_reasons = Vec::new();
+        if self.environment == Some(Environment::Production) {
+            read_only_reasons.push("writes are always disabled on production (DB_ENVIRONMENT)");
+        } else if !self.allow_write_ops {
+            read_only_reasons.push("write operations are disabled (DANGEROUSLY_ALLOW_WRITE_OPS)");
+        }
+        if in_recovery == Some(true) {
+            read_only_reasons.push("the server is a standby in recovery");
+        }
+        if transaction_read_only == Some(true) {
+            read_only_reasons.push("transactions default to read-only on this server");
+        }
+
+        let info = ServerInfo {
+            product: self.flavor.name(),
+            version: identity.get(0),
+            server_version_num: self.server_version.map(|v| v.num()),
+            features: self.server_version.map(|v| v.features()),
+            database: identity.get(1),
+            user: identity.get(2),
+            environment: self.environment.map(Environment::name),
+            size_bytes: size.as_ref().map(|row| row.get(0)),
+            size: size.as_ref().map(|row| row.get(1)),
+            schema_count,
+            table_count,
+            largest_tables,
+            read_only: !read_only_reasons.is_empty(),
+            read_only_reasons,
+            tls: TlsStatus {
+                sslmode: self.tls_settings.mode.name(),
+                encrypted,
+            },
+            limits: Limits {
+                statement_timeout,
+                idle_timeout_secs: self.idle_timeout.map(|t| t.as_secs()),
+                pool_max_size: self.pool_settings.max_size,
+                pool_min_size: self.pool_settings.min_size,
+                slow_query_threshold_ms: self.slow_query_threshold.map(|t| t.as_millis() as u64),
+                query_timeout_ms: self.query_timeout.map(|t| t.as_millis() as u64),
+                max_blob_bytes: blob::MAX_BLOB_BYTES,
+                exports_enabled: self.export_dir.is_some(),
+                spool_max_bytes: self.spool.max_bytes(),
+            },
+        };
+        let text = serde_json::to_string_pretty(&info).unwrap_or_default();
+        JsonRpcResponse::success(
+            id,
+            json!({
+                "contents": [
+                    {
+                        "uri": SERVER_INFO_URI,
+                        "mimeType": "application/json",
+                        "text": text,
+                    }
+                ]
+            }),
+        )
+    }
+}
+
+/// The resource's entry in `resources/list`.
+pub(crate) fn server_info_resource() -> Value {
+    json!({
+        "uri": SERVER_INFO_URI,
+        "name": "server-info",
+        "description": "The connected database: version, size, schema count, largest tables, read-only status, and configured limits",
+        "mimeType": "application/json"
+    })
+}
+
+/// Runs a single-row query, or returns `None` if this database cannot answer
+/// it.
+async fn optional_row(client: &Client, sql: &str) -> Option<Row> {
+    match pooler::query_opt(client, sql, &[]).await {
+        Ok(row) => row,
+        Err(e) => {
+            debug!("Server info query failed: {}: {}", sql, e);
+            None
+        }
+    }
+}
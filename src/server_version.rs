@@ -0,0 +1,161 @@
+//! Features that depend on the PostgreSQL version.
+//!
+//! The server reads `server_version_num` on its first connection. A
+//! statement given to `query`, `explain`, `export_query`, or
+//! `materialize_result` that uses a feature the server is too old for is
+//! refused with the version it needs, such as "MERGE requires PostgreSQL
+//! 15+", rather than left to fail with a syntax error or a missing relation.
+//! The server-info resource reports which of the features the server has.
+//! When the version cannot be read, nothing is refused.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A feature added in a PostgreSQL release after the oldest one supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Feature {
+    Multiranges,
+    PgStatWal,
+    VerifyHeapam,
+    Merge,
+    PgStatCheckpointer,
+}
+
+impl Feature {
+    pub(crate) const ALL: [Self; 5] = [
+        Self::Multiranges,
+        Self::PgStatWal,
+        Self::VerifyHeapam,
+        Self::Merge,
+        Self::PgStatCheckpointer,
+    ];
+
+    /// How server-info names the feature.
+    pub(crate) fn key(self) -> &'static str {
+        match self {
+            Self::Multiranges => "multiranges",
+            Self::PgStatWal => "pg_stat_wal",
+            Self::VerifyHeapam => "verify_heapam",
+            Self::Merge => "merge",
+            Self::PgStatCheckpointer => "pg_stat_checkpointer",
+        }
+    }
+
+    /// How errors name the feature.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Multiranges => "Multirange types",
+            Self::PgStatWal => "pg_stat_wal",
+            Self::VerifyHeapam => "amcheck's verify_heapam",
+            Self::Merge => "MERGE",
+            Self::PgStatCheckpointer => "pg_stat_checkpointer",
+        }
+    }
+
+    /// The major version that added the feature.
+    pub(crate) fn since(self) -> u32 {
+        match self {
+            Self::Multiranges | Self::PgStatWal | Self::VerifyHeapam => 14,
+            Self::Merge => 15,
+            Self::PgStatCheckpointer => 17,
+        }
+    }
+
+    /// Whether `sql` uses the feature, as far as its text tells.
+    fn used_by(self, sql: &str) -> bool {
+        let sql = sql.to_ascii_lowercase();
+        match self {
+            // Every multirange type and its constructor have it in their
+            // names; range_agg returns one.
+            Self::Multiranges => sql.contains("multirange") || mentions(&sql, "range_agg"),
+            Self::PgStatWal => mentions(&sql, "pg_stat_wal"),
+            Self::VerifyHeapam => mentions(&sql, "verify_heapam"),
+            Self::Merge => {
+                sql.trim_start()
+                    .split(|c: char| !is_identifier_char(c))
+                    .next()
+                    == Some("merge")
+            }
+            Self::PgStatCheckpointer => mentions(&sql, "pg_stat_checkpointer"),
+        }
+    }
+}
+
+/// Whether `word` appears in `sql` as a whole identifier, not as part of a
+/// longer one such as `pg_stat_wal_receiver`.
+fn mentions(sql: &str, word: &str) -> bool {
+    sql.match_indices(word).any(|(start, _)| {
+        !sql[..start]
+            .chars()
+            .next_back()
+            .is_some_and(is_identifier_char)
+            && !sql[start + word.len()..]
+                .chars()
+                .next()
+                .is_some_and(is_identifier_char)
+    })
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// The server's `server_version_num`, such as 150004 for 15.4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ServerVersion(u32);
+
+impl ServerVersion {
+    pub(crate) fn parse(server_version_num: &str) -> Option<Self> {
+        server_version_num.trim().parse().ok().map(Self)
+    }
+
+    pub(crate) fn num(self) -> u32 {
+        self.0
+    }
+
+    pub(crate) fn has(self, feature: Feature) -> bool {
+        self.0 >= feature.since() * 10_000
+    }
+
+    /// Every feature, and whether the server has it.
+    pub(crate) fn features(self) -> BTreeMap<&'static str, bool> {
+        Feature::ALL
+            .iter()
+            .map(|feature| (feature.key(), self.has(*feature)))
+            .collect()
+    }
+
+    /// The first feature `sql` uses that the server lacks, with why.
+    pub(crate) fn unsupported(self, sql: &str) -> Option<String> {
+        Feature::ALL
+            .iter()
+            .find(|feature| !self.has(**feature) && feature.used_by(sql))
+            .map(|feature| self.lacks(*feature))
+    }
+
+    /// Says that the server is too old for `feature`.
+    pub(crate) fn lacks(self, feature: Feature) -> String {
+        format!(
+            "{} requires PostgreSQL {}+, but the server runs PostgreSQL {}",
+            feature.name(),
+            feature.since(),
+            self
+        )
+    }
+}
+
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 >= 100_000 {
+            write!(f, "{}.{}", self.0 / 10_000, self.0 % 10_000)
+        } else {
+            write!(
+                f,
+                "{}.{}.{}",
+                self.0 / 10_000,
+                self.0 / 100 % 100,
+                self.0 % 100
+            )
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Serverless databases, which suspend when idle.
+//!
+//! Serverless PostgreSQL such as Neon suspends a database's compute after a
+//! few idle minutes, closing its connections, and resumes it on the next
+//! connection attempt. Resuming takes seconds, and connections may be refused
+//! or dropped while it does. For a serverless database, the server
+//!
+//! - allows `DB_RESUME_TIMEOUT_SECS` (60 by default) for a connection to be
+//!   established, rather than `DB_POOL_TIMEOUT_SECS`
+//! - retries, with backoff and within that time, a connection that fails the
+//!   way one to a resuming database does: refused, reset, closed, timed out,
+//!   or rejected with `cannot_connect_now`
+//! - tells the client when a connection takes more than a second, that the
+//!   database is resuming: as progress of the request if it asked for
+//!   progress, and as a log message otherwise
+//!
+//! `DB_SERVERLESS` says whether the database is serverless. `auto`, the
+//! default, takes Neon hosts (`*.neon.tech`) and `BRANCH_PROVIDER=neon` to
+//! be; `true` and `false` decide outright.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use tokio_postgres::Config;
+use tokio_postgres::config::Host;
+
+use crate::config;
+
+const DEFAULT_RESUME_TIMEOUT_SECS: u64 = 60;
+/// How long a connection may take before the client is told the database is
+/// resuming.
+pub(crate) const RESUME_NOTICE_DELAY: Duration = Duration::from_secs(1);
+
+/// Host name suffixes of serverless providers.
+const SERVERLESS_HOSTS: &[&str] = &[".neon.tech"];
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Serverless {
+    /// How long establishing a connection may take, retries included.
+    pub(crate) resume_timeout: Duration,
+}
+
+impl Serverless {
+    /// Reads `DB_SERVERLESS` and `DB_RESUME_TIMEOUT_SECS`, telling for `auto`
+    /// from the hosts of `config`. `None` when the database is not
+    /// serverless.
+    pub(crate) fn from_env(config: &Config) -> Result<Option<Self>> {
+        let mode = config::var("DB_SERVERLESS").unwrap_or_default();
+        let serverless = match mode.to_ascii_lowercase().as_str() {
+            "" | "auto" => {
+                config.get_hosts().iter().any(|host| match host {
+                    Host::Tcp(name) => SERVERLESS_HOSTS
+                        .iter()
+                        .any(|suffix| name.to_ascii_lowercase().ends_with(suffix)),
+                    #[cfg(unix)]
+                    Host::Unix(_) => false,
+                }) || config::var("BRANCH_PROVIDER").is_ok_and(|v| v.eq_ignore_ascii_case("neon"))
+            }
+            "true" | "1" => true,
+            "false" | "0" => false,
+            _ => bail!(
+                "Unknown DB_SERVERLESS '{}' (expected 'auto', 'true', or 'false')",
+                mode
+            ),
+        };
+        if !serverless {
+            return Ok(None);
+        }
+        let resume_timeout = match config::var("DB_RESUME_TIMEOUT_SECS") {
+            Ok(v) => v
+                .parse::<u64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .context("DB_RESUME_TIMEOUT_SECS must be a positive number of seconds")?,
+            Err(_) => DEFAULT_RESUME_TIMEOUT_SECS,
+        };
+        Ok(Some(Self {
+            resume_timeout: Duration::from_secs(resume_timeout),
+        }))
+    }
+}
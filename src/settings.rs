@@ -0,0 +1,319 @@
+//! `SET`, `RESET`, and `SHOW` in client SQL.
+//!
+//! A setting changed on a pooled connection stays with the connection, for
+//! whichever request uses it next, and some settings would undermine the
+//! server's own checks: `search_path` changes which tables unqualified names
+//! resolve to, `role` whose privileges apply, `client_encoding` how results
+//! decode, `transaction_read_only` whether writes are refused, and
+//! `application_name` whom statements are attributed to. So SQL
+//! that changes a setting, with `SET`, `RESET`, or `set_config`, is allowed
+//! only
+//!
+//! - for the planner settings and display settings of `SAFE_SETTINGS` and
+//!   those starting with `enable_`
+//! - in a transaction from `begin_transaction`, whose connection no other
+//!   request uses meanwhile
+//! - as `SET LOCAL` or `set_config(..., true)`, which do not outlast the
+//!   transaction. `RESET`, like `SET`, lasts for the session once the
+//!   transaction commits; `SET LOCAL name TO DEFAULT` restores the default
+//!   for the transaction only
+//!
+//! Each statement of SQL holding several is checked, and each `set_config`
+//! call in it. `SHOW` and `current_setting` only read settings, and are
+//! always allowed.
+
+use std::ops::ControlFlow;
+
+use sqlparser::ast::{
+    Expr, FunctionArg, FunctionArgExpr, FunctionArguments, Value, visit_expressions,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer, Whitespace};
+
+use crate::classify;
+
+/// Settings that only change how the planner plans or how values display.
+const SAFE_SETTINGS: &[&str] = &[
+    "client_min_messages",
+    "constraint_exclusion",
+    "constraints",
+    "cpu_index_tuple_cost",
+    "cpu_operator_cost",
+    "cpu_tuple_cost",
+    "cursor_tuple_fraction",
+    "datestyle",
+    "default_statistics_target",
+    "effective_cache_size",
+    "effective_io_concurrency",
+    "extra_float_digits",
+    "from_collapse_limit",
+    "geqo",
+    "hash_mem_multiplier",
+    "intervalstyle",
+    "jit",
+    "join_collapse_limit",
+    "maintenance_work_mem",
+    "max_parallel_workers_per_gather",
+    "parallel_setup_cost",
+    "parallel_tuple_cost",
+    "plan_cache_mode",
+    "random_page_cost",
+    "seq_page_cost",
+    "timezone",
+    "work_mem",
+];
+
+/// A change of a setting that SQL makes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SettingChange {
+    /// The setting's name, lower-cased, such as "search_path"; "all" for
+    /// `RESET ALL`, and "set_config" when it cannot be told.
+    pub(crate) name: String,
+    /// How the SQL changes it: "SET", "RESET", or "set_config".
+    pub(crate) statement: &'static str,
+    /// Whether the change outlasts the transaction it is made in.
+    pub(crate) lasting: bool,
+}
+
+impl SettingChange {
+    /// Whether the setting is one SQL may change.
+    pub(crate) fn is_safe(&self) -> bool {
+        self.name.starts_with("enable_") || SAFE_SETTINGS.contains(&self.name.as_str())
+    }
+}
+
+/// The settings the statements of `sql` change.
+pub(crate) fn changes(sql: &str) -> Vec<SettingChange> {
+    statements(sql)
+        .iter()
+        .flat_map(|statement| statement_changes(statement))
+        .collect()
+}
+
+/// The statements of `sql`, split at the semicolons outside its strings,
+/// quoted names, and comments, with each comment replaced by a space. SQL
+/// that cannot be split, such as SQL with an unterminated string, is taken
+/// as one statement.
+pub(crate) fn statements(sql: &str) -> Vec<String> {
+    let Ok(tokens) = Tokenizer::new(&PostgreSqlDialect {}, sql).tokenize() else {
+        return vec![sql.to_string()];
+    };
+    tokens
+        .split(|token| *token == Token::SemiColon)
+        .map(|tokens| {
+            tokens
+                .iter()
+                .map(|token| match token {
+                    Token::Whitespace(
+                        Whitespace::SingleLineComment { .. } | Whitespace::MultiLineComment(_),
+                    ) => " ".to_string(),
+                    token => token.to_string(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The settings the single statement `sql` changes.
+fn statement_changes(sql: &str) -> Vec<SettingChange> {
+    let sql = classify::skip_comments(sql);
+    let first = sql
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    match first.as_str() {
+        "SET" => vec![set_change(&sql[3..])],
+        "RESET" => vec![SettingChange {
+            name: match setting_name(&sql[5..]).as_str() {
+                // RESET SESSION AUTHORIZATION.
+                "session" => "session_authorization".to_string(),
+                name => name.to_string(),
+            },
+            statement: "RESET",
+            lasting: true,
+        }],
+        _ => set_config_changes(sql),
+    }
+}
+
+/// The change `SET` makes, given the rest of the statement.
+fn set_change(rest: &str) -> SettingChange {
+    let mut rest = rest.trim_start();
+    let mut lasting = true;
+    let upper = rest.to_ascii_uppercase();
+    if upper.starts_with("LOCAL ") {
+        lasting = false;
+        rest = rest[6..].trim_start();
+    } else if upper.starts_with("SESSION ") && !upper[8..].trim_start().starts_with("AUTHORIZATION")
+    {
+        rest = rest[8..].trim_start();
+    }
+    let words: Vec<String> = rest
+        .split_whitespace()
+        .take(2)
+        .map(str::to_ascii_uppercase)
+        .collect();
+    let name = match words.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["TIME", "ZONE", ..] => "timezone".to_string(),
+        ["NAMES", ..] => "client_encoding".to_string(),
+        ["SCHEMA", ..] => "search_path".to_string(),
+        ["XML", "OPTION", ..] => "xmloption".to_string(),
+        ["SESSION", "AUTHORIZATION", ..] => "session_authorization".to_string(),
+        // SET TRANSACTION and SET SESSION CHARACTERISTICS AS TRANSACTION.
+        ["CHARACTERISTICS", ..] => "transaction".to_string(),
+        _ => setting_name(rest),
+    };
+    SettingChange {
+        name,
+        statement: "SET",
+        lasting,
+    }
+}
+
+/// The setting name `rest` starts with, unquoted and lower-cased.
+fn setting_name(rest: &str) -> String {
+    rest.trim_start()
+        .split(|c: char| c.is_whitespace() || c == '=' || c == ';')
+        .next()
+        .unwrap_or_default()
+        .replace('"', "")
+        .to_ascii_lowercase()
+}
+
+/// The changes the calls of `set_config` in `sql` make. A call whose
+/// arguments are not literals, or SQL that mentions `set_config` but cannot
+/// be parsed, counts as a lasting change of the setting "set_config".
+fn set_config_changes(sql: &str) -> Vec<SettingChange> {
+    if !sql.to_ascii_lowercase().contains("set_config") {
+        return Vec::new();
+    }
+    let unknown = SettingChange {
+        name: "set_config".to_string(),
+        statement: "set_config",
+        lasting: true,
+    };
+    let Ok(statements) = Parser::parse_sql(&PostgreSqlDialect {}, sql) else {
+        return vec![unknown];
+    };
+    // A mention of set_config in a string or a comment is no call.
+    let mut changes = Vec::new();
+    let _ = visit_expressions(&statements, |expr| {
+        match expr {
+            Expr::Function(function)
+                if function
+                    .name
+                    .0
+                    .last()
+                    .is_some_and(|name| name.value.eq_ignore_ascii_case("set_config")) =>
+            {
+                let args: Vec<Option<&Value>> = match &function.args {
+                    FunctionArguments::List(list) => list
+                        .args
+                        .iter()
+                        .map(|arg| match arg {
+                            FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(value))) => {
+                                Some(value)
+                            }
+                            _ => None,
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                changes.push(match args[..] {
+                    [Some(Value::SingleQuotedString(name)), _, local] => SettingChange {
+                        name: name.to_ascii_lowercase(),
+                        statement: "set_config",
+                        lasting: local != Some(&Value::Boolean(true)),
+                    },
+                    _ => unknown.clone(),
+                });
+            }
+            _ => {}
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(name: &str, statement: &'static str, lasting: bool) -> SettingChange {
+        SettingChange {
+            name: name.to_string(),
+            statement,
+            lasting,
+        }
+    }
+
+    #[test]
+    fn set_and_reset() {
+        assert_eq!(
+            changes("SET search_path = evil"),
+            [change("search_path", "SET", true)]
+        );
+        assert_eq!(
+            changes("set local work_mem to '64MB'"),
+            [change("work_mem", "SET", false)]
+        );
+        assert_eq!(
+            changes("SET SESSION \"Role\" = x"),
+            [change("role", "SET", true)]
+        );
+        assert_eq!(
+            changes("SET TIME ZONE 'UTC'"),
+            [change("timezone", "SET", true)]
+        );
+        assert_eq!(
+            changes("SET SESSION AUTHORIZATION bob"),
+            [change("session_authorization", "SET", true)]
+        );
+        assert_eq!(changes("RESET ALL"), [change("all", "RESET", true)]);
+    }
+
+    #[test]
+    fn every_statement_is_checked() {
+        assert_eq!(
+            changes("SELECT 1; /* x */ SET role = admin"),
+            [change("role", "SET", true)]
+        );
+        // A semicolon in a string does not end the statement.
+        assert_eq!(changes("SELECT 'a; SET role = admin'"), []);
+    }
+
+    #[test]
+    fn set_config_calls() {
+        assert_eq!(
+            changes("SELECT set_config('Search_Path', 'x', false)"),
+            [change("search_path", "set_config", true)]
+        );
+        assert_eq!(
+            changes("SELECT set_config('work_mem', '1MB', true)"),
+            [change("work_mem", "set_config", false)]
+        );
+        assert_eq!(
+            changes("SELECT set_config(name, 'x', true) FROM t"),
+            [change("set_config", "set_config", true)]
+        );
+        assert_eq!(changes("SELECT 'set_config'"), []);
+    }
+
+    #[test]
+    fn reads_change_nothing() {
+        assert_eq!(changes("SHOW search_path"), []);
+        assert_eq!(changes("SELECT current_setting('role')"), []);
+    }
+
+    #[test]
+    fn safe_settings() {
+        assert!(change("work_mem", "SET", true).is_safe());
+        assert!(change("enable_seqscan", "SET", true).is_safe());
+        assert!(!change("search_path", "SET", true).is_safe());
+        assert!(!change("role", "SET", true).is_safe());
+        assert!(!change("application_name", "SET", false).is_safe());
+        assert!(!change("set_config", "set_config", true).is_safe());
+    }
+}
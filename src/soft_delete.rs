@@ -0,0 +1,56 @@
+//! Soft-delete filters.
+//!
+//! Tables that mark rows deleted instead of removing them can be given the
+//! predicate their live rows satisfy in `SOFT_DELETE_FILTERS`, such as
+//! `users=deleted_at IS NULL`. Table resources, `search_values`, and
+//! `find_duplicates` then leave out the deleted rows unless asked to include
+//! them, so an agent does not report on deleted records as if they were live.
+
+use std::collections::HashMap;
+
+use anyhow::{Result, bail};
+
+use crate::config;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SoftDeleteFilters {
+    /// Predicates by schema and table name.
+    filters: HashMap<(String, String), String>,
+}
+
+impl SoftDeleteFilters {
+    /// Reads `SOFT_DELETE_FILTERS`: `[schema.]table=predicate` entries
+    /// separated by semicolons, the schema defaulting to `public`.
+    pub(crate) fn from_env() -> Result<Self> {
+        let mut filters = HashMap::new();
+        let value = config::var("SOFT_DELETE_FILTERS").unwrap_or_default();
+        for entry in value.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((name, predicate)) = entry.split_once('=') else {
+                bail!(
+                    "SOFT_DELETE_FILTERS entry '{}' must look like table=predicate, as in users=deleted_at IS NULL",
+                    entry
+                );
+            };
+            let (name, predicate) = (name.trim(), predicate.trim());
+            let (schema, table) = name.split_once('.').unwrap_or(("public", name));
+            if table.is_empty() || predicate.is_empty() {
+                bail!(
+                    "SOFT_DELETE_FILTERS entry '{}' needs both a table and a predicate",
+                    entry
+                );
+            }
+            filters.insert(
+                (schema.to_string(), table.to_string()),
+                predicate.to_string(),
+            );
+        }
+        Ok(Self { filters })
+    }
+
+    /// The predicate selecting the live rows of `schema.table`, if it has one.
+    pub(crate) fn filter(&self, schema: &str, table: &str) -> Option<&str> {
+        self.filters
+            .get(&(schema.to_string(), table.to_string()))
+            .map(String::as_str)
+    }
+}
@@ -0,0 +1,397 @@
+//! Spooling of large query results to disk.
+//!
+//! `export_query` streams a query's result through `COPY ... TO STDOUT` into
+//! a CSV file instead of collecting its rows in memory, so a result of any
+//! size can be produced without exhausting the process's memory. Each
+//! session spools into its own directory beneath `SPOOL_DIR` (the system
+//! temporary directory by default), which only the server's user may enter,
+//! and its files together may use at most `SPOOL_MAX_BYTES`. A spool stops
+//! at the quota and is reported as truncated; starting one first deletes the
+//! session's oldest files until at least half the quota is free. The files
+//! are readable as `postgres:///spool/<name>` resources and are removed when
+//! the session ends. With `SPOOL_ENCRYPTION_KEY` set, they are encrypted at
+//! rest (see `encryption`), and the server refuses to start with settings
+//! that would write results elsewhere in plain text. A read returns at most `MAX_READ_BYTES`; larger
+//! files are read in chunks with `offset` and `length`, which are read from
+//! the file without loading the rest of it.
+
+use std::env;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::{Context, Result, anyhow, bail};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{Value, json};
+use tokio::fs::{DirBuilder, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+mod encryption;
+
+use encryption::{SpoolKey, SpoolWriter};
+
+use crate::chunk::{self, ByteRange};
+use crate::{JsonRpcResponse, McpServer, config, wirelog};
+
+/// Refuses `SPOOL_ENCRYPTION_KEY` alongside settings that write query
+/// results to disk in plain text, which would leave the data the key
+/// protects readable anyway. Runs once the wire log is open.
+pub(crate) fn check_encryption() -> Result<()> {
+    let set = |name: &str| config::var(name).is_ok_and(|v| !v.is_empty());
+    if !set("SPOOL_ENCRYPTION_KEY") {
+        return Ok(());
+    }
+    if set("EXPORT_DIR") {
+        bail!(
+            "SPOOL_ENCRYPTION_KEY cannot be set with EXPORT_DIR, since export_schema_data and export_large_object write plain-text files there for other programs to read; unset EXPORT_DIR, or unset the key and keep EXPORT_DIR on encrypted storage"
+        );
+    }
+    if wirelog::logs_data() {
+        bail!(
+            "SPOOL_ENCRYPTION_KEY cannot be set with WIRE_LOG_DATA=true, since the wire log would hold query results in plain text; unset WIRE_LOG_DATA"
+        );
+    }
+    Ok(())
+}
+
+/// The URI prefix of spooled result resources.
+pub(crate) const SPOOL_URI_PREFIX: &str = "postgres:///spool/";
+
+const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// The most bytes of a spool file returned by one `resources/read`.
+pub(crate) const MAX_READ_BYTES: u64 = 10 * 1024 * 1024;
+
+/// A spooled result file.
+#[derive(Debug, Clone)]
+pub(crate) struct SpoolFile {
+    pub(crate) name: String,
+    pub(crate) path: PathBuf,
+    pub(crate) bytes: u64,
+}
+
+impl SpoolFile {
+    pub(crate) fn uri(&self) -> String {
+        format!("{}{}", SPOOL_URI_PREFIX, self.name)
+    }
+
+    pub(crate) fn file_uri(&self) -> String {
+        format!("file://{}", self.path.display())
+    }
+
+    /// The file's `resources/list` entry.
+    pub(crate) fn resource(&self) -> Value {
+        json!({
+            "uri": self.uri(),
+            "name": self.name,
+            "description": format!("Spooled query result ({} bytes)", self.bytes),
+            "mimeType": "text/csv",
+        })
+    }
+}
+
+/// The session's spool directory and the files in it. Clones share them.
+#[derive(Clone)]
+pub(crate) struct Spool(Arc<SpoolState>);
+
+struct SpoolState {
+    root: PathBuf,
+    max_bytes: u64,
+    /// Created on first use.
+    dir: OnceCell<PathBuf>,
+    /// Oldest first.
+    files: Mutex<Vec<SpoolFile>>,
+    random: SystemRandom,
+    /// The key files are encrypted with, from `SPOOL_ENCRYPTION_KEY`.
+    key: Option<Arc<SpoolKey>>,
+}
+
+impl Spool {
+    /// Reads `SPOOL_DIR`, `SPOOL_MAX_BYTES`, and `SPOOL_ENCRYPTION_KEY`; a
+    /// quota of 0 disables spooling.
+    pub(crate) fn from_env() -> Result<Self> {
+        let root = config::var("SPOOL_DIR")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| env::temp_dir().join("postgres-mcp-spool"));
+        let max_bytes = match config::var("SPOOL_MAX_BYTES") {
+            Ok(v) => v
+                .parse::<u64>()
+                .context("SPOOL_MAX_BYTES must be a number of bytes")?,
+            Err(_) => DEFAULT_MAX_BYTES,
+        };
+        let key = match config::var("SPOOL_ENCRYPTION_KEY") {
+            Ok(v) if !v.is_empty() => Some(Arc::new(SpoolKey::parse(&v)?)),
+            _ => None,
+        };
+        Ok(Self(Arc::new(SpoolState {
+            root,
+            max_bytes,
+            dir: OnceCell::new(),
+            files: Mutex::new(Vec::new()),
+            random: SystemRandom::new(),
+            key,
+        })))
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.0.max_bytes > 0
+    }
+
+    pub(crate) fn max_bytes(&self) -> u64 {
+        self.0.max_bytes
+    }
+
+    /// Whether spool files are encrypted, and so unreadable but through the
+    /// server.
+    pub(crate) fn encrypted(&self) -> bool {
+        self.0.key.is_some()
+    }
+
+    /// Makes room for a new spool file and returns its path and how many
+    /// bytes it may hold. The file is not created.
+    pub(crate) async fn reserve(&self, extension: &str) -> Result<(SpoolFile, u64)> {
+        let removed: Vec<SpoolFile> = {
+            let mut files = self.files();
+            let mut removed = Vec::new();
+            while used(&files) > self.0.max_bytes / 2 && !files.is_empty() {
+                removed.push(files.remove(0));
+            }
+            removed
+        };
+        for oldest in removed {
+            if let Err(e) = tokio::fs::remove_file(&oldest.path).await {
+                warn!(
+                    "Could not remove spool file {}: {}",
+                    oldest.path.display(),
+                    e
+                );
+            }
+        }
+        let dir = self
+            .0
+            .dir
+            .get_or_try_init(|| async {
+                let root = &self.0.root;
+                private_dir(true)
+                    .create(root)
+                    .await
+                    .with_context(|| format!("Failed to create {}", root.display()))?;
+                let dir = root.join(self.token()?);
+                private_dir(false)
+                    .create(&dir)
+                    .await
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+                if let Err(e) = check_owner(root, &dir).await {
+                    let _ = tokio::fs::remove_dir(&dir).await;
+                    return Err(e);
+                }
+                anyhow::Ok(tokio::fs::canonicalize(&dir).await?)
+            })
+            .await?;
+        let name = format!("{}.{}", self.token()?, extension);
+        let file = SpoolFile {
+            path: dir.join(&name),
+            name,
+            bytes: 0,
+        };
+        let available = self.0.max_bytes.saturating_sub(used(&self.files()));
+        Ok((file, available))
+    }
+
+    /// Creates `file` for writing, readable by the server's user alone and
+    /// encrypted with `SPOOL_ENCRYPTION_KEY` when it is set.
+    pub(crate) async fn create(&self, file: &SpoolFile) -> io::Result<SpoolWriter> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        SpoolWriter::new(options.open(&file.path).await?, self.0.key.clone())
+    }
+
+    /// Reads `length` bytes of `file` from `offset`, fewer at its end.
+    async fn read(&self, file: &SpoolFile, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+        let mut reader = File::open(&file.path).await?;
+        if let Some(key) = &self.0.key {
+            return encryption::read_sealed(key, &mut reader, file.bytes, offset, length).await;
+        }
+        reader.seek(SeekFrom::Start(offset)).await?;
+        let mut bytes = Vec::new();
+        reader.take(length).read_to_end(&mut bytes).await?;
+        Ok(bytes)
+    }
+
+    /// Records a written spool file.
+    pub(crate) fn add(&self, file: SpoolFile) {
+        self.files().push(file);
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<SpoolFile> {
+        self.files().iter().find(|file| file.name == name).cloned()
+    }
+
+    /// The `resources/list` entries of the spooled files.
+    pub(crate) fn resources(&self) -> Vec<Value> {
+        self.files().iter().map(SpoolFile::resource).collect()
+    }
+
+    fn files(&self) -> MutexGuard<'_, Vec<SpoolFile>> {
+        self.0.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn token(&self) -> Result<String> {
+        let mut bytes = [0u8; 12];
+        self.0
+            .random
+            .fill(&mut bytes)
+            .map_err(|_| anyhow!("no random numbers for a spool file name"))?;
+        Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+/// A builder for a directory only the server's user can enter, as the one
+/// for spool files holds query results.
+fn private_dir(recursive: bool) -> DirBuilder {
+    let mut builder = DirBuilder::new();
+    builder.recursive(recursive);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder
+}
+
+/// Refuses a spool directory `root` owned by another user, who could read
+/// or replace the files in it: its owner must be that of `dir`, which the
+/// server just created in it. A symbolic link must be the server's too.
+async fn check_owner(root: &Path, dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let uid = tokio::fs::metadata(dir).await?.uid();
+        let link = tokio::fs::symlink_metadata(root).await?;
+        let target = tokio::fs::metadata(root).await?;
+        if link.uid() != uid || target.uid() != uid {
+            bail!(
+                "The spool directory {} belongs to another user; set SPOOL_DIR to a directory of the server's own",
+                root.display()
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (root, dir);
+    Ok(())
+}
+
+fn used(files: &[SpoolFile]) -> u64 {
+    files.iter().map(|file| file.bytes).sum()
+}
+
+impl Drop for SpoolState {
+    fn drop(&mut self) {
+        if let Some(dir) = self.dir.get()
+            && let Err(e) = std::fs::remove_dir_all(dir)
+        {
+            warn!("Could not remove spool directory {}: {}", dir.display(), e);
+        }
+    }
+}
+
+impl McpServer {
+    /// Reads a spooled result resource, or the `range` of it, which needs no
+    /// connection.
+    pub(crate) async fn read_spool_file(
+        &self,
+        id: Option<Value>,
+        uri: &str,
+        name: &str,
+        range: Option<ByteRange>,
+    ) -> JsonRpcResponse {
+        let Some(file) = self.spool.get(name) else {
+            return JsonRpcResponse::error(
+                id,
+                -32602,
+                format!(
+                    "No spooled result '{}': spooled results last only as long as the session that made them",
+                    name
+                ),
+            );
+        };
+        let chunked = range.is_some();
+        let range = match range {
+            Some(range) => range,
+            None if file.bytes > MAX_READ_BYTES => {
+                // An encrypted file is no use read from disk.
+                let instead = if self.spool.encrypted() {
+                    String::new()
+                } else {
+                    format!(", or read {} instead", file.file_uri())
+                };
+                return JsonRpcResponse::error(
+                    id,
+                    -32602,
+                    format!(
+                        "The spooled result is {} bytes, more than the {} a resource read returns; read it in chunks with offset and length{}",
+                        file.bytes, MAX_READ_BYTES, instead
+                    ),
+                );
+            }
+            None => ByteRange {
+                offset: 0,
+                length: None,
+            },
+        };
+        if let Err(message) = chunk::check_offset(range.offset, file.bytes) {
+            return JsonRpcResponse::error(id, -32602, message);
+        }
+        let length = range
+            .length
+            .unwrap_or(MAX_READ_BYTES)
+            .min(MAX_READ_BYTES)
+            .min(file.bytes - range.offset);
+        match read_text_chunk(&self.spool, &file, range.offset, length).await {
+            Ok(Ok(text)) => {
+                let end = range.offset + text.len() as u64;
+                let mut content = json!({
+                    "uri": uri,
+                    "mimeType": "text/csv",
+                    "text": text,
+                });
+                if chunked {
+                    content["_meta"] =
+                        json!({ "chunk": chunk::chunk_meta(range.offset, end, file.bytes) });
+                }
+                JsonRpcResponse::success(id, json!({ "contents": [content] }))
+            }
+            Ok(Err(message)) => JsonRpcResponse::error(id, -32602, message),
+            Err(e) => JsonRpcResponse::error(
+                id,
+                -32603,
+                format!("Failed to read {}: {}", file.path.display(), e),
+            ),
+        }
+    }
+}
+
+/// Reads about `length` bytes of `file` from `offset`, ending at a character
+/// boundary. The inner error explains an offset that cannot be used.
+async fn read_text_chunk(
+    spool: &Spool,
+    file: &SpoolFile,
+    offset: u64,
+    length: u64,
+) -> std::io::Result<Result<String, String>> {
+    // Up to three more bytes finish a character cut at the end.
+    let mut window = spool.read(file, offset, length + 3).await?;
+    if chunk::splits_character(&window, 0) {
+        return Ok(Err(format!(
+            "Offset {} is inside a UTF-8 character; continue from the nextOffset of the previous chunk",
+            offset
+        )));
+    }
+    let end = chunk::text_end(&window, 0, (length as usize).min(window.len()));
+    window.truncate(end);
+    Ok(String::from_utf8(window).map_err(|_| format!("{} is not UTF-8 text", file.uri())))
+}
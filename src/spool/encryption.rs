@@ -0,0 +1,331 @@
+//! Encryption of spool files at rest.
+//!
+//! With `SPOOL_ENCRYPTION_KEY` set, a spool file is written as a header,
+//! the magic bytes and a random salt, followed by the CSV in records of
+//! `RECORD_BYTES`, each sealed with AES-256-GCM. A record's nonce is the
+//! file's salt and the record's number, and whether it is the file's last
+//! record is authenticated with it, so records can be neither reordered nor
+//! cut off unnoticed. The last record may be empty. Since every record but
+//! the last holds the same number of bytes, a range of the CSV is read by
+//! opening only the records it falls in.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use anyhow::{Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, SeekFrom};
+
+const MAGIC: &[u8; 8] = b"PGMCPSP1";
+const SALT_BYTES: usize = 8;
+const HEADER_BYTES: u64 = (MAGIC.len() + SALT_BYTES) as u64;
+/// CSV bytes in each record but the last.
+const RECORD_BYTES: usize = 64 * 1024;
+const TAG_BYTES: usize = 16;
+
+/// The key spool files are sealed with.
+pub(crate) struct SpoolKey(LessSafeKey);
+
+impl SpoolKey {
+    /// Reads a 256-bit key given as 64 hexadecimal digits or in base64.
+    pub(crate) fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        let bytes = if value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+            (0..64)
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&value[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()?
+        } else {
+            BASE64_STANDARD.decode(value).unwrap_or_default()
+        };
+        let Ok(key) = UnboundKey::new(&AES_256_GCM, &bytes) else {
+            bail!(
+                "SPOOL_ENCRYPTION_KEY must be a 32-byte key, as 64 hexadecimal digits or in base64"
+            );
+        };
+        Ok(Self(LessSafeKey::new(key)))
+    }
+
+    fn nonce(salt: &[u8; SALT_BYTES], record: u32) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[..SALT_BYTES].copy_from_slice(salt);
+        nonce[SALT_BYTES..].copy_from_slice(&record.to_be_bytes());
+        Nonce::assume_unique_for_key(nonce)
+    }
+}
+
+/// Writes a spool file, sealing it with `key` when there is one. The file
+/// is complete once shut down.
+pub(crate) struct SpoolWriter {
+    file: File,
+    key: Option<Arc<SpoolKey>>,
+    salt: [u8; SALT_BYTES],
+    /// The number of the next record to seal.
+    record: u32,
+    /// CSV not yet sealed into a record.
+    buffer: Vec<u8>,
+    /// Sealed bytes not yet written to the file, from `written` on.
+    sealed: Vec<u8>,
+    written: usize,
+    finished: bool,
+}
+
+impl SpoolWriter {
+    pub(crate) fn new(file: File, key: Option<Arc<SpoolKey>>) -> io::Result<Self> {
+        let mut salt = [0u8; SALT_BYTES];
+        let mut sealed = Vec::new();
+        if key.is_some() {
+            SystemRandom::new()
+                .fill(&mut salt)
+                .map_err(|_| io::Error::other("no random numbers for a spool file salt"))?;
+            sealed.extend_from_slice(MAGIC);
+            sealed.extend_from_slice(&salt);
+        }
+        Ok(Self {
+            file,
+            key,
+            salt,
+            record: 0,
+            buffer: Vec::new(),
+            sealed,
+            written: 0,
+            finished: false,
+        })
+    }
+
+    /// Seals the buffered CSV as the next record.
+    fn seal(&mut self, key: &SpoolKey, last: bool) -> io::Result<()> {
+        let mut record = std::mem::replace(&mut self.buffer, Vec::with_capacity(RECORD_BYTES));
+        key.0
+            .seal_in_place_append_tag(
+                SpoolKey::nonce(&self.salt, self.record),
+                Aad::from([last as u8]),
+                &mut record,
+            )
+            .map_err(|_| io::Error::other("failed to encrypt a spool record"))?;
+        self.record += 1;
+        self.sealed.extend_from_slice(&record);
+        Ok(())
+    }
+
+    /// Writes out the sealed bytes.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.sealed.len() {
+            let n = ready!(Pin::new(&mut self.file).poll_write(cx, &self.sealed[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.sealed.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for SpoolWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(key) = this.key.clone() else {
+            return Pin::new(&mut this.file).poll_write(cx, buf);
+        };
+        ready!(this.poll_drain(cx))?;
+        // A full record is sealed only once more follows, as the last
+        // record is sealed differently.
+        if this.buffer.len() == RECORD_BYTES {
+            this.seal(&key, false)?;
+            ready!(this.poll_drain(cx))?;
+        }
+        let n = buf.len().min(RECORD_BYTES - this.buffer.len());
+        this.buffer.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let (Some(key), false) = (this.key.clone(), this.finished) {
+            ready!(this.poll_drain(cx))?;
+            if this.buffer.len() == RECORD_BYTES {
+                this.seal(&key, false)?;
+            }
+            this.seal(&key, true)?;
+            this.finished = true;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.file).poll_shutdown(cx)
+    }
+}
+
+/// Reads `length` bytes of CSV from `offset` of the sealed spool file at
+/// `file`, which holds `total` bytes of CSV.
+pub(crate) async fn read_sealed(
+    key: &SpoolKey,
+    file: &mut File,
+    total: u64,
+    offset: u64,
+    length: u64,
+) -> io::Result<Vec<u8>> {
+    let mut header = [0u8; HEADER_BYTES as usize];
+    file.read_exact(&mut header).await?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an encrypted spool file",
+        ));
+    }
+    let mut salt = [0u8; SALT_BYTES];
+    salt.copy_from_slice(&header[MAGIC.len()..]);
+
+    let end = (offset + length).min(total);
+    let mut csv = Vec::new();
+    if offset >= end {
+        return Ok(csv);
+    }
+    let record_bytes = RECORD_BYTES as u64;
+    let last = total / record_bytes;
+    let first = offset / record_bytes;
+    file.seek(SeekFrom::Start(
+        HEADER_BYTES + first * (record_bytes + TAG_BYTES as u64),
+    ))
+    .await?;
+    for record in first..=(end - 1) / record_bytes {
+        let bytes = record_bytes.min(total - record * record_bytes) as usize;
+        let mut sealed = vec![0u8; bytes + TAG_BYTES];
+        file.read_exact(&mut sealed).await?;
+        let opened = key
+            .0
+            .open_in_place(
+                SpoolKey::nonce(&salt, record as u32),
+                Aad::from([(record == last) as u8]),
+                &mut sealed,
+            )
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "a spool file record failed to decrypt; the file was changed, or SPOOL_ENCRYPTION_KEY was",
+                )
+            })?;
+        csv.extend_from_slice(opened);
+    }
+    let start = (offset - first * record_bytes) as usize;
+    csv.drain(..start);
+    csv.truncate((end - offset) as usize);
+    Ok(csv)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    /// Seals `csv`, written in pieces of `piece` bytes, into a file and
+    /// returns its path.
+    async fn seal(name: &str, csv: &[u8], piece: usize) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "postgres-mcp-spool-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let file = File::create(&path).await.unwrap();
+        let key = Arc::new(SpoolKey::parse(KEY).unwrap());
+        let mut writer = SpoolWriter::new(file, Some(key)).unwrap();
+        for piece in csv.chunks(piece) {
+            writer.write_all(piece).await.unwrap();
+        }
+        writer.shutdown().await.unwrap();
+        path
+    }
+
+    async fn read(
+        path: &std::path::Path,
+        total: u64,
+        offset: u64,
+        length: u64,
+    ) -> io::Result<Vec<u8>> {
+        let key = SpoolKey::parse(KEY).unwrap();
+        let mut file = File::open(path).await?;
+        read_sealed(&key, &mut file, total, offset, length).await
+    }
+
+    #[tokio::test]
+    async fn ranges_read_back() {
+        let csv: Vec<u8> = (0..3 * RECORD_BYTES + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let path = seal("ranges", &csv, 1000).await;
+        let total = csv.len() as u64;
+        let record = RECORD_BYTES as u64;
+        for (offset, length) in [
+            (0, total),
+            (0, 10),
+            (record - 5, 10),
+            (2 * record, record),
+            (total - 3, 100),
+            (total, 10),
+        ] {
+            let end = (offset + length).min(total) as usize;
+            assert_eq!(
+                read(&path, total, offset, length).await.unwrap(),
+                &csv[offset as usize..end]
+            );
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn whole_records_end_with_an_empty_one() {
+        let csv = vec![b'x'; 2 * RECORD_BYTES];
+        let path = seal("whole", &csv, RECORD_BYTES).await;
+        let sealed = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(
+            sealed,
+            HEADER_BYTES + 2 * (RECORD_BYTES + TAG_BYTES) as u64 + TAG_BYTES as u64
+        );
+        assert_eq!(
+            read(&path, csv.len() as u64, 0, u64::MAX / 2)
+                .await
+                .unwrap(),
+            csv
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn changed_files_fail_to_read() {
+        let csv = b"id,name\n1,alice\n".to_vec();
+        let path = seal("changed", &csv, 4).await;
+        let mut sealed = std::fs::read(&path).unwrap();
+        sealed[HEADER_BYTES as usize + 1] ^= 1;
+        std::fs::write(&path, sealed).unwrap();
+        let e = read(&path, csv.len() as u64, 0, 100).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn keys_parse() {
+        assert!(SpoolKey::parse(KEY).is_ok());
+        assert!(SpoolKey::parse(&BASE64_STANDARD.encode([7u8; 32])).is_ok());
+        assert!(SpoolKey::parse("abc").is_err());
+        assert!(SpoolKey::parse(&BASE64_STANDARD.encode([7u8; 16])).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+//! Warnings about stale planner statistics on the tables a query reads.
+//!
+//! A plan is only as good as the statistics `ANALYZE` last gathered, and
+//! plans made from stale ones are among the commonest causes of slow
+//! queries. Before a `query` statement runs, the tables it refers to are
+//! looked up in `pg_stat_all_tables`, and one of at least 1000 rows is
+//! reported when
+//!
+//! - it has never been analyzed,
+//! - a fifth or more of its rows changed since it last was,
+//! - the planner's row estimate is off from its live row count by a factor
+//!   of two or more, or
+//! - it was last analyzed more than `STALE_STATS_DAYS` (30 by default) days
+//!   ago and has changed since.
+//!
+//! The tables are listed in the result's `_meta.staleStatistics`, and a
+//! second text item suggests analyzing them. `STALE_STATS_DAYS=0` turns the
+//! check off.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio_postgres::types::Type;
+use tracing::debug;
+
+use crate::access::referenced_tables;
+use crate::{McpServer, config, pooler, quote_ident};
+
+const DEFAULT_MAX_AGE_DAYS: u64 = 30;
+/// Tables smaller than this are not worth a warning.
+const MIN_ROWS: i64 = 1000;
+/// The share of a table's rows that may change before its statistics are
+/// stale, whenever it was analyzed.
+const MAX_CHANGED_FRACTION: f64 = 0.2;
+/// How far the planner's row estimate may be off, as a ratio.
+const MAX_ESTIMATE_ERROR: f64 = 2.0;
+
+/// Reads `STALE_STATS_DAYS`; `None` when it is 0, which turns the check off.
+pub(crate) fn max_age_from_env() -> Result<Option<u64>> {
+    match config::var("STALE_STATS_DAYS") {
+        Ok(v) => match v
+            .parse::<u64>()
+            .context("STALE_STATS_DAYS must be a number of days")?
+        {
+            0 => Ok(None),
+            days => Ok(Some(days)),
+        },
+        Err(_) => Ok(Some(DEFAULT_MAX_AGE_DAYS)),
+    }
+}
+
+/// A table whose statistics look stale.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StaleTable {
+    table: String,
+    reason: String,
+    /// When it was last analyzed, by `ANALYZE` or autovacuum.
+    last_analyzed: Option<String>,
+    /// The planner's row estimate, unless it has none.
+    estimated_rows: Option<i64>,
+    live_rows: i64,
+    modified_since_analyze: i64,
+    /// The statement that refreshes the statistics.
+    suggestion: String,
+}
+
+/// The warning shown alongside a result that read `tables`.
+pub(crate) fn warning(tables: &[StaleTable]) -> String {
+    let mut text = String::from(
+        "Warning: the planner statistics of tables this query reads look stale, which can make PostgreSQL choose slow plans:\n",
+    );
+    for table in tables {
+        text.push_str(&format!("- {}: {}\n", table.table, table.reason));
+    }
+    let names: Vec<&str> = tables.iter().map(|t| t.table.as_str()).collect();
+    text.push_str(&format!(
+        "Running ANALYZE {}; refreshes them.",
+        names.join(", ")
+    ));
+    text
+}
+
+impl McpServer {
+    /// The tables `sql` refers to whose statistics look stale. A failure to
+    /// tell is logged and reported as none.
+    pub(crate) async fn stale_statistics(&self, sql: &str) -> Vec<StaleTable> {
+        let Some(max_age_days) = self.stale_stats_days else {
+            return Vec::new();
+        };
+        let Ok(tables) = referenced_tables(sql) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = tables
+            .iter()
+            .map(|(schema, table)| match schema {
+                Some(schema) => format!("{}.{}", quote_ident(schema), quote_ident(table)),
+                None => quote_ident(table),
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        if names.is_empty() {
+            return Vec::new();
+        }
+        let client = self.client.as_ref().unwrap();
+        let rows = match pooler::query(client,
+                "SELECT DISTINCT quote_ident(n.nspname) || '.' || quote_ident(c.relname), \
+                     to_json(greatest(s.last_analyze, s.last_autoanalyze)) #>> '{}', \
+                     extract(epoch FROM now() - greatest(s.last_analyze, s.last_autoanalyze))::float8 / 86400, \
+                     CASE WHEN c.reltuples >= 0 THEN c.reltuples::int8 END, \
+                     s.n_live_tup, s.n_mod_since_analyze \
+                 FROM unnest($1::text[]) AS r(name) \
+                 JOIN pg_class c ON c.oid = to_regclass(r.name) \
+                 JOIN pg_namespace n ON n.oid = c.relnamespace \
+                 JOIN pg_stat_all_tables s ON s.relid = c.oid \
+                 ORDER BY 1",
+                &[(&names, Type::TEXT_ARRAY)],
+            )
+            .await
+        {
+            Ok(rows) => rows,
+            Err(e) => {
+                debug!("Could not read table statistics: {}", e);
+                return Vec::new();
+            }
+        };
+
+        let mut stale = Vec::new();
+        for row in rows {
+            let table: String = row.get(0);
+            let last_analyzed: Option<String> = row.get(1);
+            let age_days: Option<f64> = row.get(2);
+            let estimated_rows: Option<i64> = row.get(3);
+            let live_rows: i64 = row.get(4);
+            let modified: i64 = row.get(5);
+            let rows = live_rows.max(estimated_rows.unwrap_or(0));
+            if rows < MIN_ROWS {
+                continue;
+            }
+            let estimate_error = estimated_rows.map(|estimated| {
+                let (low, high) = (estimated.min(live_rows), estimated.max(live_rows));
+                high as f64 / low.max(1) as f64
+            });
+            let reason = match age_days {
+                None => "never analyzed".to_string(),
+                Some(_) if modified as f64 >= MAX_CHANGED_FRACTION * rows as f64 => format!(
+                    "{}% of its rows changed since it was last analyzed",
+                    (100 * modified / rows).min(100)
+                ),
+                Some(_) if estimate_error.is_some_and(|e| e >= MAX_ESTIMATE_ERROR) => format!(
+                    "the planner estimates {} rows, but it has about {}",
+                    estimated_rows.unwrap_or(0),
+                    live_rows
+                ),
+                Some(days) if days > max_age_days as f64 && modified > 0 => format!(
+                    "last analyzed {} days ago, and {} rows changed since",
+                    days as u64, modified
+                ),
+                Some(_) => continue,
+            };
+            stale.push(StaleTable {
+                suggestion: format!("ANALYZE {};", table),
+                table,
+                reason,
+                last_analyzed,
+                estimated_rows,
+                live_rows,
+                modified_since_analyze: modified,
+            });
+        }
+        stale
+    }
+}
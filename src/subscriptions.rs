@@ -0,0 +1,286 @@
+//! Table resource subscriptions backed by LISTEN/NOTIFY.
+//!
+//! `resources/subscribe` to a table resource makes the session `LISTEN` on
+//! `SUBSCRIPTION_CHANNEL` (`mcp_table_changed` by default), over a database
+//! connection of its own that stays open while the session lasts. A
+//! notification on that channel whose payload names a subscribed table, as
+//! `schema.table` or just `table` for the public schema, is passed on to the
+//! client as `notifications/resources/updated` for each URI subscribed to
+//! that table.
+//!
+//! Something has to send those notifications. Applications can call
+//! `pg_notify` themselves, or, with `SUBSCRIPTION_TRIGGERS=true`, subscribing
+//! installs a statement-level trigger on the table that does so after every
+//! `INSERT`, `UPDATE`, `DELETE`, and `TRUNCATE`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, bail};
+use futures_util::{StreamExt, stream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_postgres::{AsyncMessage, Client, Config};
+use tracing::{debug, info, warn};
+
+use crate::tls::Connector;
+use crate::{config, quote_ident, reconnect};
+
+const DEFAULT_CHANNEL: &str = "mcp_table_changed";
+/// The longest channel name PostgreSQL keeps without truncating it.
+const MAX_CHANNEL_LEN: usize = 63;
+const TRIGGER_NAME: &str = "mcp_notify_change";
+const TRIGGER_FUNCTION: &str = "public.mcp_notify_table_change";
+
+#[derive(Debug, Clone)]
+pub(crate) struct SubscriptionSettings {
+    channel: String,
+    /// Whether subscribing installs a trigger that notifies the channel.
+    install_triggers: bool,
+}
+
+impl SubscriptionSettings {
+    /// Reads `SUBSCRIPTION_CHANNEL` and `SUBSCRIPTION_TRIGGERS`.
+    pub(crate) fn from_env() -> Result<Self> {
+        let channel = config::var("SUBSCRIPTION_CHANNEL")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| DEFAULT_CHANNEL.to_string());
+        if channel.len() > MAX_CHANNEL_LEN {
+            bail!(
+                "SUBSCRIPTION_CHANNEL must be at most {} bytes long",
+                MAX_CHANNEL_LEN
+            );
+        }
+        let install_triggers = config::var("SUBSCRIPTION_TRIGGERS")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+        Ok(Self {
+            channel,
+            install_triggers,
+        })
+    }
+}
+
+/// A session's subscribed URIs and the connection listening for changes.
+/// Clones share them.
+#[derive(Clone)]
+pub(crate) struct Subscriptions {
+    settings: SubscriptionSettings,
+    /// The schema and name of the table each subscribed URI shows, by URI.
+    uris: Arc<Mutex<HashMap<String, (String, String)>>>,
+    /// Where the URIs of changed resources are sent.
+    updates: mpsc::UnboundedSender<String>,
+    listener: Arc<tokio::sync::Mutex<Option<Listener>>>,
+}
+
+/// The task keeping the `LISTEN` connection open and reading its
+/// notifications.
+struct Listener {
+    task: JoinHandle<()>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Subscriptions {
+    /// Returns the subscriptions along with the receiver of the URIs of
+    /// changed resources, which the transport forwards to the client.
+    pub(crate) fn new(settings: SubscriptionSettings) -> (Self, mpsc::UnboundedReceiver<String>) {
+        let (updates, receiver) = mpsc::unbounded_channel();
+        let subscriptions = Self {
+            settings,
+            uris: Arc::new(Mutex::new(HashMap::new())),
+            updates,
+            listener: Arc::new(tokio::sync::Mutex::new(None)),
+        };
+        (subscriptions, receiver)
+    }
+
+    pub(crate) fn install_triggers(&self) -> bool {
+        self.settings.install_triggers
+    }
+
+    pub(crate) fn channel(&self) -> &str {
+        &self.settings.channel
+    }
+
+    pub(crate) fn add(&self, uri: String, schema: String, table: String) {
+        lock(&self.uris).insert(uri, (schema, table));
+    }
+
+    /// Returns whether `uri` was subscribed.
+    pub(crate) fn remove(&self, uri: &str) -> bool {
+        lock(&self.uris).remove(uri).is_some()
+    }
+
+    /// Opens the `LISTEN` connection, unless it is already open. Should it
+    /// close, it is reopened, with backoff, for as long as the session lasts.
+    pub(crate) async fn listen(&self, config: &Config, tls: Connector) -> Result<()> {
+        let mut listener = self.listener.lock().await;
+        if listener
+            .as_ref()
+            .is_some_and(|listener| !listener.task.is_finished())
+        {
+            return Ok(());
+        }
+        let (client, relay) = self.open(config, tls.clone()).await?;
+        info!(
+            "Listening for table changes on channel '{}'",
+            self.settings.channel
+        );
+        let subscriptions = self.clone();
+        let config = config.clone();
+        let task = tokio::spawn(async move {
+            subscriptions
+                .keep_listening(config, tls, client, relay)
+                .await
+        });
+        *listener = Some(Listener { task });
+        Ok(())
+    }
+
+    /// Opens a connection that listens on the channel, and a task passing
+    /// its notifications on until it closes.
+    async fn open(&self, config: &Config, tls: Connector) -> Result<(Client, JoinHandle<()>)> {
+        let (client, mut connection) = config
+            .connect(tls)
+            .await
+            .context("Failed to open the connection for change notifications")?;
+        let channel = self.settings.channel.clone();
+        let uris = self.uris.clone();
+        let updates = self.updates.clone();
+        let relay = tokio::spawn(async move {
+            let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+            while let Some(message) = messages.next().await {
+                match message {
+                    Ok(AsyncMessage::Notification(notification))
+                        if notification.channel() == channel =>
+                    {
+                        for uri in changed_uris(&uris, notification.payload()) {
+                            if updates.send(uri).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Change notification connection failed: {}", e);
+                        return;
+                    }
+                }
+            }
+            warn!("Change notification connection closed");
+        });
+        client
+            .batch_execute(&format!("LISTEN {}", quote_ident(&self.settings.channel)))
+            .await
+            .context("Failed to listen for change notifications")?;
+        Ok((client, relay))
+    }
+
+    /// Reopens the `LISTEN` connection whenever `relay` ends, such as when
+    /// the database restarts, until the session does. Since notifications
+    /// sent meanwhile are lost, every subscribed URI is then reported
+    /// changed, for the client to read it again.
+    async fn keep_listening(
+        &self,
+        config: Config,
+        tls: Connector,
+        mut client: Client,
+        mut relay: JoinHandle<()>,
+    ) {
+        loop {
+            let _ = (&mut relay).await;
+            drop(client);
+            let mut attempt = 0;
+            (client, relay) = loop {
+                attempt += 1;
+                tokio::time::sleep(reconnect::retry_delay(attempt)).await;
+                if self.updates.is_closed() {
+                    return;
+                }
+                match self.open(&config, tls.clone()).await {
+                    Ok(opened) => break opened,
+                    Err(e) => debug!(
+                        "Reopening the change notification connection failed: {:#}",
+                        e
+                    ),
+                }
+            };
+            info!("Reopened the change notification connection");
+            let uris: Vec<String> = lock(&self.uris).keys().cloned().collect();
+            for uri in uris {
+                if self.updates.send(uri).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// The subscribed URIs showing the table a notification `payload` names.
+fn changed_uris(uris: &Mutex<HashMap<String, (String, String)>>, payload: &str) -> Vec<String> {
+    let (schema, table) = payload.split_once('.').unwrap_or(("public", payload));
+    lock(uris)
+        .iter()
+        .filter(|(_, (s, t))| s == schema && t == table)
+        .map(|(uri, _)| uri.clone())
+        .collect()
+}
+
+/// Installs the trigger that notifies `channel` whenever a statement changes
+/// the rows of `qualified`, unless the table has it already.
+pub(crate) async fn install_trigger(
+    client: &Client,
+    qualified: &str,
+    channel: &str,
+) -> Result<(), tokio_postgres::Error> {
+    let function_missing: bool = client
+        .query_one(
+            &format!("SELECT to_regprocedure('{}()') IS NULL", TRIGGER_FUNCTION),
+            &[],
+        )
+        .await?
+        .get(0);
+    if function_missing {
+        client
+            .batch_execute(&format!(
+                "CREATE OR REPLACE FUNCTION {}() RETURNS trigger LANGUAGE plpgsql AS $$ \
+                 BEGIN \
+                     PERFORM pg_notify(TG_ARGV[0], TG_TABLE_SCHEMA || '.' || TG_TABLE_NAME); \
+                     RETURN NULL; \
+                 END $$",
+                TRIGGER_FUNCTION
+            ))
+            .await?;
+    }
+    let installed: bool = client
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM pg_trigger \
+             WHERE tgrelid = to_regclass($1) AND tgname = $2)",
+            &[&qualified, &TRIGGER_NAME],
+        )
+        .await?
+        .get(0);
+    if !installed {
+        client
+            .batch_execute(&format!(
+                "CREATE TRIGGER {} AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON {} \
+                 FOR EACH STATEMENT EXECUTE FUNCTION {}('{}')",
+                TRIGGER_NAME,
+                qualified,
+                TRIGGER_FUNCTION,
+                channel.replace('\'', "''")
+            ))
+            .await?;
+    }
+    Ok(())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
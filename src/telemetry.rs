@@ -0,0 +1,227 @@
+//! Opt-in anonymous usage statistics.
+//!
+//! Nothing is collected or sent unless `TELEMETRY_ENDPOINT` is set. When it
+//! is, the server counts tool calls by tool, failed requests by category, and
+//! the protocol versions clients initialize with, and every
+//! `TELEMETRY_INTERVAL_SECS` POSTs the counts since the last report as JSON:
+//!
+//! ```json
+//! {"server_version": "0.1.0", "transport": "stdio", "period_secs": 3600,
+//!  "tool_calls": {"query": 41, "describe_table": 7},
+//!  "errors": {"invalid_params": 2, "policy_denied:read_only": 1},
+//!  "protocol_versions": {"2025-06-18": 1}}
+//! ```
+//!
+//! Every key is drawn from a fixed set: the server's own tool names (any
+//! other name counts as `unknown`), JSON-RPC error categories and policy rule
+//! names, and protocol versions of the form `YYYY-MM-DD` (any other counts as
+//! `other`). No SQL, arguments, results, table or column names, connection
+//! details, principals, request ids, or installation identifier are ever
+//! included. Each report is written to the log at `info` before it is sent,
+//! and one that cannot be delivered is dropped rather than retried.
+
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{JsonRpcRequest, JsonRpcResponse, config, outbound, tools};
+
+const DEFAULT_INTERVAL_SECS: u64 = 3600;
+/// How long a report may take to deliver.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+static TELEMETRY: OnceLock<Telemetry> = OnceLock::new();
+
+struct Telemetry {
+    endpoint: String,
+    transport: &'static str,
+    known_tools: Vec<String>,
+    counts: Mutex<Counts>,
+}
+
+#[derive(Default)]
+struct Counts {
+    since: Option<Instant>,
+    tool_calls: BTreeMap<String, u64>,
+    errors: BTreeMap<String, u64>,
+    protocol_versions: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    server_version: &'static str,
+    transport: &'static str,
+    period_secs: u64,
+    tool_calls: &'a BTreeMap<String, u64>,
+    errors: &'a BTreeMap<String, u64>,
+    protocol_versions: &'a BTreeMap<String, u64>,
+}
+
+/// Starts reporting if `TELEMETRY_ENDPOINT` is set, from a server serving
+/// over `transport`.
+pub(crate) fn init(transport: &'static str) -> Result<()> {
+    let Some(endpoint) = config::var("TELEMETRY_ENDPOINT")
+        .ok()
+        .filter(|v| !v.is_empty())
+    else {
+        return Ok(());
+    };
+    if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+        bail!("TELEMETRY_ENDPOINT must be an http:// or https:// URL");
+    }
+    outbound::require("TELEMETRY_ENDPOINT")?;
+    let interval = match config::var("TELEMETRY_INTERVAL_SECS") {
+        Ok(v) => v
+            .parse::<u64>()
+            .context("TELEMETRY_INTERVAL_SECS must be a number of seconds")?,
+        Err(_) => DEFAULT_INTERVAL_SECS,
+    };
+    if interval == 0 {
+        bail!("TELEMETRY_INTERVAL_SECS must be at least 1");
+    }
+    let telemetry = TELEMETRY.get_or_init(|| Telemetry {
+        endpoint,
+        transport,
+        known_tools: tools::tool_names(),
+        counts: Mutex::new(Counts {
+            since: Some(Instant::now()),
+            ..Counts::default()
+        }),
+    });
+    info!(
+        "Sending anonymous usage statistics to {} every {}s",
+        telemetry.endpoint, interval
+    );
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(Duration::from_secs(interval));
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            telemetry.report().await;
+        }
+    });
+    Ok(())
+}
+
+/// Counts what a request asks for.
+pub(crate) fn record_request(request: &JsonRpcRequest) {
+    let Some(telemetry) = TELEMETRY.get() else {
+        return;
+    };
+    let param = |name: &str| {
+        request
+            .params
+            .as_ref()
+            .and_then(|p| p.get(name))
+            .and_then(Value::as_str)
+    };
+    let mut counts = telemetry.lock();
+    match request.method.as_str() {
+        "tools/call" => {
+            let tool = param("name")
+                .filter(|name| telemetry.known_tools.iter().any(|t| t == name))
+                .unwrap_or("unknown");
+            *counts.tool_calls.entry(tool.to_string()).or_default() += 1;
+        }
+        "initialize" => {
+            let version = param("protocolVersion")
+                .filter(|v| is_protocol_version(v))
+                .unwrap_or("other");
+            *counts
+                .protocol_versions
+                .entry(version.to_string())
+                .or_default() += 1;
+        }
+        _ => {}
+    }
+}
+
+/// Counts a failed response by category.
+pub(crate) fn record_response(response: &JsonRpcResponse) {
+    let (Some(telemetry), Some(error)) = (TELEMETRY.get(), &response.error) else {
+        return;
+    };
+    let data = error.data.as_ref();
+    let field = |name: &str| data.and_then(|d| d.get(name)).and_then(Value::as_str);
+    let category = match (field("type"), field("rule")) {
+        (Some("policy_denied"), Some(rule)) => format!("policy_denied:{}", rule),
+        _ => match error.code {
+            -32700 => "parse_error",
+            -32600 => "invalid_request",
+            -32601 => "method_not_found",
+            -32602 => "invalid_params",
+            -32603 => "internal_error",
+            _ => "other",
+        }
+        .to_string(),
+    };
+    *telemetry.lock().errors.entry(category).or_default() += 1;
+}
+
+/// Sends what has been counted since the last report, for instance when the
+/// server is shutting down.
+pub(crate) async fn flush() {
+    if let Some(telemetry) = TELEMETRY.get() {
+        telemetry.report().await;
+    }
+}
+
+impl Telemetry {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Counts> {
+        self.counts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn report(&self) {
+        let body = {
+            let mut counts = self.lock();
+            let counts = std::mem::replace(
+                &mut *counts,
+                Counts {
+                    since: Some(Instant::now()),
+                    ..Counts::default()
+                },
+            );
+            if counts.tool_calls.is_empty()
+                && counts.errors.is_empty()
+                && counts.protocol_versions.is_empty()
+            {
+                return;
+            }
+            let report = Report {
+                server_version: env!("CARGO_PKG_VERSION"),
+                transport: self.transport,
+                period_secs: counts.since.map_or(0, |since| since.elapsed().as_secs()),
+                tool_calls: &counts.tool_calls,
+                errors: &counts.errors,
+                protocol_versions: &counts.protocol_versions,
+            };
+            match serde_json::to_string(&report) {
+                Ok(body) => body,
+                Err(e) => {
+                    warn!("Could not encode usage statistics: {}", e);
+                    return;
+                }
+            }
+        };
+        info!(telemetry = true, "Sending usage statistics: {}", body);
+        let sent = outbound::post_json(self.endpoint.clone(), None, body, SEND_TIMEOUT).await;
+        if let Err(e) = sent {
+            warn!("Could not send usage statistics: {}", e);
+        }
+    }
+}
+
+/// Whether `version` looks like an MCP protocol version, `YYYY-MM-DD`.
+fn is_protocol_version(version: &str) -> bool {
+    let bytes = version.as_bytes();
+    bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            4 | 7 => *b == b'-',
+            _ => b.is_ascii_digit(),
+        })
+}
@@ -0,0 +1,364 @@
+//! TLS for database connections.
+//!
+//! The settings follow libpq: `sslmode`, `sslrootcert`, `sslcert`, and
+//! `sslkey` may be given in the `DATABASE_URL` query string or as the
+//! `PGSSLMODE`, `PGSSLROOTCERT`, `PGSSLCERT`, and `PGSSLKEY` environment
+//! variables, with the URL taking precedence. `tokio_postgres` only knows the
+//! modes up to `require`, so these parameters are removed from the URL before
+//! it is parsed and certificate verification is configured here instead.
+//!
+//! TLS needs the `tls` feature. Without it connections are plain text:
+//! `disable` and `prefer` connect without TLS, and the modes that require it
+//! are refused.
+
+#[cfg(feature = "tls")]
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+#[cfg(feature = "tls")]
+use rustls::client::WebPkiServerVerifier;
+#[cfg(feature = "tls")]
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+#[cfg(feature = "tls")]
+use rustls::crypto::WebPkiSupportedAlgorithms;
+#[cfg(feature = "tls")]
+use rustls::pki_types::pem::PemObject;
+#[cfg(feature = "tls")]
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+#[cfg(feature = "tls")]
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use tokio_postgres::config::SslMode as PgSslMode;
+
+use crate::config;
+
+/// Makes the connections to the database.
+#[cfg(feature = "tls")]
+pub(crate) type Connector = tokio_postgres_rustls::MakeRustlsConnect;
+/// Makes the connections to the database, which are plain text in this build.
+#[cfg(not(feature = "tls"))]
+pub(crate) type Connector = tokio_postgres::NoTls;
+
+/// Connection string parameters handled here rather than by `tokio_postgres`.
+const SSL_PARAMS: &[&str] = &["sslmode", "sslrootcert", "sslcert", "sslkey"];
+
+/// How much TLS a connection requires, as libpq's `sslmode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SslMode {
+    Disable,
+    /// Use TLS when the server supports it, without verifying its certificate.
+    Prefer,
+    /// Always use TLS, without verifying the server's certificate.
+    Require,
+    /// Always use TLS and verify the certificate chain, but not the host name.
+    VerifyCa,
+    /// Always use TLS and verify the certificate chain and host name.
+    VerifyFull,
+}
+
+impl SslMode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "disable" => Some(Self::Disable),
+            // libpq's `allow` tries plain text first; TLS-first is the safer
+            // approximation.
+            "allow" | "prefer" => Some(Self::Prefer),
+            "require" => Some(Self::Require),
+            "verify-ca" => Some(Self::VerifyCa),
+            "verify-full" => Some(Self::VerifyFull),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Disable => "disable",
+            Self::Prefer => "prefer",
+            Self::Require => "require",
+            Self::VerifyCa => "verify-ca",
+            Self::VerifyFull => "verify-full",
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn verifies(self) -> bool {
+        matches!(self, Self::VerifyCa | Self::VerifyFull)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TlsSettings {
+    pub(crate) mode: SslMode,
+    root_cert: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+}
+
+impl TlsSettings {
+    /// Removes the TLS parameters from `database_url`, returning the URL that
+    /// remains for `tokio_postgres` and the settings from the URL and the
+    /// environment.
+    pub(crate) fn from_url_and_env(database_url: &str) -> Result<(String, Self)> {
+        let (url, params) = take_ssl_params(database_url);
+        let setting = |name: &str, var: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone())
+                .or_else(|| config::var(var).ok())
+                .filter(|v| !v.is_empty())
+        };
+
+        let mode = match setting("sslmode", "PGSSLMODE") {
+            Some(mode) => SslMode::parse(&mode).with_context(|| {
+                format!(
+                    "Unknown sslmode '{}' (expected disable, allow, prefer, require, verify-ca, or verify-full)",
+                    mode
+                )
+            })?,
+            None => SslMode::Prefer,
+        };
+        let settings = Self {
+            mode,
+            root_cert: setting("sslrootcert", "PGSSLROOTCERT").map(PathBuf::from),
+            client_cert: setting("sslcert", "PGSSLCERT").map(PathBuf::from),
+            client_key: setting("sslkey", "PGSSLKEY").map(PathBuf::from),
+        };
+        if settings.client_cert.is_some() != settings.client_key.is_some() {
+            bail!("sslcert and sslkey must be set together for client certificate authentication");
+        }
+        Ok((url, settings))
+    }
+
+    /// The mode `tokio_postgres` negotiates with; verification happens in the
+    /// connector.
+    pub(crate) fn pg_ssl_mode(&self) -> PgSslMode {
+        match self.mode {
+            SslMode::Disable => PgSslMode::Disable,
+            SslMode::Prefer => PgSslMode::Prefer,
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => PgSslMode::Require,
+        }
+    }
+
+    /// Builds the connector, loading the CA and client certificates.
+    #[cfg(feature = "tls")]
+    pub(crate) fn connector(&self) -> Result<Connector> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .context("Failed to configure TLS")?;
+
+        let builder = if self.mode.verifies() {
+            let roots = self.root_store()?;
+            let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("Failed to configure TLS certificate verification")?;
+            if self.mode == SslMode::VerifyFull {
+                builder.with_webpki_verifier(verifier)
+            } else {
+                builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(IgnoreHostName(verifier)))
+            }
+        } else {
+            let algorithms = provider.signature_verification_algorithms;
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(algorithms)))
+        };
+
+        let config = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                let certs = load_certs(cert)?;
+                let key = PrivateKeyDer::from_pem_file(key)
+                    .with_context(|| format!("Failed to read sslkey {}", key.display()))?;
+                builder
+                    .with_client_auth_cert(certs, key)
+                    .context("Invalid client certificate or key")?
+            }
+            _ => builder.with_no_client_auth(),
+        };
+        Ok(Connector::new(config))
+    }
+
+    /// The plain-text connector, if the settings do without TLS.
+    #[cfg(not(feature = "tls"))]
+    pub(crate) fn connector(&self) -> Result<Connector> {
+        if !matches!(self.mode, SslMode::Disable | SslMode::Prefer) {
+            bail!(
+                "sslmode {} needs the 'tls' feature, which this build does not include",
+                self.mode.name()
+            );
+        }
+        if self.root_cert.is_some() || self.client_cert.is_some() {
+            bail!(
+                "sslrootcert and sslcert need the 'tls' feature, which this build does not include"
+            );
+        }
+        Ok(tokio_postgres::NoTls)
+    }
+
+    /// Trusted CAs: those in `sslrootcert`, or the system's when unset.
+    #[cfg(feature = "tls")]
+    fn root_store(&self) -> Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        match &self.root_cert {
+            Some(path) => {
+                for cert in load_certs(path)? {
+                    roots
+                        .add(cert)
+                        .with_context(|| format!("Invalid certificate in {}", path.display()))?;
+                }
+            }
+            None => {
+                let native = rustls_native_certs::load_native_certs();
+                roots.add_parsable_certificates(native.certs);
+                if roots.is_empty() {
+                    bail!(
+                        "sslmode {:?} needs trusted CA certificates: set sslrootcert or PGSSLROOTCERT",
+                        self.mode
+                    );
+                }
+            }
+        }
+        Ok(roots)
+    }
+}
+
+#[cfg(feature = "tls")]
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates from {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("No certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+/// Splits the TLS parameters out of a URL's query string, or out of a
+/// `key=value` connection string.
+fn take_ssl_params(database_url: &str) -> (String, Vec<(String, String)>) {
+    let mut taken = Vec::new();
+    let mut take = |pair: &str| match pair.split_once('=') {
+        Some((key, value)) if SSL_PARAMS.contains(&key) => {
+            taken.push((key.to_string(), crate::percent_decode(value)));
+            false
+        }
+        _ => true,
+    };
+
+    if !database_url.contains("://") {
+        let kept: Vec<&str> = database_url
+            .split_whitespace()
+            .filter(|pair| take(pair))
+            .collect();
+        return (kept.join(" "), taken);
+    }
+    let Some((base, query)) = database_url.split_once('?') else {
+        return (database_url.to_string(), taken);
+    };
+    let kept: Vec<&str> = query.split('&').filter(|pair| take(pair)).collect();
+    let url = if kept.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}?{}", base, kept.join("&"))
+    };
+    (url, taken)
+}
+
+/// Encrypts without authenticating the server, as libpq's `prefer` and
+/// `require` do. Handshake signatures are still checked.
+#[cfg(feature = "tls")]
+#[derive(Debug)]
+struct AcceptAnyCertificate(WebPkiSupportedAlgorithms);
+
+#[cfg(feature = "tls")]
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+/// Verifies the certificate chain but accepts any host name, as libpq's
+/// `verify-ca` does.
+#[cfg(feature = "tls")]
+#[derive(Debug)]
+struct IgnoreHostName(Arc<WebPkiServerVerifier>);
+
+#[cfg(feature = "tls")]
+impl ServerCertVerifier for IgnoreHostName {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
@@ -0,0 +1,6784 @@
+//! The MCP tools.
+//!
+//! Each tool takes a typed argument struct and returns a typed result. The
+//! argument struct's doc comments are the tool's description and parameter
+//! descriptions, and its `inputSchema` is generated from it, so a tool is
+//! added by writing its argument struct, its implementation, and one line in
+//! each of `tool_definitions` and `call_tool`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::bail;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use bytes::Bytes;
+use deadpool_postgres::Object;
+use futures_util::{SinkExt, pin_mut};
+use schemars::JsonSchema;
+use schemars::Schema;
+use schemars::generate::{Contract, SchemaSettings};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::{FromSql, ToSql, Type};
+use tokio_postgres::{Client, Column, Row};
+use tracing::{debug, error, info, warn};
+
+use crate::branch::{self, BranchProvider};
+use crate::catalog::{self, Catalog, TableDescription};
+use crate::classify;
+use crate::cursor::{CURSOR_NAME, Fetched, QueryCursor};
+use crate::environment::{Environment, PRODUCTION_MAX_ROWS};
+use crate::export::{self, ExportFormat, ExportOptions};
+use crate::fixtures::{self, FixtureContent, FixtureFile};
+use crate::format::ResultFormat;
+use crate::joins::{self, Link, TableColumns, TableName};
+use crate::params::TextParam;
+use crate::pooler::{self, Prepared, RowError};
+use crate::result_limits::Truncation;
+use crate::server_version::Feature;
+use crate::settings::{self, SettingChange};
+use crate::stale_stats::{self, StaleTable};
+use crate::transaction::{self, OpenTransaction, Unavailable};
+use crate::usage::UsageStats;
+use crate::values::OutputOptions;
+use crate::{
+    JsonRpcNotification, JsonRpcResponse, McpServer, PolicyDenial, blob, column_types,
+    connection_config, db_error_data, db_error_message, fingerprint_sql, identity,
+    primary_key_columns, quote_ident, row_to_map, timeout_message, tool_result,
+};
+use crate::{config, conninfo, metrics, plugins, pool, results, values, wide_results};
+
+/// A result row keyed by column name, as built by `row_to_map`.
+type RowMap = HashMap<String, Value>;
+
+/// Why a tool call failed.
+pub(crate) enum ToolError {
+    /// The arguments do not match the tool's input schema or cannot be used.
+    InvalidArguments(String),
+    Denied(PolicyDenial),
+    /// PostgreSQL rejected a statement, or the connection was lost while it
+    /// ran; `data` is the report of why, as built by `db_error_data`.
+    Database {
+        message: String,
+        data: Value,
+    },
+    Failed(String),
+}
+
+impl ToolError {
+    pub(crate) fn into_response(self, id: Option<Value>) -> JsonRpcResponse {
+        match self {
+            Self::InvalidArguments(message) => JsonRpcResponse::error(id, -32602, message),
+            Self::Denied(denial) => denial.into_response(id),
+            Self::Database { message, data } => {
+                let mut response = JsonRpcResponse::error(id, -32603, message);
+                if let Some(error) = response.error.as_mut() {
+                    error.data = Some(data);
+                }
+                response
+            }
+            Self::Failed(message) => JsonRpcResponse::error(id, -32603, message),
+        }
+    }
+
+    /// The message the error's JSON-RPC response carries.
+    pub(crate) fn message(self) -> String {
+        self.into_response(None)
+            .error
+            .map(|error| error.message)
+            .unwrap_or_default()
+    }
+
+    /// Points a database error's `position` into the client's SQL, which the
+    /// server ran after `prefix`.
+    fn after_prefix(mut self, prefix: &str) -> Self {
+        if let Self::Database { data, .. } = &mut self
+            && let Some(position) = data["position"].as_u64()
+        {
+            let shift = prefix.chars().count() as u64;
+            data["position"] = (position > shift).then(|| position - shift).into();
+        }
+        self
+    }
+}
+
+impl From<PolicyDenial> for ToolError {
+    fn from(denial: PolicyDenial) -> Self {
+        Self::Denied(denial)
+    }
+}
+
+type ToolResult<T> = Result<T, ToolError>;
+
+/// Deserializes a tool's arguments, reporting mismatches as invalid params.
+fn parse_args<A: DeserializeOwned>(tool: &str, arguments: Value) -> ToolResult<A> {
+    serde_json::from_value(arguments)
+        .map_err(|e| ToolError::InvalidArguments(format!("Invalid arguments for {}: {}", tool, e)))
+}
+
+/// Generates the inlined schema of `T` as deserialized from arguments or
+/// serialized into results.
+fn schema_for<T: JsonSchema>(contract: Contract) -> Schema {
+    let mut schema = SchemaSettings::draft07()
+        .with(|s| {
+            s.meta_schema = None;
+            s.inline_subschemas = true;
+            s.contract = contract;
+        })
+        .into_generator()
+        .into_root_schema_for::<T>();
+    schema.remove("title");
+    schema
+}
+
+/// Builds a `tools/list` entry from the argument and result types: the
+/// argument type's doc comment is the description, its schema the
+/// `inputSchema`, and the result type's schema the `outputSchema`. MCP only
+/// allows object output schemas, so tools returning a bare list declare none.
+fn tool<A: JsonSchema, R: JsonSchema>(name: &str) -> Value {
+    let mut input_schema = schema_for::<A>(Contract::Deserialize);
+    let description = input_schema.remove("description").unwrap_or(Value::Null);
+    let mut tool = json!({
+        "name": name,
+        "description": description,
+        "inputSchema": input_schema.to_value(),
+    });
+    let mut output_schema = schema_for::<R>(Contract::Serialize);
+    if output_schema.get("type") == Some(&json!("object")) {
+        output_schema.remove("description");
+        tool["outputSchema"] = output_schema.to_value();
+    }
+    unwrap_descriptions(&mut tool);
+    tool
+}
+
+/// Rejoins descriptions taken from doc comments, which schemars keeps wrapped
+/// at the source line width, into one line per paragraph.
+fn unwrap_descriptions(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    Value::String(text) if key == "description" => {
+                        *text = text
+                            .split("\n\n")
+                            .map(|paragraph| paragraph.split('\n').collect::<Vec<_>>().join(" "))
+                            .collect::<Vec<_>>()
+                            .join("\n\n");
+                    }
+                    _ => unwrap_descriptions(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(unwrap_descriptions),
+        _ => {}
+    }
+}
+
+/// Reads `DISABLED_TOOLS`, a comma-separated list of tools to hide from
+/// `tools/list` and refuse to call.
+pub(crate) fn disabled_tools_from_env() -> anyhow::Result<Vec<String>> {
+    let value = config::var("DISABLED_TOOLS").unwrap_or_default();
+    let names: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    let tools = tool_definitions();
+    for name in &names {
+        if !tools.iter().any(|tool| tool["name"] == name.as_str()) && !plugins::provides(name) {
+            bail!("DISABLED_TOOLS names '{}', which is not a tool", name);
+        }
+    }
+    Ok(names)
+}
+
+/// The names of every tool the server defines.
+pub(crate) fn tool_names() -> Vec<String> {
+    tool_definitions()
+        .iter()
+        .filter_map(|tool| tool["name"].as_str().map(str::to_string))
+        .collect()
+}
+
+/// Tools that write, listed only when write operations are enabled. Calling
+/// one otherwise is refused with the reason.
+const WRITE_TOOLS: &[&str] = &[
+    "import_large_object",
+    "export_large_object",
+    "insert_rows",
+    "upsert_rows",
+    "update_rows",
+    "delete_rows",
+    "load_fixtures",
+];
+
+/// Tools that hold a connection across calls, listed only when the pool has
+/// one to spare.
+const TRANSACTION_TOOLS: &[&str] = &[
+    "begin_transaction",
+    "commit_transaction",
+    "rollback_transaction",
+];
+
+/// Tools built on an extension, listed only while it is installed in the
+/// database.
+const EXTENSION_TOOLS: &[(&str, &str)] = &[
+    ("verify_integrity", "amcheck"),
+    ("vector_search", "vector"),
+    ("top_queries", "pg_stat_statements"),
+];
+
+/// Tools that read whole tables or copy data out of the database, which on
+/// production take a `confirm` argument and run only when it is true.
+const CONFIRM_TOOLS: &[&str] = &[
+    "verify_integrity",
+    "export_schema_data",
+    "export_query",
+    "materialize_result",
+    "search_values",
+    "find_duplicates",
+    "find_orphans",
+    "export_large_object",
+    "create_branch",
+];
+
+/// Adds the required `confirm` argument to a tool's input schema.
+fn require_confirmation(input_schema: &mut Value) {
+    input_schema["properties"]["confirm"] = json!({
+        "type": "boolean",
+        "description": "Must be true: this is a production database, so ask the user before calling this tool.",
+    });
+    if let Some(Value::Array(required)) = input_schema.get_mut("required") {
+        required.push(json!("confirm"));
+    } else {
+        input_schema["required"] = json!(["confirm"]);
+    }
+}
+
+fn tool_definitions() -> Vec<Value> {
+    vec![
+        tool::<QueryArgs, Vec<RowMap>>("query"),
+        tool::<AutovacuumStatusArgs, AutovacuumStatus>("autovacuum_status"),
+        tool::<WraparoundCheckArgs, WraparoundReport>("wraparound_check"),
+        tool::<ListActivityArgs, ActivityReport>("list_activity"),
+        tool::<ListLocksArgs, LockReport>("list_locks"),
+        tool::<VerifyIntegrityArgs, IntegrityReport>("verify_integrity"),
+        tool::<TopQueriesArgs, TopQueries>("top_queries"),
+        tool::<TableSizesArgs, TableSizes>("table_sizes"),
+        tool::<ListSchemasArgs, SchemaList>("list_schemas"),
+        tool::<UsageStatsArgs, UsageStats>("usage_stats"),
+        tool::<ExportSchemaDataArgs, ExportResult>("export_schema_data"),
+        tool::<ExportQueryArgs, SpooledResult>("export_query"),
+        tool::<MaterializeResultArgs, MaterializedResultInfo>("materialize_result"),
+        tool::<SearchValuesArgs, SearchResult>("search_values"),
+        tool::<FindDuplicatesArgs, DuplicateReport>("find_duplicates"),
+        tool::<FindOrphansArgs, OrphanReport>("find_orphans"),
+        tool::<SuggestJoinsArgs, JoinSuggestions>("suggest_joins"),
+        tool::<VectorSearchArgs, VectorSearchResult>("vector_search"),
+        tool::<ListLargeObjectsArgs, LargeObjectList>("list_large_objects"),
+        tool::<ReadLargeObjectArgs, LargeObjectChunk>("read_large_object"),
+        tool::<ImportLargeObjectArgs, ImportedLargeObject>("import_large_object"),
+        tool::<ExportLargeObjectArgs, ExportedLargeObject>("export_large_object"),
+        tool::<SessionTimeInfoArgs, SessionTimeInfo>("session_time_info"),
+        tool::<SetSessionTimezoneArgs, SessionTimeInfo>("set_session_timezone"),
+        tool::<DescribeTableArgs, TableDescription>("describe_table"),
+        tool::<InsertRowsArgs, InsertResult>("insert_rows"),
+        tool::<UpsertRowsArgs, UpsertResult>("upsert_rows"),
+        tool::<UpdateRowsArgs, UpdateResult>("update_rows"),
+        tool::<DeleteRowsArgs, DeleteResult>("delete_rows"),
+        tool::<LoadFixturesArgs, FixturesReport>("load_fixtures"),
+        tool::<CreateBranchArgs, BranchInfo>("create_branch"),
+        tool::<BeginTransactionArgs, TransactionInfo>("begin_transaction"),
+        tool::<CommitTransactionArgs, TransactionEnd>("commit_transaction"),
+        tool::<RollbackTransactionArgs, TransactionEnd>("rollback_transaction"),
+        tool::<ExplainArgs, ExplainResult>("explain"),
+    ]
+}
+
+fn default_schema() -> String {
+    "public".to_string()
+}
+
+fn default_int<const N: i64>() -> i64 {
+    N
+}
+
+/// Execute a SQL query against the PostgreSQL database
+#[derive(Deserialize, JsonSchema)]
+struct QueryArgs {
+    /// SQL query to execute. Omit when passing cursor
+    sql: Option<String>,
+    /// Values for the query's $1, $2, ... placeholders, in order. They are
+    /// bound rather than interpolated, and converted to each parameter's type
+    /// by PostgreSQL: pass numbers, booleans, or strings (for dates, UUIDs,
+    /// and other types, their text form), arrays for array parameters,
+    /// objects or arrays for json and jsonb parameters, and null for NULL
+    #[serde(default)]
+    params: Vec<Value>,
+    /// Return at most this many rows, with a next_cursor for fetching the
+    /// rest. Applies to queries (SELECT, VALUES, TABLE, and WITH without
+    /// data-modifying statements); other statements return all their rows.
+    /// On a production database, queries are paged 500 rows at a time by
+    /// default, and no page holds more. Results and pages are also capped
+    /// at the server's MAX_RESULT_ROWS (1000 by default) and
+    /// MAX_RESULT_BYTES, with _meta.truncated telling how to continue
+    max_rows: Option<i64>,
+    /// The next_cursor of a previous page, to fetch the next page of the same
+    /// query instead of running sql. max_rows defaults to the first page's
+    cursor: Option<String>,
+    /// Cancel the statement (or page fetch) if it runs longer than this many
+    /// milliseconds. Defaults to the server's limit, if it has one, and
+    /// cannot exceed it
+    timeout_ms: Option<u64>,
+    /// Run the statement in this transaction from begin_transaction, which
+    /// is rolled back if the statement fails. The rows are returned whole:
+    /// max_rows and cursor cannot be given
+    transaction_id: Option<String>,
+    /// How to return the rows: json, csv, or markdown, a table that takes
+    /// far fewer tokens than JSON for the same rows. csv and markdown have a
+    /// header line and then a line per row, with NULL as an empty cell
+    #[serde(default)]
+    format: ResultFormat,
+    /// Return only these result columns, in this order. Without it, a result
+    /// with more columns than the server shows (50 by default) returns the
+    /// first ones, and lists the rest in _meta.omittedColumns. Pass it again
+    /// with cursor to keep the same columns
+    columns: Option<Vec<String>>,
+    /// Representations to write values in instead of the defaults, for
+    /// spreadsheets and BI tools: dates_as_epoch, numeric_as_float,
+    /// uuid_uppercase, and bool_as_int, each false by default. Pass them
+    /// again with cursor to keep them
+    #[serde(default)]
+    output: OutputOptions,
+}
+
+/// A `query` result rendered as a CSV or markdown table. The cursor of the
+/// next page, if any, follows the table as a text item of its own.
+fn table_result(format: ResultFormat, columns: &[ColumnType], rows: QueryRows) -> Value {
+    let names: Vec<String> = columns.iter().map(|c| c.name.clone()).collect();
+    let (rows, next_cursor) = match rows {
+        QueryRows::All(rows) => (rows, None),
+        QueryRows::Page(page) => (page.rows, page.next_cursor),
+    };
+    let mut content = vec![json!({ "type": "text", "text": format.render(&names, &rows) })];
+    if let Some(cursor) = next_cursor {
+        content.push(json!({
+            "type": "text",
+            "text": format!("More rows follow: pass \"cursor\": \"{}\" for the next page", cursor),
+        }));
+    }
+    json!({ "content": content })
+}
+
+/// The `query` result: every row, or with `max_rows` or `cursor`, a page.
+enum QueryRows {
+    All(Vec<RowMap>),
+    Page(QueryPage),
+}
+
+/// What a `query` result reports about its statement besides the rows.
+#[derive(Default)]
+struct QueryNotes {
+    /// The planner's estimate, with `QUERY_COST_ESTIMATES`.
+    estimate: Option<Value>,
+    stale_statistics: Vec<StaleTable>,
+    /// How `MAX_RESULT_ROWS` or `MAX_RESULT_BYTES` cut the result short, if
+    /// they did.
+    truncated: Option<Truncation>,
+}
+
+#[derive(Serialize)]
+struct QueryPage {
+    rows: Vec<RowMap>,
+    /// Pass as `cursor` to fetch the next page; null after the last page.
+    next_cursor: Option<String>,
+}
+
+/// A result column, listed in the `query` result's `_meta` so that clients
+/// can tell an `int8` or `numeric` written as a string from a text value.
+#[derive(Serialize)]
+struct ColumnType {
+    name: String,
+    r#type: String,
+}
+
+/// Show running autovacuum workers, autovacuum settings and per-table
+/// overrides, and tables with a high dead-tuple ratio that have not been
+/// vacuumed recently
+#[derive(Deserialize, JsonSchema)]
+struct AutovacuumStatusArgs {
+    /// Minimum fraction of dead tuples (0-1) for a table to be reported
+    #[serde(default = "default_min_dead_ratio")]
+    min_dead_ratio: f64,
+    /// Only report tables not vacuumed within this many hours
+    #[serde(default = "default_stale_hours")]
+    stale_hours: i32,
+    /// Maximum number of tables to report
+    #[serde(default = "default_int::<20>")]
+    limit: i64,
+}
+
+fn default_min_dead_ratio() -> f64 {
+    0.1
+}
+
+fn default_stale_hours() -> i32 {
+    24
+}
+
+#[derive(Serialize, JsonSchema)]
+struct AutovacuumStatus {
+    running_workers: Vec<RowMap>,
+    settings: Vec<RowMap>,
+    table_overrides: Vec<RowMap>,
+    tables_needing_vacuum: Vec<RowMap>,
+}
+
+/// Report transaction ID wraparound risk: datfrozenxid age per database and
+/// relfrozenxid age of the oldest tables, flagged against warning/critical
+/// thresholds
+#[derive(Deserialize, JsonSchema)]
+struct WraparoundCheckArgs {
+    /// Percent of the 2^31 XID horizon at which to flag a warning
+    #[serde(default = "default_warning_percent")]
+    warning_percent: f64,
+    /// Percent of the 2^31 XID horizon at which to flag a critical risk
+    #[serde(default = "default_critical_percent")]
+    critical_percent: f64,
+    /// Number of oldest tables to report
+    #[serde(default = "default_int::<10>")]
+    limit: i64,
+}
+
+fn default_warning_percent() -> f64 {
+    50.0
+}
+
+fn default_critical_percent() -> f64 {
+    75.0
+}
+
+#[derive(Serialize, JsonSchema)]
+struct WraparoundReport {
+    /// The worst status found: `ok`, `warning`, or `critical`
+    status: &'static str,
+    warning_percent: f64,
+    critical_percent: f64,
+    databases: Vec<RowMap>,
+    oldest_tables: Vec<RowMap>,
+}
+
+/// List the sessions connected to the current database from
+/// pg_stat_activity: what each is running, for how long, what it waits on,
+/// and which sessions block it. Read-only
+#[derive(Deserialize, JsonSchema)]
+struct ListActivityArgs {
+    /// Also list idle sessions; by default only those running a statement or
+    /// idle in a transaction are
+    #[serde(default)]
+    include_idle: bool,
+    /// Only list sessions whose current statement, or last one if idle, has
+    /// run at least this many seconds
+    min_duration_secs: Option<f64>,
+    /// Maximum number of sessions to list, longest running first
+    #[serde(default = "default_int::<50>")]
+    limit: i64,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ActivityReport {
+    sessions: Vec<RowMap>,
+}
+
+/// List the current database's lock waits from pg_locks: which session waits
+/// on which, for what lock, and what each is running, to diagnose lock
+/// contention. Read-only
+#[derive(Deserialize, JsonSchema)]
+struct ListLocksArgs {
+    /// Also list granted locks, not only those sessions are waiting for
+    #[serde(default)]
+    include_granted: bool,
+    /// Maximum number of locks to list, waiting ones first
+    #[serde(default = "default_int::<100>")]
+    limit: i64,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct LockReport {
+    /// Each waiting session paired with a session blocking it
+    blocking: Vec<RowMap>,
+    locks: Vec<RowMap>,
+}
+
+/// Report the statements of the current database that took the most time or
+/// ran the most often, from the pg_stat_statements extension: their calls,
+/// total and mean execution time, and rows. Read-only
+#[derive(Deserialize, JsonSchema)]
+struct TopQueriesArgs {
+    /// What to rank statements by: total_time (default), mean_time, calls, or
+    /// rows
+    #[serde(default)]
+    sort: TopQueriesSort,
+    /// Maximum number of statements to report (at most 100)
+    #[serde(default = "default_int::<10>")]
+    limit: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum TopQueriesSort {
+    #[default]
+    TotalTime,
+    MeanTime,
+    Calls,
+    Rows,
+}
+
+impl TopQueriesSort {
+    /// The result column to order by.
+    fn column(self) -> &'static str {
+        match self {
+            Self::TotalTime => "total_time_ms",
+            Self::MeanTime => "mean_time_ms",
+            Self::Calls => "calls",
+            Self::Rows => "rows",
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TopQueries {
+    sort: TopQueriesSort,
+    pg_stat_statements_version: String,
+    queries: Vec<TopQuery>,
+    /// Statements left out because they refer to tables the access lists
+    /// hide, or cannot be checked against them
+    hidden: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TopQuery {
+    /// pg_stat_statements' identifier for the statement, as a string since
+    /// it may exceed what JSON numbers hold exactly
+    query_id: Option<String>,
+    /// The statement, with its constants replaced by $1, $2, ...; null for
+    /// another user's statement unless the connecting user is a superuser or
+    /// a member of pg_read_all_stats
+    query: Option<String>,
+    calls: i64,
+    total_time_ms: f64,
+    mean_time_ms: f64,
+    rows: i64,
+    /// Share of the total execution time of every statement in the database
+    percent_of_total_time: Option<f64>,
+}
+
+/// Report the disk space each table takes: its heap, its indexes, its TOAST
+/// table, and the total, largest first. Read-only
+#[derive(Deserialize, JsonSchema)]
+struct TableSizesArgs {
+    /// Only report tables in this schema. Defaults to every schema but the
+    /// system ones
+    schema: Option<String>,
+    /// Maximum number of tables to report
+    #[serde(default = "default_int::<50>")]
+    limit: i64,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TableSizes {
+    tables: Vec<TableSize>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TableSize {
+    schema: String,
+    table: String,
+    /// `table`, `partition`, or `materialized_view`
+    kind: String,
+    /// The heap's main fork, from pg_relation_size
+    heap_bytes: i64,
+    /// Every index on the table, from pg_indexes_size
+    index_bytes: i64,
+    /// The TOAST table holding out-of-line values, and its index
+    toast_bytes: i64,
+    /// Everything, from pg_total_relation_size: the parts above plus the
+    /// heap's free space and visibility maps
+    total_bytes: i64,
+    /// total_bytes for reading, such as "12 MB"
+    total_size: String,
+}
+
+/// List the database's schemas, with their owners, comments, and how many
+/// tables and views each holds. Tables outside public are read as resources
+/// named postgres:///<schema>/<table>. Read-only
+#[derive(Deserialize, JsonSchema)]
+struct ListSchemasArgs {
+    /// Also list the system schemas: pg_catalog, information_schema, and
+    /// the pg_toast and temporary schemas
+    #[serde(default)]
+    include_system: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SchemaList {
+    schemas: Vec<SchemaInfo>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SchemaInfo {
+    name: String,
+    owner: String,
+    /// The schema's comment, if it has one
+    description: Option<String>,
+    /// Tables, partitioned tables, and foreign tables
+    tables: usize,
+    /// Views and materialized views
+    views: usize,
+}
+
+/// Summarize this server's own usage, for this session and since the server
+/// started: calls and errors per tool, the error rate, rows the query tool
+/// returned, and the most accessed tables. The counts are kept in memory and
+/// start over when the server restarts. Does not query the database
+#[derive(Deserialize, JsonSchema)]
+struct UsageStatsArgs {
+    /// Maximum number of tables to report in each top_tables
+    #[serde(default = "default_int::<10>")]
+    limit: i64,
+}
+
+/// Check tables and indexes for corruption using the amcheck extension
+/// (verify_heapam, bt_index_check) and report data checksum status and
+/// failures. Read-only.
+#[derive(Deserialize, JsonSchema)]
+struct VerifyIntegrityArgs {
+    /// Tables or btree indexes to check, optionally schema-qualified. Checking
+    /// a table also checks its btree indexes.
+    relations: Vec<String>,
+    /// Also verify that every heap tuple has an index entry (slower)
+    #[serde(default)]
+    heapallindexed: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct IntegrityReport {
+    /// `corruption_found` if any check found corruption, otherwise `ok`
+    status: &'static str,
+    amcheck_version: String,
+    data_checksums: Option<String>,
+    checksum_failures: Option<Vec<RowMap>>,
+    results: Vec<RelationCheck>,
+}
+
+/// The outcome of one amcheck check, or why a relation could not be checked.
+#[derive(Serialize, JsonSchema)]
+struct RelationCheck {
+    relation: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    check: Option<&'static str>,
+    /// `ok`, `corrupt`, or `error`
+    status: &'static str,
+    /// Corrupt tuples reported by verify_heapam, or the btree check's error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    findings: Option<Vec<Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl RelationCheck {
+    fn error(relation: &str, check: Option<&'static str>, error: impl Into<String>) -> Self {
+        Self {
+            relation: relation.to_string(),
+            check,
+            status: "error",
+            findings: None,
+            error: Some(error.into()),
+        }
+    }
+}
+
+/// Export every table in a schema to CSV or Parquet files plus a schema.json
+/// manifest, in a directory under the server's configured export root. Sends
+/// progress notifications when a progressToken is supplied.
+#[derive(Deserialize, JsonSchema)]
+struct ExportSchemaDataArgs {
+    /// Directory to create, relative to the server's export root
+    output_dir: String,
+    /// Schema to export
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Output file format
+    #[serde(default)]
+    format: ExportFormat,
+    /// Maximum rows exported per table
+    #[serde(default = "default_int::<100_000>")]
+    max_rows_per_table: i64,
+    /// Maximum total bytes written across all tables
+    #[serde(default = "default_max_bytes")]
+    max_bytes: u64,
+}
+
+fn default_max_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ExportResult {
+    output_dir: String,
+    manifest: Value,
+}
+
+/// Run a read-only query and write its result to a CSV file on the server
+/// instead of returning the rows, for results too large to return directly.
+/// The file is readable as a resource for the rest of the session
+#[derive(Deserialize, JsonSchema)]
+struct ExportQueryArgs {
+    /// Query to run: a SELECT, VALUES, TABLE, or WITH query. It runs in a
+    /// read-only transaction and cannot take $1 placeholders
+    sql: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SpooledResult {
+    /// Resource URI to read the CSV through resources/read
+    uri: String,
+    /// The file on the server's disk; left out when spool files are
+    /// encrypted, as the file is then readable only through `uri`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_uri: Option<String>,
+    mime_type: &'static str,
+    rows: u64,
+    bytes: u64,
+    /// Whether the spool quota stopped the file before the result's last row
+    truncated: bool,
+}
+
+/// Run a read-only query and keep its result on the server as a resource,
+/// readable a page at a time through resources/read until it expires, so a
+/// large intermediate result can be consulted repeatedly without running the
+/// query again or returning every row. Results last at most for the session
+#[derive(Deserialize, JsonSchema)]
+struct MaterializeResultArgs {
+    /// Query to run: a SELECT, VALUES, TABLE, or WITH query. It runs in a
+    /// read-only transaction
+    sql: String,
+    /// Values for the query's $1, $2, ... placeholders, in order, as for the
+    /// query tool
+    #[serde(default)]
+    params: Vec<Value>,
+    /// Seconds to keep the result, at most 86400
+    #[serde(default = "default_materialize_ttl_secs")]
+    ttl_secs: u64,
+}
+
+fn default_materialize_ttl_secs() -> u64 {
+    900
+}
+
+const MAX_MATERIALIZE_TTL_SECS: u64 = 86400;
+
+#[derive(Serialize, JsonSchema)]
+struct MaterializedResultInfo {
+    /// Resource URI to read the result through resources/read. It takes
+    /// limit, offset, and format (json, csv, or markdown) query parameters
+    uri: String,
+    rows: usize,
+    columns: Vec<String>,
+    /// The result's size as JSON
+    bytes: u64,
+    /// Seconds until the result expires
+    ttl_secs: u64,
+}
+
+/// Search for a string or number across the text and numeric columns of every
+/// table in a schema, returning the table, column, and row identifier (primary
+/// key or ctid) of each match
+#[derive(Deserialize, JsonSchema)]
+struct SearchValuesArgs {
+    /// Value to search for. Strings match text columns case-insensitively as a
+    /// substring; numbers also match numeric columns exactly.
+    value: SearchValue,
+    /// Schema to search
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Only search these tables
+    tables: Option<Vec<String>>,
+    /// Match text columns exactly instead of by substring
+    #[serde(default)]
+    exact: bool,
+    /// Maximum matching rows returned per table
+    #[serde(default = "default_int::<10>")]
+    limit_per_table: i64,
+    /// Overall time budget for the search in milliseconds
+    #[serde(default = "default_search_timeout_ms")]
+    timeout_ms: u64,
+    /// Also search rows that a table's soft-delete filter marks deleted
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+fn default_search_timeout_ms() -> u64 {
+    10_000
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum SearchValue {
+    Text(String),
+    Number(serde_json::Number),
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SearchResult {
+    value: String,
+    schema: String,
+    /// Whether the time budget ran out before every table was searched
+    timed_out: bool,
+    tables_searched: Vec<TableSearch>,
+    matches: Vec<ValueMatch>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TableSearch {
+    table: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matching_rows: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The soft-delete filter rows had to pass, if the table has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    soft_delete_filter: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ValueMatch {
+    table: String,
+    column: String,
+    /// Primary key (or ctid) of the matching row
+    row: HashMap<String, Option<String>>,
+    /// The matched value, truncated to 200 characters
+    value: Option<String>,
+}
+
+/// Find groups of rows in a table that share the same values in the given
+/// columns, with the size of each group and sample primary keys
+#[derive(Deserialize, JsonSchema)]
+struct FindDuplicatesArgs {
+    /// Table to check
+    table: String,
+    /// Schema containing the table
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Columns whose combined values should be unique
+    columns: Vec<String>,
+    /// Treat rows with NULL in any of the columns as potential duplicates
+    /// (matching unique constraint semantics when false)
+    #[serde(default)]
+    include_nulls: bool,
+    /// Maximum number of duplicate groups to return, largest first
+    #[serde(default = "default_int::<20>")]
+    limit: i64,
+    /// Number of sample primary keys to return per group
+    #[serde(default = "default_int::<5>")]
+    sample_size: i64,
+    /// Also count rows that the table's soft-delete filter marks deleted
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct DuplicateReport {
+    table: String,
+    columns: Vec<String>,
+    /// The soft-delete filter rows had to pass, if the table has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    soft_delete_filter: Option<String>,
+    duplicate_groups: i64,
+    duplicate_rows: i64,
+    groups: Vec<DuplicateGroup>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct DuplicateGroup {
+    values: HashMap<String, Option<String>>,
+    count: i64,
+    /// Primary keys (or ctids) of some rows in the group
+    sample_keys: Value,
+}
+
+/// Audit referential integrity: for foreign keys in a schema (or an undeclared
+/// relationship you describe), count and sample child rows whose parent row is
+/// missing, and optionally parent rows with no children
+#[derive(Deserialize, JsonSchema)]
+struct FindOrphansArgs {
+    /// Schema whose foreign keys are checked
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Only check the foreign key with this name
+    constraint: Option<String>,
+    /// Only check foreign keys referencing or referenced by this table
+    table: Option<String>,
+    /// Check an undeclared relationship: the referencing table (requires
+    /// child_columns, parent_table, parent_columns)
+    child_table: Option<String>,
+    /// Referencing columns of child_table
+    #[serde(default)]
+    child_columns: Vec<String>,
+    /// Referenced table of the undeclared relationship
+    parent_table: Option<String>,
+    /// Referenced columns of parent_table, in the same order as child_columns
+    #[serde(default)]
+    parent_columns: Vec<String>,
+    /// Also report parent rows that no child row references
+    #[serde(default)]
+    include_childless_parents: bool,
+    /// Number of sample rows to return per relationship
+    #[serde(default = "default_int::<5>")]
+    sample_size: i64,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct OrphanReport {
+    schema: String,
+    relationships_checked: usize,
+    relationships: Vec<RelationshipAudit>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct RelationshipAudit {
+    constraint: Option<String>,
+    child: String,
+    parent: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    orphaned_children: Option<SampledCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    childless_parents: Option<SampledCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Propose how to join two or more tables: join conditions along their
+/// foreign keys, through other tables where they are not directly related,
+/// and a SELECT skeleton. Where no foreign key relates two tables, a column
+/// named after the other table's key (orders.customer_id for customers.id)
+/// with a matching type is proposed instead, marked as a name match
+#[derive(Deserialize, JsonSchema)]
+struct SuggestJoinsArgs {
+    /// Tables to join, at least two
+    tables: Vec<String>,
+    /// Schema containing the tables
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Most joins to chain between two of the tables, from 1 to 6
+    #[serde(default = "default_int::<3>")]
+    max_hops: i64,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct JoinSuggestions {
+    /// The tables in join order with their aliases in sql, including any
+    /// joined only to reach others
+    tables: Vec<JoinedTable>,
+    joins: Vec<SuggestedJoin>,
+    /// Requested tables no path of at most max_hops joins reaches; sql leaves
+    /// them out
+    unconnected: Vec<String>,
+    /// A SELECT joining the tables, to fill in
+    sql: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct JoinedTable {
+    table: String,
+    alias: String,
+    /// Whether the table is only joined to reach the requested ones
+    intermediate: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SuggestedJoin {
+    /// The referencing table
+    from_table: String,
+    from_columns: Vec<String>,
+    /// The referenced table
+    to_table: String,
+    to_columns: Vec<String>,
+    /// The join condition, using the tables' aliases
+    condition: String,
+    /// 'foreign_key', or 'name_match' for a relationship inferred from column
+    /// names and types that no constraint declares
+    source: &'static str,
+    /// The foreign key constraint of a foreign_key join
+    constraint: Option<String>,
+}
+
+/// Find the rows whose embedding is nearest to a query vector, using the
+/// pgvector extension: the k nearest rows by the chosen distance, nearest
+/// first
+#[derive(Deserialize, JsonSchema)]
+struct VectorSearchArgs {
+    /// Table to search
+    table: String,
+    /// Schema containing the table
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// The vector column to compare with the query vector
+    column: String,
+    /// The query vector, with as many numbers as the column's dimensions
+    vector: Vec<f64>,
+    /// Distance to rank by: l2 (Euclidean, <->), cosine (<=>), or
+    /// inner_product (<#>, which is the negative inner product)
+    #[serde(default)]
+    metric: VectorMetric,
+    /// Number of rows to return
+    #[serde(default = "default_int::<10>")]
+    k: i64,
+    /// Conditions the rows must meet, as in update_rows
+    #[serde(default)]
+    filter: Vec<Condition>,
+    /// Columns to return with each row; all but the vector column by default
+    #[serde(default)]
+    columns: Vec<String>,
+    /// Also search rows that the table's soft-delete filter marks deleted
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum VectorMetric {
+    #[default]
+    L2,
+    Cosine,
+    InnerProduct,
+}
+
+impl VectorMetric {
+    fn operator(self) -> &'static str {
+        match self {
+            Self::L2 => "<->",
+            Self::Cosine => "<=>",
+            Self::InnerProduct => "<#>",
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+struct VectorSearchResult {
+    table: String,
+    column: String,
+    metric: VectorMetric,
+    /// The soft-delete filter rows had to pass, if the table has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    soft_delete_filter: Option<String>,
+    /// The nearest rows, nearest first
+    matches: Vec<VectorMatch>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct VectorMatch {
+    /// The row's distance from the query vector; for inner_product, the
+    /// negative inner product, so that smaller is nearer here too
+    distance: f64,
+    row: RowMap,
+}
+
+/// List the large objects in the database with their OID, owner, and size
+#[derive(Deserialize, JsonSchema)]
+struct ListLargeObjectsArgs {
+    /// Maximum number of large objects to return
+    #[serde(default = "default_int::<100>")]
+    limit: i64,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct LargeObjectList {
+    count: usize,
+    large_objects: Vec<RowMap>,
+}
+
+/// How large object bytes are carried in tool arguments and results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    #[default]
+    Base64,
+    Text,
+}
+
+/// Read a chunk of a large object. The result includes the total size and the
+/// offset of the next chunk, so large documents can be retrieved piece by
+/// piece
+#[derive(Deserialize, JsonSchema)]
+struct ReadLargeObjectArgs {
+    /// OID of the large object
+    oid: i64,
+    /// Byte offset to start reading at
+    #[serde(default)]
+    offset: i64,
+    /// Maximum number of bytes to read (at most 10485760)
+    #[serde(default = "default_int::<1_048_576>")]
+    length: i64,
+    /// Return the bytes base64-encoded or as UTF-8 text
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct LargeObjectChunk {
+    oid: i64,
+    size: i64,
+    offset: i64,
+    bytes_read: usize,
+    /// Offset of the next chunk, or null at the end of the object
+    next_offset: Option<i64>,
+    /// MIME type sniffed from the data, for chunks at offset 0
+    mime_type: Option<&'static str>,
+    encoding: Encoding,
+    data: String,
+}
+
+/// Create a large object from the given data and return its OID (requires
+/// write operations to be enabled)
+#[derive(Deserialize, JsonSchema)]
+struct ImportLargeObjectArgs {
+    /// Contents of the new large object
+    data: String,
+    /// How 'data' is encoded
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ImportedLargeObject {
+    oid: i64,
+    size: usize,
+    mime_type: &'static str,
+}
+
+/// Write a large object to a file beneath the server's export directory
+/// (requires EXPORT_DIR and DANGEROUSLY_ALLOW_WRITE_OPS to be set)
+#[derive(Deserialize, JsonSchema)]
+struct ExportLargeObjectArgs {
+    /// OID of the large object
+    oid: i64,
+    /// File to write, relative to EXPORT_DIR
+    path: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ExportedLargeObject {
+    oid: i64,
+    path: String,
+    size: u64,
+}
+
+/// Report the database's current time, the session time zone and its UTC
+/// offset, and today's date both in the session time zone and in UTC. Check it
+/// before computing relative dates such as "yesterday", which depend on the
+/// session time zone
+#[derive(Deserialize, JsonSchema)]
+struct SessionTimeInfoArgs {}
+
+/// Set the time zone this session uses to display timestamptz values and to
+/// evaluate current_date and now()::date. It stays in effect for the rest of
+/// the session
+#[derive(Deserialize, JsonSchema)]
+struct SetSessionTimezoneArgs {
+    /// IANA time zone name such as 'UTC' or 'America/New_York'. Prefer names
+    /// to offsets: PostgreSQL reads POSIX-style offsets such as '+05' as west
+    /// of UTC
+    timezone: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SessionTimeInfo {
+    /// The session's TimeZone setting
+    timezone: String,
+    /// The time zone the session started with; pass it to set_session_timezone
+    /// to restore it
+    default_timezone: Option<String>,
+    /// Offset of the session time zone from UTC, such as '+02:00'
+    utc_offset: String,
+    utc_offset_seconds: i32,
+    /// now() in the session time zone, with its offset
+    server_time: String,
+    /// now() in UTC
+    utc_time: String,
+    /// current_date in the session time zone
+    current_date: String,
+    /// The current date in UTC, which differs from current_date near midnight
+    utc_date: String,
+}
+
+/// Describe a table, view, or materialized view: its columns with their
+/// types, nullability, and defaults, and its primary key, unique constraints,
+/// foreign keys, check constraints, and indexes. Use it to learn a table's
+/// structure before writing queries against it
+#[derive(Deserialize, JsonSchema)]
+struct DescribeTableArgs {
+    /// Table to describe
+    table: String,
+    /// Schema containing the table
+    #[serde(default = "default_schema")]
+    schema: String,
+}
+
+/// Insert many rows into a table in one transaction, batched into multi-row
+/// INSERT statements with bound values. Optionally skip or update rows that
+/// conflict with a unique constraint (requires write operations to be
+/// enabled)
+#[derive(Deserialize, JsonSchema)]
+struct InsertRowsArgs {
+    /// Table to insert into
+    table: String,
+    /// Schema containing the table
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Rows to insert, each an object mapping column names to values. A
+    /// column missing from a row gets its default; null inserts NULL. Values
+    /// are converted to the column types as in the query tool's params
+    rows: Vec<Map<String, Value>>,
+    /// What to do with rows that conflict with an existing row
+    on_conflict: Option<OnConflict>,
+    /// Maximum rows per INSERT statement; lowered automatically to stay under
+    /// the limit of 32767 bound values per statement
+    #[serde(default = "default_int::<500>")]
+    batch_size: i64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct OnConflict {
+    /// 'nothing' skips conflicting rows; 'update' overwrites the existing row
+    action: ConflictAction,
+    /// Columns of the unique constraint or index to detect conflicts on.
+    /// Required for 'update'; for 'nothing', omit to skip conflicts on any
+    /// constraint
+    #[serde(default)]
+    columns: Vec<String>,
+    /// Columns to overwrite on conflict; defaults to every inserted column not
+    /// in 'columns'
+    update_columns: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum ConflictAction {
+    Nothing,
+    Update,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct InsertResult {
+    table: String,
+    /// Rows inserted or updated across all batches
+    affected: u64,
+    /// Rows skipped because of a conflict ('nothing' only)
+    skipped: u64,
+    batches: Vec<InsertBatch>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct InsertBatch {
+    /// Index in 'rows' of the batch's first row
+    first_row: usize,
+    rows: usize,
+    /// Rows inserted or updated by the batch
+    affected: u64,
+}
+
+/// Insert or update rows by a natural key in one transaction: rows whose key
+/// columns match an existing row update it, and the rest are inserted. The
+/// key columns must be exactly the columns of a unique constraint or index,
+/// unless the rows are merged with MERGE on PostgreSQL 15+ (requires write
+/// operations to be enabled)
+#[derive(Deserialize, JsonSchema)]
+struct UpsertRowsArgs {
+    /// Table to write to
+    table: String,
+    /// Schema containing the table
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Columns identifying a row, such as ['email'] or ['tenant_id', 'sku'].
+    /// Every row must give a value for each
+    key_columns: Vec<String>,
+    /// Rows to write, each an object mapping column names to values. A column
+    /// missing from a row gets its default when the row is inserted and is
+    /// left unchanged when it is updated
+    rows: Vec<Map<String, Value>>,
+    /// Columns to overwrite in existing rows; defaults to every column given
+    /// in the rows except the key columns
+    update_columns: Option<Vec<String>>,
+    /// Maximum rows per statement; lowered automatically to stay under the
+    /// limit of 32767 bound values per statement
+    #[serde(default = "default_int::<500>")]
+    batch_size: i64,
+    /// How rows are written: 'on_conflict' (INSERT ... ON CONFLICT, needing a
+    /// unique constraint or index on exactly the key columns), 'merge' (MERGE,
+    /// PostgreSQL 15+, needing none, but liable to fail with a unique
+    /// violation when another session inserts the same key meanwhile), or
+    /// 'auto' (default), which uses on_conflict when such a constraint exists
+    /// and merge otherwise
+    #[serde(default)]
+    method: UpsertMethod,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum UpsertMethod {
+    #[default]
+    Auto,
+    OnConflict,
+    Merge,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct UpsertResult {
+    table: String,
+    /// 'on_conflict' or 'merge'
+    method: UpsertMethod,
+    inserted: u64,
+    updated: u64,
+    batches: Vec<UpsertBatch>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct UpsertBatch {
+    /// Index in 'rows' of the batch's first row
+    first_row: usize,
+    rows: usize,
+    inserted: u64,
+    updated: u64,
+}
+
+/// Update the rows matching a key and filter. With expected_values, the
+/// update applies only if the row still holds the values it was read with, so
+/// an edit made meanwhile by someone else is reported as a conflict instead of
+/// being overwritten (requires write operations to be enabled)
+#[derive(Deserialize, JsonSchema)]
+struct UpdateRowsArgs {
+    /// Table to update
+    table: String,
+    /// Schema containing the table
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Column values identifying the rows to update, such as {"id": 42}
+    #[serde(default)]
+    key: Map<String, Value>,
+    /// Conditions the rows to update must also meet. At least one of key and
+    /// filter is required
+    #[serde(default)]
+    filter: Vec<Condition>,
+    /// New values by column name; null sets NULL. Values are converted to the
+    /// column types as in the query tool's params
+    set: Map<String, Value>,
+    /// Values the row must still hold, as read before editing it, such as
+    /// {"version": 7} or {"status": "draft"}. NULL matches NULL
+    #[serde(default)]
+    expected_values: Map<String, Value>,
+    /// A version column, incremented by one on every update made by this
+    /// tool. Its value as read must be given in expected_values
+    version_column: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum UpdateStatus {
+    Updated,
+    /// Rows match the key but not expected_values: they changed since they
+    /// were read
+    Conflict,
+    /// No row matches the key
+    NotFound,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct UpdateResult {
+    table: String,
+    status: UpdateStatus,
+    updated: u64,
+    /// On conflict, the current values of the rows matching the key and
+    /// filter (at most 10), to re-read before trying again
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    current: Vec<RowMap>,
+}
+
+/// A condition on a column, such as {"column": "created_at", "op": "lt",
+/// "value": "2024-01-01"}
+#[derive(Deserialize, JsonSchema)]
+struct Condition {
+    column: String,
+    op: ConditionOp,
+    /// Value to compare with, converted to the column type as in the query
+    /// tool's params: an array for in and not_in, a pattern for like and
+    /// ilike, and omitted for is_null and is_not_null
+    #[serde(default)]
+    value: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum ConditionOp {
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    In,
+    NotIn,
+    Like,
+    Ilike,
+    IsNull,
+    IsNotNull,
+}
+
+/// Delete the rows matching a key and filter, such as {"key": {"id": 42}} or
+/// {"filter": [{"column": "status", "op": "eq", "value": "spam"}]}. Nothing is
+/// deleted if more than max_rows rows match (requires write operations to be
+/// enabled)
+#[derive(Deserialize, JsonSchema)]
+struct DeleteRowsArgs {
+    /// Table to delete from
+    table: String,
+    /// Schema containing the table
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Column values identifying the rows to delete, such as {"id": 42}
+    #[serde(default)]
+    key: Map<String, Value>,
+    /// Conditions the rows to delete must also meet. At least one of key and
+    /// filter is required
+    #[serde(default)]
+    filter: Vec<Condition>,
+    /// The most rows to delete; if more match, none are
+    #[serde(default = "default_int::<100>")]
+    max_rows: i64,
+    /// Return the deleted rows
+    #[serde(default)]
+    returning: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct DeleteResult {
+    table: String,
+    deleted: u64,
+    /// The deleted rows, with returning
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rows: Option<Vec<RowMap>>,
+}
+
+/// Reset a development database to known data by loading a directory of
+/// fixture files beneath FIXTURES_DIR: .sql files are run, then the tables
+/// named by .csv, .json, and flow style .yaml files are emptied and loaded,
+/// parents first. Everything runs in one transaction (requires write
+/// operations to be enabled; refused when DB_ENVIRONMENT is production)
+#[derive(Deserialize, JsonSchema)]
+struct LoadFixturesArgs {
+    /// Directory of fixture files, relative to FIXTURES_DIR. Omit to load
+    /// the files in FIXTURES_DIR itself
+    path: Option<String>,
+    /// Schema of the tables data files name without one
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Keep the rows already in the loaded tables instead of emptying them
+    /// first
+    #[serde(default)]
+    keep_existing: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct FixturesReport {
+    /// The fixture directory
+    dir: String,
+    /// The files loaded, in the order they were
+    files: Vec<LoadedFixture>,
+    /// Tables emptied before loading
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    truncated: Vec<String>,
+    /// Sequences advanced past the loaded values of the columns they feed
+    sequences_reset: usize,
+    /// Files in the directory that are not fixtures
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    skipped: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct LoadedFixture {
+    file: String,
+    /// The table a data file loaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    table: Option<String>,
+    /// Rows a data file loaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rows: Option<u64>,
+}
+
+/// Make a disposable copy of the database to experiment on, as a Neon branch
+/// or a database copied from this one as a template, and return how to
+/// connect to it. The copy is not dropped automatically
+#[derive(Deserialize, JsonSchema)]
+struct CreateBranchArgs {
+    /// Name of the branch or database to create. Defaults to the database's
+    /// name followed by _branch_ and the current Unix time
+    name: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct BranchInfo {
+    /// How the copy was made: neon or template
+    provider: &'static str,
+    /// Name of the Neon branch, or of the copied database
+    name: String,
+    /// Id of the Neon branch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch_id: Option<String>,
+    /// The database to connect to
+    database: String,
+    /// Connection string for the copy, with the password masked: it has the
+    /// roles and passwords of this database
+    database_url: String,
+}
+
+/// Start a transaction that later query calls join by passing its
+/// transaction_id, so that their statements take effect together on
+/// commit_transaction or not at all. The transaction holds a database
+/// connection until it ends, and is rolled back if a statement in it fails or
+/// it goes unused for the server's transaction timeout
+#[derive(Deserialize, JsonSchema)]
+struct BeginTransactionArgs {
+    /// Isolation level of the transaction
+    #[serde(default)]
+    isolation: IsolationLevel,
+    /// Refuse writes in the transaction. Always true unless write operations
+    /// are enabled
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum IsolationLevel {
+    #[default]
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl IsolationLevel {
+    fn sql(self) -> &'static str {
+        match self {
+            Self::ReadCommitted => "READ COMMITTED",
+            Self::RepeatableRead => "REPEATABLE READ",
+            Self::Serializable => "SERIALIZABLE",
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TransactionInfo {
+    /// Pass as transaction_id to query, commit_transaction, and
+    /// rollback_transaction
+    transaction_id: String,
+    isolation: IsolationLevel,
+    read_only: bool,
+    /// Seconds the transaction may go unused before it is rolled back
+    timeout_secs: u64,
+}
+
+/// Commit a transaction started with begin_transaction, making its
+/// statements' changes visible. If the commit fails, as it can for a
+/// serializable transaction, the transaction is rolled back
+#[derive(Deserialize, JsonSchema)]
+struct CommitTransactionArgs {
+    /// The transaction_id returned by begin_transaction
+    transaction_id: String,
+}
+
+/// Roll back a transaction started with begin_transaction, undoing its
+/// statements
+#[derive(Deserialize, JsonSchema)]
+struct RollbackTransactionArgs {
+    /// The transaction_id returned by begin_transaction
+    transaction_id: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TransactionEnd {
+    transaction_id: String,
+    /// committed or rolled_back
+    outcome: &'static str,
+    /// Statements run in the transaction
+    statements: usize,
+    /// Milliseconds from begin_transaction to the transaction's end
+    duration_ms: u64,
+}
+
+impl TransactionEnd {
+    fn new(transaction_id: String, outcome: &'static str, transaction: &OpenTransaction) -> Self {
+        Self {
+            transaction_id,
+            outcome,
+            statements: transaction.statements,
+            duration_ms: transaction.started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+/// Show the plan PostgreSQL would use for a statement, as JSON. With analyze,
+/// the statement is also run to report actual row counts and timings
+#[derive(Deserialize, JsonSchema)]
+struct ExplainArgs {
+    /// Statement to explain: a SELECT, INSERT, UPDATE, DELETE, MERGE, VALUES,
+    /// or CREATE TABLE AS
+    sql: String,
+    /// Values for the statement's $1, $2, ... placeholders, as in the query
+    /// tool
+    #[serde(default)]
+    params: Vec<Value>,
+    /// Run the statement and report what it actually did (EXPLAIN ANALYZE).
+    /// Requires write operations to be enabled, since the statement is
+    /// executed; its changes are rolled back afterwards
+    #[serde(default)]
+    analyze: bool,
+    /// Report shared buffer hits and reads
+    #[serde(default)]
+    buffers: bool,
+    /// Report each node's output columns and other detail
+    #[serde(default)]
+    verbose: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ExplainResult {
+    /// The plan tree, as output by EXPLAIN (FORMAT JSON): nodes with their
+    /// "Node Type", estimated "Total Cost" and "Plan Rows", and child "Plans"
+    plan: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    planning_time_ms: Option<f64>,
+    /// Set with analyze
+    #[serde(skip_serializing_if = "Option::is_none")]
+    execution_time_ms: Option<f64>,
+    /// Trigger timings, with analyze
+    #[serde(skip_serializing_if = "Option::is_none")]
+    triggers: Option<Value>,
+}
+
+/// The `json` output of EXPLAIN (FORMAT JSON), which is only ever text.
+struct ExplainOutput(Value);
+
+impl<'a> FromSql<'a> for ExplainOutput {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(Self(serde_json::from_slice(raw)?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::JSON
+    }
+}
+
+/// The most bind parameters one statement can carry: the protocol allows
+/// 65,535, but `tokio_postgres` sends the count as a signed 16-bit integer.
+const MAX_STATEMENT_PARAMS: usize = 32_767;
+/// Most rows per INSERT when loading a fixture file.
+const FIXTURE_BATCH_SIZE: usize = 500;
+/// Column alias carrying each row's distance in `vector_search`.
+const DISTANCE_COLUMN: &str = "__distance";
+
+const EXPORTS_DISABLED: &str =
+    "Exports are disabled. Set EXPORT_DIR to the directory exports may be written to.";
+
+impl McpServer {
+    pub(crate) async fn handle_tools_list(&mut self, id: Option<Value>) -> JsonRpcResponse {
+        // Which extension tools to list depends on the database, so the
+        // first listing connects to find out.
+        if self.installed_extensions().is_none() {
+            match self.ensure_connected().await {
+                Ok(()) => {
+                    self.refresh_extensions().await;
+                }
+                Err(e) => warn!("Cannot look up installed extensions: {:#}", e),
+            }
+        }
+        let mut tools: Vec<Value> = tool_definitions()
+            .into_iter()
+            .chain(plugins::definitions())
+            .filter(|tool| self.offers_tool(tool["name"].as_str().unwrap_or("")))
+            .collect();
+        if self.environment == Some(Environment::Production) {
+            for tool in &mut tools {
+                if CONFIRM_TOOLS.contains(&tool["name"].as_str().unwrap_or("")) {
+                    require_confirmation(&mut tool["inputSchema"]);
+                }
+            }
+        }
+
+        JsonRpcResponse::success(id, json!({ "tools": tools }))
+    }
+
+    /// Whether `tools/list` lists the tool `name`: it is supported by the
+    /// database, not disabled, and has the write permission, extension, or
+    /// configuration it needs.
+    fn offers_tool(&self, name: &str) -> bool {
+        if self.flavor.unsupported_tools().contains(&name)
+            || self.disabled_tools.iter().any(|d| d == name)
+            || (WRITE_TOOLS.contains(&name) && !self.allow_write_ops)
+            || (name == "create_branch" && self.branching.is_none())
+            || (TRANSACTION_TOOLS.contains(&name) && self.pool_settings.max_size < 2)
+        {
+            return false;
+        }
+        match EXTENSION_TOOLS.iter().find(|(tool, _)| *tool == name) {
+            Some((_, extension)) => self
+                .installed_extensions()
+                .is_some_and(|installed| installed.iter().any(|e| e == extension)),
+            None => true,
+        }
+    }
+
+    /// The extensions installed in the database, once looked up.
+    fn installed_extensions(&self) -> Option<Vec<String>> {
+        self.extensions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Refuses `sql` if it uses a feature added after the server's version.
+    fn check_server_version(&self, sql: &str) -> ToolResult<()> {
+        match self.server_version.and_then(|v| v.unsupported(sql)) {
+            Some(message) => Err(ToolError::InvalidArguments(message)),
+            None => Ok(()),
+        }
+    }
+
+    /// Refuses a query with a row-locking clause where it would run in a
+    /// read-only transaction, which PostgreSQL fails it in: always for
+    /// `export_query` and `materialize_result`, and for `query` without write
+    /// operations.
+    fn check_locking(&self, sql: &str, tool: &str) -> ToolResult<()> {
+        if tool == "query" && self.allow_write_ops {
+            return Ok(());
+        }
+        let Some(clause) = classify::locking_clause(sql) else {
+            return Ok(());
+        };
+        let (message, remedy) = if tool == "query" {
+            (
+                format!(
+                    "SELECT ... {} locks the rows it reads, which is not allowed without write operations",
+                    clause
+                ),
+                format!(
+                    "Remove {} to read the rows without locking them, or set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.",
+                    clause
+                ),
+            )
+        } else {
+            (
+                format!(
+                    "{} runs the query in a read-only transaction, which cannot lock rows with {}",
+                    tool, clause
+                ),
+                format!("Remove {} to read the rows without locking them.", clause),
+            )
+        };
+        Err(PolicyDenial {
+            rule: "read_only",
+            matched: clause.to_string(),
+            message,
+            remedy,
+        }
+        .into())
+    }
+
+    /// Refuses SQL that changes a setting other SQL could be affected by: one
+    /// outside the safe subset, one made outside a transaction from
+    /// `begin_transaction`, or one that would outlast the transaction.
+    fn check_settings(&self, sql: &str, in_transaction: bool) -> ToolResult<()> {
+        for change in settings::changes(sql) {
+            self.check_setting(change, in_transaction)?;
+        }
+        Ok(())
+    }
+
+    fn check_setting(&self, change: SettingChange, in_transaction: bool) -> ToolResult<()> {
+        let (message, remedy) = if !change.is_safe() {
+            (
+                format!(
+                    "Changing the setting '{}' is not allowed, as it could affect how the server checks or routes statements",
+                    change.name
+                ),
+                "Only planner settings, such as enable_seqscan and work_mem, and display settings, such as TimeZone and DateStyle, may be changed.",
+            )
+        } else if !in_transaction {
+            (
+                format!(
+                    "{} outside a transaction would change '{}' for whichever request next uses the pooled connection",
+                    change.statement, change.name
+                ),
+                "Begin a transaction with begin_transaction and change the setting in it with SET LOCAL.",
+            )
+        } else if change.lasting {
+            (
+                format!(
+                    "{} would keep '{}' changed after the transaction ends, for whichever request next uses the pooled connection",
+                    change.statement, change.name
+                ),
+                "Use SET LOCAL, SET LOCAL ... TO DEFAULT in place of RESET, or set_config with is_local true, which last until the transaction ends.",
+            )
+        } else {
+            return Ok(());
+        };
+        Err(PolicyDenial {
+            rule: "session_setting",
+            matched: change.name,
+            message,
+            remedy: remedy.to_string(),
+        }
+        .into())
+    }
+
+    /// Looks up the installed extensions again, telling the client to list
+    /// the tools again if that changes which are offered. A failure to look
+    /// them up is logged and changes nothing.
+    pub(crate) async fn refresh_extensions(&self) {
+        let client = self.client.as_ref().unwrap();
+        let installed: Vec<String> = match pooler::query(
+            client,
+            "SELECT extname::text FROM pg_extension ORDER BY 1",
+            &[],
+        )
+        .await
+        {
+            Ok(rows) => rows.iter().map(|row| row.get(0)).collect(),
+            Err(e) => {
+                warn!("Cannot look up installed extensions: {}", e);
+                return;
+            }
+        };
+        let offered = |extensions: Option<&Vec<String>>| -> Vec<&str> {
+            EXTENSION_TOOLS
+                .iter()
+                .filter(|(_, extension)| {
+                    extensions.is_some_and(|e| e.iter().any(|e| e == extension))
+                })
+                .map(|(tool, _)| *tool)
+                .collect()
+        };
+        let previous = self
+            .extensions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(installed.clone());
+        // Before the first lookup, the client has been offered none of them.
+        if offered(previous.as_ref()) != offered(Some(&installed)) {
+            debug!("Installed extensions changed the tools offered");
+            self.notify(&JsonRpcNotification::tools_list_changed());
+        }
+    }
+
+    pub(crate) async fn handle_tools_call(
+        &mut self,
+        id: Option<Value>,
+        params: Option<Value>,
+    ) -> JsonRpcResponse {
+        if let Err(e) = self.ensure_connected().await {
+            return JsonRpcResponse::connect_error(id, &e);
+        }
+
+        let params = match params {
+            Some(p) => p,
+            None => return JsonRpcResponse::error(id, -32602, "Missing parameters"),
+        };
+
+        let tool_name = params.get("name").and_then(|v| v.as_str()).unwrap_or("");
+        let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+        let progress_token = self.progress_token.clone();
+
+        if self.flavor.unsupported_tools().contains(&tool_name) {
+            return JsonRpcResponse::error(
+                id,
+                -32602,
+                format!(
+                    "The '{}' tool is not supported on {}",
+                    tool_name,
+                    self.flavor.name()
+                ),
+            );
+        }
+        if self.disabled_tools.iter().any(|d| d == tool_name) {
+            return JsonRpcResponse::error(
+                id,
+                -32602,
+                format!(
+                    "The '{}' tool is disabled by this server's DISABLED_TOOLS setting",
+                    tool_name
+                ),
+            );
+        }
+
+        let production = self.environment == Some(Environment::Production);
+        let mut arguments = arguments;
+        if production && CONFIRM_TOOLS.contains(&tool_name) {
+            let confirmed = arguments
+                .as_object_mut()
+                .and_then(|args| args.remove("confirm"))
+                == Some(Value::Bool(true));
+            if !confirmed {
+                return PolicyDenial {
+                    rule: "environment",
+                    matched: "production".to_string(),
+                    message: format!(
+                        "The '{}' tool reads whole tables or copies data out of the database, which on production needs confirmation",
+                        tool_name
+                    ),
+                    remedy: "Ask the user, then call it again with \"confirm\": true.".to_string(),
+                }
+                .into_response(id);
+            }
+        }
+
+        if self.installed_extensions().is_none() {
+            self.refresh_extensions().await;
+        }
+        match self.call_tool(tool_name, arguments, progress_token).await {
+            Ok(mut result) => {
+                if let Some(environment) = self.environment
+                    && let Some(Value::Array(content)) = result.get_mut("content")
+                {
+                    content.insert(0, json!({ "type": "text", "text": environment.header() }));
+                }
+                JsonRpcResponse::success(id, result)
+            }
+            Err(ToolError::Denied(mut denial))
+                if production && denial.remedy.contains("DANGEROUSLY_ALLOW_WRITE_OPS") =>
+            {
+                denial.remedy = "Writes are always disabled on production (DB_ENVIRONMENT); write to a staging or development database instead.".to_string();
+                denial.into_response(id)
+            }
+            Err(e) => e.into_response(id),
+        }
+    }
+
+    async fn call_tool(
+        &mut self,
+        name: &str,
+        arguments: Value,
+        progress_token: Option<Value>,
+    ) -> ToolResult<Value> {
+        match name {
+            "query" => {
+                let args: QueryArgs = parse_args(name, arguments)?;
+                // CREATE, ALTER, and DROP EXTENSION can change which tools
+                // are offered.
+                let extension_ddl = args
+                    .sql
+                    .as_ref()
+                    .is_some_and(|sql| sql.to_ascii_lowercase().contains("extension"));
+                let format = args.format;
+                let wanted = args.columns.clone();
+                let output = args.output;
+                let (mut rows, mut columns, notes) = self.execute_query(args).await?;
+                self.usage.record_rows(match &rows {
+                    QueryRows::All(rows) => rows.len(),
+                    QueryRows::Page(page) => page.rows.len(),
+                });
+                if extension_ddl && self.client.is_some() {
+                    self.refresh_extensions().await;
+                }
+                let names: Vec<String> = columns.iter().map(|c| c.name.clone()).collect();
+                let selection =
+                    wide_results::select(&names, wanted.as_deref(), self.max_result_columns);
+                if let Some(selection) = &selection {
+                    match &mut rows {
+                        QueryRows::All(rows) => selection.apply(rows),
+                        QueryRows::Page(page) => selection.apply(&mut page.rows),
+                    }
+                    columns = selection
+                        .shown
+                        .iter()
+                        .filter_map(|name| {
+                            let index = columns.iter().position(|c| &c.name == name)?;
+                            Some(columns.swap_remove(index))
+                        })
+                        .collect();
+                }
+                if !output.is_default() {
+                    let rows = match &mut rows {
+                        QueryRows::All(rows) => rows,
+                        QueryRows::Page(page) => &mut page.rows,
+                    };
+                    for row in rows {
+                        for column in &columns {
+                            if let Some(value) = row.get_mut(&column.name) {
+                                output.apply(&column.r#type, value);
+                            }
+                        }
+                    }
+                }
+                let mut result = match (format, rows) {
+                    (ResultFormat::Json, QueryRows::All(rows)) => tool_result(rows),
+                    (ResultFormat::Json, QueryRows::Page(page)) => tool_result(page),
+                    (format, rows) => table_result(format, &columns, rows),
+                };
+                result["_meta"] = json!({
+                    "columns": columns,
+                    "bigNumbersAsStrings": values::big_numbers_as_strings(),
+                });
+                if let Some(estimate) = notes.estimate {
+                    result["_meta"]["estimate"] = estimate;
+                }
+                if !notes.stale_statistics.is_empty() {
+                    if let Some(Value::Array(content)) = result.get_mut("content") {
+                        content.push(json!({
+                            "type": "text",
+                            "text": stale_stats::warning(&notes.stale_statistics),
+                        }));
+                    }
+                    result["_meta"]["staleStatistics"] = json!(notes.stale_statistics);
+                }
+                if let Some(truncated) = notes.truncated {
+                    if let Some(Value::Array(content)) = result.get_mut("content") {
+                        content.push(json!({ "type": "text", "text": truncated.note() }));
+                    }
+                    result["_meta"]["truncated"] = json!(truncated);
+                }
+                if let Some(selection) = selection {
+                    if let Some(note) = selection.note()
+                        && let Some(Value::Array(content)) = result.get_mut("content")
+                    {
+                        content.push(json!({ "type": "text", "text": note }));
+                    }
+                    if !selection.omitted.is_empty() {
+                        result["_meta"]["omittedColumns"] = json!(selection.omitted);
+                    }
+                }
+                Ok(result)
+            }
+            "autovacuum_status" => self
+                .autovacuum_status(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "wraparound_check" => self
+                .wraparound_check(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "list_activity" => self
+                .list_activity(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "list_locks" => self
+                .list_locks(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "top_queries" => self
+                .top_queries(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "table_sizes" => self
+                .table_sizes(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "list_schemas" => self
+                .list_schemas(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "usage_stats" => {
+                let args: UsageStatsArgs = parse_args(name, arguments)?;
+                let stats = self
+                    .usage
+                    .stats(args.limit.max(0) as usize, |schema, table| {
+                        self.access.allows(schema, table)
+                    });
+                Ok(tool_result(stats))
+            }
+            "verify_integrity" => self
+                .verify_integrity(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "export_schema_data" => self
+                .export_schema_data(parse_args(name, arguments)?, progress_token)
+                .await
+                .map(tool_result),
+            "export_query" => self
+                .export_query(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "materialize_result" => self
+                .materialize_result(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "search_values" => self
+                .search_values(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "find_duplicates" => self
+                .find_duplicates(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "find_orphans" => self
+                .find_orphans(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "suggest_joins" => self
+                .suggest_joins(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "vector_search" => self
+                .vector_search(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "list_large_objects" => self
+                .list_large_objects(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "read_large_object" => self
+                .read_large_object(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "import_large_object" => self
+                .import_large_object(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "export_large_object" => self
+                .export_large_object(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "session_time_info" => {
+                parse_args::<SessionTimeInfoArgs>(name, arguments)?;
+                self.session_time_info().await.map(tool_result)
+            }
+            "set_session_timezone" => self
+                .set_session_timezone(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "describe_table" => self
+                .describe_table(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "upsert_rows" => self
+                .upsert_rows(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "insert_rows" => self
+                .insert_rows(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "update_rows" => self
+                .update_rows(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "delete_rows" => self
+                .delete_rows(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "load_fixtures" => {
+                let args: LoadFixturesArgs = parse_args(name, arguments)?;
+                let Some(root) = &self.fixtures_dir else {
+                    return Err(ToolError::Failed(
+                        "Fixtures are disabled. Set FIXTURES_DIR to the directory fixture files are read from."
+                            .to_string(),
+                    ));
+                };
+                let dir = match args.path.as_deref().filter(|p| !p.is_empty()) {
+                    Some(path) => export::resolve_output_dir(root, path)
+                        .map_err(|e| ToolError::InvalidArguments(e.to_string()))?,
+                    None => root.clone(),
+                };
+                self.load_fixtures(&dir, &args.schema, args.keep_existing)
+                    .await
+                    .map(tool_result)
+            }
+            "create_branch" => self
+                .create_branch(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "begin_transaction" => self
+                .begin_transaction(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "commit_transaction" => self
+                .commit_transaction(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "rollback_transaction" => self
+                .rollback_transaction(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            "explain" => self
+                .explain(parse_args(name, arguments)?)
+                .await
+                .map(tool_result),
+            _ if plugins::provides(name) => plugins::call(name, arguments, self.allow_write_ops)
+                .await
+                .map(tool_result),
+            _ => Err(ToolError::InvalidArguments(format!(
+                "Unknown tool: {}",
+                name
+            ))),
+        }
+    }
+
+    /// Runs the `query` tool, returning the rows, their columns, and with
+    /// `QUERY_COST_ESTIMATES`, the planner's estimate for a query.
+    async fn execute_query(
+        &mut self,
+        args: QueryArgs,
+    ) -> ToolResult<(QueryRows, Vec<ColumnType>, QueryNotes)> {
+        let max_rows = match args.max_rows {
+            Some(max_rows) if max_rows < 1 => {
+                return Err(ToolError::InvalidArguments(
+                    "max_rows must be at least 1".to_string(),
+                ));
+            }
+            max_rows => max_rows.map(|n| n as usize),
+        };
+        if args.transaction_id.is_some() && (max_rows.is_some() || args.cursor.is_some()) {
+            return Err(ToolError::InvalidArguments(
+                "max_rows and cursor cannot be given with transaction_id: statements in a transaction return all their rows"
+                    .to_string(),
+            ));
+        }
+        // Production caps pages, and pages every query it can, which needs a
+        // connection to spare for the cursor.
+        let max_rows = match (self.environment, max_rows) {
+            (Some(Environment::Production), Some(n)) => Some(n.min(PRODUCTION_MAX_ROWS)),
+            (Some(Environment::Production), None)
+                if args.cursor.is_none()
+                    && args.transaction_id.is_none()
+                    && self.pool_settings.max_size > 1 =>
+            {
+                Some(PRODUCTION_MAX_ROWS)
+            }
+            (_, max_rows) => max_rows,
+        };
+        // MAX_RESULT_ROWS caps pages as well. A full page is then a truncated
+        // result if the caller asked for more rows than it allows.
+        let row_limit = self.result_limits.max_rows;
+        let capped = row_limit.is_some_and(|limit| match max_rows {
+            Some(n) => n > limit,
+            None => args.cursor.is_none(),
+        });
+        let max_rows = match (max_rows, row_limit) {
+            (Some(n), Some(limit)) => Some(n.min(limit)),
+            (max_rows, _) => max_rows,
+        };
+        let timeout = match (args.timeout_ms, self.query_timeout) {
+            (Some(0), _) => {
+                return Err(ToolError::InvalidArguments(
+                    "timeout_ms must be at least 1".to_string(),
+                ));
+            }
+            (Some(ms), limit) => Some(limit.map_or(Duration::from_millis(ms), |limit| {
+                limit.min(Duration::from_millis(ms))
+            })),
+            (None, limit) => limit,
+        };
+        let sql = match (&args.sql, &args.cursor) {
+            (Some(sql), None) => sql.as_str(),
+            (None, Some(cursor)) if args.params.is_empty() => {
+                return self.fetch_page(cursor, max_rows, capped, timeout).await;
+            }
+            (None, Some(_)) => {
+                return Err(ToolError::InvalidArguments(
+                    "params cannot be given with cursor: the query's values were bound when it was first run"
+                        .to_string(),
+                ));
+            }
+            (Some(_), Some(_)) => {
+                return Err(ToolError::InvalidArguments(
+                    "Pass either sql or cursor, not both".to_string(),
+                ));
+            }
+            (None, None) => {
+                return Err(ToolError::InvalidArguments(
+                    "Either sql or cursor is required".to_string(),
+                ));
+            }
+        };
+
+        let write = classify::write_keyword(sql);
+        if !self.allow_write_ops
+            && let Some(keyword) = write
+        {
+            return Err(PolicyDenial {
+                rule: "read_only",
+                matched: keyword.to_string(),
+                message: "Write operations are not allowed".to_string(),
+                remedy: "Set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.".to_string(),
+            }
+            .into());
+        }
+
+        if let Some(identity) = &self.identity
+            && let Some(construct) = identity::role_change(sql)
+        {
+            return Err(PolicyDenial {
+                rule: "session_role",
+                matched: construct.to_string(),
+                message: format!(
+                    "Statements that can change the session role are not allowed while running as role '{}'",
+                    identity.role
+                ),
+                remedy: "Unset MCP_PRINCIPAL and ROLE_MAP to run as the connecting user.".to_string(),
+            }
+            .into());
+        }
+        self.check_locking(sql, "query")?;
+        self.check_settings(sql, args.transaction_id.is_some())?;
+        self.check_server_version(sql)?;
+
+        if let Some(id) = &args.transaction_id {
+            let (mut rows, columns) = self
+                .query_in_transaction(id, sql, &args.params, timeout)
+                .await?;
+            let notes = QueryNotes {
+                truncated: self.result_limits.truncate(&mut rows),
+                ..QueryNotes::default()
+            };
+            if let Some(keyword) = write {
+                self.report_write(
+                    "query",
+                    &format!("query ran {} in transaction {}", keyword, id),
+                    json!({
+                        "statement": keyword,
+                        "fingerprint": fingerprint_sql(sql),
+                        "transaction": id,
+                    }),
+                );
+            }
+            return Ok((QueryRows::All(rows), columns, notes));
+        }
+
+        self.check_sql_access(sql).await?;
+        let client = self.client.as_ref().unwrap();
+
+        let started = Instant::now();
+        let statement = match Prepared::new(client, sql).await {
+            Ok(statement) => statement,
+            Err(e) => {
+                metrics::record_query_error(started.elapsed(), e.code().map(SqlState::code));
+                return Err(statement_error(
+                    format!("Query execution error: {}", db_error_message(&e)),
+                    &e,
+                ));
+            }
+        };
+        if let Some(types) = statement.params()
+            && types.len() != args.params.len()
+        {
+            return Err(ToolError::InvalidArguments(format!(
+                "The query has {} parameter(s) but {} value(s) were given in params",
+                types.len(),
+                args.params.len()
+            )));
+        }
+        let params = statement.text_params(&args.params);
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+        let estimate = if self.cost_estimates && statement.returns_rows() && write.is_none() {
+            self.estimate_cost(sql, &statement, &params, timeout).await
+        } else {
+            None
+        };
+        let mut notes = QueryNotes {
+            estimate,
+            stale_statistics: self.stale_statistics(sql).await,
+            truncated: None,
+        };
+
+        // A query for all its rows is paged at MAX_RESULT_ROWS, so that the
+        // rows past it are not read unless asked for, if a connection can be
+        // spared for the cursor.
+        let whole = max_rows.is_none();
+        let page_size = match (max_rows, row_limit) {
+            (None, Some(limit))
+                if write.is_none() && self.pool_settings.max_size > self.transactions.len() + 1 =>
+            {
+                Some(limit)
+            }
+            (max_rows, _) => max_rows,
+        };
+        if let Some(page_size) = page_size
+            && statement.returns_rows()
+            && let Some((page, columns, truncated)) = self
+                .open_cursor(sql, &statement, &params, page_size, capped, timeout)
+                .await?
+        {
+            notes.truncated = truncated;
+            let rows = if whole && page.next_cursor.is_none() {
+                QueryRows::All(page.rows)
+            } else {
+                QueryRows::Page(page)
+            };
+            return Ok((rows, columns, notes));
+        }
+
+        let client = self.client.as_ref().unwrap();
+        // Without write access the statement runs in a read-only transaction,
+        // so PostgreSQL rejects writes the keyword check cannot see, such as
+        // those made by functions.
+        let read_only = !self.allow_write_ops;
+        if read_only {
+            pooler::begin(client, "BEGIN TRANSACTION READ ONLY")
+                .await
+                .map_err(|e| failed("Failed to start a read-only transaction", e))?;
+        }
+        let result = self
+            .with_timeout(
+                client.cancel_token(),
+                timeout,
+                statement.query(client, &params),
+            )
+            .await;
+        let elapsed = started.elapsed();
+        self.report_slow_query(sql, elapsed);
+        match &result {
+            Some(Ok(rows)) => metrics::record_query(elapsed, rows.len()),
+            Some(Err(e)) => metrics::record_query_error(elapsed, e.code().map(SqlState::code)),
+            None => metrics::record_query_error(elapsed, Some(metrics::QUERY_CANCELED)),
+        }
+        if read_only {
+            let end = if matches!(result, Some(Ok(_))) {
+                "COMMIT"
+            } else {
+                "ROLLBACK"
+            };
+            if let Err(e) = client.batch_execute(end).await {
+                error!("Could not end read-only transaction: {}", e);
+            }
+        }
+
+        match result.ok_or_else(|| timed_out(timeout))? {
+            Ok(rows) => {
+                if let Some(keyword) = write {
+                    self.report_write(
+                        "query",
+                        &format!("query ran {}", keyword),
+                        json!({ "statement": keyword, "fingerprint": fingerprint_sql(sql) }),
+                    );
+                }
+                if write.is_some_and(classify::changes_schema) {
+                    self.sandboxes.record(sql);
+                    if let Some(catalog) = Catalog::shared() {
+                        catalog.schema_changed();
+                    }
+                }
+                let columns = result_columns(
+                    statement
+                        .columns()
+                        .or_else(|| rows.first().map(Row::columns))
+                        .unwrap_or_default(),
+                );
+                let mut rows = rows.iter().map(row_to_map).collect();
+                notes.truncated = self.result_limits.truncate(&mut rows);
+                let rows = match max_rows {
+                    Some(_) => QueryRows::Page(QueryPage {
+                        rows,
+                        next_cursor: None,
+                    }),
+                    None => QueryRows::All(rows),
+                };
+                Ok((rows, columns, notes))
+            }
+            Err(e) => Err(query_error(e)),
+        }
+    }
+
+    /// Runs `sql` on the connection of the transaction `id`, rolling the
+    /// transaction back if it fails.
+    async fn query_in_transaction(
+        &self,
+        id: &str,
+        sql: &str,
+        params: &[Value],
+        timeout: Option<Duration>,
+    ) -> ToolResult<(Vec<RowMap>, Vec<ColumnType>)> {
+        if let Some(keyword) = transaction::control_keyword(sql) {
+            return Err(ToolError::InvalidArguments(format!(
+                "{} cannot run in a transaction from begin_transaction; end it with commit_transaction or rollback_transaction",
+                keyword
+            )));
+        }
+        let client = self
+            .transactions
+            .start_statement(id)
+            .map_err(|e| self.transaction_unavailable(id, e))?;
+        // Names are resolved on the transaction's connection, which sees the
+        // tables and search_path the transaction set up.
+        match self.check_sql_access_on(&client, sql).await {
+            Ok(()) => {}
+            Err(e @ ToolError::Denied(_)) => {
+                self.transactions.put_back(id, client);
+                return Err(e);
+            }
+            Err(e) => {
+                self.transactions.fail_statement(id, client);
+                return Err(rolled_back(e));
+            }
+        }
+
+        let started = Instant::now();
+        let statement = match client.prepare(sql).await {
+            Ok(statement) => statement,
+            Err(e) => {
+                metrics::record_query_error(started.elapsed(), e.code().map(SqlState::code));
+                self.transactions.fail_statement(id, client);
+                return Err(rolled_back(statement_error(
+                    format!("Query execution error: {}", db_error_message(&e)),
+                    &e,
+                )));
+            }
+        };
+        if statement.params().len() != params.len() {
+            self.transactions.put_back(id, client);
+            return Err(ToolError::InvalidArguments(format!(
+                "The query has {} parameter(s) but {} value(s) were given in params",
+                statement.params().len(),
+                params.len()
+            )));
+        }
+        let params: Vec<TextParam> = params
+            .iter()
+            .zip(statement.params())
+            .map(|(value, ty)| TextParam::from_json(value, ty))
+            .collect();
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+        let result = self
+            .with_timeout(
+                client.cancel_token(),
+                timeout,
+                client.query(&statement, &params),
+            )
+            .await;
+        let elapsed = started.elapsed();
+        self.report_slow_query(sql, elapsed);
+        match result {
+            Some(Ok(rows)) => {
+                metrics::record_query(elapsed, rows.len());
+                let changed_schema =
+                    classify::write_keyword(sql).is_some_and(classify::changes_schema);
+                if changed_schema {
+                    self.sandboxes.record(sql);
+                }
+                self.transactions
+                    .finish_statement(id, client, changed_schema);
+                Ok((
+                    rows.iter().map(row_to_map).collect(),
+                    result_columns(statement.columns()),
+                ))
+            }
+            Some(Err(e)) => {
+                metrics::record_query_error(elapsed, e.code().map(SqlState::code));
+                self.transactions.fail_statement(id, client);
+                Err(rolled_back(query_error(e)))
+            }
+            None => {
+                metrics::record_query_error(elapsed, Some(metrics::QUERY_CANCELED));
+                self.transactions.fail_statement(id, client);
+                Err(rolled_back(timed_out(timeout)))
+            }
+        }
+    }
+
+    /// The planner's estimate for `statement`, from a plain EXPLAIN that does
+    /// not run it: `rows`, `startupCost`, `totalCost`, and `width`. `None` if
+    /// the query cannot be explained.
+    async fn estimate_cost(
+        &self,
+        sql: &str,
+        statement: &Prepared,
+        params: &[&(dyn ToSql + Sync)],
+        timeout: Option<Duration>,
+    ) -> Option<Value> {
+        let client = self.client.as_ref().unwrap();
+        let explain = format!("EXPLAIN (FORMAT JSON) {}", sql);
+        let explained = self
+            .with_timeout(client.cancel_token(), timeout, async {
+                Prepared::with_types(client, &explain, statement.param_types())
+                    .await?
+                    .query_one(client, params)
+                    .await
+            })
+            .await;
+        let row = match explained {
+            Some(Ok(row)) => row,
+            Some(Err(e)) => {
+                debug!("Could not estimate the query's cost: {}", e);
+                return None;
+            }
+            None => return None,
+        };
+        let ExplainOutput(output) = row.get(0);
+        let plan = output.get(0)?.get("Plan")?;
+        Some(json!({
+            "rows": plan.get("Plan Rows"),
+            "startupCost": plan.get("Startup Cost"),
+            "totalCost": plan.get("Total Cost"),
+            "width": plan.get("Plan Width"),
+        }))
+    }
+
+    /// Declares `statement` as a cursor on the request's connection and
+    /// fetches the first page with its columns, or returns `None` if it is
+    /// not a query that can be a cursor. An open cursor keeps the connection.
+    async fn open_cursor(
+        &mut self,
+        sql: &str,
+        statement: &Prepared,
+        params: &[&(dyn ToSql + Sync)],
+        max_rows: usize,
+        capped: bool,
+        timeout: Option<Duration>,
+    ) -> ToolResult<Option<(QueryPage, Vec<ColumnType>, Option<Truncation>)>> {
+        // Leave a connection for requests other than paging, besides those
+        // open transactions hold.
+        let limit = self.pool_settings.max_size - 1;
+        if limit == 0 {
+            return Err(ToolError::Failed(
+                "max_rows needs DB_POOL_MAX_SIZE of at least 2, since a paged query holds a connection until its last page is read"
+                    .to_string(),
+            ));
+        }
+        let limit = limit.saturating_sub(self.transactions.len());
+        if limit == 0 {
+            return Err(ToolError::Failed(
+                "max_rows needs a connection to spare, and open transactions hold all DB_POOL_MAX_SIZE allows; commit or roll one back first"
+                    .to_string(),
+            ));
+        }
+
+        let client = self.client.as_ref().unwrap();
+        let begin = if self.allow_write_ops {
+            "BEGIN"
+        } else {
+            "BEGIN TRANSACTION READ ONLY"
+        };
+        pooler::begin(client, begin)
+            .await
+            .map_err(|e| failed("Failed to start the query's transaction", e))?;
+        let declare = format!(
+            "DECLARE {} NO SCROLL CURSOR FOR {}",
+            CURSOR_NAME,
+            sql.trim().trim_end_matches(';')
+        );
+        let declared = match Prepared::with_types(client, &declare, statement.param_types()).await {
+            Ok(declare) => declare.execute(client, params).await,
+            Err(e) => Err(e),
+        };
+        if declared.is_err() {
+            // Statements such as INSERT ... RETURNING cannot be cursors.
+            client
+                .batch_execute("ROLLBACK")
+                .await
+                .map_err(|e| failed("Failed to end the query's transaction", e))?;
+            return Ok(None);
+        }
+
+        let client = self.client.take().unwrap();
+        let cancel_token = client.cancel_token();
+        let mut cursor = QueryCursor::new(client, max_rows);
+        let started = Instant::now();
+        let fetched = self
+            .with_timeout(cancel_token, timeout, cursor.fetch(None))
+            .await;
+        let elapsed = started.elapsed();
+        self.report_slow_query(sql, elapsed);
+        match &fetched {
+            Some(Ok(fetched)) => metrics::record_query(elapsed, fetched.rows.len()),
+            Some(Err(e)) => metrics::record_query_error(elapsed, e.code().map(SqlState::code)),
+            None => metrics::record_query_error(elapsed, Some(metrics::QUERY_CANCELED)),
+        }
+        let fetched = fetched
+            .ok_or_else(|| timed_out(timeout))?
+            .map_err(query_error)?;
+
+        let columns = result_columns(
+            statement
+                .columns()
+                .or_else(|| fetched.rows.first().map(Row::columns))
+                .unwrap_or_default(),
+        );
+        let (rows, more, cut_at) = self.fit_page(&mut cursor, fetched, capped);
+        let next_cursor = if more {
+            let token = self
+                .cursors
+                .insert(cursor, limit)
+                .map_err(|e| ToolError::Failed(e.to_string()))?;
+            Some(token)
+        } else {
+            None
+        };
+        let truncated = cut_at.map(|limit| Truncation {
+            limit,
+            rows_returned: rows.len(),
+            rows_available: None,
+            next_cursor: next_cursor.clone(),
+        });
+        Ok(Some((QueryPage { rows, next_cursor }, columns, truncated)))
+    }
+
+    /// Fetches the next page of a query run with `max_rows`.
+    async fn fetch_page(
+        &mut self,
+        token: &str,
+        max_rows: Option<usize>,
+        capped: bool,
+        timeout: Option<Duration>,
+    ) -> ToolResult<(QueryRows, Vec<ColumnType>, QueryNotes)> {
+        let Some(mut cursor) = self.cursors.take(token) else {
+            return Err(ToolError::InvalidArguments(format!(
+                "Unknown cursor '{}': it was read to the end, failed, or was closed after going unused",
+                token
+            )));
+        };
+        // A failed or cancelled fetch aborts the cursor's transaction, so the
+        // cursor is dropped and closed.
+        // Once its last rows are read, a cursor fetches only those it holds.
+        let fetched = match cursor.cancel_token() {
+            Some(cancel_token) => self
+                .with_timeout(cancel_token, timeout, cursor.fetch(max_rows))
+                .await
+                .ok_or_else(|| timed_out(timeout))?,
+            None => cursor.fetch(max_rows).await,
+        }
+        .map_err(query_error)?;
+        metrics::record_rows(fetched.rows.len());
+
+        // A page fetched from an open cursor holds at least the row read
+        // ahead of it.
+        let columns = result_columns(fetched.rows.first().map(Row::columns).unwrap_or_default());
+        let (rows, more, cut_at) = self.fit_page(&mut cursor, fetched, capped);
+        let next_cursor = if more {
+            self.cursors.put_back(token.to_string(), cursor);
+            Some(token.to_string())
+        } else {
+            None
+        };
+        let notes = QueryNotes {
+            truncated: cut_at.map(|limit| Truncation {
+                limit,
+                rows_returned: rows.len(),
+                rows_available: None,
+                next_cursor: next_cursor.clone(),
+            }),
+            ..QueryNotes::default()
+        };
+        Ok((
+            QueryRows::Page(QueryPage { rows, next_cursor }),
+            columns,
+            notes,
+        ))
+    }
+
+    /// Converts the rows of a page `fetched` from `cursor`, cutting it down
+    /// to `MAX_RESULT_BYTES` and giving the rest back to the cursor. Returns
+    /// the rows, whether more follow, and the limit that cut the page short,
+    /// if one did: `MAX_RESULT_BYTES`, or with `capped`, `MAX_RESULT_ROWS`.
+    fn fit_page(
+        &self,
+        cursor: &mut QueryCursor,
+        mut fetched: Fetched,
+        capped: bool,
+    ) -> (Vec<RowMap>, bool, Option<&'static str>) {
+        let mut rows: Vec<RowMap> = fetched.rows.iter().map(row_to_map).collect();
+        if let Some(fit) = self.result_limits.fit_bytes(&rows) {
+            rows.truncate(fit);
+            cursor.give_back(fetched.rows.split_off(fit));
+            return (rows, true, Some("MAX_RESULT_BYTES"));
+        }
+        let cut_at = (capped && fetched.more).then_some("MAX_RESULT_ROWS");
+        (rows, fetched.more, cut_at)
+    }
+
+    async fn autovacuum_status(&self, args: AutovacuumStatusArgs) -> ToolResult<AutovacuumStatus> {
+        let client = self.client.as_ref().unwrap();
+
+        let workers_query = if self.flavor.has_vacuum_progress() {
+            "SELECT a.pid, a.datname::text AS database, a.query, \
+                    a.xact_start::text AS started_at, \
+                    EXTRACT(EPOCH FROM now() - a.xact_start)::float8 AS running_seconds, \
+                    p.phase, p.heap_blks_total, p.heap_blks_scanned \
+             FROM pg_stat_activity a \
+             LEFT JOIN pg_stat_progress_vacuum p ON p.pid = a.pid \
+             WHERE a.backend_type = 'autovacuum worker' \
+             ORDER BY a.xact_start"
+        } else {
+            "SELECT a.pid, a.datname::text AS database, a.query, \
+                    a.xact_start::text AS started_at, \
+                    EXTRACT(EPOCH FROM now() - a.xact_start)::float8 AS running_seconds \
+             FROM pg_stat_activity a \
+             WHERE a.query LIKE 'autovacuum:%' \
+             ORDER BY a.xact_start"
+        };
+        let settings_query = "SELECT name, setting, unit FROM pg_settings \
+             WHERE name LIKE 'autovacuum%' ORDER BY name";
+        let overrides_query = "SELECT n.nspname::text AS schema, c.relname::text AS table, \
+                    string_agg(opt, ', ' ORDER BY opt) AS settings \
+             FROM pg_class c \
+             JOIN pg_namespace n ON n.oid = c.relnamespace \
+             CROSS JOIN LATERAL unnest(c.reloptions) AS opt \
+             WHERE c.relkind IN ('r', 'm', 'p') \
+               AND (opt LIKE 'autovacuum%' OR opt LIKE 'toast.autovacuum%') \
+             GROUP BY n.nspname, c.relname \
+             ORDER BY 1, 2";
+        let dead_tuples_query = "SELECT schemaname::text AS schema, relname::text AS table, \
+                    n_live_tup, n_dead_tup, \
+                    n_dead_tup::float8 / NULLIF(n_live_tup + n_dead_tup, 0) AS dead_ratio, \
+                    last_vacuum::text AS last_vacuum, last_autovacuum::text AS last_autovacuum, \
+                    vacuum_count, autovacuum_count \
+             FROM pg_stat_user_tables \
+             WHERE n_dead_tup::float8 / NULLIF(n_live_tup + n_dead_tup, 0) >= $1 \
+               AND COALESCE(GREATEST(last_vacuum, last_autovacuum), '-infinity') \
+                   < now() - make_interval(hours => $2) \
+             ORDER BY dead_ratio DESC, n_dead_tup DESC \
+             LIMIT $3";
+
+        let running_workers = query_rows(client, workers_query, &[])
+            .await
+            .map_err(|e| failed("Failed to read autovacuum workers", e))?;
+        let settings = query_rows(client, settings_query, &[])
+            .await
+            .map_err(|e| failed("Failed to read autovacuum settings", e))?;
+        let table_overrides = query_rows(client, overrides_query, &[])
+            .await
+            .map_err(|e| failed("Failed to read table autovacuum overrides", e))?;
+        let tables_needing_vacuum = query_rows(
+            client,
+            dead_tuples_query,
+            &[
+                (&args.min_dead_ratio, Type::FLOAT8),
+                (&args.stale_hours, Type::INT4),
+                (&args.limit, Type::INT8),
+            ],
+        )
+        .await
+        .map_err(|e| failed("Failed to read table statistics", e))?;
+
+        Ok(AutovacuumStatus {
+            running_workers,
+            settings,
+            table_overrides,
+            tables_needing_vacuum,
+        })
+    }
+
+    async fn wraparound_check(&self, args: WraparoundCheckArgs) -> ToolResult<WraparoundReport> {
+        let client = self.client.as_ref().unwrap();
+
+        // 2147483647 is the distance to the XID wraparound horizon; PostgreSQL
+        // refuses new transactions a few million XIDs before reaching it.
+        let databases_query = "SELECT datname::text AS database, \
+                    age(datfrozenxid) AS xid_age, mxid_age(datminmxid) AS mxid_age, \
+                    round(100 * age(datfrozenxid) / 2147483647.0, 2)::float8 AS percent_towards_wraparound, \
+                    CASE WHEN 100 * age(datfrozenxid) / 2147483647.0 >= $2::float8 THEN 'critical' \
+                         WHEN 100 * age(datfrozenxid) / 2147483647.0 >= $1::float8 THEN 'warning' \
+                         ELSE 'ok' END AS status \
+             FROM pg_database \
+             ORDER BY age(datfrozenxid) DESC";
+        let tables_query = "SELECT n.nspname::text AS schema, c.relname::text AS table, \
+                    c.relkind::text AS kind, \
+                    age(c.relfrozenxid) AS xid_age, mxid_age(c.relminmxid) AS mxid_age, \
+                    round(100 * age(c.relfrozenxid) / 2147483647.0, 2)::float8 AS percent_towards_wraparound, \
+                    age(c.relfrozenxid) > current_setting('autovacuum_freeze_max_age')::int8 \
+                        AS exceeds_freeze_max_age, \
+                    pg_size_pretty(pg_total_relation_size(c.oid)) AS size, \
+                    CASE WHEN 100 * age(c.relfrozenxid) / 2147483647.0 >= $2::float8 THEN 'critical' \
+                         WHEN 100 * age(c.relfrozenxid) / 2147483647.0 >= $1::float8 THEN 'warning' \
+                         ELSE 'ok' END AS status \
+             FROM pg_class c \
+             JOIN pg_namespace n ON n.oid = c.relnamespace \
+             WHERE c.relkind IN ('r', 'm', 't') \
+             ORDER BY age(c.relfrozenxid) DESC \
+             LIMIT $3";
+
+        let databases = query_rows(
+            client,
+            databases_query,
+            &[
+                (&args.warning_percent, Type::FLOAT8),
+                (&args.critical_percent, Type::FLOAT8),
+            ],
+        )
+        .await
+        .map_err(|e| failed("Failed to read database XID ages", e))?;
+        let oldest_tables = query_rows(
+            client,
+            tables_query,
+            &[
+                (&args.warning_percent, Type::FLOAT8),
+                (&args.critical_percent, Type::FLOAT8),
+                (&args.limit, Type::INT8),
+            ],
+        )
+        .await
+        .map_err(|e| failed("Failed to read table XID ages", e))?;
+
+        let statuses = databases
+            .iter()
+            .chain(oldest_tables.iter())
+            .map(|r| r.get("status").and_then(|s| s.as_str()));
+        let status = if statuses.clone().any(|s| s == Some("critical")) {
+            "critical"
+        } else if statuses.clone().any(|s| s == Some("warning")) {
+            "warning"
+        } else {
+            "ok"
+        };
+
+        Ok(WraparoundReport {
+            status,
+            warning_percent: args.warning_percent,
+            critical_percent: args.critical_percent,
+            databases,
+            oldest_tables,
+        })
+    }
+
+    async fn list_activity(&self, args: ListActivityArgs) -> ToolResult<ActivityReport> {
+        let client = self.client.as_ref().unwrap();
+        let mut sessions = query_rows(
+            client,
+            "SELECT pid, usename::text AS user, application_name, \
+                    client_addr::text AS client_addr, backend_type, state, \
+                    wait_event_type, wait_event, pg_blocking_pids(pid) AS blocked_by, \
+                    xact_start::text AS transaction_start, query_start::text AS query_start, \
+                    EXTRACT(EPOCH FROM now() - query_start)::float8 AS query_seconds, \
+                    EXTRACT(EPOCH FROM now() - xact_start)::float8 AS transaction_seconds, \
+                    query \
+             FROM pg_stat_activity \
+             WHERE datname = current_database() AND pid <> pg_backend_pid() \
+               AND ($1 OR state IS DISTINCT FROM 'idle') \
+               AND ($2::float8 IS NULL OR now() - query_start >= make_interval(secs => $2)) \
+             ORDER BY query_start NULLS LAST \
+             LIMIT $3",
+            &[
+                (&args.include_idle, Type::BOOL),
+                (&args.min_duration_secs, Type::FLOAT8),
+                (&args.limit, Type::INT8),
+            ],
+        )
+        .await
+        .map_err(|e| failed("Failed to read pg_stat_activity", e))?;
+        self.hide_query_texts(&mut sessions, &["query"]).await;
+        Ok(ActivityReport { sessions })
+    }
+
+    async fn list_locks(&self, args: ListLocksArgs) -> ToolResult<LockReport> {
+        let client = self.client.as_ref().unwrap();
+        let mut blocking = query_rows(
+            client,
+            "SELECT waiting.pid AS waiting_pid, waiting.usename::text AS waiting_user, \
+                    EXTRACT(EPOCH FROM now() - waiting.query_start)::float8 AS waiting_seconds, \
+                    waiting.query AS waiting_query, \
+                    blocker.pid AS blocking_pid, blocker.usename::text AS blocking_user, \
+                    blocker.state AS blocking_state, \
+                    EXTRACT(EPOCH FROM now() - blocker.xact_start)::float8 AS blocking_transaction_seconds, \
+                    blocker.query AS blocking_query \
+             FROM pg_stat_activity waiting \
+             CROSS JOIN LATERAL unnest(pg_blocking_pids(waiting.pid)) AS b(pid) \
+             JOIN pg_stat_activity blocker ON blocker.pid = b.pid \
+             WHERE waiting.datname = current_database() \
+             ORDER BY waiting.query_start, blocker.pid",
+            &[],
+        )
+        .await
+        .map_err(|e| failed("Failed to read blocking sessions", e))?;
+        let mut locks = query_rows(
+            client,
+            "SELECT l.pid, a.usename::text AS user, l.locktype, l.mode, l.granted, \
+                    n.nspname::text AS schema, c.relname::text AS relation, \
+                    l.transactionid::text AS transaction_id, a.state, \
+                    EXTRACT(EPOCH FROM now() - a.xact_start)::float8 AS transaction_seconds, \
+                    a.query \
+             FROM pg_locks l \
+             LEFT JOIN pg_stat_activity a ON a.pid = l.pid \
+             LEFT JOIN pg_class c ON c.oid = l.relation \
+             LEFT JOIN pg_namespace n ON n.oid = c.relnamespace \
+             WHERE l.pid IS DISTINCT FROM pg_backend_pid() \
+               AND (l.database IS NULL \
+                    OR l.database = (SELECT oid FROM pg_database WHERE datname = current_database())) \
+               AND ($1 OR NOT l.granted) \
+             ORDER BY l.granted, a.xact_start NULLS LAST, l.pid \
+             LIMIT $2",
+            &[(&args.include_granted, Type::BOOL), (&args.limit, Type::INT8)],
+        )
+        .await
+        .map_err(|e| failed("Failed to read pg_locks", e))?;
+        locks.retain(|lock| match (lock.get("schema"), lock.get("relation")) {
+            (Some(Value::String(schema)), Some(Value::String(relation))) => {
+                self.access.allows(schema, relation)
+            }
+            _ => true,
+        });
+        self.hide_query_texts(&mut blocking, &["waiting_query", "blocking_query"])
+            .await;
+        self.hide_query_texts(&mut locks, &["query"]).await;
+        Ok(LockReport { blocking, locks })
+    }
+
+    /// Replaces with null the query texts under `keys` of `rows` that refer
+    /// to tables the access lists hide, or cannot be checked against them.
+    async fn hide_query_texts(&self, rows: &mut [RowMap], keys: &[&str]) {
+        if !self.access.is_restricted() {
+            return;
+        }
+        for row in rows {
+            for key in keys {
+                if let Some(Value::String(query)) = row.get(*key)
+                    && self.check_sql_access(query).await.is_err()
+                {
+                    row.insert(key.to_string(), Value::Null);
+                }
+            }
+        }
+    }
+
+    async fn top_queries(&self, args: TopQueriesArgs) -> ToolResult<TopQueries> {
+        let client = self.client.as_ref().unwrap();
+        let limit = args.limit.clamp(1, 100);
+
+        // pg_stat_statements 1.8 (PostgreSQL 13) split total_time into
+        // planning and execution time. It hides other users' statements
+        // from those without pg_read_all_stats, giving no queryid and the
+        // text "<insufficient privilege>".
+        let (schema, version, exec_time) = match pooler::query_opt(
+            client,
+            "SELECT n.nspname::text, e.extversion, \
+                    EXISTS (SELECT 1 FROM pg_attribute a \
+                            JOIN pg_class c ON c.oid = a.attrelid \
+                            WHERE c.relname = 'pg_stat_statements' \
+                              AND c.relnamespace = e.extnamespace \
+                              AND a.attname = 'total_exec_time') \
+             FROM pg_extension e JOIN pg_namespace n ON n.oid = e.extnamespace \
+             WHERE e.extname = 'pg_stat_statements'",
+            &[],
+        )
+        .await
+        {
+            Ok(Some(row)) => (row.get::<_, String>(0), row.get::<_, String>(1), row.get(2)),
+            Ok(None) => {
+                return Err(ToolError::Failed(
+                    "The pg_stat_statements extension is not installed. Add it to shared_preload_libraries and run CREATE EXTENSION pg_stat_statements.".to_string(),
+                ));
+            }
+            Err(e) => return Err(failed("Failed to look up pg_stat_statements extension", e)),
+        };
+        let (total, mean) = if exec_time {
+            ("total_exec_time", "mean_exec_time")
+        } else {
+            ("total_time", "mean_time")
+        };
+        let sql = format!(
+            "SELECT queryid::text, CASE WHEN queryid IS NOT NULL THEN query END, calls, \
+                    {total}::float8 AS total_time_ms, \
+                    {mean}::float8 AS mean_time_ms, rows, \
+                    (100 * {total} / NULLIF(sum({total}) OVER (), 0))::float8 \
+             FROM {schema}.pg_stat_statements \
+             WHERE dbid = (SELECT oid FROM pg_database WHERE datname = current_database()) \
+             ORDER BY {order} DESC \
+             LIMIT $1",
+            total = total,
+            mean = mean,
+            schema = quote_ident(&schema),
+            order = args.sort.column(),
+        );
+        let rows = pooler::query(client, &sql, &[(&limit, Type::INT8)])
+            .await
+            .map_err(|e| failed("Failed to read pg_stat_statements", e))?;
+
+        let mut queries = Vec::new();
+        let mut hidden = 0;
+        for row in &rows {
+            let query: Option<String> = row.get(1);
+            if let Some(query) = &query
+                && self.access.is_restricted()
+                && self.check_sql_access(query).await.is_err()
+            {
+                hidden += 1;
+                continue;
+            }
+            queries.push(TopQuery {
+                query_id: row.get(0),
+                query,
+                calls: row.get(2),
+                total_time_ms: row.get(3),
+                mean_time_ms: row.get(4),
+                rows: row.get(5),
+                percent_of_total_time: row.get(6),
+            });
+        }
+        Ok(TopQueries {
+            sort: args.sort,
+            pg_stat_statements_version: version,
+            queries,
+            hidden,
+        })
+    }
+
+    async fn table_sizes(&self, args: TableSizesArgs) -> ToolResult<TableSizes> {
+        let client = self.client.as_ref().unwrap();
+        // Tables the access lists hide are dropped after the query, so it
+        // only limits the rows when nothing is hidden.
+        let limit = (!self.access.is_restricted()).then_some(args.limit);
+        let rows = pooler::query(
+            client,
+            "SELECT n.nspname::text, c.relname::text, \
+                    CASE WHEN c.relkind = 'm' THEN 'materialized_view' \
+                         WHEN c.relispartition THEN 'partition' \
+                         ELSE 'table' END, \
+                    pg_relation_size(c.oid), pg_indexes_size(c.oid), \
+                    CASE WHEN c.reltoastrelid = 0 THEN 0 \
+                         ELSE pg_total_relation_size(c.reltoastrelid) END, \
+                    pg_total_relation_size(c.oid), \
+                    pg_size_pretty(pg_total_relation_size(c.oid)) \
+             FROM pg_class c \
+             JOIN pg_namespace n ON n.oid = c.relnamespace \
+             WHERE c.relkind IN ('r', 'm') \
+               AND CASE WHEN $1::text IS NULL \
+                        THEN n.nspname <> 'information_schema' AND n.nspname NOT LIKE 'pg\\_%' \
+                        ELSE n.nspname = $1 END \
+             ORDER BY 7 DESC, 1, 2 \
+             LIMIT $2",
+            &[(&args.schema, Type::TEXT), (&limit, Type::INT8)],
+        )
+        .await
+        .map_err(|e| failed("Failed to read table sizes", e))?;
+        let tables = rows
+            .iter()
+            .filter(|row| self.access.allows(row.get(0), row.get(1)))
+            .take(args.limit.max(0) as usize)
+            .map(|row| TableSize {
+                schema: row.get(0),
+                table: row.get(1),
+                kind: row.get(2),
+                heap_bytes: row.get(3),
+                index_bytes: row.get(4),
+                toast_bytes: row.get(5),
+                total_bytes: row.get(6),
+                total_size: row.get(7),
+            })
+            .collect();
+        Ok(TableSizes { tables })
+    }
+
+    async fn list_schemas(&self, args: ListSchemasArgs) -> ToolResult<SchemaList> {
+        let client = self.client.as_ref().unwrap();
+        let rows = pooler::query(
+            client,
+            "SELECT n.nspname::text, pg_get_userbyid(n.nspowner)::text, \
+                    obj_description(n.oid, 'pg_namespace'), \
+                    COALESCE(array_agg(c.relname::text) \
+                        FILTER (WHERE c.relkind IN ('r', 'p', 'f')), '{}'), \
+                    COALESCE(array_agg(c.relname::text) \
+                        FILTER (WHERE c.relkind IN ('v', 'm')), '{}') \
+             FROM pg_namespace n \
+             LEFT JOIN pg_class c ON c.relnamespace = n.oid \
+               AND c.relkind IN ('r', 'p', 'f', 'v', 'm') AND NOT c.relispartition \
+             WHERE $1 OR (n.nspname <> 'information_schema' AND n.nspname NOT LIKE 'pg\\_%') \
+             GROUP BY n.oid, n.nspname, n.nspowner \
+             ORDER BY n.nspname",
+            &[(&args.include_system, Type::BOOL)],
+        )
+        .await
+        .map_err(|e| failed("Failed to list schemas", e))?;
+        let schemas = rows
+            .iter()
+            .filter_map(|row| {
+                let name: String = row.get(0);
+                let visible = |names: Vec<String>| {
+                    names
+                        .iter()
+                        .filter(|table| self.access.allows(&name, table))
+                        .count()
+                };
+                let all: Vec<String> = row.get(3);
+                let all_views: Vec<String> = row.get(4);
+                let (total, tables, views) = (
+                    all.len() + all_views.len(),
+                    visible(all),
+                    visible(all_views),
+                );
+                // A schema whose every table the access lists hide is hidden
+                // too; an empty one is not.
+                if total > 0 && tables + views == 0 {
+                    return None;
+                }
+                Some(SchemaInfo {
+                    owner: row.get(1),
+                    description: row.get(2),
+                    tables,
+                    views,
+                    name,
+                })
+            })
+            .collect();
+        Ok(SchemaList { schemas })
+    }
+
+    async fn verify_integrity(&self, args: VerifyIntegrityArgs) -> ToolResult<IntegrityReport> {
+        let client = self.client.as_ref().unwrap();
+
+        let amcheck_version = match pooler::query_opt(
+            client,
+            "SELECT extversion FROM pg_extension WHERE extname = 'amcheck'",
+            &[],
+        )
+        .await
+        {
+            Ok(Some(row)) => row.get::<_, String>(0),
+            Ok(None) => {
+                return Err(ToolError::Failed(
+                    "The amcheck extension is not installed. Run CREATE EXTENSION amcheck to enable integrity checks.".to_string(),
+                ));
+            }
+            Err(e) => return Err(failed("Failed to look up amcheck extension", e)),
+        };
+
+        let data_checksums =
+            pooler::query_one(client, "SELECT current_setting('data_checksums')", &[])
+                .await
+                .map(|row| row.get::<_, String>(0))
+                .ok();
+        // checksum_failures was added to pg_stat_database in PostgreSQL 12.
+        let checksum_failures = query_rows(
+            client,
+            "SELECT datname::text AS database, checksum_failures, \
+                    checksum_last_failure::text AS checksum_last_failure \
+             FROM pg_stat_database \
+             WHERE datname IS NOT NULL AND checksum_failures > 0",
+            &[],
+        )
+        .await
+        .ok();
+
+        let mut results = Vec::new();
+        for relation in &args.relations {
+            // An index is checked against the access lists as its table.
+            let target = match pooler::query_opt(
+                client,
+                "SELECT c.oid, c.relkind::text, am.amname::text, n.nspname::text, t.relname::text \
+                     FROM pg_class c LEFT JOIN pg_am am ON am.oid = c.relam \
+                     LEFT JOIN pg_index i ON i.indexrelid = c.oid \
+                     JOIN pg_class t ON t.oid = COALESCE(i.indrelid, c.oid) \
+                     JOIN pg_namespace n ON n.oid = t.relnamespace \
+                     WHERE c.oid = to_regclass($1)",
+                &[(relation, Type::TEXT)],
+            )
+            .await
+            {
+                Ok(Some(row)) => row,
+                Ok(None) => {
+                    results.push(RelationCheck::error(relation, None, "Relation not found"));
+                    continue;
+                }
+                Err(e) => {
+                    results.push(RelationCheck::error(relation, None, e.to_string()));
+                    continue;
+                }
+            };
+            let oid: u32 = target.get(0);
+            let relkind: String = target.get(1);
+            let amname: Option<String> = target.get(2);
+            self.check_table_access(target.get(3), target.get(4))?;
+
+            let mut indexes = Vec::new();
+            match relkind.as_str() {
+                "r" | "m" | "t" => {
+                    results.push(match self.server_version {
+                        Some(v) if !v.has(Feature::VerifyHeapam) => RelationCheck::error(
+                            relation,
+                            Some("verify_heapam"),
+                            v.lacks(Feature::VerifyHeapam),
+                        ),
+                        _ => check_heap(client, relation, oid).await,
+                    });
+                    match pooler::query(
+                        client,
+                        "SELECT i.indexrelid, i.indexrelid::regclass::text \
+                             FROM pg_index i \
+                             JOIN pg_class c ON c.oid = i.indexrelid \
+                             JOIN pg_am am ON am.oid = c.relam \
+                             WHERE i.indrelid = $1 AND am.amname = 'btree'",
+                        &[(&oid, Type::OID)],
+                    )
+                    .await
+                    {
+                        Ok(rows) => {
+                            indexes.extend(rows.iter().map(|r| (r.get::<_, u32>(0), r.get(1))))
+                        }
+                        Err(e) => results.push(RelationCheck::error(
+                            relation,
+                            Some("bt_index_check"),
+                            format!("Failed to list indexes: {}", e),
+                        )),
+                    }
+                }
+                "i" if amname.as_deref() == Some("btree") => indexes.push((oid, relation.clone())),
+                _ => results.push(RelationCheck::error(
+                    relation,
+                    None,
+                    "Only tables, materialized views, and btree indexes can be checked",
+                )),
+            }
+
+            for (index_oid, index_name) in indexes {
+                let outcome = pooler::query(
+                    client,
+                    "SELECT bt_index_check($1::oid::regclass, $2)",
+                    &[(&index_oid, Type::OID), (&args.heapallindexed, Type::BOOL)],
+                )
+                .await;
+                results.push(integrity_result(
+                    &index_name,
+                    "bt_index_check",
+                    outcome.map(|_| vec![]),
+                ));
+            }
+        }
+
+        let corrupt = results.iter().any(|r| r.status == "corrupt");
+
+        Ok(IntegrityReport {
+            status: if corrupt { "corruption_found" } else { "ok" },
+            amcheck_version,
+            data_checksums,
+            checksum_failures,
+            results,
+        })
+    }
+
+    async fn search_values(&self, args: SearchValuesArgs) -> ToolResult<SearchResult> {
+        let (needle, number) = match args.value {
+            SearchValue::Text(s) => {
+                let number = s.trim().parse::<f64>().ok().map(|_| s.trim().to_string());
+                (s, number)
+            }
+            SearchValue::Number(n) => (n.to_string(), Some(n.to_string())),
+        };
+        let schema = args.schema;
+        let exact = args.exact;
+        let limit_per_table = args.limit_per_table.max(1);
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(args.timeout_ms);
+
+        let client = self.client.as_ref().unwrap();
+
+        let columns = pooler::query(
+            client,
+            "SELECT c.table_name::text, c.column_name::text, c.data_type::text \
+                 FROM information_schema.columns c \
+                 JOIN information_schema.tables t \
+                   ON t.table_schema = c.table_schema AND t.table_name = c.table_name \
+                 WHERE c.table_schema = $1 AND t.table_type = 'BASE TABLE' \
+                 ORDER BY c.table_name, c.ordinal_position",
+            &[(&schema, Type::TEXT)],
+        )
+        .await
+        .map_err(|e| failed("Failed to list columns", e))?;
+        let primary_keys = pooler::query(
+            client,
+            "SELECT tc.table_name::text, kcu.column_name::text \
+                 FROM information_schema.table_constraints tc \
+                 JOIN information_schema.key_column_usage kcu \
+                   ON kcu.constraint_schema = tc.constraint_schema \
+                  AND kcu.constraint_name = tc.constraint_name \
+                 WHERE tc.table_schema = $1 AND tc.constraint_type = 'PRIMARY KEY' \
+                 ORDER BY tc.table_name, kcu.ordinal_position",
+            &[(&schema, Type::TEXT)],
+        )
+        .await
+        .map_err(|e| failed("Failed to list primary keys", e))?;
+
+        // Group searchable columns by table, preserving catalog order.
+        let mut tables: Vec<(String, Vec<(String, bool)>)> = Vec::new();
+        for row in &columns {
+            let table: String = row.get(0);
+            let column: String = row.get(1);
+            let data_type: String = row.get(2);
+            if args.tables.as_ref().is_some_and(|t| !t.contains(&table))
+                || !self.access.allows(&schema, &table)
+            {
+                continue;
+            }
+            let is_numeric = matches!(
+                data_type.as_str(),
+                "smallint" | "integer" | "bigint" | "numeric" | "real" | "double precision"
+            );
+            let is_text = matches!(
+                data_type.as_str(),
+                "text" | "character varying" | "character" | "uuid" | "json" | "jsonb"
+            );
+            if !(is_text || (is_numeric && number.is_some())) {
+                continue;
+            }
+            if tables.last().is_none_or(|(t, _)| *t != table) {
+                tables.push((table.clone(), Vec::new()));
+            }
+            tables.last_mut().unwrap().1.push((column, is_numeric));
+        }
+
+        let pattern = if exact {
+            needle.clone()
+        } else {
+            format!(
+                "%{}%",
+                needle
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            )
+        };
+        let number = number.unwrap_or_default();
+
+        let mut matches = Vec::new();
+        let mut searched = Vec::new();
+        let mut timed_out = false;
+        for (table, search_columns) in &tables {
+            let pk: Vec<String> = primary_keys
+                .iter()
+                .filter(|r| r.get::<_, String>(0) == *table)
+                .map(|r| r.get(1))
+                .collect();
+            let id_columns = if pk.is_empty() {
+                vec!["ctid".to_string()]
+            } else {
+                pk
+            };
+
+            // Only bind the parameters this table's columns actually reference.
+            let mut params: Vec<pooler::Param> = Vec::new();
+            let mut text_param = None;
+            let mut number_param = None;
+            let mut conditions = Vec::new();
+            for (column, is_numeric) in search_columns {
+                let slot = if *is_numeric {
+                    &mut number_param
+                } else {
+                    &mut text_param
+                };
+                let placeholder = *slot.get_or_insert_with(|| {
+                    params.push((if *is_numeric { &number } else { &pattern }, Type::TEXT));
+                    params.len()
+                });
+                conditions.push(if *is_numeric {
+                    format!("{} = ${}::text::numeric", quote_ident(column), placeholder)
+                } else if exact {
+                    format!("{}::text = ${}", quote_ident(column), placeholder)
+                } else {
+                    format!(
+                        "{}::text ILIKE ${} ESCAPE '\\'",
+                        quote_ident(column),
+                        placeholder
+                    )
+                });
+            }
+            let select_list: Vec<String> =
+                id_columns
+                    .iter()
+                    .map(|c| {
+                        let ident = if c == "ctid" {
+                            c.clone()
+                        } else {
+                            quote_ident(c)
+                        };
+                        format!("{}::text", ident)
+                    })
+                    .chain(search_columns.iter().zip(&conditions).map(
+                        |((column, _), condition)| {
+                            format!(
+                                "{}::text, COALESCE({}, false)",
+                                quote_ident(column),
+                                condition
+                            )
+                        },
+                    ))
+                    .collect();
+            let soft_delete_filter = self
+                .soft_delete
+                .filter(&schema, table)
+                .filter(|_| !args.include_deleted);
+            let sql = format!(
+                "SELECT {} FROM {}.{} WHERE ({}){} LIMIT {}",
+                select_list.join(", "),
+                quote_ident(&schema),
+                quote_ident(table),
+                conditions.join(" OR "),
+                soft_delete_filter
+                    .map(|predicate| format!(" AND ({})", predicate))
+                    .unwrap_or_default(),
+                limit_per_table
+            );
+            let soft_delete_filter = soft_delete_filter.map(str::to_string);
+
+            if !self.cancellation.statement_started(client.cancel_token()) {
+                break;
+            }
+            let result =
+                tokio::time::timeout_at(deadline, pooler::query(client, &sql, &params)).await;
+            self.cancellation.statement_finished();
+            let rows = match result {
+                Ok(Ok(rows)) => rows,
+                Ok(Err(e)) => {
+                    searched.push(TableSearch {
+                        table: table.clone(),
+                        matching_rows: None,
+                        error: Some(db_error_message(&e)),
+                        soft_delete_filter,
+                    });
+                    continue;
+                }
+                Err(_) => {
+                    if let Err(e) = client.cancel_token().cancel_query(self.tls.clone()).await {
+                        error!("Failed to cancel timed out search: {}", e);
+                    }
+                    timed_out = true;
+                    break;
+                }
+            };
+            searched.push(TableSearch {
+                table: table.clone(),
+                matching_rows: Some(rows.len()),
+                error: None,
+                soft_delete_filter,
+            });
+
+            for row in &rows {
+                let row_id: HashMap<String, Option<String>> = id_columns
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, c)| (c.clone(), row.get(idx)))
+                    .collect();
+                for (i, (column, _)) in search_columns.iter().enumerate() {
+                    let base = id_columns.len() + i * 2;
+                    if row.get::<_, bool>(base + 1) {
+                        let value: Option<String> = row.get(base);
+                        matches.push(ValueMatch {
+                            table: table.clone(),
+                            column: column.clone(),
+                            row: row_id.clone(),
+                            value: value.map(|v| v.chars().take(200).collect()),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(SearchResult {
+            value: needle,
+            schema,
+            timed_out,
+            tables_searched: searched,
+            matches,
+        })
+    }
+
+    async fn find_duplicates(&self, args: FindDuplicatesArgs) -> ToolResult<DuplicateReport> {
+        let FindDuplicatesArgs {
+            table,
+            schema,
+            columns,
+            include_nulls,
+            include_deleted,
+            ..
+        } = args;
+        self.check_table_access(&schema, &table)?;
+        if columns.is_empty() {
+            return Err(ToolError::InvalidArguments(
+                "'columns' must name at least one column".to_string(),
+            ));
+        }
+        let limit = args.limit.max(1);
+        let sample_size = args.sample_size.max(1);
+
+        let client = self.client.as_ref().unwrap();
+
+        let table_columns: Vec<String> = pooler::query(
+            client,
+            "SELECT column_name::text FROM information_schema.columns \
+                 WHERE table_schema = $1 AND table_name = $2 ORDER BY ordinal_position",
+            &[(&schema, Type::TEXT), (&table, Type::TEXT)],
+        )
+        .await
+        .map_err(|e| failed("Failed to read columns", e))?
+        .iter()
+        .map(|r| r.get(0))
+        .collect();
+        if table_columns.is_empty() {
+            return Err(ToolError::InvalidArguments(format!(
+                "Table '{}.{}' not found",
+                schema, table
+            )));
+        }
+        if let Some(missing) = columns.iter().find(|c| !table_columns.contains(c)) {
+            return Err(ToolError::InvalidArguments(format!(
+                "Column '{}' not found in {}.{}. Available columns: {}",
+                missing,
+                schema,
+                table,
+                table_columns.join(", ")
+            )));
+        }
+
+        let qualified = format!("{}.{}", quote_ident(&schema), quote_ident(&table));
+        let primary_key = primary_key_columns(client, &qualified)
+            .await
+            .map_err(|e| failed("Failed to read primary key", e))?;
+        let key_expr = row_key_expr(&primary_key, None);
+
+        let quoted: Vec<String> = columns.iter().map(|c| quote_ident(c)).collect();
+        let soft_delete_filter = self
+            .soft_delete
+            .filter(&schema, &table)
+            .filter(|_| !include_deleted);
+        let mut conditions: Vec<String> = soft_delete_filter
+            .map(|predicate| format!("({})", predicate))
+            .into_iter()
+            .collect();
+        if !include_nulls {
+            conditions.extend(quoted.iter().map(|c| format!("{} IS NOT NULL", c)));
+        }
+        let row_filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        let groups_sql = format!(
+            "SELECT {values}, count(*) AS count, \
+                    array_to_json((array_agg({key}))[1:{sample}])::text AS sample_keys \
+             FROM {table} {filter} \
+             GROUP BY {group} HAVING count(*) > 1 \
+             ORDER BY count(*) DESC LIMIT {limit}",
+            values = quoted
+                .iter()
+                .map(|c| format!("{}::text", c))
+                .collect::<Vec<_>>()
+                .join(", "),
+            key = key_expr,
+            sample = sample_size,
+            table = qualified,
+            filter = row_filter,
+            group = quoted.join(", "),
+            limit = limit,
+        );
+        let totals_sql = format!(
+            "SELECT count(*), COALESCE(sum(n), 0)::int8 FROM \
+             (SELECT count(*) AS n FROM {} {} GROUP BY {} HAVING count(*) > 1) d",
+            qualified,
+            row_filter,
+            quoted.join(", ")
+        );
+
+        let groups = pooler::query(client, groups_sql.as_str(), &[])
+            .await
+            .map_err(|e| failed("Failed to find duplicates", e))?
+            .iter()
+            .map(|row| {
+                let sample_keys: Option<String> = row.get(columns.len() + 1);
+                DuplicateGroup {
+                    values: columns
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, c)| (c.clone(), row.get(idx)))
+                        .collect(),
+                    count: row.get(columns.len()),
+                    sample_keys: sample_keys
+                        .and_then(|k| serde_json::from_str::<Value>(&k).ok())
+                        .unwrap_or(Value::Null),
+                }
+            })
+            .collect();
+        let totals = pooler::query_one(client, totals_sql.as_str(), &[])
+            .await
+            .map_err(|e| failed("Failed to count duplicates", e))?;
+
+        Ok(DuplicateReport {
+            table: format!("{}.{}", schema, table),
+            columns,
+            soft_delete_filter: soft_delete_filter.map(str::to_string),
+            duplicate_groups: totals.get(0),
+            duplicate_rows: totals.get(1),
+            groups,
+        })
+    }
+
+    /// The foreign keys of tables in `schema`, or only the one named
+    /// `constraint` or those referencing or referenced by `table`, leaving
+    /// out those of hidden tables.
+    async fn foreign_keys(
+        &self,
+        schema: &str,
+        constraint: Option<&str>,
+        table: Option<&str>,
+    ) -> ToolResult<Vec<ForeignKey>> {
+        let client = self.client.as_ref().unwrap();
+        let foreign_keys = pooler::query(
+            client,
+            "SELECT c.conname::text, cn.nspname::text, cc.relname::text, \
+                            ARRAY(SELECT a.attname::text \
+                                  FROM unnest(c.conkey) WITH ORDINALITY k(attnum, ord) \
+                                  JOIN pg_attribute a \
+                                    ON a.attrelid = c.conrelid AND a.attnum = k.attnum \
+                                  ORDER BY k.ord), \
+                            pn.nspname::text, pc.relname::text, \
+                            ARRAY(SELECT a.attname::text \
+                                  FROM unnest(c.confkey) WITH ORDINALITY k(attnum, ord) \
+                                  JOIN pg_attribute a \
+                                    ON a.attrelid = c.confrelid AND a.attnum = k.attnum \
+                                  ORDER BY k.ord) \
+                     FROM pg_constraint c \
+                     JOIN pg_class cc ON cc.oid = c.conrelid \
+                     JOIN pg_namespace cn ON cn.oid = cc.relnamespace \
+                     JOIN pg_class pc ON pc.oid = c.confrelid \
+                     JOIN pg_namespace pn ON pn.oid = pc.relnamespace \
+                     WHERE c.contype = 'f' AND cn.nspname = $1 \
+                       AND ($2::text IS NULL OR c.conname = $2) \
+                       AND ($3::text IS NULL OR cc.relname = $3 OR pc.relname = $3) \
+                     ORDER BY cc.relname, c.conname",
+            &[
+                (&schema, Type::TEXT),
+                (&constraint, Type::TEXT),
+                (&table, Type::TEXT),
+            ],
+        )
+        .await
+        .map_err(|e| failed("Failed to list foreign keys", e))?
+        .iter()
+        .map(|row| ForeignKey {
+            name: Some(row.get(0)),
+            child_schema: row.get(1),
+            child_table: row.get(2),
+            child_columns: row.get(3),
+            parent_schema: row.get(4),
+            parent_table: row.get(5),
+            parent_columns: row.get(6),
+        })
+        .filter(|fk| {
+            self.access.allows(&fk.child_schema, &fk.child_table)
+                && self.access.allows(&fk.parent_schema, &fk.parent_table)
+        })
+        .collect();
+        Ok(foreign_keys)
+    }
+
+    async fn find_orphans(&self, args: FindOrphansArgs) -> ToolResult<OrphanReport> {
+        let schema = args.schema;
+        let sample_size = args.sample_size.max(1);
+
+        let client = self.client.as_ref().unwrap();
+
+        let relationships: Vec<ForeignKey> = if let Some(child_table) = args.child_table {
+            let Some(parent_table) = args.parent_table else {
+                return Err(ToolError::InvalidArguments(
+                    "'child_table' requires 'parent_table', 'child_columns', and 'parent_columns'"
+                        .to_string(),
+                ));
+            };
+            if args.child_columns.is_empty()
+                || args.child_columns.len() != args.parent_columns.len()
+            {
+                return Err(ToolError::InvalidArguments(
+                    "'child_columns' and 'parent_columns' must be non-empty and the same length"
+                        .to_string(),
+                ));
+            }
+            self.check_table_access(&schema, &child_table)?;
+            self.check_table_access(&schema, &parent_table)?;
+            vec![ForeignKey {
+                name: None,
+                child_schema: schema.clone(),
+                child_table,
+                child_columns: args.child_columns,
+                parent_schema: schema.clone(),
+                parent_table,
+                parent_columns: args.parent_columns,
+            }]
+        } else {
+            self.foreign_keys(&schema, args.constraint.as_deref(), args.table.as_deref())
+                .await?
+        };
+
+        let mut results = Vec::new();
+        for fk in relationships {
+            let child = format!(
+                "{}.{}",
+                quote_ident(&fk.child_schema),
+                quote_ident(&fk.child_table)
+            );
+            let parent = format!(
+                "{}.{}",
+                quote_ident(&fk.parent_schema),
+                quote_ident(&fk.parent_table)
+            );
+            let join_condition = fk
+                .child_columns
+                .iter()
+                .zip(&fk.parent_columns)
+                .map(|(c, p)| format!("p.{} = c.{}", quote_ident(p), quote_ident(c)))
+                .collect::<Vec<_>>()
+                .join(" AND ");
+            // Rows with a NULL in any referencing column are exempt from the
+            // foreign key, as with MATCH SIMPLE.
+            let not_null = fk
+                .child_columns
+                .iter()
+                .map(|c| format!("c.{} IS NOT NULL", quote_ident(c)))
+                .collect::<Vec<_>>()
+                .join(" AND ");
+
+            let mut result = RelationshipAudit {
+                constraint: fk.name.clone(),
+                child: format!(
+                    "{}.{}({})",
+                    fk.child_schema,
+                    fk.child_table,
+                    fk.child_columns.join(", ")
+                ),
+                parent: format!(
+                    "{}.{}({})",
+                    fk.parent_schema,
+                    fk.parent_table,
+                    fk.parent_columns.join(", ")
+                ),
+                orphaned_children: None,
+                childless_parents: None,
+                error: None,
+            };
+
+            let child_key = match primary_key_columns(client, &child).await {
+                Ok(pk) => row_key_expr(&pk, Some("c")),
+                Err(e) => {
+                    result.error = Some(e.to_string());
+                    results.push(result);
+                    continue;
+                }
+            };
+            let orphans_sql = format!(
+                "SELECT count(*) OVER (), ({key} || {cols})::text \
+                 FROM {child} c \
+                 WHERE {not_null} AND NOT EXISTS (SELECT 1 FROM {parent} p WHERE {join}) \
+                 LIMIT {sample}",
+                key = child_key,
+                cols = jsonb_object_expr(&fk.child_columns, "c."),
+                child = child,
+                not_null = not_null,
+                parent = parent,
+                join = join_condition,
+                sample = sample_size,
+            );
+            match sampled_count(client, &orphans_sql).await {
+                Ok(found) => result.orphaned_children = Some(found),
+                Err(e) => {
+                    result.error = Some(db_error_message(&e));
+                    results.push(result);
+                    continue;
+                }
+            }
+
+            if args.include_childless_parents {
+                let parent_key = match primary_key_columns(client, &parent).await {
+                    Ok(pk) => row_key_expr(&pk, Some("p")),
+                    Err(e) => {
+                        result.error = Some(e.to_string());
+                        results.push(result);
+                        continue;
+                    }
+                };
+                let childless_sql = format!(
+                    "SELECT count(*) OVER (), ({key} || {cols})::text \
+                     FROM {parent} p \
+                     WHERE NOT EXISTS (SELECT 1 FROM {child} c WHERE {join}) \
+                     LIMIT {sample}",
+                    key = parent_key,
+                    cols = jsonb_object_expr(&fk.parent_columns, "p."),
+                    parent = parent,
+                    child = child,
+                    join = join_condition,
+                    sample = sample_size,
+                );
+                match sampled_count(client, &childless_sql).await {
+                    Ok(found) => result.childless_parents = Some(found),
+                    Err(e) => result.error = Some(db_error_message(&e)),
+                }
+            }
+            results.push(result);
+        }
+
+        Ok(OrphanReport {
+            schema,
+            relationships_checked: results.len(),
+            relationships: results,
+        })
+    }
+
+    async fn suggest_joins(&self, args: SuggestJoinsArgs) -> ToolResult<JoinSuggestions> {
+        let schema = args.schema;
+        let mut requested: Vec<TableName> = Vec::new();
+        for table in args.tables {
+            self.check_table_access(&schema, &table)?;
+            let name = (schema.clone(), table);
+            if !requested.contains(&name) {
+                requested.push(name);
+            }
+        }
+        if requested.len() < 2 {
+            return Err(ToolError::InvalidArguments(
+                "'tables' must name at least two tables".to_string(),
+            ));
+        }
+        if !(1..=6).contains(&args.max_hops) {
+            return Err(ToolError::InvalidArguments(
+                "'max_hops' must be between 1 and 6".to_string(),
+            ));
+        }
+
+        let client = self.client.as_ref().unwrap();
+        let tables: Vec<TableColumns> = pooler::query(
+            client,
+            "SELECT c.relname::text, \
+                        array_agg(a.attname::text ORDER BY a.attnum), \
+                        array_agg(format_type(a.atttypid, NULL) ORDER BY a.attnum), \
+                        ARRAY(SELECT pa.attname::text \
+                              FROM pg_index i \
+                              CROSS JOIN unnest(i.indkey) WITH ORDINALITY k(attnum, ord) \
+                              JOIN pg_attribute pa \
+                                ON pa.attrelid = i.indrelid AND pa.attnum = k.attnum \
+                              WHERE i.indrelid = c.oid AND i.indisprimary \
+                              ORDER BY k.ord) \
+                 FROM pg_class c \
+                 JOIN pg_namespace n ON n.oid = c.relnamespace \
+                 JOIN pg_attribute a \
+                   ON a.attrelid = c.oid AND a.attnum > 0 AND NOT a.attisdropped \
+                 WHERE n.nspname = $1 AND c.relkind IN ('r', 'p', 'v', 'm', 'f') \
+                   AND NOT c.relispartition \
+                 GROUP BY c.oid, c.relname",
+            &[(&schema, Type::TEXT)],
+        )
+        .await
+        .map_err(|e| failed("Failed to read table columns", e))?
+        .iter()
+        .filter(|row| self.access.allows(&schema, row.get(0)))
+        .map(|row| {
+            let names: Vec<String> = row.get(1);
+            let types: Vec<String> = row.get(2);
+            TableColumns {
+                name: (schema.clone(), row.get(0)),
+                columns: names.into_iter().zip(types).collect(),
+                primary_key: row.get(3),
+            }
+        })
+        .collect();
+        if let Some((_, missing)) = requested
+            .iter()
+            .find(|name| !tables.iter().any(|t| &t.name == *name))
+        {
+            return Err(ToolError::InvalidArguments(format!(
+                "Table '{}.{}' not found",
+                schema, missing
+            )));
+        }
+
+        let mut links: Vec<Link> = self
+            .foreign_keys(&schema, None, None)
+            .await?
+            .into_iter()
+            .map(|fk| Link {
+                constraint: fk.name,
+                from: (fk.child_schema, fk.child_table),
+                from_columns: fk.child_columns,
+                to: (fk.parent_schema, fk.parent_table),
+                to_columns: fk.parent_columns,
+            })
+            .collect();
+        links.extend(joins::undeclared_links(&tables, &links));
+        let plan = joins::plan(&requested, &links, args.max_hops as usize);
+
+        let display = |(table_schema, table): &TableName| {
+            if *table_schema == schema {
+                table.clone()
+            } else {
+                format!("{}.{}", table_schema, table)
+            }
+        };
+        let aliases = joins::aliases(&plan.tables);
+        let alias_of = |name: &TableName| {
+            let index = plan.tables.iter().position(|t| t == name).unwrap_or(0);
+            aliases[index].as_str()
+        };
+        let from_clause = |name: &TableName| {
+            format!(
+                "{}.{} AS {}",
+                joins::sql_ident(&name.0),
+                joins::sql_ident(&name.1),
+                alias_of(name)
+            )
+        };
+        let joins: Vec<SuggestedJoin> = plan
+            .links
+            .iter()
+            .map(|link| {
+                let condition = link
+                    .from_columns
+                    .iter()
+                    .zip(&link.to_columns)
+                    .map(|(from, to)| {
+                        format!(
+                            "{}.{} = {}.{}",
+                            alias_of(&link.from),
+                            joins::sql_ident(from),
+                            alias_of(&link.to),
+                            joins::sql_ident(to)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" AND ");
+                SuggestedJoin {
+                    from_table: display(&link.from),
+                    from_columns: link.from_columns.clone(),
+                    to_table: display(&link.to),
+                    to_columns: link.to_columns.clone(),
+                    condition,
+                    source: if link.constraint.is_some() {
+                        "foreign_key"
+                    } else {
+                        "name_match"
+                    },
+                    constraint: link.constraint.clone(),
+                }
+            })
+            .collect();
+
+        let selected: Vec<String> = plan
+            .tables
+            .iter()
+            .filter(|name| requested.contains(name))
+            .map(|name| format!("{}.*", alias_of(name)))
+            .collect();
+        let mut sql = format!(
+            "SELECT {}\nFROM {}",
+            selected.join(", "),
+            from_clause(&plan.tables[0])
+        );
+        for (table, join) in plan.tables[1..].iter().zip(&joins) {
+            sql.push_str(&format!(
+                "\nJOIN {} ON {}",
+                from_clause(table),
+                join.condition
+            ));
+        }
+        sql.push_str("\nLIMIT 100");
+
+        Ok(JoinSuggestions {
+            tables: plan
+                .tables
+                .iter()
+                .map(|name| JoinedTable {
+                    table: display(name),
+                    alias: alias_of(name).to_string(),
+                    intermediate: !requested.contains(name),
+                })
+                .collect(),
+            joins,
+            unconnected: plan.unconnected.iter().map(display).collect(),
+            sql,
+        })
+    }
+
+    async fn vector_search(&self, args: VectorSearchArgs) -> ToolResult<VectorSearchResult> {
+        let VectorSearchArgs {
+            table,
+            schema,
+            column,
+            vector,
+            metric,
+            filter,
+            columns,
+            include_deleted,
+            ..
+        } = args;
+        self.check_table_access(&schema, &table)?;
+        check_conditions(&filter)?;
+        if vector.is_empty() {
+            return Err(ToolError::InvalidArguments(
+                "'vector' must have at least one number".to_string(),
+            ));
+        }
+        let k = args.k.max(1);
+
+        let client = self.client.as_ref().unwrap();
+        let qualified = format!("{}.{}", quote_ident(&schema), quote_ident(&table));
+        let table_columns = column_types(client, &qualified)
+            .await
+            .map_err(|e| failed("Failed to read columns", e))?;
+        if table_columns.is_empty() {
+            return Err(ToolError::InvalidArguments(format!(
+                "Table '{}.{}' not found",
+                schema, table
+            )));
+        }
+        let unknown = std::iter::once(&column)
+            .chain(&columns)
+            .chain(filter.iter().map(|c| &c.column))
+            .find(|c| !table_columns.iter().any(|(name, _)| name == *c));
+        if let Some(unknown) = unknown {
+            return Err(ToolError::InvalidArguments(format!(
+                "Column '{}' not found in {}.{}. Available columns: {}",
+                unknown,
+                schema,
+                table,
+                table_columns
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+        // The type may be qualified by the extension's schema.
+        let vector_type = table_columns
+            .iter()
+            .find(|(name, _)| *name == column)
+            .map(|(_, ty)| ty.as_str())
+            .unwrap_or_default();
+        if vector_type.rsplit('.').next() != Some("vector") {
+            return Err(ToolError::InvalidArguments(format!(
+                "Column '{}' is of type {}, not vector",
+                column, vector_type
+            )));
+        }
+
+        let selected: Vec<String> = if columns.is_empty() {
+            table_columns
+                .iter()
+                .map(|(name, _)| name)
+                .filter(|name| **name != column)
+                .map(|name| quote_ident(name))
+                .collect()
+        } else {
+            columns.iter().map(|c| quote_ident(c)).collect()
+        };
+        // The query vector in pgvector's text form, such as [1,2.5,3].
+        let query_vector = Value::String(format!(
+            "[{}]",
+            vector
+                .iter()
+                .map(f64::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        ));
+        let no_key = Map::new();
+        let mut values: Vec<&Value> = vec![&query_vector];
+        let soft_delete_filter = self
+            .soft_delete
+            .filter(&schema, &table)
+            .filter(|_| !include_deleted);
+        let mut conditions = vec![format!("{} IS NOT NULL", quote_ident(&column))];
+        conditions.extend(soft_delete_filter.map(|predicate| format!("({})", predicate)));
+        conditions.extend(row_filter(&no_key, &filter, &mut |value| {
+            values.push(value);
+            format!("${}", values.len())
+        }));
+        // Ordering by the distance expression itself lets an HNSW or
+        // IVFFlat index on the column serve the search.
+        let distance = format!(
+            "{} {} $1::{}",
+            quote_ident(&column),
+            metric.operator(),
+            vector_type
+        );
+        let sql = format!(
+            "SELECT {distance} AS {alias}{columns} FROM {table} WHERE {conditions} \
+             ORDER BY {distance} LIMIT {k}",
+            distance = distance,
+            alias = DISTANCE_COLUMN,
+            columns = selected
+                .iter()
+                .map(|c| format!(", {}", c))
+                .collect::<String>(),
+            table = qualified,
+            conditions = conditions.join(" AND "),
+            k = k,
+        );
+        let search_failed = |e: tokio_postgres::Error| {
+            statement_error(
+                format!("Vector search failed: {}", db_error_message(&e)),
+                &e,
+            )
+        };
+        let statement = Prepared::new(client, &sql).await.map_err(search_failed)?;
+        let params = statement.text_params(values.iter().copied());
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+        let rows = self
+            .with_timeout(
+                client.cancel_token(),
+                self.query_timeout,
+                statement.query(client, &params),
+            )
+            .await
+            .ok_or_else(|| timed_out(self.query_timeout))?
+            .map_err(search_failed)?;
+
+        Ok(VectorSearchResult {
+            table: format!("{}.{}", schema, table),
+            column,
+            metric,
+            soft_delete_filter: soft_delete_filter.map(str::to_string),
+            matches: rows
+                .iter()
+                .map(|row| {
+                    let mut row_map = row_to_map(row);
+                    row_map.remove(DISTANCE_COLUMN);
+                    VectorMatch {
+                        distance: row.get(0),
+                        row: row_map,
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    async fn list_large_objects(&self, args: ListLargeObjectsArgs) -> ToolResult<LargeObjectList> {
+        let client = self.client.as_ref().unwrap();
+        // Seeking to the end of a freshly opened descriptor gives the size
+        // without reading the object.
+        let query = "SELECT m.oid::int8 AS oid, pg_get_userbyid(m.lomowner)::text AS owner, \
+                    lo_lseek64(lo_open(m.oid, 262144), 0, 2) AS size \
+             FROM pg_largeobject_metadata m \
+             ORDER BY m.oid \
+             LIMIT $1";
+
+        let large_objects = query_rows(client, query, &[(&args.limit, Type::INT8)])
+            .await
+            .map_err(|e| failed("Failed to list large objects", e))?;
+        Ok(LargeObjectList {
+            count: large_objects.len(),
+            large_objects,
+        })
+    }
+
+    async fn read_large_object(&self, args: ReadLargeObjectArgs) -> ToolResult<LargeObjectChunk> {
+        let offset = args.offset.max(0);
+        let length = args.length.clamp(1, blob::MAX_BLOB_BYTES) as i32;
+
+        let client = self.client.as_ref().unwrap();
+        let row = pooler::query_one(
+            client,
+            "SELECT lo_get($1::int8::oid, $2, $3), \
+                        lo_lseek64(lo_open($1::int8::oid, 262144), 0, 2)",
+            &[
+                (&args.oid, Type::INT8),
+                (&offset, Type::INT8),
+                (&length, Type::INT4),
+            ],
+        )
+        .await
+        .map_err(|e| failed("Failed to read large object", e))?;
+        let data: Vec<u8> = row.get(0);
+        let size: i64 = row.get(1);
+        let end = offset + data.len() as i64;
+
+        Ok(LargeObjectChunk {
+            oid: args.oid,
+            size,
+            offset,
+            bytes_read: data.len(),
+            next_offset: (end < size).then_some(end),
+            // File signatures are only meaningful at the start of the object.
+            mime_type: (offset == 0).then(|| blob::detect_mime_type(&data)),
+            encoding: args.encoding,
+            data: match args.encoding {
+                Encoding::Text => String::from_utf8_lossy(&data).into_owned(),
+                Encoding::Base64 => BASE64_STANDARD.encode(&data),
+            },
+        })
+    }
+
+    async fn import_large_object(
+        &self,
+        args: ImportLargeObjectArgs,
+    ) -> ToolResult<ImportedLargeObject> {
+        if !self.allow_write_ops {
+            return Err(PolicyDenial {
+                rule: "read_only",
+                matched: "import_large_object".to_string(),
+                message: "Creating large objects is a write operation".to_string(),
+                remedy: "Set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.".to_string(),
+            }
+            .into());
+        }
+        let data = match args.encoding {
+            Encoding::Base64 => BASE64_STANDARD.decode(&args.data).map_err(|e| {
+                ToolError::InvalidArguments(format!("'data' is not valid base64: {}", e))
+            })?,
+            Encoding::Text => args.data.into_bytes(),
+        };
+
+        let client = self.client.as_ref().unwrap();
+        let row = pooler::query_one(
+            client,
+            "SELECT lo_from_bytea(0, $1)::int8",
+            &[(&data, Type::BYTEA)],
+        )
+        .await
+        .map_err(|e| failed("Failed to create large object", e))?;
+
+        let oid: i64 = row.get(0);
+        self.report_write(
+            "import_large_object",
+            &format!("import_large_object created large object {}", oid),
+            json!({ "oid": oid, "bytes": data.len() }),
+        );
+        Ok(ImportedLargeObject {
+            oid,
+            size: data.len(),
+            mime_type: blob::detect_mime_type(&data),
+        })
+    }
+
+    async fn export_large_object(
+        &self,
+        args: ExportLargeObjectArgs,
+    ) -> ToolResult<ExportedLargeObject> {
+        if !self.allow_write_ops {
+            return Err(PolicyDenial {
+                rule: "read_only",
+                matched: "export_large_object".to_string(),
+                message: "Exporting large objects writes files on the server".to_string(),
+                remedy: "Set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.".to_string(),
+            }
+            .into());
+        }
+        let Some(export_root) = &self.export_dir else {
+            return Err(ToolError::Failed(EXPORTS_DISABLED.to_string()));
+        };
+        let path = export::resolve_output_dir(export_root, &args.path)
+            .map_err(|e| ToolError::InvalidArguments(e.to_string()))?;
+
+        let client = self.client.as_ref().unwrap();
+        let size = blob::export_large_object(client, args.oid, &path)
+            .await
+            .map_err(|e| ToolError::Failed(format!("Export failed: {:#}", e)))?;
+
+        Ok(ExportedLargeObject {
+            oid: args.oid,
+            path: path.display().to_string(),
+            size,
+        })
+    }
+
+    async fn export_schema_data(
+        &self,
+        args: ExportSchemaDataArgs,
+        progress_token: Option<Value>,
+    ) -> ToolResult<ExportResult> {
+        let Some(export_root) = &self.export_dir else {
+            return Err(ToolError::Failed(EXPORTS_DISABLED.to_string()));
+        };
+        let dir = export::resolve_output_dir(export_root, &args.output_dir)
+            .map_err(|e| ToolError::InvalidArguments(e.to_string()))?;
+        let options = ExportOptions {
+            schema: args.schema,
+            dir,
+            format: args.format,
+            max_rows_per_table: args.max_rows_per_table.max(0),
+            max_bytes: args.max_bytes,
+            access: self.access.clone(),
+        };
+
+        let client = self.client.as_ref().unwrap();
+        // COPY prepares its statement, which a transaction pooler only keeps
+        // on the same server connection within a transaction.
+        let pinned = pooler::transaction_pooling();
+        if pinned {
+            pooler::begin(client, "BEGIN TRANSACTION READ ONLY")
+                .await
+                .map_err(|e| failed("Failed to start a read-only transaction", e))?;
+        }
+        let exported = export::export_schema_data(client, &options, |done, total, message| {
+            if let Some(token) = &progress_token {
+                self.notify(&JsonRpcNotification::progress(
+                    token.clone(),
+                    done,
+                    total,
+                    message,
+                ));
+            }
+        })
+        .await;
+        if pinned {
+            let end = if exported.is_ok() {
+                "COMMIT"
+            } else {
+                "ROLLBACK"
+            };
+            if let Err(e) = client.batch_execute(end).await {
+                error!("Could not end read-only transaction: {}", e);
+            }
+        }
+        let manifest =
+            exported.map_err(|e| ToolError::Failed(format!("Export failed: {:#}", e)))?;
+
+        Ok(ExportResult {
+            output_dir: options.dir.display().to_string(),
+            manifest,
+        })
+    }
+
+    async fn export_query(&mut self, args: ExportQueryArgs) -> ToolResult<SpooledResult> {
+        if !self.spool.enabled() {
+            return Err(ToolError::Failed(
+                "Spooling is disabled. Set SPOOL_MAX_BYTES to the disk space spooled results may use."
+                    .to_string(),
+            ));
+        }
+        if let Some(identity) = &self.identity
+            && let Some(construct) = identity::role_change(&args.sql)
+        {
+            return Err(PolicyDenial {
+                rule: "session_role",
+                matched: construct.to_string(),
+                message: format!(
+                    "Statements that can change the session role are not allowed while running as role '{}'",
+                    identity.role
+                ),
+                remedy: "Unset MCP_PRINCIPAL and ROLE_MAP to run as the connecting user.".to_string(),
+            }
+            .into());
+        }
+        self.check_locking(&args.sql, "export_query")?;
+        self.check_settings(&args.sql, false)?;
+        self.check_server_version(&args.sql)?;
+        self.check_sql_access(&args.sql).await?;
+        let (mut file, budget) =
+            self.spool.reserve("csv").await.map_err(|e| {
+                ToolError::Failed(format!("Failed to create a spool file: {:#}", e))
+            })?;
+
+        let mut writer = self.spool.create(&file).await.map_err(|e| {
+            ToolError::Failed(format!(
+                "Failed to create spool file {}: {}",
+                file.path.display(),
+                e
+            ))
+        })?;
+
+        let client = self.client.as_ref().unwrap();
+        if let Err(e) = pooler::begin(client, "BEGIN TRANSACTION READ ONLY").await {
+            let _ = tokio::fs::remove_file(&file.path).await;
+            return Err(failed("Failed to start a read-only transaction", e));
+        }
+        let started = Instant::now();
+        let result = self
+            .with_timeout(
+                client.cancel_token(),
+                self.query_timeout,
+                export::copy_csv(client, &args.sql, &mut writer, budget),
+            )
+            .await;
+        drop(writer);
+        let elapsed = started.elapsed();
+        self.report_slow_query(&args.sql, elapsed);
+        match &result {
+            Some(Ok(export)) => metrics::record_query(elapsed, export.rows as usize),
+            Some(Err(e)) => metrics::record_query_error(
+                elapsed,
+                e.downcast_ref::<tokio_postgres::Error>()
+                    .and_then(|e| e.code())
+                    .map(SqlState::code),
+            ),
+            None => metrics::record_query_error(elapsed, Some(metrics::QUERY_CANCELED)),
+        }
+        let outcome = match result {
+            Some(Ok(export)) => Ok(export),
+            Some(Err(e)) => Err(match e.downcast::<tokio_postgres::Error>() {
+                Ok(e) if e.code() == Some(&SqlState::READ_ONLY_SQL_TRANSACTION) => PolicyDenial {
+                    rule: "read_only",
+                    matched: db_error_message(&e),
+                    message: "export_query only runs read-only queries".to_string(),
+                    remedy: "Run statements that write with the query tool.".to_string(),
+                }
+                .into(),
+                Ok(e) => query_error(e).after_prefix(export::COPY_PREFIX),
+                Err(e) => ToolError::Failed(format!("Spooling failed: {:#}", e)),
+            }),
+            None => Err(timed_out(self.query_timeout)),
+        };
+        let export = match outcome {
+            Ok(export) => export,
+            Err(e) => {
+                if let Err(e) = client.batch_execute("ROLLBACK").await {
+                    error!("Could not end read-only transaction: {}", e);
+                }
+                let _ = tokio::fs::remove_file(&file.path).await;
+                return Err(e);
+            }
+        };
+        if export.truncated {
+            // The rest of the result is still on its way; closing the
+            // connection stops it instead of reading it only to discard it.
+            if let Some(client) = self.client.take() {
+                drop(Object::take(client));
+            }
+        } else if let Err(e) = client.batch_execute("COMMIT").await {
+            error!("Could not end read-only transaction: {}", e);
+        }
+
+        file.bytes = export.bytes;
+        let result = SpooledResult {
+            uri: file.uri(),
+            file_uri: (!self.spool.encrypted()).then(|| file.file_uri()),
+            mime_type: "text/csv",
+            rows: export.rows,
+            bytes: export.bytes,
+            truncated: export.truncated,
+        };
+        self.spool.add(file);
+        Ok(result)
+    }
+
+    async fn materialize_result(
+        &mut self,
+        args: MaterializeResultArgs,
+    ) -> ToolResult<MaterializedResultInfo> {
+        if !self.results.enabled() {
+            return Err(ToolError::Failed(
+                "Materializing is disabled. Set MATERIALIZE_MAX_BYTES to the memory materialized results may use."
+                    .to_string(),
+            ));
+        }
+        if !(1..=MAX_MATERIALIZE_TTL_SECS).contains(&args.ttl_secs) {
+            return Err(ToolError::InvalidArguments(format!(
+                "ttl_secs must be between 1 and {}",
+                MAX_MATERIALIZE_TTL_SECS
+            )));
+        }
+        if let Some(identity) = &self.identity
+            && let Some(construct) = identity::role_change(&args.sql)
+        {
+            return Err(PolicyDenial {
+                rule: "session_role",
+                matched: construct.to_string(),
+                message: format!(
+                    "Statements that can change the session role are not allowed while running as role '{}'",
+                    identity.role
+                ),
+                remedy: "Unset MCP_PRINCIPAL and ROLE_MAP to run as the connecting user.".to_string(),
+            }
+            .into());
+        }
+        self.check_locking(&args.sql, "materialize_result")?;
+        self.check_settings(&args.sql, false)?;
+        self.check_server_version(&args.sql)?;
+        self.check_sql_access(&args.sql).await?;
+
+        let client = self.client.as_ref().unwrap();
+        let started = Instant::now();
+        let statement = match Prepared::new(client, &args.sql).await {
+            Ok(statement) => statement,
+            Err(e) => {
+                metrics::record_query_error(started.elapsed(), e.code().map(SqlState::code));
+                return Err(statement_error(
+                    format!("Query execution error: {}", db_error_message(&e)),
+                    &e,
+                ));
+            }
+        };
+        if !statement.returns_rows() {
+            return Err(ToolError::InvalidArguments(
+                "materialize_result only keeps the results of queries that return rows".to_string(),
+            ));
+        }
+        if let Some(types) = statement.params()
+            && types.len() != args.params.len()
+        {
+            return Err(ToolError::InvalidArguments(format!(
+                "The query has {} parameter(s) but {} value(s) were given in params",
+                types.len(),
+                args.params.len()
+            )));
+        }
+        let params = statement.text_params(&args.params);
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+
+        pooler::begin(client, "BEGIN TRANSACTION READ ONLY")
+            .await
+            .map_err(|e| failed("Failed to start a read-only transaction", e))?;
+        let max_bytes = self.results.max_bytes();
+        let result = self
+            .with_timeout(
+                client.cancel_token(),
+                self.query_timeout,
+                results::collect_rows(client, &statement, &params, max_bytes),
+            )
+            .await;
+        let elapsed = started.elapsed();
+        self.report_slow_query(&args.sql, elapsed);
+        match &result {
+            Some(Ok(Some((rows, _, _)))) => metrics::record_query(elapsed, rows.len()),
+            Some(Ok(None)) => metrics::record_query(elapsed, 0),
+            Some(Err(e)) => metrics::record_query_error(elapsed, e.code().map(SqlState::code)),
+            None => metrics::record_query_error(elapsed, Some(metrics::QUERY_CANCELED)),
+        }
+        let oversized = matches!(result, Some(Ok(None)));
+        let outcome = match result {
+            Some(Ok(Some(collected))) => Ok(collected),
+            Some(Ok(None)) => Err(ToolError::Failed(format!(
+                "The result is larger than MATERIALIZE_MAX_BYTES ({} bytes). Narrow the query, or write it to a file with export_query.",
+                max_bytes
+            ))),
+            Some(Err(e)) if e.code() == Some(&SqlState::READ_ONLY_SQL_TRANSACTION) => {
+                Err(PolicyDenial {
+                    rule: "read_only",
+                    matched: db_error_message(&e),
+                    message: "materialize_result only runs read-only queries".to_string(),
+                    remedy: "Run statements that write with the query tool.".to_string(),
+                }
+                .into())
+            }
+            Some(Err(e)) => Err(query_error(e)),
+            None => Err(timed_out(self.query_timeout)),
+        };
+        let (rows, columns, bytes) = match outcome {
+            Ok(collected) => collected,
+            Err(e) => {
+                // The rest of an oversized result is still on its way; closing
+                // the connection stops it instead of reading it only to
+                // discard it.
+                if oversized {
+                    if let Some(client) = self.client.take() {
+                        drop(Object::take(client));
+                    }
+                } else if let Err(e) = self
+                    .client
+                    .as_ref()
+                    .unwrap()
+                    .batch_execute("ROLLBACK")
+                    .await
+                {
+                    error!("Could not end read-only transaction: {}", e);
+                }
+                return Err(e);
+            }
+        };
+        let client = self.client.as_ref().unwrap();
+        if let Err(e) = client.batch_execute("COMMIT").await {
+            error!("Could not end read-only transaction: {}", e);
+        }
+
+        let row_count = rows.len();
+        let uri = self
+            .results
+            .add(
+                columns.clone(),
+                rows,
+                bytes,
+                Duration::from_secs(args.ttl_secs),
+            )
+            .map_err(|e| ToolError::Failed(format!("{:#}", e)))?;
+        Ok(MaterializedResultInfo {
+            uri,
+            rows: row_count,
+            columns,
+            bytes,
+            ttl_secs: args.ttl_secs,
+        })
+    }
+
+    async fn session_time_info(&self) -> ToolResult<SessionTimeInfo> {
+        let client = self.client.as_ref().unwrap();
+        // Dates and times are formatted explicitly so DateStyle cannot change
+        // how they read.
+        let row = pooler::query_one(
+            client,
+            "SELECT current_setting('TimeZone'), \
+                        (SELECT reset_val FROM pg_settings WHERE name = 'TimeZone'), \
+                        extract(timezone FROM now())::int4, \
+                        to_char(now(), 'YYYY-MM-DD\"T\"HH24:MI:SS.MS'), \
+                        to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS.MS\"Z\"'), \
+                        to_char(current_date, 'YYYY-MM-DD'), \
+                        to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD')",
+            &[],
+        )
+        .await
+        .map_err(|e| failed("Failed to read session time", e))?;
+
+        let utc_offset_seconds: i32 = row.get(2);
+        let utc_offset = format!(
+            "{}{:02}:{:02}",
+            if utc_offset_seconds < 0 { '-' } else { '+' },
+            utc_offset_seconds.abs() / 3600,
+            utc_offset_seconds.abs() % 3600 / 60
+        );
+        Ok(SessionTimeInfo {
+            timezone: row.get(0),
+            default_timezone: row.get(1),
+            server_time: format!("{}{}", row.get::<_, String>(3), utc_offset),
+            utc_offset,
+            utc_offset_seconds,
+            utc_time: row.get(4),
+            current_date: row.get(5),
+            utc_date: row.get(6),
+        })
+    }
+
+    async fn set_session_timezone(
+        &mut self,
+        args: SetSessionTimezoneArgs,
+    ) -> ToolResult<SessionTimeInfo> {
+        if pooler::transaction_pooling() {
+            return Err(ToolError::Failed(
+                "The session time zone cannot be changed with DB_POOLER=pgbouncer, since the setting would stay with the server connection for other clients of the pooler; set DB_TIMEZONE instead".to_string(),
+            ));
+        }
+        let client = self.client.as_ref().unwrap();
+        pooler::execute(
+            client,
+            "SELECT set_config('TimeZone', $1, false)",
+            &[(&args.timezone, Type::TEXT)],
+        )
+        .await
+        .map_err(|e| {
+            ToolError::InvalidArguments(format!(
+                "Invalid time zone '{}': {}",
+                args.timezone,
+                db_error_message(&e)
+            ))
+        })?;
+        // Applied to every connection checked out from now on.
+        *self
+            .session_timezone
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(args.timezone);
+        self.session_time_info().await
+    }
+
+    async fn describe_table(&self, args: DescribeTableArgs) -> ToolResult<TableDescription> {
+        let DescribeTableArgs { table, schema } = args;
+        self.check_table_access(&schema, &table)?;
+        self.describe_relations(&[(schema.clone(), table.clone())])
+            .await?
+            .pop()
+            .ok_or_else(|| {
+                ToolError::InvalidArguments(format!("Table '{}.{}' not found", schema, table))
+            })
+    }
+
+    /// Describes those of `relations`, given by schema and name, that exist:
+    /// from the catalog cache where it has them, and otherwise from the
+    /// system catalogs.
+    pub(crate) async fn describe_relations(
+        &self,
+        relations: &[(String, String)],
+    ) -> ToolResult<Vec<TableDescription>> {
+        let catalog = Catalog::shared();
+        let mut descriptions = Vec::new();
+        let mut missing = Vec::new();
+        for (schema, table) in relations {
+            match catalog.and_then(|c| c.get(schema, table)) {
+                Some(description) => descriptions.push(description),
+                None => missing.push(format!("{}.{}", quote_ident(schema), quote_ident(table))),
+            }
+        }
+        if !missing.is_empty() {
+            let client = self.client.as_ref().unwrap();
+            descriptions.extend(
+                catalog::describe_tables(client, &missing)
+                    .await
+                    .map_err(|e| failed("Failed to describe table", e))?,
+            );
+        }
+        Ok(descriptions)
+    }
+
+    /// The plan PostgreSQL would choose for `sql`, without running it.
+    pub(crate) async fn estimated_plan(&self, sql: &str) -> ToolResult<Value> {
+        let result = self
+            .explain(ExplainArgs {
+                sql: sql.to_string(),
+                params: Vec::new(),
+                analyze: false,
+                buffers: false,
+                verbose: false,
+            })
+            .await?;
+        Ok(result.plan)
+    }
+
+    async fn insert_rows(&self, args: InsertRowsArgs) -> ToolResult<InsertResult> {
+        if !self.allow_write_ops {
+            return Err(PolicyDenial {
+                rule: "read_only",
+                matched: "insert_rows".to_string(),
+                message: "Inserting rows is a write operation".to_string(),
+                remedy: "Set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.".to_string(),
+            }
+            .into());
+        }
+        let InsertRowsArgs {
+            table,
+            schema,
+            rows,
+            on_conflict,
+            batch_size,
+        } = args;
+        self.check_table_access(&schema, &table)?;
+        let client = self.client.as_ref().unwrap();
+        let qualified = format!("{}.{}", quote_ident(&schema), quote_ident(&table));
+        let columns = insert_columns(
+            client,
+            &schema,
+            &table,
+            &rows,
+            &on_conflict
+                .iter()
+                .flat_map(|c| c.columns.iter().chain(c.update_columns.iter().flatten()))
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+        let conflict_clause = match &on_conflict {
+            None => String::new(),
+            Some(conflict) => {
+                let target = if conflict.columns.is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        " ({})",
+                        conflict
+                            .columns
+                            .iter()
+                            .map(|c| quote_ident(c))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                };
+                match conflict.action {
+                    ConflictAction::Nothing => format!(" ON CONFLICT{} DO NOTHING", target),
+                    ConflictAction::Update => {
+                        if conflict.columns.is_empty() {
+                            return Err(ToolError::InvalidArguments(
+                                "'on_conflict.columns' is required for the 'update' action"
+                                    .to_string(),
+                            ));
+                        }
+                        let update_columns: Vec<&String> = match &conflict.update_columns {
+                            Some(update_columns) => update_columns.iter().collect(),
+                            None => columns
+                                .iter()
+                                .filter(|c| !conflict.columns.contains(c))
+                                .collect(),
+                        };
+                        if update_columns.is_empty() {
+                            return Err(ToolError::InvalidArguments(
+                                "No columns to update on conflict; set 'on_conflict.update_columns' or use the 'nothing' action"
+                                    .to_string(),
+                            ));
+                        }
+                        format!(
+                            " ON CONFLICT{} DO UPDATE SET {}",
+                            target,
+                            update_columns
+                                .iter()
+                                .map(|c| format!("{0} = EXCLUDED.{0}", quote_ident(c)))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )
+                    }
+                }
+            }
+        };
+
+        let batches: Vec<InsertBatch> = write_batches(
+            client,
+            &qualified,
+            &columns,
+            &rows,
+            batch_size,
+            &conflict_clause,
+            "Insert",
+        )
+        .await?
+        .into_iter()
+        .map(|batch| InsertBatch {
+            first_row: batch.first_row,
+            rows: batch.rows,
+            affected: batch.affected,
+        })
+        .collect();
+
+        let affected = batches.iter().map(|b| b.affected).sum();
+        let skipped = match on_conflict.map(|c| c.action) {
+            Some(ConflictAction::Nothing) => rows.len() as u64 - affected,
+            _ => 0,
+        };
+        self.report_write(
+            "insert_rows",
+            &format!(
+                "insert_rows inserted {} row(s) into {}.{}",
+                affected, schema, table
+            ),
+            json!({ "table": format!("{}.{}", schema, table), "rows": affected }),
+        );
+        Ok(InsertResult {
+            table: format!("{}.{}", schema, table),
+            affected,
+            skipped,
+            batches,
+        })
+    }
+
+    async fn upsert_rows(&self, args: UpsertRowsArgs) -> ToolResult<UpsertResult> {
+        if !self.allow_write_ops {
+            return Err(PolicyDenial {
+                rule: "read_only",
+                matched: "upsert_rows".to_string(),
+                message: "Upserting rows is a write operation".to_string(),
+                remedy: "Set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.".to_string(),
+            }
+            .into());
+        }
+        let UpsertRowsArgs {
+            table,
+            schema,
+            key_columns,
+            rows,
+            update_columns,
+            batch_size,
+            method,
+        } = args;
+        self.check_table_access(&schema, &table)?;
+        if key_columns.is_empty() {
+            return Err(ToolError::InvalidArguments(
+                "'key_columns' must name at least one column".to_string(),
+            ));
+        }
+        let client = self.client.as_ref().unwrap();
+        let qualified = format!("{}.{}", quote_ident(&schema), quote_ident(&table));
+        let columns = insert_columns(
+            client,
+            &schema,
+            &table,
+            &rows,
+            &key_columns
+                .iter()
+                .chain(update_columns.iter().flatten())
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+        // ON CONFLICT needs a unique index on exactly the key columns to
+        // detect conflicts with; MERGE joins on them instead.
+        let unique_keys: Vec<Vec<String>> = pooler::query(
+            client,
+            "SELECT array_agg(a.attname::text ORDER BY k.ord)
+                 FROM pg_index i
+                 CROSS JOIN LATERAL unnest(i.indkey) WITH ORDINALITY AS k(attnum, ord)
+                 JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = k.attnum
+                 WHERE i.indrelid = $1::text::regclass
+                   AND i.indisunique AND i.indimmediate AND i.indpred IS NULL
+                   AND i.indexprs IS NULL
+                 GROUP BY i.indexrelid
+                 ORDER BY i.indisprimary DESC, i.indexrelid",
+            &[(&qualified, Type::TEXT)],
+        )
+        .await
+        .map_err(|e| failed("Failed to read unique constraints", e))?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+        let matches_key = |key: &Vec<String>| {
+            key.len() == key_columns.len() && key.iter().all(|c| key_columns.contains(c))
+        };
+        let has_unique_key = unique_keys.iter().any(matches_key);
+        let lacks_merge = self.server_version.filter(|v| !v.has(Feature::Merge));
+        let method = match method {
+            UpsertMethod::Auto if has_unique_key || lacks_merge.is_some() => {
+                UpsertMethod::OnConflict
+            }
+            UpsertMethod::Auto => UpsertMethod::Merge,
+            method => method,
+        };
+        if method == UpsertMethod::Merge
+            && let Some(version) = lacks_merge
+        {
+            return Err(ToolError::InvalidArguments(format!(
+                "{}: use method 'on_conflict' with a unique constraint on the key columns",
+                version.lacks(Feature::Merge)
+            )));
+        }
+        if method == UpsertMethod::OnConflict && !has_unique_key {
+            let existing = if unique_keys.is_empty() {
+                "It has no unique constraints".to_string()
+            } else {
+                format!(
+                    "Its unique keys are {}",
+                    unique_keys
+                        .iter()
+                        .map(|key| format!("({})", key.join(", ")))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            };
+            return Err(ToolError::InvalidArguments(format!(
+                "No unique constraint or index on {}.{} has exactly the key columns ({}). {}: use one of those as key_columns, or add a constraint with ALTER TABLE {} ADD UNIQUE ({})",
+                schema,
+                table,
+                key_columns.join(", "),
+                existing,
+                qualified,
+                key_columns
+                    .iter()
+                    .map(|c| quote_ident(c))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+
+        // A key missing from a row would be filled by its default, and a key
+        // given twice would make PostgreSQL update the same row twice.
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for (index, row) in rows.iter().enumerate() {
+            let key: Vec<&Value> = key_columns.iter().filter_map(|c| row.get(c)).collect();
+            if key.len() < key_columns.len() {
+                return Err(ToolError::InvalidArguments(format!(
+                    "Row {} is missing a value for a key column; every row needs {}",
+                    index,
+                    key_columns.join(", ")
+                )));
+            }
+            if let Some(first) = seen.insert(json!(key).to_string(), index) {
+                return Err(ToolError::InvalidArguments(format!(
+                    "Rows {} and {} have the same key; give each key once",
+                    first, index
+                )));
+            }
+        }
+
+        let update_columns: Vec<&String> = match &update_columns {
+            Some(update_columns) => update_columns.iter().collect(),
+            None => columns
+                .iter()
+                .filter(|c| !key_columns.contains(c))
+                .collect(),
+        };
+        if update_columns.is_empty() {
+            return Err(ToolError::InvalidArguments(
+                "No columns to update: the rows give only key columns. Use insert_rows with on_conflict 'nothing' to skip existing rows"
+                    .to_string(),
+            ));
+        }
+        if method == UpsertMethod::Merge {
+            let types: HashMap<String, String> = column_types(client, &qualified)
+                .await
+                .map_err(|e| failed("Failed to read column types", e))?
+                .into_iter()
+                .collect();
+            let merge = MergeUpsert {
+                qualified: &qualified,
+                columns: columns
+                    .iter()
+                    .map(|c| (c, types.get(c).map_or("text", String::as_str)))
+                    .collect(),
+                key_columns: &key_columns,
+                update_columns: &update_columns,
+            };
+            let batches = merge.write(client, &rows, batch_size).await?;
+            return Ok(self.upserted(schema, table, method, batches));
+        }
+
+        // xmax is zero only in row versions created by an insert.
+        let upsert_clause = format!(
+            " ON CONFLICT ({}) DO UPDATE SET {} RETURNING (xmax = 0)",
+            key_columns
+                .iter()
+                .map(|c| quote_ident(c))
+                .collect::<Vec<_>>()
+                .join(", "),
+            update_columns
+                .iter()
+                .map(|c| format!("{0} = EXCLUDED.{0}", quote_ident(c)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let batches: Vec<UpsertBatch> = write_batches(
+            client,
+            &qualified,
+            &columns,
+            &rows,
+            batch_size,
+            &upsert_clause,
+            "Upsert",
+        )
+        .await?
+        .into_iter()
+        .map(|batch| {
+            let inserted = batch
+                .returned
+                .iter()
+                .filter(|row| row.get::<_, bool>(0))
+                .count() as u64;
+            UpsertBatch {
+                first_row: batch.first_row,
+                rows: batch.rows,
+                inserted,
+                updated: batch.affected - inserted,
+            }
+        })
+        .collect();
+        Ok(self.upserted(schema, table, method, batches))
+    }
+
+    /// Reports the write `upsert_rows` made and builds its result.
+    fn upserted(
+        &self,
+        schema: String,
+        table: String,
+        method: UpsertMethod,
+        batches: Vec<UpsertBatch>,
+    ) -> UpsertResult {
+        let result = UpsertResult {
+            table: format!("{}.{}", schema, table),
+            method,
+            inserted: batches.iter().map(|b| b.inserted).sum(),
+            updated: batches.iter().map(|b| b.updated).sum(),
+            batches,
+        };
+        self.report_write(
+            "upsert_rows",
+            &format!(
+                "upsert_rows wrote {} row(s) to {}",
+                result.inserted + result.updated,
+                result.table
+            ),
+            json!({ "table": result.table, "rows": result.inserted + result.updated }),
+        );
+        result
+    }
+
+    async fn update_rows(&self, args: UpdateRowsArgs) -> ToolResult<UpdateResult> {
+        if !self.allow_write_ops {
+            return Err(PolicyDenial {
+                rule: "read_only",
+                matched: "update_rows".to_string(),
+                message: "Updating rows is a write operation".to_string(),
+                remedy: "Set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.".to_string(),
+            }
+            .into());
+        }
+        let UpdateRowsArgs {
+            table,
+            schema,
+            key,
+            filter,
+            set,
+            expected_values,
+            version_column,
+        } = args;
+        self.check_table_access(&schema, &table)?;
+        check_row_filter(&key, &filter)?;
+        if set.is_empty() {
+            return Err(ToolError::InvalidArguments(
+                "'set' must give at least one column a value".to_string(),
+            ));
+        }
+        if let Some(version_column) = &version_column {
+            if set.contains_key(version_column) {
+                return Err(ToolError::InvalidArguments(format!(
+                    "Version column '{}' is incremented automatically; leave it out of 'set'",
+                    version_column
+                )));
+            }
+            if !expected_values.contains_key(version_column) {
+                return Err(ToolError::InvalidArguments(format!(
+                    "Give the value of version column '{}' as read in expected_values",
+                    version_column
+                )));
+            }
+        }
+
+        let client = self.client.as_ref().unwrap();
+        let qualified = format!("{}.{}", quote_ident(&schema), quote_ident(&table));
+        let table_columns: Vec<String> = column_types(client, &qualified)
+            .await
+            .map_err(|e| failed("Failed to read columns", e))?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        if table_columns.is_empty() {
+            return Err(ToolError::InvalidArguments(format!(
+                "Table '{}.{}' not found",
+                schema, table
+            )));
+        }
+        let unknown = key
+            .keys()
+            .chain(filter.iter().map(|c| &c.column))
+            .chain(set.keys())
+            .chain(expected_values.keys())
+            .chain(version_column.iter())
+            .find(|c| !table_columns.contains(c));
+        if let Some(unknown) = unknown {
+            return Err(ToolError::InvalidArguments(format!(
+                "Column '{}' not found in {}.{}. Available columns: {}",
+                unknown,
+                schema,
+                table,
+                table_columns.join(", ")
+            )));
+        }
+
+        let mut values: Vec<&Value> = Vec::new();
+        let mut bind = |value| {
+            values.push(value);
+            format!("${}", values.len())
+        };
+        let mut assignments: Vec<String> = set
+            .iter()
+            .map(|(column, value)| format!("{} = {}", quote_ident(column), bind(value)))
+            .collect();
+        if let Some(version_column) = &version_column {
+            let column = quote_ident(version_column);
+            assignments.push(format!("{} = {} + 1", column, column));
+        }
+        let key_filter = row_filter(&key, &filter, &mut bind);
+        // IS NOT DISTINCT FROM, so that an expected NULL matches a NULL.
+        let expected_filter: Vec<String> = expected_values
+            .iter()
+            .map(|(column, value)| {
+                format!(
+                    "{} IS NOT DISTINCT FROM {}",
+                    quote_ident(column),
+                    bind(value)
+                )
+            })
+            .collect();
+        let sql = format!(
+            "UPDATE {} SET {} WHERE {}",
+            qualified,
+            assignments.join(", "),
+            key_filter
+                .iter()
+                .chain(&expected_filter)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" AND ")
+        );
+        let update_failed = |e: tokio_postgres::Error| {
+            statement_error(format!("Update failed: {}", db_error_message(&e)), &e)
+        };
+        let statement = Prepared::new(client, &sql).await.map_err(update_failed)?;
+        let params = statement.text_params(values.iter().copied());
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+        let updated = statement
+            .execute(client, &params)
+            .await
+            .map_err(update_failed)?;
+
+        let mut result = UpdateResult {
+            table: format!("{}.{}", schema, table),
+            status: UpdateStatus::Updated,
+            updated,
+            current: Vec::new(),
+        };
+        if updated > 0 {
+            self.report_write(
+                "update_rows",
+                &format!("update_rows updated {} row(s) of {}", updated, result.table),
+                json!({ "table": result.table, "rows": updated }),
+            );
+            return Ok(result);
+        }
+        // Nothing updated: tell a changed row from a missing one.
+        let mut values: Vec<&Value> = Vec::new();
+        let key_filter = row_filter(&key, &filter, &mut |value| {
+            values.push(value);
+            format!("${}", values.len())
+        });
+        let sql = format!(
+            "SELECT * FROM {} WHERE {} LIMIT 10",
+            qualified,
+            key_filter.join(" AND ")
+        );
+        let statement = Prepared::new(client, &sql)
+            .await
+            .map_err(|e| failed("Failed to read the rows", e))?;
+        let params = statement.text_params(values.iter().copied());
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+        result.current = statement
+            .query(client, &params)
+            .await
+            .map_err(|e| failed("Failed to read the rows", e))?
+            .iter()
+            .map(row_to_map)
+            .collect();
+        result.status = if result.current.is_empty() {
+            UpdateStatus::NotFound
+        } else {
+            UpdateStatus::Conflict
+        };
+        Ok(result)
+    }
+
+    async fn delete_rows(&self, args: DeleteRowsArgs) -> ToolResult<DeleteResult> {
+        if !self.allow_write_ops {
+            return Err(PolicyDenial {
+                rule: "read_only",
+                matched: "delete_rows".to_string(),
+                message: "Deleting rows is a write operation".to_string(),
+                remedy: "Set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.".to_string(),
+            }
+            .into());
+        }
+        let DeleteRowsArgs {
+            table,
+            schema,
+            key,
+            filter,
+            max_rows,
+            returning,
+        } = args;
+        self.check_table_access(&schema, &table)?;
+        check_row_filter(&key, &filter)?;
+        if max_rows < 1 {
+            return Err(ToolError::InvalidArguments(
+                "max_rows must be at least 1".to_string(),
+            ));
+        }
+
+        let client = self.client.as_ref().unwrap();
+        let qualified = format!("{}.{}", quote_ident(&schema), quote_ident(&table));
+        let table_columns: Vec<String> = column_types(client, &qualified)
+            .await
+            .map_err(|e| failed("Failed to read columns", e))?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        if table_columns.is_empty() {
+            return Err(ToolError::InvalidArguments(format!(
+                "Table '{}.{}' not found",
+                schema, table
+            )));
+        }
+        let unknown = key
+            .keys()
+            .chain(filter.iter().map(|c| &c.column))
+            .find(|c| !table_columns.contains(c));
+        if let Some(unknown) = unknown {
+            return Err(ToolError::InvalidArguments(format!(
+                "Column '{}' not found in {}.{}. Available columns: {}",
+                unknown,
+                schema,
+                table,
+                table_columns.join(", ")
+            )));
+        }
+
+        let mut values: Vec<&Value> = Vec::new();
+        let conditions = row_filter(&key, &filter, &mut |value| {
+            values.push(value);
+            format!("${}", values.len())
+        });
+        let sql = format!(
+            "DELETE FROM {} WHERE {} RETURNING *",
+            qualified,
+            conditions.join(" AND ")
+        );
+        let delete_failed = |e: tokio_postgres::Error| {
+            statement_error(format!("Delete failed: {}", db_error_message(&e)), &e)
+        };
+        let statement = Prepared::new(client, &sql).await.map_err(delete_failed)?;
+        let params = statement.text_params(values.iter().copied());
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+
+        // Deleted in a transaction, to be rolled back if too many rows match.
+        pooler::begin(client, "BEGIN")
+            .await
+            .map_err(|e| failed("Failed to start transaction", e))?;
+        let rows = match statement.query(client, &params).await {
+            Ok(rows) if rows.len() as i64 <= max_rows => rows,
+            outcome => {
+                if let Err(e) = client.batch_execute("ROLLBACK").await {
+                    error!("Could not roll back delete_rows: {}", e);
+                }
+                return Err(match outcome {
+                    Ok(rows) => ToolError::InvalidArguments(format!(
+                        "{} rows match, more than max_rows ({}), so none were deleted. Narrow the key or filter, or raise max_rows",
+                        rows.len(),
+                        max_rows
+                    )),
+                    Err(e) => delete_failed(e),
+                });
+            }
+        };
+        client
+            .batch_execute("COMMIT")
+            .await
+            .map_err(|e| failed("Failed to commit", e))?;
+
+        let result = DeleteResult {
+            table: format!("{}.{}", schema, table),
+            deleted: rows.len() as u64,
+            rows: returning.then(|| rows.iter().map(row_to_map).collect()),
+        };
+        if result.deleted > 0 {
+            self.report_write(
+                "delete_rows",
+                &format!(
+                    "delete_rows deleted {} row(s) of {}",
+                    result.deleted, result.table
+                ),
+                json!({ "table": result.table, "rows": result.deleted }),
+            );
+        }
+        Ok(result)
+    }
+
+    /// Loads the fixture files in `dir` in one transaction, for the
+    /// `load_fixtures` tool and the `load-fixtures` command.
+    pub(crate) async fn load_fixtures(
+        &self,
+        dir: &Path,
+        schema: &str,
+        keep_existing: bool,
+    ) -> ToolResult<FixturesReport> {
+        if !self.allow_write_ops {
+            return Err(PolicyDenial {
+                rule: "read_only",
+                matched: "load_fixtures".to_string(),
+                message: "Loading fixtures is a write operation".to_string(),
+                remedy: "Set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.".to_string(),
+            }
+            .into());
+        }
+        if let Some(environment) = self.environment
+            && environment == Environment::Production
+        {
+            return Err(PolicyDenial {
+                rule: "environment",
+                matched: environment.name().to_string(),
+                message: "Fixtures replace the data of the tables they load, and this is a production database"
+                    .to_string(),
+                remedy: "Load fixtures into a development database.".to_string(),
+            }
+            .into());
+        }
+        let (files, skipped) = fixtures::discover(dir, schema)
+            .await
+            .map_err(|e| ToolError::InvalidArguments(format!("{:#}", e)))?;
+        if files.is_empty() {
+            return Err(ToolError::InvalidArguments(format!(
+                "{} holds no fixture files (.sql, .csv, .json, or .yaml)",
+                dir.display()
+            )));
+        }
+        let mut contents = Vec::new();
+        for file in &files {
+            if let Some((schema, table)) = &file.table {
+                self.check_table_access(schema, table)?;
+            }
+            contents.push(
+                file.read()
+                    .await
+                    .map_err(|e| ToolError::InvalidArguments(format!("{:#}", e)))?,
+            );
+        }
+
+        let client = self.client.as_ref().unwrap();
+        pooler::begin(client, "BEGIN")
+            .await
+            .map_err(|e| failed("Failed to start transaction", e))?;
+        let loaded = self
+            .with_timeout(
+                client.cancel_token(),
+                self.query_timeout,
+                load_fixture_files(client, &files, contents, keep_existing),
+            )
+            .await;
+        let (loaded, truncated, sequences_reset) = match loaded {
+            Some(Ok(loaded)) => loaded,
+            outcome => {
+                if let Err(e) = client.batch_execute("ROLLBACK").await {
+                    error!("Could not roll back fixtures: {}", e);
+                }
+                return Err(match outcome {
+                    Some(Err(e)) => e,
+                    _ => timed_out(self.query_timeout),
+                });
+            }
+        };
+        client
+            .batch_execute("COMMIT")
+            .await
+            .map_err(|e| failed("Failed to commit", e))?;
+
+        let rows: u64 = loaded.iter().filter_map(|f| f.rows).sum();
+        self.report_write(
+            "load_fixtures",
+            &format!(
+                "load_fixtures loaded {} file(s) with {} row(s) from {}",
+                loaded.len(),
+                rows,
+                dir.display()
+            ),
+            json!({ "dir": dir.display().to_string(), "files": loaded.len(), "rows": rows }),
+        );
+        Ok(FixturesReport {
+            dir: dir.display().to_string(),
+            files: loaded,
+            truncated,
+            sequences_reset,
+            skipped,
+        })
+    }
+
+    /// Copies the database with the provider `BRANCH_PROVIDER` names.
+    async fn create_branch(&mut self, args: CreateBranchArgs) -> ToolResult<BranchInfo> {
+        let Some(provider) = self.branching.clone() else {
+            return Err(ToolError::Failed(
+                "Branching is disabled. Set BRANCH_PROVIDER to 'neon' or 'template' to enable it."
+                    .to_string(),
+            ));
+        };
+        let client = self.client.as_ref().unwrap();
+        let database: String = pooler::query_one(client, "SELECT current_database()::text", &[])
+            .await
+            .map_err(|e| failed("Failed to look up the database", e))?
+            .get(0);
+        let name = match args.name.filter(|n| !n.is_empty()) {
+            Some(name) => name,
+            None => format!(
+                "{}_branch_{}",
+                database,
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs())
+            ),
+        };
+        let config = conninfo::parse(&self.database_url)
+            .map_err(|e| ToolError::Failed(format!("{:#}", e)))?;
+        let sslmode = self.tls_settings.mode.name();
+
+        let neon = match &provider {
+            BranchProvider::Neon(neon) => neon,
+            BranchProvider::Template => {
+                self.copy_database(&database, &name).await?;
+                info!("create_branch copied database {} to {}", database, name);
+                return Ok(BranchInfo {
+                    provider: provider.name(),
+                    database_url: branch::masked_url(&config, None, &name, sslmode),
+                    database: name.clone(),
+                    name,
+                    branch_id: None,
+                });
+            }
+        };
+        let created = neon
+            .create_branch(&name)
+            .await
+            .map_err(|e| ToolError::Failed(format!("{:#}", e)))?;
+        info!(
+            "create_branch created Neon branch {} ({})",
+            created.name, created.id
+        );
+        Ok(BranchInfo {
+            provider: provider.name(),
+            database_url: branch::masked_url(&config, Some(&created.host), &database, sslmode),
+            database,
+            name: created.name,
+            branch_id: Some(created.id),
+        })
+    }
+
+    /// Creates the database `name` with `database` as its template, from a
+    /// connection to the maintenance database, after closing this server's
+    /// idle connections to `database`.
+    async fn copy_database(&mut self, database: &str, name: &str) -> ToolResult<()> {
+        if name.len() > branch::MAX_DATABASE_NAME_BYTES {
+            return Err(ToolError::InvalidArguments(format!(
+                "'{}' is longer than PostgreSQL's {} byte limit for database names",
+                name,
+                branch::MAX_DATABASE_NAME_BYTES
+            )));
+        }
+        self.client = None;
+        self.pool.retain(|_, _| false);
+
+        let mut config = connection_config(&self.database_url, &self.tls_settings)
+            .map_err(|e| ToolError::Failed(format!("{:#}", e)))?;
+        config.dbname(branch::MAINTENANCE_DATABASE);
+        let maintenance = pool::build_pool(
+            config,
+            self.tls.clone(),
+            &self.pool_settings.with_max_size(1),
+            self.identity.clone(),
+        )
+        .map_err(|e| ToolError::Failed(format!("{:#}", e)))?;
+        let client = maintenance.get().await.map_err(|e| {
+            ToolError::Failed(format!(
+                "Cannot connect to the {} database to copy {}: {:#}",
+                branch::MAINTENANCE_DATABASE,
+                database,
+                pool::checkout_error(e)
+            ))
+        })?;
+        let sql = format!(
+            "CREATE DATABASE {} TEMPLATE {}",
+            quote_ident(name),
+            quote_ident(database)
+        );
+        match self
+            .with_timeout(client.cancel_token(), None, client.batch_execute(&sql))
+            .await
+        {
+            Some(Ok(())) => Ok(()),
+            Some(Err(e)) if e.code() == Some(&SqlState::OBJECT_IN_USE) => {
+                let sessions: i64 = pooler::query_one(
+                    &client,
+                    "SELECT count(*) FROM pg_stat_activity WHERE datname = $1",
+                    &[(&database, Type::TEXT)],
+                )
+                .await
+                .map_or(0, |row| row.get(0));
+                Err(ToolError::Failed(format!(
+                    "PostgreSQL copies a database only while no other session is connected to it, and {} session(s) are connected to {}. Close them and try again, or branch with BRANCH_PROVIDER=neon.",
+                    sessions, database
+                )))
+            }
+            Some(Err(e)) => Err(failed("Failed to copy the database", e)),
+            None => Err(timed_out(None)),
+        }
+    }
+
+    /// Runs EXPLAIN on the statement in a transaction that is rolled back,
+    /// read-only unless write operations are enabled.
+    async fn begin_transaction(
+        &mut self,
+        args: BeginTransactionArgs,
+    ) -> ToolResult<TransactionInfo> {
+        // Leave a connection for requests outside transactions.
+        let limit = self.pool_settings.max_size - 1;
+        let open = self.transactions.len();
+        if open >= limit {
+            return Err(ToolError::Failed(format!(
+                "{} transaction(s) are already open, the most DB_POOL_MAX_SIZE allows; commit or roll one back first",
+                open
+            )));
+        }
+        let closed = self.cursors.shrink_to(limit - open - 1);
+        if closed > 0 {
+            info!(
+                "Closed {} unfinished query cursor(s) to free a connection for a transaction",
+                closed
+            );
+        }
+
+        let read_only = args.read_only || !self.allow_write_ops;
+        let begin = format!(
+            "BEGIN ISOLATION LEVEL {}{}",
+            args.isolation.sql(),
+            if read_only { " READ ONLY" } else { "" }
+        );
+        pooler::begin(self.client.as_ref().unwrap(), &begin)
+            .await
+            .map_err(|e| failed("Failed to begin the transaction", e))?;
+        let transaction = OpenTransaction::new(self.client.take().unwrap());
+        let transaction_id = self
+            .transactions
+            .insert(transaction)
+            .map_err(|e| ToolError::Failed(e.to_string()))?;
+        info!("Began transaction {}", transaction_id);
+        Ok(TransactionInfo {
+            transaction_id,
+            isolation: args.isolation,
+            read_only,
+            timeout_secs: self.transactions.timeout().as_secs(),
+        })
+    }
+
+    async fn commit_transaction(&self, args: CommitTransactionArgs) -> ToolResult<TransactionEnd> {
+        let id = args.transaction_id;
+        let (transaction, client) = self
+            .transactions
+            .remove(&id)
+            .map_err(|e| self.transaction_unavailable(&id, e))?;
+        if let Err(e) = client.batch_execute("COMMIT").await {
+            drop(Object::take(client));
+            return Err(rolled_back(query_error(e)));
+        }
+        drop(client);
+        if transaction.changed_schema
+            && let Some(catalog) = Catalog::shared()
+        {
+            catalog.schema_changed();
+        }
+        info!(
+            "Committed transaction {} ({} statement(s))",
+            id, transaction.statements
+        );
+        Ok(TransactionEnd::new(id, "committed", &transaction))
+    }
+
+    async fn rollback_transaction(
+        &self,
+        args: RollbackTransactionArgs,
+    ) -> ToolResult<TransactionEnd> {
+        let id = args.transaction_id;
+        let (transaction, client) = self
+            .transactions
+            .remove(&id)
+            .map_err(|e| self.transaction_unavailable(&id, e))?;
+        // A connection that cannot roll back is closed, which does.
+        match client.batch_execute("ROLLBACK").await {
+            Ok(()) => drop(client),
+            Err(e) => {
+                warn!("Could not roll back transaction {}: {}", id, e);
+                drop(Object::take(client));
+            }
+        }
+        info!("Rolled back transaction {}", id);
+        Ok(TransactionEnd::new(id, "rolled_back", &transaction))
+    }
+
+    /// The error for a `transaction_id` naming no transaction to use.
+    fn transaction_unavailable(&self, id: &str, reason: Unavailable) -> ToolError {
+        match reason {
+            Unavailable::Unknown => ToolError::InvalidArguments(format!(
+                "Unknown transaction '{}': it was committed, rolled back, or rolled back when a statement in it failed",
+                id
+            )),
+            Unavailable::Expired => ToolError::Failed(format!(
+                "Transaction '{}' was rolled back after going unused for {}s (TRANSACTION_TIMEOUT_SECS)",
+                id,
+                self.transactions.timeout().as_secs()
+            )),
+            Unavailable::Busy => ToolError::Failed(format!(
+                "Transaction '{}' is running another statement; wait for it to finish",
+                id
+            )),
+        }
+    }
+
+    async fn explain(&self, args: ExplainArgs) -> ToolResult<ExplainResult> {
+        if args.analyze && !self.allow_write_ops {
+            return Err(PolicyDenial {
+                rule: "read_only",
+                matched: "analyze".to_string(),
+                message:
+                    "EXPLAIN ANALYZE runs the statement, which is not allowed without write access"
+                        .to_string(),
+                remedy:
+                    "Explain without analyze, or set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable."
+                        .to_string(),
+            }
+            .into());
+        }
+        if let Some(identity) = &self.identity
+            && let Some(construct) = identity::role_change(&args.sql)
+        {
+            return Err(PolicyDenial {
+                rule: "session_role",
+                matched: construct.to_string(),
+                message: format!(
+                    "Statements that can change the session role are not allowed while running as role '{}'",
+                    identity.role
+                ),
+                remedy: "Unset MCP_PRINCIPAL and ROLE_MAP to run as the connecting user.".to_string(),
+            }
+            .into());
+        }
+        self.check_server_version(&args.sql)?;
+        self.check_sql_access(&args.sql).await?;
+
+        let mut options = vec!["FORMAT JSON"];
+        for (enabled, option) in [
+            (args.analyze, "ANALYZE"),
+            (args.buffers, "BUFFERS"),
+            (args.verbose, "VERBOSE"),
+        ] {
+            if enabled {
+                options.push(option);
+            }
+        }
+        let prefix = format!("EXPLAIN ({}) ", options.join(", "));
+        let sql = format!("{}{}", prefix, args.sql);
+        let client = self.client.as_ref().unwrap();
+        let statement = Prepared::new(client, &sql).await.map_err(|e| {
+            statement_error(format!("Explain error: {}", db_error_message(&e)), &e)
+                .after_prefix(&prefix)
+        })?;
+        if let Some(types) = statement.params()
+            && types.len() != args.params.len()
+        {
+            return Err(ToolError::InvalidArguments(format!(
+                "The statement has {} parameter(s) but {} value(s) were given in params",
+                types.len(),
+                args.params.len()
+            )));
+        }
+        let params = statement.text_params(&args.params);
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+
+        let begin = if self.allow_write_ops {
+            "BEGIN"
+        } else {
+            "BEGIN TRANSACTION READ ONLY"
+        };
+        pooler::begin(client, begin)
+            .await
+            .map_err(|e| failed("Failed to start a transaction", e))?;
+        let result = self
+            .with_timeout(
+                client.cancel_token(),
+                self.query_timeout,
+                statement.query_one(client, &params),
+            )
+            .await;
+        // Rolled back so that an analyzed write leaves nothing behind.
+        if let Err(e) = client.batch_execute("ROLLBACK").await {
+            error!("Could not roll back after EXPLAIN: {}", e);
+        }
+        let row = result
+            .ok_or_else(|| timed_out(self.query_timeout))?
+            .map_err(|e| query_error(e).after_prefix(&prefix))?;
+
+        // The output is a one-element array holding the plan and its totals.
+        let ExplainOutput(mut output) = row.get(0);
+        let mut output = match output.get_mut(0).map(Value::take) {
+            Some(Value::Object(output)) => output,
+            _ => {
+                return Err(ToolError::Failed(format!(
+                    "Unexpected EXPLAIN output: {}",
+                    output
+                )));
+            }
+        };
+        Ok(ExplainResult {
+            plan: output.remove("Plan").unwrap_or(Value::Null),
+            planning_time_ms: output.get("Planning Time").and_then(Value::as_f64),
+            execution_time_ms: output.get("Execution Time").and_then(Value::as_f64),
+            triggers: output.remove("Triggers"),
+        })
+    }
+}
+
+/// How a column gets a value when an INSERT gives none, from
+/// `information_schema.columns`.
+struct InsertColumn {
+    name: String,
+    nullable: bool,
+    has_default: bool,
+    /// `GENERATED ALWAYS AS IDENTITY`, which refuses explicit values.
+    identity_always: bool,
+    /// `GENERATED ALWAYS AS (...)`, whose value is always computed.
+    generated: bool,
+}
+
+/// Checks `rows` and the `referenced` column names against the table's
+/// columns, returning the columns the rows give values for, in table order so
+/// that every batch lists them the same way. Values for columns PostgreSQL
+/// always generates, and rows missing a required value, are reported here
+/// rather than as constraint violations.
+async fn insert_columns(
+    client: &Client,
+    schema: &str,
+    table: &str,
+    rows: &[Map<String, Value>],
+    referenced: &[&String],
+) -> ToolResult<Vec<String>> {
+    if rows.is_empty() {
+        return Err(ToolError::InvalidArguments(
+            "'rows' must contain at least one row".to_string(),
+        ));
+    }
+    let table_columns: Vec<InsertColumn> = pooler::query(
+        client,
+        "SELECT column_name::text, is_nullable = 'YES', column_default IS NOT NULL, \
+                    is_identity = 'YES' AND identity_generation = 'ALWAYS', \
+                    is_identity = 'YES', is_generated = 'ALWAYS' \
+             FROM information_schema.columns \
+             WHERE table_schema = $1 AND table_name = $2 ORDER BY ordinal_position",
+        &[(&schema, Type::TEXT), (&table, Type::TEXT)],
+    )
+    .await
+    .map_err(|e| failed("Failed to read columns", e))?
+    .iter()
+    .map(|row| InsertColumn {
+        name: row.get(0),
+        nullable: row.get(1),
+        // Identity columns draw from their sequence instead.
+        has_default: row.get::<_, bool>(2) || row.get::<_, bool>(4),
+        identity_always: row.get(3),
+        generated: row.get(5),
+    })
+    .collect();
+    if table_columns.is_empty() {
+        return Err(ToolError::InvalidArguments(format!(
+            "Table '{}.{}' not found",
+            schema, table
+        )));
+    }
+    let unknown = rows
+        .iter()
+        .flat_map(|row| row.keys())
+        .chain(referenced.iter().copied())
+        .find(|c| !table_columns.iter().any(|column| column.name == **c));
+    if let Some(unknown) = unknown {
+        return Err(ToolError::InvalidArguments(format!(
+            "Column '{}' not found in {}.{}. Available columns: {}",
+            unknown,
+            schema,
+            table,
+            table_columns
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+
+    for column in &table_columns {
+        let given = rows.iter().position(|row| row.contains_key(&column.name));
+        if let Some(index) = given
+            && (column.generated || column.identity_always)
+        {
+            let kind = if column.generated {
+                "a generated column"
+            } else {
+                "an identity column GENERATED ALWAYS"
+            };
+            return Err(ToolError::InvalidArguments(format!(
+                "Row {} gives a value for '{}', which is {}: PostgreSQL computes its value, so leave it out of the rows",
+                index, column.name, kind
+            )));
+        }
+        if column.nullable || column.has_default || column.generated {
+            continue;
+        }
+        let missing = rows
+            .iter()
+            .position(|row| row.get(&column.name).is_none_or(Value::is_null));
+        if let Some(index) = missing {
+            return Err(ToolError::InvalidArguments(format!(
+                "Row {} has no value for '{}', which is NOT NULL and has no default",
+                index, column.name
+            )));
+        }
+    }
+
+    let columns: Vec<String> = table_columns
+        .into_iter()
+        .map(|c| c.name)
+        .filter(|c| rows.iter().any(|row| row.contains_key(c)))
+        .collect();
+    if columns.is_empty() {
+        return Err(ToolError::InvalidArguments(
+            "The rows name no columns; give each row at least one value".to_string(),
+        ));
+    }
+    Ok(columns)
+}
+
+/// One batch written by `write_batches`.
+struct WrittenBatch {
+    first_row: usize,
+    rows: usize,
+    /// Rows inserted or updated.
+    affected: u64,
+    /// The rows of the statement's RETURNING clause, if it has one.
+    returned: Vec<tokio_postgres::Row>,
+}
+
+/// Inserts `rows` in batches of at most `batch_size` in one transaction,
+/// appending `clause` (ON CONFLICT and RETURNING) to each INSERT. If a batch
+/// fails, every batch is rolled back.
+async fn write_batches(
+    client: &Client,
+    qualified: &str,
+    columns: &[String],
+    rows: &[Map<String, Value>],
+    batch_size: i64,
+    clause: &str,
+    operation: &str,
+) -> ToolResult<Vec<WrittenBatch>> {
+    let batch_size = (batch_size.max(1) as usize).min(MAX_STATEMENT_PARAMS / columns.len());
+    let returning = clause.contains(" RETURNING ");
+
+    pooler::begin(client, "BEGIN")
+        .await
+        .map_err(|e| failed("Failed to start transaction", e))?;
+    let mut batches = Vec::new();
+    for (index, chunk) in rows.chunks(batch_size).enumerate() {
+        let first_row = index * batch_size;
+        let (sql, values) = insert_statement(qualified, columns, chunk, clause);
+        let result = match Prepared::new(client, &sql).await {
+            Ok(statement) => {
+                let params = statement.text_params(values.iter().copied());
+                let params: Vec<&(dyn ToSql + Sync)> =
+                    params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+                if returning {
+                    statement
+                        .query(client, &params)
+                        .await
+                        .map(|returned| (returned.len() as u64, returned))
+                } else {
+                    statement
+                        .execute(client, &params)
+                        .await
+                        .map(|affected| (affected, Vec::new()))
+                }
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok((affected, returned)) => batches.push(WrittenBatch {
+                first_row,
+                rows: chunk.len(),
+                affected,
+                returned,
+            }),
+            Err(e) => {
+                if let Err(e) = client.batch_execute("ROLLBACK").await {
+                    error!("Could not roll back {} batches: {}", operation, e);
+                }
+                return Err(statement_error(
+                    format!(
+                        "{} failed in the batch of rows {} to {}, so no rows were written: {}",
+                        operation,
+                        first_row,
+                        first_row + chunk.len() - 1,
+                        db_error_message(&e)
+                    ),
+                    &e,
+                ));
+            }
+        }
+    }
+    client
+        .batch_execute("COMMIT")
+        .await
+        .map_err(|e| failed("Failed to commit", e))?;
+    Ok(batches)
+}
+
+/// MERGE statements for `upsert_rows`, matching rows on the key columns.
+struct MergeUpsert<'a> {
+    qualified: &'a str,
+    /// The columns the rows give values for, and their types.
+    columns: Vec<(&'a String, &'a str)>,
+    key_columns: &'a [String],
+    update_columns: &'a [&'a String],
+}
+
+impl MergeUpsert<'_> {
+    /// Merges `rows` in batches of at most `batch_size` in one transaction.
+    /// MERGE returns no rows before PostgreSQL 17, so each batch first counts
+    /// its keys that exist to tell inserted rows from updated ones. If a
+    /// batch fails, every batch is rolled back.
+    async fn write(
+        &self,
+        client: &Client,
+        rows: &[Map<String, Value>],
+        batch_size: i64,
+    ) -> ToolResult<Vec<UpsertBatch>> {
+        let batch_size =
+            (batch_size.max(1) as usize).min(MAX_STATEMENT_PARAMS / self.columns.len());
+        pooler::begin(client, "BEGIN")
+            .await
+            .map_err(|e| failed("Failed to start transaction", e))?;
+        let mut batches = Vec::new();
+        for (index, chunk) in rows.chunks(batch_size).enumerate() {
+            let first_row = index * batch_size;
+            match self.write_batch(client, chunk).await {
+                Ok((matched, affected)) => {
+                    let inserted = chunk.len() as u64 - matched;
+                    batches.push(UpsertBatch {
+                        first_row,
+                        rows: chunk.len(),
+                        inserted,
+                        updated: affected.saturating_sub(inserted),
+                    });
+                }
+                Err(e) => {
+                    if let Err(e) = client.batch_execute("ROLLBACK").await {
+                        error!("Could not roll back Upsert batches: {}", e);
+                    }
+                    return Err(statement_error(
+                        format!(
+                            "Upsert failed in the batch of rows {} to {}, so no rows were written: {}",
+                            first_row,
+                            first_row + chunk.len() - 1,
+                            db_error_message(&e)
+                        ),
+                        &e,
+                    ));
+                }
+            }
+        }
+        client
+            .batch_execute("COMMIT")
+            .await
+            .map_err(|e| failed("Failed to commit", e))?;
+        Ok(batches)
+    }
+
+    /// Merges `rows`, returning how many matched an existing row and how
+    /// many rows were inserted or updated.
+    async fn write_batch(
+        &self,
+        client: &Client,
+        rows: &[Map<String, Value>],
+    ) -> Result<(u64, u64), tokio_postgres::Error> {
+        let (source, values) = self.source(rows);
+        let on = self
+            .key_columns
+            .iter()
+            .map(|key| format!("t.{} = s.{}", quote_ident(key), self.source_column(key)))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        let count = Prepared::new(
+            client,
+            &format!(
+                "SELECT count(*) FROM {} WHERE EXISTS (SELECT 1 FROM {} AS t WHERE {})",
+                source, self.qualified, on
+            ),
+        )
+        .await?;
+        let merge = Prepared::new(
+            client,
+            &format!(
+                "MERGE INTO {} AS t USING {} ON {}{}",
+                self.qualified,
+                source,
+                on,
+                self.when_clauses(rows)
+            ),
+        )
+        .await?;
+        let params = count.text_params(values.iter().copied());
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+        let matched: i64 = count
+            .query(client, &params)
+            .await?
+            .first()
+            .map_or(0, |row| row.get(0));
+        let affected = merge.execute(client, &params).await?;
+        Ok((matched as u64, affected))
+    }
+
+    /// Builds the `VALUES` list of `rows` as the relation `s`, with a column
+    /// `cN` for each of the columns, cast to its type and null where a row
+    /// gives no value, and a column `shape` numbering the distinct sets of
+    /// columns rows give, and the values to bind in order.
+    fn source<'v>(&self, rows: &'v [Map<String, Value>]) -> (String, Vec<&'v Value>) {
+        let shapes = self.shapes(rows);
+        let mut values = Vec::new();
+        let tuples: Vec<String> = rows
+            .iter()
+            .map(|row| {
+                let mut items: Vec<String> = self
+                    .columns
+                    .iter()
+                    .map(|(column, ty)| match row.get(*column) {
+                        Some(value) => {
+                            values.push(value);
+                            format!("${}::{}", values.len(), ty)
+                        }
+                        None => format!("NULL::{}", ty),
+                    })
+                    .collect();
+                let shape = shapes.iter().position(|s| *s == self.shape(row));
+                items.push(shape.unwrap_or_default().to_string());
+                format!("({})", items.join(", "))
+            })
+            .collect();
+        let names: Vec<String> = (1..=self.columns.len())
+            .map(|i| format!("c{}", i))
+            .collect();
+        (
+            format!(
+                "(VALUES {}) AS s({}, shape)",
+                tuples.join(", "),
+                names.join(", ")
+            ),
+            values,
+        )
+    }
+
+    /// A WHEN MATCHED and a WHEN NOT MATCHED clause for each shape of
+    /// `rows`: an update of the update columns it gives, if any, and an
+    /// insert of every column it gives, which leaves the rest to their
+    /// defaults.
+    fn when_clauses(&self, rows: &[Map<String, Value>]) -> String {
+        let mut clauses = String::new();
+        for (index, shape) in self.shapes(rows).iter().enumerate() {
+            let given: Vec<&String> = self
+                .columns
+                .iter()
+                .zip(shape)
+                .filter(|(_, given)| **given)
+                .map(|((column, _), _)| *column)
+                .collect();
+            let updates: Vec<String> = given
+                .iter()
+                .filter(|column| self.update_columns.contains(column))
+                .map(|column| format!("{} = s.{}", quote_ident(column), self.source_column(column)))
+                .collect();
+            if !updates.is_empty() {
+                clauses.push_str(&format!(
+                    " WHEN MATCHED AND s.shape = {} THEN UPDATE SET {}",
+                    index,
+                    updates.join(", ")
+                ));
+            }
+            clauses.push_str(&format!(
+                " WHEN NOT MATCHED AND s.shape = {} THEN INSERT ({}) VALUES ({})",
+                index,
+                given
+                    .iter()
+                    .map(|column| quote_ident(column))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                given
+                    .iter()
+                    .map(|column| format!("s.{}", self.source_column(column)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        clauses
+    }
+
+    /// The distinct sets of columns `rows` give, in order of first use.
+    fn shapes(&self, rows: &[Map<String, Value>]) -> Vec<Vec<bool>> {
+        let mut shapes: Vec<Vec<bool>> = Vec::new();
+        for row in rows {
+            let shape = self.shape(row);
+            if !shapes.contains(&shape) {
+                shapes.push(shape);
+            }
+        }
+        shapes
+    }
+
+    /// Which of the columns `row` gives.
+    fn shape(&self, row: &Map<String, Value>) -> Vec<bool> {
+        self.columns
+            .iter()
+            .map(|(column, _)| row.contains_key(*column))
+            .collect()
+    }
+
+    /// The column of `s` holding `column`.
+    fn source_column(&self, column: &str) -> String {
+        let index = self.columns.iter().position(|(c, _)| *c == column);
+        format!("c{}", index.unwrap_or_default() + 1)
+    }
+}
+
+/// Loads `files`, whose contents are `contents`, in the transaction the
+/// caller opened: the SQL files first, then the data files' tables, emptied
+/// unless `keep_existing`, parents first. Returns the files loaded, the
+/// tables emptied, and the number of sequences reset.
+async fn load_fixture_files(
+    client: &Client,
+    files: &[FixtureFile],
+    contents: Vec<FixtureContent>,
+    keep_existing: bool,
+) -> ToolResult<(Vec<LoadedFixture>, Vec<String>, usize)> {
+    let mut loaded = Vec::new();
+    let mut data = Vec::new();
+    client
+        .batch_execute("SET CONSTRAINTS ALL DEFERRED")
+        .await
+        .map_err(|e| failed("Failed to defer constraints", e))?;
+    for (file, content) in files.iter().zip(contents) {
+        match (&file.table, content) {
+            (_, FixtureContent::Sql(sql)) => {
+                client.batch_execute(&sql).await.map_err(|e| {
+                    statement_error(
+                        format!("{} failed: {}", file.name, db_error_message(&e)),
+                        &e,
+                    )
+                })?;
+                loaded.push(LoadedFixture {
+                    file: file.name.clone(),
+                    table: None,
+                    rows: None,
+                });
+            }
+            (Some(table), content) => data.push((file, table, content)),
+            (None, _) => {}
+        }
+    }
+
+    let tables: Vec<TableName> = data.iter().map(|(_, table, _)| (*table).clone()).collect();
+    let mut distinct: Vec<&TableName> = Vec::new();
+    for table in &tables {
+        if !distinct.contains(&table) {
+            distinct.push(table);
+        }
+    }
+    let qualify =
+        |(schema, table): &TableName| format!("{}.{}", quote_ident(schema), quote_ident(table));
+    let references: Vec<(TableName, TableName)> = client
+        .query(
+            "SELECT fn.nspname::text, fc.relname::text, tn.nspname::text, tc.relname::text \
+             FROM pg_constraint c \
+             JOIN pg_class fc ON fc.oid = c.conrelid \
+             JOIN pg_namespace fn ON fn.oid = fc.relnamespace \
+             JOIN pg_class tc ON tc.oid = c.confrelid \
+             JOIN pg_namespace tn ON tn.oid = tc.relnamespace \
+             WHERE c.contype = 'f'",
+            &[],
+        )
+        .await
+        .map_err(|e| failed("Failed to read foreign keys", e))?
+        .iter()
+        .map(|row| ((row.get(0), row.get(1)), (row.get(2), row.get(3))))
+        .filter(|(from, to)| tables.contains(from) && tables.contains(to))
+        .collect();
+
+    let mut truncated = Vec::new();
+    if !keep_existing && !distinct.is_empty() {
+        let names: Vec<String> = distinct.iter().map(|t| qualify(t)).collect();
+        client
+            .batch_execute(&format!("TRUNCATE {} RESTART IDENTITY", names.join(", ")))
+            .await
+            .map_err(|e| failed("Failed to empty the fixture tables", e))?;
+        truncated = distinct
+            .iter()
+            .map(|(s, t)| format!("{}.{}", s, t))
+            .collect();
+    }
+
+    let mut data: Vec<Option<(&FixtureFile, &TableName, FixtureContent)>> =
+        data.into_iter().map(Some).collect();
+    for index in fixtures::load_order(&tables, &references) {
+        let Some((file, table, content)) = data[index].take() else {
+            continue;
+        };
+        let qualified = qualify(table);
+        let rows = match content {
+            FixtureContent::Csv { columns, text } => {
+                let sql = format!(
+                    "COPY {} ({}) FROM STDIN WITH (FORMAT csv, HEADER true)",
+                    qualified,
+                    columns
+                        .iter()
+                        .map(|c| quote_ident(c))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                copy_fixture(client, &sql, text).await.map_err(|e| {
+                    statement_error(
+                        format!("Loading {} failed: {}", file.name, db_error_message(&e)),
+                        &e,
+                    )
+                })?
+            }
+            FixtureContent::Rows(rows) if rows.is_empty() => 0,
+            FixtureContent::Rows(rows) => {
+                let columns = insert_columns(client, &table.0, &table.1, &rows, &[])
+                    .await
+                    .map_err(|e| match e {
+                        ToolError::InvalidArguments(message) => {
+                            ToolError::InvalidArguments(format!("{}: {}", file.name, message))
+                        }
+                        e => e,
+                    })?;
+                let batch_size = FIXTURE_BATCH_SIZE.min(MAX_STATEMENT_PARAMS / columns.len());
+                let mut inserted = 0;
+                for (index, chunk) in rows.chunks(batch_size).enumerate() {
+                    let (sql, values) = insert_statement(&qualified, &columns, chunk, "");
+                    let result = match client.prepare(&sql).await {
+                        Ok(statement) => {
+                            let params: Vec<TextParam> = values
+                                .iter()
+                                .zip(statement.params())
+                                .map(|(value, ty)| TextParam::from_json(value, ty))
+                                .collect();
+                            let params: Vec<&(dyn ToSql + Sync)> =
+                                params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+                            client.execute(&statement, &params).await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    inserted += result.map_err(|e| {
+                        let first_row = index * batch_size;
+                        statement_error(
+                            format!(
+                                "Loading {} failed in the batch of rows {} to {}: {}",
+                                file.name,
+                                first_row,
+                                first_row + chunk.len() - 1,
+                                db_error_message(&e)
+                            ),
+                            &e,
+                        )
+                    })?;
+                }
+                inserted
+            }
+            FixtureContent::Sql(_) => continue,
+        };
+        loaded.push(LoadedFixture {
+            file: file.name.clone(),
+            table: Some(format!("{}.{}", table.0, table.1)),
+            rows: Some(rows),
+        });
+    }
+
+    // Serial and identity columns were given explicit values, which their
+    // sequences know nothing of.
+    let mut sequences_reset = 0;
+    for table in distinct {
+        let qualified = qualify(table);
+        let sequences = client
+            .query(
+                "SELECT s.name, a.attname::text \
+                 FROM pg_attribute a, \
+                      LATERAL (SELECT pg_get_serial_sequence($1, a.attname) AS name) s \
+                 WHERE a.attrelid = $1::regclass AND a.attnum > 0 \
+                   AND NOT a.attisdropped AND s.name IS NOT NULL",
+                &[&qualified],
+            )
+            .await
+            .map_err(|e| failed("Failed to read sequences", e))?;
+        for row in sequences {
+            let sequence: String = row.get(0);
+            let column: String = row.get(1);
+            let set = client
+                .query(
+                    &format!(
+                        "SELECT setval($1::text::regclass, max({0})::int8) FROM {1} HAVING max({0}) IS NOT NULL",
+                        quote_ident(&column),
+                        qualified
+                    ),
+                    &[&sequence],
+                )
+                .await
+                .map_err(|e| failed("Failed to reset a sequence", e))?;
+            sequences_reset += set.len();
+        }
+    }
+    Ok((loaded, truncated, sequences_reset))
+}
+
+/// Sends `text` to the `COPY ... FROM STDIN` statement `sql`, returning the
+/// rows copied.
+async fn copy_fixture(
+    client: &Client,
+    sql: &str,
+    text: String,
+) -> Result<u64, tokio_postgres::Error> {
+    let sink = client.copy_in::<_, Bytes>(sql).await?;
+    pin_mut!(sink);
+    sink.as_mut().send(Bytes::from(text)).await?;
+    sink.finish().await
+}
+
+/// Builds a multi-row INSERT of `rows`, with a placeholder for each value
+/// present and DEFAULT for each missing one, and the values to bind in order.
+fn insert_statement<'a>(
+    qualified: &str,
+    columns: &[String],
+    rows: &'a [Map<String, Value>],
+    conflict_clause: &str,
+) -> (String, Vec<&'a Value>) {
+    let mut values = Vec::new();
+    let tuples: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            let items: Vec<String> = columns
+                .iter()
+                .map(|column| match row.get(column) {
+                    Some(value) => {
+                        values.push(value);
+                        format!("${}", values.len())
+                    }
+                    None => "DEFAULT".to_string(),
+                })
+                .collect();
+            format!("({})", items.join(", "))
+        })
+        .collect();
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES {}{}",
+        qualified,
+        columns
+            .iter()
+            .map(|c| quote_ident(c))
+            .collect::<Vec<_>>()
+            .join(", "),
+        tuples.join(", "),
+        conflict_clause
+    );
+    (sql, values)
+}
+
+/// Checks that `key` and `filter` together pick rows: that they name at
+/// least one column, and compare none with null.
+fn check_row_filter(key: &Map<String, Value>, filter: &[Condition]) -> ToolResult<()> {
+    if key.is_empty() && filter.is_empty() {
+        return Err(ToolError::InvalidArguments(
+            "'key' or 'filter' must name at least one column".to_string(),
+        ));
+    }
+    if let Some((column, _)) = key.iter().find(|(_, value)| value.is_null()) {
+        return Err(ToolError::InvalidArguments(format!(
+            "Key column '{}' is null, which matches no row",
+            column
+        )));
+    }
+    check_conditions(filter)
+}
+
+/// Checks that each condition of `filter` has the value its operator needs.
+fn check_conditions(filter: &[Condition]) -> ToolResult<()> {
+    for condition in filter {
+        match condition.op {
+            ConditionOp::IsNull | ConditionOp::IsNotNull => {}
+            ConditionOp::In | ConditionOp::NotIn if !condition.value.is_array() => {
+                return Err(ToolError::InvalidArguments(format!(
+                    "The condition on '{}' needs an array of values",
+                    condition.column
+                )));
+            }
+            _ if condition.value.is_null() => {
+                return Err(ToolError::InvalidArguments(format!(
+                    "The condition on '{}' compares with null, which matches no row; use is_null or is_not_null",
+                    condition.column
+                )));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The SQL conditions of `key` and `filter`, with their values bound by
+/// `bind`.
+fn row_filter<'a>(
+    key: &'a Map<String, Value>,
+    filter: &'a [Condition],
+    bind: &mut impl FnMut(&'a Value) -> String,
+) -> Vec<String> {
+    let mut conditions: Vec<String> = key
+        .iter()
+        .map(|(column, value)| format!("{} = {}", quote_ident(column), bind(value)))
+        .collect();
+    for condition in filter {
+        let column = quote_ident(&condition.column);
+        let value = &condition.value;
+        conditions.push(match condition.op {
+            ConditionOp::Eq => format!("{} = {}", column, bind(value)),
+            ConditionOp::Ne => format!("{} <> {}", column, bind(value)),
+            ConditionOp::Lt => format!("{} < {}", column, bind(value)),
+            ConditionOp::Lte => format!("{} <= {}", column, bind(value)),
+            ConditionOp::Gt => format!("{} > {}", column, bind(value)),
+            ConditionOp::Gte => format!("{} >= {}", column, bind(value)),
+            ConditionOp::In => format!("{} = ANY({})", column, bind(value)),
+            ConditionOp::NotIn => format!("{} <> ALL({})", column, bind(value)),
+            ConditionOp::Like => format!("{} LIKE {}", column, bind(value)),
+            ConditionOp::Ilike => format!("{} ILIKE {}", column, bind(value)),
+            ConditionOp::IsNull => format!("{} IS NULL", column),
+            ConditionOp::IsNotNull => format!("{} IS NOT NULL", column),
+        });
+    }
+    conditions
+}
+
+/// The error for a failed `query` statement. A write rejected by the
+/// read-only transaction is a policy denial, like one caught by keyword.
+fn query_error(e: impl Into<RowError>) -> ToolError {
+    let e = match e.into() {
+        RowError::Statement(e) => e,
+        e => return ToolError::Failed(format!("Query execution error: {}", e)),
+    };
+    if e.code() == Some(&SqlState::READ_ONLY_SQL_TRANSACTION) {
+        return PolicyDenial {
+            rule: "read_only",
+            matched: db_error_message(&e),
+            message: "Write operations are not allowed".to_string(),
+            remedy: "Set DANGEROUSLY_ALLOW_WRITE_OPS=true to enable.".to_string(),
+        }
+        .into();
+    }
+    statement_error(
+        format!("Query execution error: {}", db_error_message(&e)),
+        &e,
+    )
+}
+
+/// Adds to `e` that the transaction the statement ran in was rolled back.
+fn rolled_back(e: ToolError) -> ToolError {
+    const ROLLED_BACK: &str = "; the transaction was rolled back";
+    match e {
+        ToolError::Database { message, data } => ToolError::Database {
+            message: message + ROLLED_BACK,
+            data,
+        },
+        ToolError::Denied(mut denial) => {
+            denial.message.push_str(ROLLED_BACK);
+            ToolError::Denied(denial)
+        }
+        ToolError::Failed(message) => ToolError::Failed(message + ROLLED_BACK),
+        e => e,
+    }
+}
+
+fn timed_out(timeout: Option<Duration>) -> ToolError {
+    ToolError::Failed(timeout_message(timeout.unwrap_or_default()))
+}
+
+/// The columns of a statement's result rows.
+fn result_columns(columns: &[Column]) -> Vec<ColumnType> {
+    columns
+        .iter()
+        .map(|c| ColumnType {
+            name: c.name().to_string(),
+            r#type: c.type_().name().to_string(),
+        })
+        .collect()
+}
+
+pub(crate) fn failed(context: &str, e: impl Into<RowError>) -> ToolError {
+    match e.into() {
+        RowError::Statement(e) => {
+            statement_error(format!("{}: {}", context, db_error_message(&e)), &e)
+        }
+        e => ToolError::Failed(format!("{}: {}", context, e)),
+    }
+}
+
+/// The error for the failed statement `e`, described by `message`.
+fn statement_error(message: String, e: &tokio_postgres::Error) -> ToolError {
+    match db_error_data(e) {
+        Some(data) => ToolError::Database { message, data },
+        None => ToolError::Failed(message),
+    }
+}
+
+async fn query_rows(
+    client: &Client,
+    sql: &str,
+    params: &[pooler::Param<'_>],
+) -> Result<Vec<RowMap>, tokio_postgres::Error> {
+    Ok(pooler::query(client, sql, params)
+        .await?
+        .iter()
+        .map(row_to_map)
+        .collect())
+}
+
+/// Runs amcheck's `verify_heapam` (amcheck 1.3+, PostgreSQL 14+) on a table.
+async fn check_heap(client: &Client, relation: &str, oid: u32) -> RelationCheck {
+    let outcome = pooler::query(
+        client,
+        "SELECT blkno, offnum, attnum, msg FROM verify_heapam($1::oid::regclass)",
+        &[(&oid, Type::OID)],
+    )
+    .await
+    .map(|rows| rows.iter().map(|r| json!(row_to_map(r))).collect());
+    integrity_result(relation, "verify_heapam", outcome)
+}
+
+/// Classifies an amcheck outcome. amcheck reports btree corruption by raising
+/// `index_corrupted`/`data_corrupted` errors and heap corruption as result rows.
+fn integrity_result(
+    relation: &str,
+    check: &'static str,
+    outcome: Result<Vec<Value>, tokio_postgres::Error>,
+) -> RelationCheck {
+    let (status, findings, error) = match outcome {
+        Ok(findings) if findings.is_empty() => ("ok", None, None),
+        Ok(findings) => ("corrupt", Some(findings), None),
+        Err(e) => {
+            let corrupted = matches!(
+                e.code(),
+                Some(&SqlState::INDEX_CORRUPTED) | Some(&SqlState::DATA_CORRUPTED)
+            );
+            let message = db_error_message(&e);
+            if corrupted {
+                ("corrupt", Some(vec![json!(message)]), None)
+            } else {
+                ("error", None, Some(message))
+            }
+        }
+    };
+    RelationCheck {
+        relation: relation.to_string(),
+        check: Some(check),
+        status,
+        findings,
+        error,
+    }
+}
+
+/// A foreign key relationship, declared or described by the caller.
+struct ForeignKey {
+    name: Option<String>,
+    child_schema: String,
+    child_table: String,
+    child_columns: Vec<String>,
+    parent_schema: String,
+    parent_table: String,
+    parent_columns: Vec<String>,
+}
+
+/// A total row count with a sample of the rows.
+#[derive(Serialize, JsonSchema)]
+struct SampledCount {
+    count: i64,
+    samples: Vec<Value>,
+}
+
+/// Runs a query selecting `count(*) OVER ()` and a JSON sample row, returning
+/// the total count with the sampled rows.
+async fn sampled_count(client: &Client, sql: &str) -> Result<SampledCount, tokio_postgres::Error> {
+    let rows = pooler::query(client, sql, &[]).await?;
+    let count: i64 = rows.first().map(|r| r.get(0)).unwrap_or(0);
+    let samples: Vec<Value> = rows
+        .iter()
+        .filter_map(|r| serde_json::from_str(r.get::<_, &str>(1)).ok())
+        .collect();
+    Ok(SampledCount { count, samples })
+}
+
+/// Builds a `jsonb` expression identifying a row by its primary key columns,
+/// or by `ctid` when the table has no primary key.
+fn row_key_expr(primary_key: &[String], alias: Option<&str>) -> String {
+    let prefix = alias.map(|a| format!("{}.", a)).unwrap_or_default();
+    if primary_key.is_empty() {
+        return format!("jsonb_build_object('ctid', {}ctid::text)", prefix);
+    }
+    jsonb_object_expr(primary_key, &prefix)
+}
+
+/// Builds `jsonb_build_object('col', prefix"col", ...)` for the given columns.
+fn jsonb_object_expr(columns: &[String], prefix: &str) -> String {
+    format!(
+        "jsonb_build_object({})",
+        columns
+            .iter()
+            .map(|c| format!("'{}', {}{}", c.replace('\'', "''"), prefix, quote_ident(c)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
@@ -0,0 +1,260 @@
+//! Transactions spanning several tool calls.
+//!
+//! `begin_transaction` starts a transaction on a connection of its own and
+//! returns an id, which `query` calls pass as `transaction_id` to run their
+//! statement in it, until `commit_transaction` or `rollback_transaction` ends
+//! it. The connection stays checked out of the pool meanwhile. A transaction
+//! is rolled back, its connection closed rather than returned,
+//!
+//! - when one of its statements fails, since PostgreSQL refuses any other
+//!   statement in the transaction after that
+//! - when it goes unused for `TRANSACTION_TIMEOUT_SECS` (300 by default)
+//! - when the session goes idle for `IDLE_TIMEOUT_SECS`
+//! - when the session ends, such as when the client disconnects
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use deadpool_postgres::Object;
+use ring::rand::{SecureRandom, SystemRandom};
+use tracing::warn;
+
+use crate::config;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+
+/// Reads `TRANSACTION_TIMEOUT_SECS`, how long a transaction may go unused
+/// before it is rolled back.
+pub(crate) fn timeout_from_env() -> Result<Duration> {
+    let secs = match config::var("TRANSACTION_TIMEOUT_SECS") {
+        Ok(v) => v
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .context("TRANSACTION_TIMEOUT_SECS must be a positive number of seconds")?,
+        Err(_) => DEFAULT_TIMEOUT_SECS,
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+/// An open transaction and the connection it runs on.
+pub(crate) struct OpenTransaction {
+    /// `None` while a statement runs on it.
+    client: Option<Object>,
+    pub(crate) started: Instant,
+    last_used: Instant,
+    /// Statements run in the transaction so far.
+    pub(crate) statements: usize,
+    /// Whether one of them changed the schema, such as `CREATE` or `ALTER`.
+    pub(crate) changed_schema: bool,
+}
+
+impl OpenTransaction {
+    /// Takes over `client`, on which a transaction has been begun.
+    pub(crate) fn new(client: Object) -> Self {
+        let now = Instant::now();
+        Self {
+            client: Some(client),
+            started: now,
+            last_used: now,
+            statements: 0,
+            changed_schema: false,
+        }
+    }
+
+    fn take_client(&mut self) -> Option<Object> {
+        self.last_used = Instant::now();
+        self.client.take()
+    }
+}
+
+impl Drop for OpenTransaction {
+    fn drop(&mut self) {
+        // Closing the connection rolls back its transaction; returned to the
+        // pool, it would hand the open transaction to the next request.
+        if let Some(client) = self.client.take() {
+            drop(Object::take(client));
+        }
+    }
+}
+
+/// Why a transaction id names no transaction the caller can use.
+pub(crate) enum Unavailable {
+    Unknown,
+    /// It was rolled back after going unused for the timeout.
+    Expired,
+    /// Another statement is running in it.
+    Busy,
+}
+
+#[derive(Default)]
+struct State {
+    open: HashMap<String, OpenTransaction>,
+    /// Transactions rolled back for going unused, until their id is next
+    /// used.
+    expired: HashSet<String>,
+}
+
+/// The session's open transactions, by id. Clones share them.
+#[derive(Clone)]
+pub(crate) struct Transactions {
+    state: Arc<Mutex<State>>,
+    timeout: Duration,
+    random: SystemRandom,
+}
+
+impl Transactions {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::default())),
+            timeout,
+            random: SystemRandom::new(),
+        }
+    }
+
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        lock(&self.state).open.len()
+    }
+
+    /// Registers `transaction` and returns its id, rolling it back once it
+    /// goes unused for the timeout.
+    pub(crate) fn insert(&self, transaction: OpenTransaction) -> Result<String> {
+        let mut bytes = [0u8; 12];
+        self.random
+            .fill(&mut bytes)
+            .map_err(|_| anyhow!("no random numbers for a transaction id"))?;
+        let id: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        lock(&self.state).open.insert(id.clone(), transaction);
+        tokio::spawn(expire(
+            Arc::downgrade(&self.state),
+            id.clone(),
+            self.timeout,
+        ));
+        Ok(id)
+    }
+
+    /// Takes the connection of the transaction `id` to run a statement on.
+    pub(crate) fn start_statement(&self, id: &str) -> Result<Object, Unavailable> {
+        let mut state = lock(&self.state);
+        if state.expired.remove(id) {
+            return Err(Unavailable::Expired);
+        }
+        let transaction = state.open.get_mut(id).ok_or(Unavailable::Unknown)?;
+        transaction.take_client().ok_or(Unavailable::Busy)
+    }
+
+    /// Returns the connection of the transaction `id` without having run a
+    /// statement on it.
+    pub(crate) fn put_back(&self, id: &str, client: Object) {
+        if let Some(transaction) = lock(&self.state).open.get_mut(id) {
+            transaction.client = Some(client);
+            transaction.last_used = Instant::now();
+        }
+    }
+
+    /// Returns the connection of the transaction `id` after a statement
+    /// succeeded on it.
+    pub(crate) fn finish_statement(&self, id: &str, client: Object, changed_schema: bool) {
+        if let Some(transaction) = lock(&self.state).open.get_mut(id) {
+            transaction.client = Some(client);
+            transaction.last_used = Instant::now();
+            transaction.statements += 1;
+            transaction.changed_schema |= changed_schema;
+        }
+    }
+
+    /// Rolls back the transaction `id` after a statement failed on its
+    /// connection `client`, which PostgreSQL aborted the transaction for.
+    pub(crate) fn fail_statement(&self, id: &str, client: Object) {
+        lock(&self.state).open.remove(id);
+        drop(Object::take(client));
+    }
+
+    /// Removes the transaction `id` to end it, with its connection, which is
+    /// returned to the pool when dropped: the caller closes it instead with
+    /// `Object::take` if the transaction could not be ended.
+    pub(crate) fn remove(&self, id: &str) -> Result<(OpenTransaction, Object), Unavailable> {
+        let mut state = lock(&self.state);
+        if state.expired.remove(id) {
+            return Err(Unavailable::Expired);
+        }
+        let transaction = state.open.get_mut(id).ok_or(Unavailable::Unknown)?;
+        let client = transaction.take_client().ok_or(Unavailable::Busy)?;
+        let transaction = state.open.remove(id).ok_or(Unavailable::Unknown)?;
+        Ok((transaction, client))
+    }
+
+    /// Rolls back every open transaction, returning how many there were.
+    pub(crate) fn close_all(&self) -> usize {
+        let mut state = lock(&self.state);
+        let closed = state.open.len();
+        state.open.clear();
+        closed
+    }
+}
+
+/// Rolls back the transaction `id` once it has gone unused for `timeout`,
+/// unless it ended first or the session did.
+async fn expire(session: Weak<Mutex<State>>, id: String, timeout: Duration) {
+    let mut deadline = Instant::now() + timeout;
+    loop {
+        tokio::time::sleep_until(deadline.into()).await;
+        let Some(state) = session.upgrade() else {
+            return;
+        };
+        let mut state = lock(&state);
+        let Some(transaction) = state.open.get(&id) else {
+            return;
+        };
+        // A statement still running counts as use.
+        if transaction.client.is_none() {
+            deadline = Instant::now() + timeout;
+            continue;
+        }
+        deadline = transaction.last_used + timeout;
+        if deadline > Instant::now() {
+            continue;
+        }
+        state.open.remove(&id);
+        state.expired.insert(id.clone());
+        warn!(
+            "Rolled back transaction {} after {}s unused (TRANSACTION_TIMEOUT_SECS)",
+            id,
+            timeout.as_secs()
+        );
+        return;
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The statement keyword of `sql` if it would begin or end a transaction,
+/// which the server does with `begin_transaction`, `commit_transaction`, and
+/// `rollback_transaction` instead. Savepoints are allowed.
+pub(crate) fn control_keyword(sql: &str) -> Option<&'static str> {
+    let upper = sql.trim_start().to_ascii_uppercase();
+    let mut words = upper
+        .split(|c: char| c.is_whitespace() || c == ';')
+        .filter(|w| !w.is_empty());
+    match words.next()? {
+        "BEGIN" => Some("BEGIN"),
+        "START" => Some("START TRANSACTION"),
+        "COMMIT" => Some("COMMIT"),
+        "END" => Some("END"),
+        "ABORT" => Some("ABORT"),
+        "PREPARE" if words.next() == Some("TRANSACTION") => Some("PREPARE TRANSACTION"),
+        "ROLLBACK" => {
+            let next = words.find(|w| !matches!(*w, "WORK" | "TRANSACTION"));
+            (next != Some("TO")).then_some("ROLLBACK")
+        }
+        _ => None,
+    }
+}
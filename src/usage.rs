@@ -0,0 +1,290 @@
+//! Statistics on how the server itself is used, for the `usage_stats` tool.
+//!
+//! An administrator deciding whether an agent is using the database well
+//! wants to know what it has been doing: which tools it calls and how often
+//! they fail, how many rows it has taken in, and which tables it keeps coming
+//! back to. Each session counts, in memory, its
+//!
+//! - tool calls, by tool, with how many failed and how many of those were
+//!   policy denials
+//! - rows the `query` tool returned, including later pages of paged results
+//! - accesses of each table: by a tool's `table` and `schema` arguments, by
+//!   the tables a `query` statement refers to, and by reads of table
+//!   resources
+//!
+//! and the same counts are kept for the server's lifetime across all
+//! sessions. Tables named without a schema are counted under `public`, and
+//! tables the access lists hide are left out of the report. The counts start
+//! over when the server restarts, and at most 1000 tools and 1000 tables are
+//! counted, so that a client cannot grow them without bound.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use std::time::Instant;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::JsonRpcResponse;
+use crate::access::referenced_tables;
+
+/// The most distinct tools, and tables, a set of counts keeps.
+const MAX_KEYS: usize = 1000;
+
+static SERVER: LazyLock<ServerUsage> = LazyLock::new(|| ServerUsage {
+    started: Instant::now(),
+    sessions: AtomicU64::new(0),
+    counts: Mutex::new(Counts::default()),
+});
+
+struct ServerUsage {
+    started: Instant,
+    sessions: AtomicU64,
+    counts: Mutex<Counts>,
+}
+
+#[derive(Default)]
+struct Counts {
+    tools: BTreeMap<String, ToolCounts>,
+    rows_returned: u64,
+    /// Accesses by schema and table.
+    tables: BTreeMap<(String, String), u64>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct ToolCounts {
+    calls: u64,
+    errors: u64,
+    denials: u64,
+}
+
+/// The session's usage counts. Clones share them.
+#[derive(Clone)]
+pub(crate) struct Usage {
+    started: Instant,
+    counts: Arc<Mutex<Counts>>,
+}
+
+/// The tool a `tools/call` request calls and the tables it names, noted
+/// before it runs.
+pub(crate) struct ToolCall {
+    tool: String,
+    tables: Vec<(String, String)>,
+}
+
+impl Usage {
+    /// Starts counting a new session. The first also starts the server's
+    /// uptime.
+    pub(crate) fn new() -> Self {
+        LazyLock::force(&SERVER);
+        Self {
+            started: Instant::now(),
+            counts: Arc::new(Mutex::new(Counts::default())),
+        }
+    }
+
+    /// Counts a session initialized by a client.
+    pub(crate) fn record_initialize(&self) {
+        SERVER.sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts `rows` rows returned by the `query` tool.
+    pub(crate) fn record_rows(&self, rows: usize) {
+        for mut counts in [lock(&self.counts), lock(&SERVER.counts)] {
+            counts.rows_returned += rows as u64;
+        }
+    }
+
+    /// Counts a read of the table resource for `schema.table`.
+    pub(crate) fn record_table_read(&self, schema: &str, table: &str) {
+        let table = (schema.to_string(), table.to_string());
+        for mut counts in [lock(&self.counts), lock(&SERVER.counts)] {
+            if let Some(accesses) = entry(&mut counts.tables, &table) {
+                *accesses += 1;
+            }
+        }
+    }
+
+    /// Counts `call`, which got `response`.
+    pub(crate) fn record_call(&self, call: Option<ToolCall>, response: &JsonRpcResponse) {
+        let Some(call) = call else {
+            return;
+        };
+        let error = response.error.as_ref();
+        let denied = error
+            .and_then(|e| e.data.as_ref())
+            .and_then(|data| data.get("type"))
+            .and_then(Value::as_str)
+            == Some("policy_denied");
+        for mut counts in [lock(&self.counts), lock(&SERVER.counts)] {
+            if let Some(tool) = entry(&mut counts.tools, &call.tool) {
+                tool.calls += 1;
+                tool.errors += u64::from(error.is_some());
+                tool.denials += u64::from(denied);
+            }
+            for table in &call.tables {
+                if let Some(accesses) = entry(&mut counts.tables, table) {
+                    *accesses += 1;
+                }
+            }
+        }
+    }
+
+    /// The session's and the server's counts, each with at most `limit` of
+    /// the tables `visible` accepts.
+    pub(crate) fn stats(&self, limit: usize, visible: impl Fn(&str, &str) -> bool) -> UsageStats {
+        UsageStats {
+            session: lock(&self.counts).summary(self.started, limit, &visible),
+            server: ServerStats {
+                sessions: SERVER.sessions.load(Ordering::Relaxed),
+                usage: lock(&SERVER.counts).summary(SERVER.started, limit, &visible),
+            },
+        }
+    }
+}
+
+/// Notes the tool a `tools/call` request with `params` calls and the tables
+/// it names.
+pub(crate) fn tool_call(params: Option<&Value>) -> Option<ToolCall> {
+    let params = params?;
+    let tool = params.get("name")?.as_str()?.to_string();
+    let arguments = params.get("arguments");
+    let argument = |name: &str| arguments?.get(name)?.as_str();
+    let mut tables = Vec::new();
+    if let Some(table) = argument("table") {
+        tables.push(qualified(argument("schema"), table));
+    }
+    if tool == "query"
+        && let Some(sql) = argument("sql")
+        && let Ok(referenced) = referenced_tables(sql)
+    {
+        for (schema, table) in referenced {
+            let name = qualified(schema.as_deref(), &table);
+            if !tables.contains(&name) {
+                tables.push(name);
+            }
+        }
+    }
+    Some(ToolCall { tool, tables })
+}
+
+fn qualified(schema: Option<&str>, table: &str) -> (String, String) {
+    (schema.unwrap_or("public").to_string(), table.to_string())
+}
+
+/// The count for `key`, unless there is none and no room for one.
+fn entry<'a, K: Ord + Clone, T: Default>(
+    map: &'a mut BTreeMap<K, T>,
+    key: &K,
+) -> Option<&'a mut T> {
+    if !map.contains_key(key) && map.len() >= MAX_KEYS {
+        return None;
+    }
+    Some(map.entry(key.clone()).or_default())
+}
+
+impl Counts {
+    fn summary(
+        &self,
+        started: Instant,
+        limit: usize,
+        visible: &impl Fn(&str, &str) -> bool,
+    ) -> UsageSummary {
+        let mut tools: Vec<ToolUsage> = self
+            .tools
+            .iter()
+            .map(|(name, counts)| ToolUsage {
+                name: name.clone(),
+                calls: counts.calls,
+                errors: counts.errors,
+                denials: counts.denials,
+            })
+            .collect();
+        tools.sort_by(|a, b| b.calls.cmp(&a.calls).then_with(|| a.name.cmp(&b.name)));
+        let mut top_tables: Vec<TableUsage> = self
+            .tables
+            .iter()
+            .filter(|((schema, table), _)| visible(schema, table))
+            .map(|((schema, table), accesses)| TableUsage {
+                schema: schema.clone(),
+                table: table.clone(),
+                accesses: *accesses,
+            })
+            .collect();
+        top_tables.sort_by(|a, b| {
+            b.accesses
+                .cmp(&a.accesses)
+                .then_with(|| (&a.schema, &a.table).cmp(&(&b.schema, &b.table)))
+        });
+        top_tables.truncate(limit);
+        let tool_calls = tools.iter().map(|t| t.calls).sum();
+        let errors = tools.iter().map(|t| t.errors).sum();
+        UsageSummary {
+            uptime_seconds: started.elapsed().as_secs(),
+            tool_calls,
+            errors,
+            denials: tools.iter().map(|t| t.denials).sum(),
+            error_rate: (tool_calls > 0).then(|| errors as f64 / tool_calls as f64),
+            rows_returned: self.rows_returned,
+            tools,
+            top_tables,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct UsageStats {
+    /// This session's activity
+    session: UsageSummary,
+    /// Activity across every session since the server started
+    server: ServerStats,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ServerStats {
+    /// Sessions clients have initialized, including this one
+    sessions: u64,
+    #[serde(flatten)]
+    usage: UsageSummary,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct UsageSummary {
+    /// Seconds since the session, or the server, started
+    uptime_seconds: u64,
+    tool_calls: u64,
+    /// Tool calls that failed, including denials
+    errors: u64,
+    /// Tool calls refused by a policy: read-only mode, the table access
+    /// lists, or production confirmation
+    denials: u64,
+    /// errors / tool_calls, or null before the first call
+    error_rate: Option<f64>,
+    /// Rows the query tool returned
+    rows_returned: u64,
+    /// Each tool called, most called first
+    tools: Vec<ToolUsage>,
+    /// The most accessed tables, leaving out those the access lists hide
+    top_tables: Vec<TableUsage>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ToolUsage {
+    name: String,
+    calls: u64,
+    errors: u64,
+    denials: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TableUsage {
+    schema: String,
+    table: String,
+    accesses: u64,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
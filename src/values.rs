@@ -0,0 +1,891 @@
+//! JSON encoding of column values that JSON has no exact type for.
+//!
+//! Most JSON clients read numbers as IEEE doubles, which hold integers exactly
+//! only up to 2^53 and about 15 significant decimal digits. With
+//! `BIG_NUMBERS_AS_STRINGS` (the default), `int8` and `numeric` values beyond
+//! that are written as strings of their exact digits instead, and values
+//! within it as numbers.
+//!
+//! JSON has no NaN or infinity either. `float4` and `float8` values that are
+//! not finite are written as the strings `"NaN"`, `"Infinity"`, and
+//! `"-Infinity"`, or as `null` with `NON_FINITE_FLOATS=null`.
+//!
+//! Dates and timestamps are written as ISO 8601 strings, with timestamptz
+//! values in UTC. PostgreSQL's `infinity` and `-infinity` are written as those
+//! strings, and years before 1 AD with PostgreSQL's ` BC` suffix. Intervals
+//! are written as ISO 8601 durations, as PostgreSQL's `iso_8601`
+//! `IntervalStyle` writes them, such as `P1Y2M3DT4H5M6.5S`, and `bytea`
+//! values as base64 strings.
+//!
+//! Arrays are written as JSON arrays, nested one level per dimension, whose
+//! elements are written like column values of the element type, and pgvector
+//! `vector` values as arrays of numbers. Values of other types, such as
+//! enums, composite types, and `json`, are written as strings of their text.
+//!
+//! A `query` call can ask for other representations with its `output`
+//! options, for consumers such as spreadsheets and BI tools that need them:
+//! dates and timestamps as seconds since the Unix epoch, `numeric` values as
+//! numbers whatever their precision, `uuid` values in upper case, and `bool`
+//! values as 1 and 0. The options rewrite values as written above, array
+//! elements included.
+
+use std::error::Error;
+use std::sync::OnceLock;
+
+use anyhow::{Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio_postgres::types::{Field, FromSql, Kind, Type};
+
+use crate::config;
+
+/// The largest integer a double holds exactly, 2^53 - 1.
+const MAX_SAFE_INTEGER: i64 = 9_007_199_254_740_991;
+
+/// Significant decimal digits a double always round-trips.
+const SAFE_DIGITS: usize = 15;
+
+static SETTINGS: OnceLock<ValueSettings> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+struct ValueSettings {
+    big_numbers_as_strings: bool,
+    /// Whether non-finite floats are written as strings rather than `null`.
+    non_finite_as_strings: bool,
+}
+
+impl Default for ValueSettings {
+    fn default() -> Self {
+        Self {
+            big_numbers_as_strings: true,
+            non_finite_as_strings: true,
+        }
+    }
+}
+
+/// Reads `BIG_NUMBERS_AS_STRINGS` and `NON_FINITE_FLOATS`.
+pub(crate) fn init() -> Result<()> {
+    let big_numbers_as_strings = config::var("BIG_NUMBERS_AS_STRINGS")
+        .map(|v| v != "false" && v != "0")
+        .unwrap_or(true);
+    let non_finite_as_strings =
+        non_finite_as_strings(config::var("NON_FINITE_FLOATS").ok().as_deref())?;
+    let _ = SETTINGS.set(ValueSettings {
+        big_numbers_as_strings,
+        non_finite_as_strings,
+    });
+    Ok(())
+}
+
+/// Whether the `NON_FINITE_FLOATS` setting `value` writes non-finite floats
+/// as strings.
+fn non_finite_as_strings(value: Option<&str>) -> Result<bool> {
+    Ok(match value {
+        None | Some("string") => true,
+        Some("null") => false,
+        Some(other) => bail!(
+            "NON_FINITE_FLOATS must be 'string' or 'null', not '{}'",
+            other
+        ),
+    })
+}
+
+fn settings() -> ValueSettings {
+    SETTINGS.get().copied().unwrap_or_default()
+}
+
+/// Whether values beyond the safe range are written as strings.
+pub(crate) fn big_numbers_as_strings() -> bool {
+    settings().big_numbers_as_strings
+}
+
+pub(crate) fn int8(value: i64) -> Value {
+    if big_numbers_as_strings() && !(-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&value) {
+        json!(value.to_string())
+    } else {
+        json!(value)
+    }
+}
+
+pub(crate) fn float4(value: f32) -> Value {
+    float4_as(value, settings().non_finite_as_strings)
+}
+
+pub(crate) fn float8(value: f64) -> Value {
+    float8_as(value, settings().non_finite_as_strings)
+}
+
+fn float4_as(value: f32, non_finite_as_strings: bool) -> Value {
+    if value.is_finite() {
+        json!(value)
+    } else {
+        non_finite(value.into(), non_finite_as_strings)
+    }
+}
+
+fn float8_as(value: f64, non_finite_as_strings: bool) -> Value {
+    if value.is_finite() {
+        json!(value)
+    } else {
+        non_finite(value, non_finite_as_strings)
+    }
+}
+
+fn non_finite(value: f64, as_strings: bool) -> Value {
+    if !as_strings {
+        Value::Null
+    } else if value.is_nan() {
+        json!("NaN")
+    } else if value > 0.0 {
+        json!("Infinity")
+    } else {
+        json!("-Infinity")
+    }
+}
+
+pub(crate) fn numeric(value: &Numeric) -> Value {
+    let text = &value.0;
+    let Ok(float) = text.parse::<f64>() else {
+        return json!(text);
+    };
+    // NaN and the infinities have no JSON number.
+    if !float.is_finite() {
+        return json!(text);
+    }
+    if !big_numbers_as_strings() {
+        return json!(float);
+    }
+    if !text.contains('.') {
+        return match text.parse::<i64>() {
+            Ok(integer) => int8(integer),
+            Err(_) => json!(text),
+        };
+    }
+    let digits = text.trim_start_matches('-').replace('.', "");
+    let significant = digits.trim_start_matches('0').trim_end_matches('0');
+    if significant.len() <= SAFE_DIGITS {
+        json!(float)
+    } else {
+        json!(text)
+    }
+}
+
+/// A `numeric` value as its exact decimal text, for example `-12.50`, `NaN`,
+/// or `Infinity`.
+#[derive(Debug)]
+pub(crate) struct Numeric(String);
+
+impl<'a> FromSql<'a> for Numeric {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        // The binary format: digit count, weight of the first digit, sign,
+        // and display scale, then the base-10000 digits.
+        let field = |i: usize| -> Result<u16, Box<dyn Error + Sync + Send>> {
+            raw.get(i * 2..i * 2 + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .ok_or_else(|| "truncated numeric value".into())
+        };
+        let ndigits = field(0)? as usize;
+        let weight = field(1)? as i16 as isize;
+        let sign = field(2)?;
+        let dscale = field(3)? as usize;
+        let digits = (0..ndigits)
+            .map(|i| field(4 + i))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut text = match sign {
+            0x0000 => String::new(),
+            0x4000 => "-".to_string(),
+            0xC000 => return Ok(Self("NaN".to_string())),
+            0xD000 => return Ok(Self("Infinity".to_string())),
+            0xF000 => return Ok(Self("-Infinity".to_string())),
+            _ => return Err(format!("invalid numeric sign {:#x}", sign).into()),
+        };
+        let digit = |i: isize| -> u16 {
+            usize::try_from(i)
+                .ok()
+                .and_then(|i| digits.get(i).copied())
+                .unwrap_or(0)
+        };
+        if weight < 0 {
+            text.push('0');
+        } else {
+            text.push_str(&digit(0).to_string());
+            for i in 1..=weight {
+                text.push_str(&format!("{:04}", digit(i)));
+            }
+        }
+        if dscale > 0 {
+            let mut fraction = String::new();
+            let mut i = weight + 1;
+            while fraction.len() < dscale {
+                fraction.push_str(&format!("{:04}", digit(i)));
+                i += 1;
+            }
+            fraction.truncate(dscale);
+            text.push('.');
+            text.push_str(&fraction);
+        }
+        Ok(Self(text))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::NUMERIC
+    }
+}
+
+/// Days from 1970-01-01 to PostgreSQL's epoch, 2000-01-01.
+const PG_EPOCH_DAYS: i64 = 10_957;
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+/// A `date`, `timestamp`, or `timestamptz` value as ISO 8601 text, for
+/// example `2024-03-01`, `2024-03-01T12:30:00.5`, or
+/// `2024-03-01T12:30:00Z`, or `infinity` or `-infinity`.
+#[derive(Debug)]
+pub(crate) struct DateTime(pub(crate) String);
+
+impl<'a> FromSql<'a> for DateTime {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let text = if *ty == Type::DATE {
+            let days = i32::from_sql(ty, raw)?;
+            match days {
+                i32::MAX => "infinity".to_string(),
+                i32::MIN => "-infinity".to_string(),
+                days => format_date(days.into(), ""),
+            }
+        } else {
+            // Microseconds since the epoch; timestamptz values are in UTC.
+            let micros = i64::from_sql(&Type::INT8, raw)?;
+            let zone = if *ty == Type::TIMESTAMPTZ { "Z" } else { "" };
+            match micros {
+                i64::MAX => "infinity".to_string(),
+                i64::MIN => "-infinity".to_string(),
+                micros => {
+                    let days = micros.div_euclid(MICROS_PER_DAY);
+                    let micros = micros.rem_euclid(MICROS_PER_DAY);
+                    let seconds = micros / 1_000_000;
+                    let mut time = format!(
+                        "T{:02}:{:02}:{:02}",
+                        seconds / 3600,
+                        seconds / 60 % 60,
+                        seconds % 60
+                    );
+                    let fraction = micros % 1_000_000;
+                    if fraction > 0 {
+                        time.push_str(format!(".{:06}", fraction).trim_end_matches('0'));
+                    }
+                    time.push_str(zone);
+                    format_date(days, &time)
+                }
+            }
+        };
+        Ok(Self(text))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::DATE || *ty == Type::TIMESTAMP || *ty == Type::TIMESTAMPTZ
+    }
+}
+
+/// An `interval` value as an ISO 8601 duration, such as `P1Y2M3DT4H5M6.5S`
+/// or `PT0S`. As in PostgreSQL's `iso_8601` output, each field carries its
+/// own sign, as in `P-1Y-2M3DT-4H`, since intervals mix months, days, and
+/// time that need not share one.
+#[derive(Debug)]
+pub(crate) struct Interval(pub(crate) String);
+
+impl<'a> FromSql<'a> for Interval {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        // The binary format: microseconds as int8, then days and months as
+        // int4.
+        let (micros, rest) = raw
+            .split_first_chunk::<8>()
+            .ok_or("truncated interval value")?;
+        let (days, rest) = rest
+            .split_first_chunk::<4>()
+            .ok_or("truncated interval value")?;
+        let months = rest.first_chunk::<4>().ok_or("truncated interval value")?;
+        let micros = i64::from_be_bytes(*micros);
+        let days = i32::from_be_bytes(*days);
+        let months = i32::from_be_bytes(*months);
+        if micros == 0 && days == 0 && months == 0 {
+            return Ok(Self("PT0S".to_string()));
+        }
+        let mut text = "P".to_string();
+        for (value, unit) in [(months / 12, 'Y'), (months % 12, 'M'), (days, 'D')] {
+            if value != 0 {
+                text.push_str(&format!("{}{}", value, unit));
+            }
+        }
+        if micros != 0 {
+            text.push('T');
+            let hours = micros / 3_600_000_000;
+            let minutes = micros / 60_000_000 % 60;
+            for (value, unit) in [(hours, 'H'), (minutes, 'M')] {
+                if value != 0 {
+                    text.push_str(&format!("{}{}", value, unit));
+                }
+            }
+            let micros = micros % 60_000_000;
+            if micros != 0 {
+                let sign = if micros < 0 { "-" } else { "" };
+                let micros = micros.unsigned_abs();
+                text.push_str(&format!("{}{}", sign, micros / 1_000_000));
+                let fraction = micros % 1_000_000;
+                if fraction > 0 {
+                    text.push_str(format!(".{:06}", fraction).trim_end_matches('0'));
+                }
+                text.push('S');
+            }
+        }
+        Ok(Self(text))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::INTERVAL
+    }
+}
+
+/// A `bytea` value as a base64 string.
+pub(crate) fn bytea(bytes: &[u8]) -> Value {
+    json!(BASE64_STANDARD.encode(bytes))
+}
+
+/// Formats the date `days` after PostgreSQL's epoch, followed by `time`.
+fn format_date(days: i64, time: &str) -> String {
+    let (year, month, day) = civil_from_days(days + PG_EPOCH_DAYS);
+    if year > 0 {
+        format!("{:04}-{:02}-{:02}{}", year, month, day, time)
+    } else {
+        // Year 0 is 1 BC.
+        format!("{:04}-{:02}-{:02}{} BC", 1 - year, month, day, time)
+    }
+}
+
+/// Days from 1970-01-01 to the proleptic Gregorian date, after Howard
+/// Hinnant's `days_from_civil`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The proleptic Gregorian date `days` after 1970-01-01, after Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// A `uuid` value in its hyphenated form, such as
+/// `a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11`.
+#[derive(Debug)]
+pub(crate) struct Uuid(pub(crate) String);
+
+impl<'a> FromSql<'a> for Uuid {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        if raw.len() != 16 {
+            return Err("uuid value is not 16 bytes".into());
+        }
+        let mut text = String::with_capacity(36);
+        for (i, byte) in raw.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                text.push('-');
+            }
+            text.push_str(&format!("{:02x}", byte));
+        }
+        Ok(Self(text))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::UUID
+    }
+}
+
+/// A pgvector `vector` value as an array of numbers, such as `[1, 2.5, 3]`.
+#[derive(Debug)]
+pub(crate) struct Vector(pub(crate) Value);
+
+impl<'a> FromSql<'a> for Vector {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        // The binary format: the dimension count and an unused field, both
+        // int2, then each element as a float4.
+        let (header, elements) = raw.split_at_checked(4).ok_or("truncated vector value")?;
+        let dimensions = usize::from(u16::from_be_bytes([header[0], header[1]]));
+        if elements.len() != dimensions * 4 {
+            return Err("vector value does not match its dimension count".into());
+        }
+        Ok(Self(Value::Array(
+            elements
+                .chunks_exact(4)
+                .map(|bytes| float4(f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])))
+                .collect(),
+        )))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty.name() == "vector"
+    }
+}
+
+/// An array as nested JSON arrays, one level per dimension, with NULL
+/// elements as `null`. Its elements are written like column values of their
+/// type.
+#[derive(Debug)]
+pub(crate) struct Array(pub(crate) Value);
+
+impl<'a> FromSql<'a> for Array {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let Kind::Array(element_type) = ty.kind() else {
+            return Err(format!("{} is not an array type", ty).into());
+        };
+        // The binary format: dimension count, a has-nulls flag, and the
+        // element type, then each dimension's length and lower bound, then
+        // each element as its length (-1 for NULL) and bytes.
+        let mut rest = raw;
+        let dimensions = next_int(&mut rest)?;
+        next_int(&mut rest)?;
+        next_int(&mut rest)?;
+        let lengths = (0..dimensions)
+            .map(|_| {
+                let length = next_int(&mut rest)?;
+                next_int(&mut rest)?;
+                usize::try_from(length).map_err(|_| "negative array length".into())
+            })
+            .collect::<Result<Vec<usize>, Box<dyn Error + Sync + Send>>>()?;
+        let count = if lengths.is_empty() {
+            0
+        } else {
+            lengths.iter().product()
+        };
+        let mut elements = Vec::with_capacity(count);
+        for _ in 0..count {
+            let value = match usize::try_from(next_int(&mut rest)?) {
+                Ok(length) => {
+                    if rest.len() < length {
+                        return Err("truncated array element".into());
+                    }
+                    let (bytes, tail) = rest.split_at(length);
+                    rest = tail;
+                    element(element_type, bytes)?
+                }
+                Err(_) => Value::Null,
+            };
+            elements.push(value);
+        }
+        Ok(Self(nest(&mut elements.into_iter(), &lengths)))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Array(_))
+    }
+}
+
+/// Reads a big-endian `int4` from the front of `rest`.
+fn next_int(rest: &mut &[u8]) -> Result<i32, Box<dyn Error + Sync + Send>> {
+    let (bytes, tail) = rest.split_first_chunk::<4>().ok_or("truncated value")?;
+    *rest = tail;
+    Ok(i32::from_be_bytes(*bytes))
+}
+
+/// A non-NULL array element.
+fn element(ty: &Type, raw: &[u8]) -> Result<Value, Box<dyn Error + Sync + Send>> {
+    if let Kind::Domain(base) = ty.kind() {
+        return element(base, raw);
+    }
+    Ok(match *ty {
+        Type::INT2 => json!(i16::from_sql(ty, raw)?),
+        Type::INT4 => json!(i32::from_sql(ty, raw)?),
+        Type::INT8 => int8(i64::from_sql(ty, raw)?),
+        Type::NUMERIC => numeric(&Numeric::from_sql(ty, raw)?),
+        Type::BOOL => json!(bool::from_sql(ty, raw)?),
+        Type::FLOAT4 => float4(f32::from_sql(ty, raw)?),
+        Type::FLOAT8 => float8(f64::from_sql(ty, raw)?),
+        Type::DATE | Type::TIMESTAMP | Type::TIMESTAMPTZ => json!(DateTime::from_sql(ty, raw)?.0),
+        Type::INTERVAL => json!(Interval::from_sql(ty, raw)?.0),
+        Type::UUID => json!(Uuid::from_sql(ty, raw)?.0),
+        Type::BYTEA => bytea(raw),
+        _ => json!(Text::from_sql(ty, raw)?.0),
+    })
+}
+
+/// A value of a type without a JSON encoding of its own, such as an enum,
+/// a composite type, `json`, `time`, `inet`, or `citext`, as its text. The
+/// text is read from the binary form the server sends: for most such types
+/// it is the text PostgreSQL writes, and the fields of a composite value are
+/// written like column values of their type. Types from extensions are read
+/// as text when their binary form is text, as for `citext`; `from_sql` fails
+/// for the others, such as `hstore`.
+#[derive(Debug)]
+pub(crate) struct Text(pub(crate) String);
+
+impl<'a> FromSql<'a> for Text {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        match ty.kind() {
+            Kind::Enum(_) => return Ok(Self(utf8(raw)?)),
+            Kind::Composite(fields) => return record(Some(fields), raw).map(Self),
+            Kind::Domain(base) => return Ok(Self(field_text(element(base, raw)?))),
+            Kind::Array(_) => return Ok(Self(field_text(Array::from_sql(ty, raw)?.0))),
+            _ => {}
+        }
+        Ok(Self(match *ty {
+            Type::RECORD => record(None, raw)?,
+            Type::TEXT
+            | Type::VARCHAR
+            | Type::BPCHAR
+            | Type::NAME
+            | Type::JSON
+            | Type::XML
+            | Type::UNKNOWN => utf8(raw)?,
+            Type::JSONB => utf8(versioned(raw)?)?,
+            Type::CHAR => match raw {
+                [byte] if byte.is_ascii() => char::from(*byte).to_string(),
+                [byte] => format!("\\{:03o}", byte),
+                _ => return Err("\"char\" value is not one byte".into()),
+            },
+            Type::OID
+            | Type::REGPROC
+            | Type::REGPROCEDURE
+            | Type::REGOPER
+            | Type::REGOPERATOR
+            | Type::REGCLASS
+            | Type::REGTYPE
+            | Type::XID
+            | Type::CID => {
+                u32::from_be_bytes(*raw.first_chunk::<4>().ok_or("truncated value")?).to_string()
+            }
+            Type::TIME => time_of_day(i64::from_sql(&Type::INT8, raw)?),
+            Type::TIMETZ => {
+                let (micros, zone) = raw.split_first_chunk::<8>().ok_or("truncated value")?;
+                let zone = i32::from_be_bytes(*zone.first_chunk::<4>().ok_or("truncated value")?);
+                // The zone is stored as seconds west of UTC.
+                let offset = -zone;
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.unsigned_abs();
+                let mut text = format!(
+                    "{}{}{:02}",
+                    time_of_day(i64::from_be_bytes(*micros)),
+                    sign,
+                    offset / 3600
+                );
+                if offset % 3600 != 0 {
+                    text.push_str(&format!(":{:02}", offset / 60 % 60));
+                }
+                if offset % 60 != 0 {
+                    text.push_str(&format!(":{:02}", offset % 60));
+                }
+                text
+            }
+            Type::INET | Type::CIDR => inet(raw)?,
+            Type::MACADDR | Type::MACADDR8 => raw
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>()
+                .join(":"),
+            Type::BIT | Type::VARBIT => {
+                let mut rest = raw;
+                let length = usize::try_from(next_int(&mut rest)?)?;
+                if rest.len() * 8 < length {
+                    return Err("truncated bit string".into());
+                }
+                (0..length)
+                    .map(|i| {
+                        if rest[i / 8] & (0x80 >> (i % 8)) != 0 {
+                            '1'
+                        } else {
+                            '0'
+                        }
+                    })
+                    .collect()
+            }
+            _ if matches!(ty.name(), "ltree" | "lquery" | "ltxtquery") => utf8(versioned(raw)?)?,
+            _ if ty.schema() != "pg_catalog" => match std::str::from_utf8(raw) {
+                Ok(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => {
+                    text.to_string()
+                }
+                _ => return Err(format!("no text form for {} values", ty.name()).into()),
+            },
+            _ => return Err(format!("no text form for {} values", ty.name()).into()),
+        }))
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+/// A value as written in the text of a value containing it.
+fn field_text(value: Value) -> String {
+    match value {
+        Value::String(text) => text,
+        value => value.to_string(),
+    }
+}
+
+fn utf8(raw: &[u8]) -> Result<String, Box<dyn Error + Sync + Send>> {
+    Ok(std::str::from_utf8(raw)?.to_string())
+}
+
+/// The text of a binary form that starts with a format version byte, as
+/// those of `jsonb` and `ltree` do.
+fn versioned(raw: &[u8]) -> Result<&[u8], Box<dyn Error + Sync + Send>> {
+    match raw.split_first() {
+        Some((1, text)) => Ok(text),
+        _ => Err("unknown binary format version".into()),
+    }
+}
+
+/// A time of day given in microseconds since midnight, such as `13:05:09`
+/// or `13:05:09.25`.
+fn time_of_day(micros: i64) -> String {
+    let seconds = micros / 1_000_000;
+    let mut text = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    let fraction = micros % 1_000_000;
+    if fraction > 0 {
+        text.push_str(format!(".{:06}", fraction).trim_end_matches('0'));
+    }
+    text
+}
+
+/// An `inet` or `cidr` value, such as `192.168.0.1` or `10.0.0.0/8`.
+fn inet(raw: &[u8]) -> Result<String, Box<dyn Error + Sync + Send>> {
+    // The binary format: the address family, the prefix length, whether it
+    // is a cidr value, and the address length, each a byte, then the
+    // address.
+    let [family, bits, is_cidr, _, address @ ..] = raw else {
+        return Err("truncated inet value".into());
+    };
+    let (address, max_bits) = match (family, address.len()) {
+        (2, 4) => (
+            std::net::Ipv4Addr::from(<[u8; 4]>::try_from(address)?).to_string(),
+            32,
+        ),
+        (3, 16) => (
+            std::net::Ipv6Addr::from(<[u8; 16]>::try_from(address)?).to_string(),
+            128,
+        ),
+        _ => return Err("unknown inet address family".into()),
+    };
+    Ok(if *is_cidr != 0 || *bits != max_bits {
+        format!("{}/{}", address, bits)
+    } else {
+        address
+    })
+}
+
+/// A composite value as PostgreSQL writes it, such as `(1,"a b",)`: its
+/// fields in parentheses, quoted where needed, with NULL fields left empty.
+/// `fields` are the type's, or `None` for an anonymous `record`, whose
+/// fields are read by the types the server sends with them.
+fn record(fields: Option<&[Field]>, raw: &[u8]) -> Result<String, Box<dyn Error + Sync + Send>> {
+    // The binary format: the field count, then each field as its type's
+    // oid, its length (-1 for NULL), and its bytes.
+    let mut rest = raw;
+    let count = next_int(&mut rest)?;
+    let mut text = "(".to_string();
+    for i in 0..usize::try_from(count)? {
+        if i > 0 {
+            text.push(',');
+        }
+        let oid = u32::try_from(next_int(&mut rest)?)?;
+        let Ok(length) = usize::try_from(next_int(&mut rest)?) else {
+            continue;
+        };
+        if rest.len() < length {
+            return Err("truncated record field".into());
+        }
+        let (bytes, tail) = rest.split_at(length);
+        rest = tail;
+        let ty = match fields.and_then(|fields| fields.get(i)) {
+            Some(field) => field.type_().clone(),
+            None => Type::from_oid(oid)
+                .unwrap_or_else(|| Type::new(String::new(), oid, Kind::Simple, String::new())),
+        };
+        let value = field_text(element(&ty, bytes)?);
+        if value.is_empty()
+            || value
+                .chars()
+                .any(|c| matches!(c, '"' | '\\' | '(' | ')' | ',') || c.is_whitespace())
+        {
+            text.push('"');
+            text.push_str(&value.replace('\\', "\\\\").replace('"', "\"\""));
+            text.push('"');
+        } else {
+            text.push_str(&value);
+        }
+    }
+    text.push(')');
+    Ok(text)
+}
+
+/// Groups `elements` into nested arrays of the given dimension lengths.
+fn nest(elements: &mut impl Iterator<Item = Value>, lengths: &[usize]) -> Value {
+    match lengths {
+        [] => Value::Array(Vec::new()),
+        [length] => Value::Array(elements.take(*length).collect()),
+        [length, inner @ ..] => Value::Array((0..*length).map(|_| nest(elements, inner)).collect()),
+    }
+}
+
+/// Representations a `query` call can ask for instead of the defaults.
+#[derive(Debug, Clone, Copy, Default, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct OutputOptions {
+    /// Write date, timestamp, and timestamptz values as seconds since
+    /// 1970-01-01 00:00 UTC, taking timestamp values to be in UTC
+    dates_as_epoch: bool,
+    /// Write numeric values as JSON numbers even beyond the precision a
+    /// double holds, rounding them
+    numeric_as_float: bool,
+    /// Write uuid values in upper case rather than lower case
+    uuid_uppercase: bool,
+    /// Write bool values as 1 and 0
+    bool_as_int: bool,
+}
+
+impl OutputOptions {
+    /// Whether the options change nothing.
+    pub(crate) fn is_default(&self) -> bool {
+        !(self.dates_as_epoch || self.numeric_as_float || self.uuid_uppercase || self.bool_as_int)
+    }
+
+    /// Rewrites `value`, a value of the column type named `type_name` as
+    /// `row_to_map` writes it, such as `date` or `_date` for an array of
+    /// dates.
+    pub(crate) fn apply(&self, type_name: &str, value: &mut Value) {
+        let element_type = type_name.strip_prefix('_');
+        match value {
+            // An array holds an array per further dimension.
+            Value::Array(elements) if element_type.is_some() => {
+                for element in elements {
+                    self.apply(type_name, element);
+                }
+            }
+            value => self.apply_scalar(element_type.unwrap_or(type_name), value),
+        }
+    }
+
+    fn apply_scalar(&self, type_name: &str, value: &mut Value) {
+        let coerced = match (type_name, &*value) {
+            ("date" | "timestamp" | "timestamptz", Value::String(text)) if self.dates_as_epoch => {
+                epoch_seconds(text)
+            }
+            ("numeric", Value::String(text)) if self.numeric_as_float => {
+                text.parse::<f64>().ok().map(float8)
+            }
+            ("uuid", Value::String(text)) if self.uuid_uppercase => {
+                Some(json!(text.to_ascii_uppercase()))
+            }
+            ("bool", Value::Bool(flag)) if self.bool_as_int => Some(json!(i32::from(*flag))),
+            _ => None,
+        };
+        if let Some(coerced) = coerced {
+            *value = coerced;
+        }
+    }
+}
+
+/// Seconds since 1970-01-01 00:00 UTC of a date or timestamp as `DateTime`
+/// writes it, a whole number unless it has a fraction. `infinity` and
+/// `-infinity` are written as non-finite floats are.
+fn epoch_seconds(text: &str) -> Option<Value> {
+    match text {
+        "infinity" => return Some(float8(f64::INFINITY)),
+        "-infinity" => return Some(float8(f64::NEG_INFINITY)),
+        _ => {}
+    }
+    let (text, bc) = match text.strip_suffix(" BC") {
+        Some(text) => (text, true),
+        None => (text, false),
+    };
+    let text = text.strip_suffix('Z').unwrap_or(text);
+    let (date, time) = text.split_once('T').unwrap_or((text, "00:00:00"));
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    let year = if bc { 1 - year } else { year };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time_parts = time.splitn(3, ':');
+    let hours: i64 = time_parts.next()?.parse().ok()?;
+    let minutes: i64 = time_parts.next()?.parse().ok()?;
+    let seconds: i64 = time_parts.next()?.parse().ok()?;
+    let whole = days_from_civil(year, month, day) * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    if fraction.is_empty() {
+        Some(json!(whole))
+    } else {
+        let fraction: f64 = format!("0.{}", fraction).parse().ok()?;
+        Some(json!(whole as f64 + fraction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_floats_as_strings() {
+        for (value, text) in [
+            (f64::NAN, "NaN"),
+            (f64::INFINITY, "Infinity"),
+            (f64::NEG_INFINITY, "-Infinity"),
+        ] {
+            assert_eq!(float8_as(value, true), json!(text));
+            assert_eq!(float4_as(value as f32, true), json!(text));
+        }
+    }
+
+    #[test]
+    fn non_finite_floats_as_null() {
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(float8_as(value, false), Value::Null);
+            assert_eq!(float4_as(value as f32, false), Value::Null);
+        }
+    }
+
+    #[test]
+    fn finite_floats_unchanged() {
+        for as_strings in [true, false] {
+            for value in [0.0, -0.0, 1.5, -2.25, f64::MAX, f64::MIN_POSITIVE] {
+                assert_eq!(float8_as(value, as_strings), json!(value));
+            }
+            for value in [0.0f32, 1.5, -2.25, f32::MAX, f32::MIN_POSITIVE] {
+                assert_eq!(float4_as(value, as_strings), json!(value));
+            }
+        }
+    }
+
+    #[test]
+    fn non_finite_floats_setting() {
+        assert!(non_finite_as_strings(None).unwrap());
+        assert!(non_finite_as_strings(Some("string")).unwrap());
+        assert!(!non_finite_as_strings(Some("null")).unwrap());
+        assert!(non_finite_as_strings(Some("zero")).is_err());
+    }
+
+    #[test]
+    fn default_setting_writes_strings() {
+        assert_eq!(float8(f64::INFINITY), json!("Infinity"));
+        assert_eq!(float4(f32::NAN), json!("NaN"));
+        assert_eq!(float8(0.5), json!(0.5));
+    }
+}
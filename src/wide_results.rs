@@ -0,0 +1,117 @@
+//! Summarizing `query` results with too many columns.
+//!
+//! A `SELECT *` from a wide table can return hundreds of columns, repeated in
+//! every row, and flood the caller's context with values it never asked
+//! about. A result with more than `MAX_RESULT_COLUMNS` (50 by default)
+//! columns is cut down to the first that many: the rest are listed in the
+//! result's `_meta.omittedColumns` and in a second text item, which suggests
+//! naming the wanted columns with the `columns` argument. A `query` that
+//! passes `columns` gets exactly those, however many there are.
+//! `MAX_RESULT_COLUMNS=0` turns the limit off.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::config;
+
+const DEFAULT_MAX_COLUMNS: usize = 50;
+
+/// Reads `MAX_RESULT_COLUMNS`; `None` when it is 0, which turns the limit
+/// off.
+pub(crate) fn max_columns_from_env() -> Result<Option<usize>> {
+    match config::var("MAX_RESULT_COLUMNS") {
+        Ok(v) => match v
+            .parse::<usize>()
+            .context("MAX_RESULT_COLUMNS must be a number of columns")?
+        {
+            0 => Ok(None),
+            columns => Ok(Some(columns)),
+        },
+        Err(_) => Ok(Some(DEFAULT_MAX_COLUMNS)),
+    }
+}
+
+/// The columns of a result to show, when not all of them.
+pub(crate) struct Selection {
+    /// In the order to show them.
+    pub(crate) shown: Vec<String>,
+    pub(crate) omitted: Vec<String>,
+    /// Names in `columns` that are not result columns.
+    pub(crate) unknown: Vec<String>,
+    /// Every result column.
+    names: Vec<String>,
+    /// Whether the result was cut down to `MAX_RESULT_COLUMNS`, rather than
+    /// to the columns asked for.
+    truncated: bool,
+}
+
+/// Which of the result columns `names` to show: those `wanted` names, if
+/// given, or else the first `max`. `None` when every column is shown.
+pub(crate) fn select(
+    names: &[String],
+    wanted: Option<&[String]>,
+    max: Option<usize>,
+) -> Option<Selection> {
+    let (shown, unknown, truncated) = match wanted {
+        Some(wanted) => {
+            let mut shown: Vec<String> = Vec::new();
+            let mut unknown = Vec::new();
+            for name in wanted {
+                if !names.contains(name) {
+                    unknown.push(name.clone());
+                } else if !shown.contains(name) {
+                    shown.push(name.clone());
+                }
+            }
+            (shown, unknown, false)
+        }
+        None => match max {
+            Some(max) if names.len() > max => (names[..max].to_vec(), Vec::new(), true),
+            _ => return None,
+        },
+    };
+    let omitted = names
+        .iter()
+        .filter(|name| !shown.contains(name))
+        .cloned()
+        .collect();
+    Some(Selection {
+        shown,
+        omitted,
+        unknown,
+        names: names.to_vec(),
+        truncated,
+    })
+}
+
+impl Selection {
+    /// Drops the columns not shown from each of `rows`.
+    pub(crate) fn apply(&self, rows: &mut [HashMap<String, Value>]) {
+        for row in rows {
+            row.retain(|name, _| self.shown.contains(name));
+        }
+    }
+
+    /// What the caller should know about the columns left out, if anything.
+    pub(crate) fn note(&self) -> Option<String> {
+        if !self.unknown.is_empty() {
+            Some(format!(
+                "The result has no column named {}. Its columns are: {}",
+                self.unknown.join(", "),
+                self.names.join(", ")
+            ))
+        } else if self.truncated {
+            Some(format!(
+                "The result has {} columns, more than MAX_RESULT_COLUMNS: only the first {} are shown. \
+                 Omitted: {}. Pass \"columns\" to choose which columns to return",
+                self.shown.len() + self.omitted.len(),
+                self.shown.len(),
+                self.omitted.join(", ")
+            ))
+        } else {
+            None
+        }
+    }
+}